@@ -56,6 +56,8 @@ fn main() {
         // Inputs must reside in some of include paths.
         .input("src/protos/WifiStartRequest.proto")
         .input("src/protos/WifiInfoResponse.proto")
+        .input("src/protos/WifiVersionResponse.proto")
+        .input("src/protos/WifiConnectStatus.proto")
         .input("src/protos/protos.proto")
         .input("src/protos/ev.proto")
         // Specify output directory relative to Cargo output directory.
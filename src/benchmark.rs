@@ -0,0 +1,244 @@
+//! Built-in throughput benchmark.
+//!
+//! Instead of a real phone and head unit, a synthetic source (acting as the MD)
+//! and a sink (acting as the HU) are connected over loopback TCP and the data
+//! goes through the regular `endpoint_reader`/`proxy` pipeline end to end.
+use crate::config::{AppConfig, SharedConfig};
+use crate::io_uring::{read_buffer_len, IoDevice, BUFFER_LEN, MITM_QUEUE_CAPACITY};
+use crate::mitm::{endpoint_reader, proxy, Packet, ProxyType};
+use crate::mitm::{ENCRYPTED, FRAME_TYPE_FIRST, FRAME_TYPE_LAST, HEADER_LENGTH};
+use bytesize::ByteSize;
+use humantime::format_duration;
+use simplelog::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio_uring::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> benchmark: </>";
+
+// Just a generic Result type to ease error handling for us. Errors in multithreaded
+// async contexts needs some extra restrictions
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// synthetic video-like frames sent by the fake phone
+const BENCH_CHANNEL: u8 = 3;
+const BENCH_PAYLOAD_LEN: usize = 16 * 1024 - HEADER_LENGTH;
+
+/// CPU time (user + system) consumed by this process so far
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    let tv = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    tv(usage.ru_utime) + tv(usage.ru_stime)
+}
+
+/// peak resident set size of this process in bytes
+fn max_rss() -> u64 {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    usage.ru_maxrss as u64 * 1024
+}
+
+/// one chunk of back-to-back single-frame packets, as a phone would send them
+fn synthetic_chunk() -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(4 * (HEADER_LENGTH + BENCH_PAYLOAD_LEN));
+    for i in 0..4u8 {
+        chunk.push(BENCH_CHANNEL);
+        chunk.push(FRAME_TYPE_FIRST | FRAME_TYPE_LAST | ENCRYPTED);
+        chunk.extend_from_slice(&(BENCH_PAYLOAD_LEN as u16).to_be_bytes());
+        chunk.extend(std::iter::repeat(i).take(BENCH_PAYLOAD_LEN));
+    }
+    chunk
+}
+
+/// fake phone: writes synthetic frames as fast as the proxy accepts them
+async fn source(stream: TcpStream) -> Result<()> {
+    let mut chunk = synthetic_chunk();
+    loop {
+        let (res, buf) = stream.write_all(chunk).await;
+        res?;
+        chunk = buf;
+    }
+}
+
+/// fake head unit: reads and discards everything, counting received bytes
+async fn sink(stream: TcpStream, received: Arc<AtomicUsize>) -> Result<()> {
+    let mut buf = vec![0u8; BUFFER_LEN];
+    loop {
+        let (res, b) = stream.read(buf).await;
+        let n = res?;
+        if n == 0 {
+            return Err("benchmark sink: connection closed".into());
+        }
+        received.fetch_add(n, Ordering::Relaxed);
+        buf = b;
+    }
+}
+
+/// returns (client, server) sides of a loopback TCP connection
+async fn loopback_pair() -> Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap())?;
+    let addr = listener.local_addr()?;
+    let client = TcpStream::connect(addr).await?;
+    let (server, _) = listener.accept().await?;
+    Ok((client, server))
+}
+
+/// Runs the benchmark for `duration` and logs sustained throughput and
+/// resource usage. Must be run inside the `tokio_uring` runtime.
+pub async fn run(mut config: AppConfig, duration: Duration) -> Result<()> {
+    // no SSL peers here, so the packets are always forwarded in passthrough mode
+    config.mitm = false;
+    config.capture_dir = None;
+    // the configured read buffers, to compare their sizes
+    let hu_buffer_len = read_buffer_len(config.hu_read_buffer_kb, "HU");
+    let md_buffer_len = read_buffer_len(config.md_read_buffer_kb, "MD");
+    let config: SharedConfig = Arc::new(RwLock::new(config));
+
+    info!(
+        "{} 🏁 Starting throughput benchmark for {} (read buffers: HU: {} KiB, MD: {} KiB)...",
+        NAME,
+        format_duration(duration),
+        hu_buffer_len / 1024,
+        md_buffer_len / 1024
+    );
+
+    let (phone, md) = loopback_pair().await?;
+    let (car, hu) = loopback_pair().await?;
+    let md = Rc::new(md);
+    let hu = Rc::new(hu);
+
+    let file_bytes = Arc::new(AtomicUsize::new(0));
+    let stream_bytes = Arc::new(AtomicUsize::new(0));
+    let received = Arc::new(AtomicUsize::new(0));
+
+    // same channel layout as in io_loop
+    let (tx_hu, rx_md): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(MITM_QUEUE_CAPACITY);
+    let (tx_md, rx_hu): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(MITM_QUEUE_CAPACITY);
+    let (txr_hu, rxr_md): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(MITM_QUEUE_CAPACITY);
+    let (txr_md, rxr_hu): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(MITM_QUEUE_CAPACITY);
+
+    // state which is not used in passthrough mode
+    let sensor_channel = Arc::new(Mutex::new(None));
+    let input_channel = Arc::new(Mutex::new(None));
+    let last_battery = Arc::new(RwLock::new(None));
+    let last_speed = Arc::new(RwLock::new(None));
+    let last_service_discovery_response = Arc::new(RwLock::new(None));
+    let (ev_tx, _ev_rx) = mpsc::channel(1);
+    let (ws_event_tx, _) = broadcast::channel(1);
+
+    let cpu_started = cpu_time();
+    let started = Instant::now();
+
+    let tasks = vec![
+        tokio_uring::spawn(source(phone)),
+        tokio_uring::spawn(sink(car, received.clone())),
+        tokio_uring::spawn(endpoint_reader(
+            IoDevice::EndpointIo(hu.clone()),
+            txr_hu,
+            true,
+            hu_buffer_len,
+            None,
+            CancellationToken::new(),
+        )),
+        tokio_uring::spawn(endpoint_reader(
+            IoDevice::EndpointIo(md.clone()),
+            txr_md,
+            false,
+            md_buffer_len,
+            None,
+            CancellationToken::new(),
+        )),
+        tokio_uring::spawn(proxy(
+            ProxyType::HeadUnit,
+            IoDevice::EndpointIo(hu.clone()),
+            file_bytes.clone(),
+            tx_hu,
+            rx_hu,
+            rxr_md,
+            config.clone(),
+            sensor_channel.clone(),
+            input_channel.clone(),
+            last_battery.clone(),
+            last_speed.clone(),
+            last_service_discovery_response.clone(),
+            ev_tx.clone(),
+            None,
+            None,
+            HashMap::new(),
+            ws_event_tx.clone(),
+            None,
+            CancellationToken::new(),
+        )),
+        tokio_uring::spawn(proxy(
+            ProxyType::MobileDevice,
+            IoDevice::EndpointIo(md.clone()),
+            stream_bytes.clone(),
+            tx_md,
+            rx_md,
+            rxr_hu,
+            config.clone(),
+            sensor_channel,
+            input_channel,
+            last_battery,
+            last_speed,
+            last_service_discovery_response,
+            ev_tx,
+            None,
+            None,
+            HashMap::new(),
+            ws_event_tx,
+            None,
+            CancellationToken::new(),
+        )),
+    ];
+
+    // let the pipeline settle before measuring
+    let warmup = (duration / 10).min(Duration::from_secs(2));
+    tokio::time::sleep(warmup).await;
+    let bytes_start = file_bytes.load(Ordering::Relaxed);
+    let measure_started = Instant::now();
+    tokio::time::sleep(duration.saturating_sub(warmup)).await;
+    let measured = measure_started.elapsed();
+    let bytes = file_bytes.load(Ordering::Relaxed) - bytes_start;
+
+    let elapsed = started.elapsed();
+    let cpu = cpu_time().saturating_sub(cpu_started);
+    let failed = tasks.iter().any(|t| t.is_finished());
+    for t in tasks {
+        t.abort();
+    }
+    let _ = md.shutdown(std::net::Shutdown::Both);
+    let _ = hu.shutdown(std::net::Shutdown::Both);
+    if failed {
+        return Err("benchmark pipeline terminated prematurely".into());
+    }
+
+    let speed = (bytes as f64 / measured.as_secs_f64()).round() as u64;
+    let frames = bytes / (HEADER_LENGTH + BENCH_PAYLOAD_LEN);
+    info!(
+        "{} 📊 sustained throughput: <b><green>{}/s</> ({:.0} frames/s), {} through the proxy, {} received by HU",
+        NAME,
+        ByteSize::b(speed).to_string_as(true),
+        frames as f64 / measured.as_secs_f64(),
+        ByteSize::b(file_bytes.load(Ordering::Relaxed) as u64).to_string_as(true),
+        ByteSize::b(received.load(Ordering::Relaxed) as u64).to_string_as(true),
+    );
+    info!(
+        "{} 📊 CPU usage: <b><green>{:.1}%</> ({} CPU time in {}), peak RSS: {}",
+        NAME,
+        100.0 * cpu.as_secs_f64() / elapsed.as_secs_f64(),
+        format_duration(Duration::from_millis(cpu.as_millis() as u64)),
+        format_duration(Duration::from_millis(elapsed.as_millis() as u64)),
+        ByteSize::b(max_rss()).to_string_as(true),
+    );
+
+    Ok(())
+}
@@ -1,36 +1,63 @@
 use crate::btle;
 use crate::config::Action;
+use crate::config::ExtraConnectPolicy;
+use crate::config::PairingPolicy;
 use crate::config::WifiConfig;
+use crate::config::WifiSecurity;
+use crate::config::WpaKeyPolicy;
 use crate::config::IDENTITY_NAME;
-use crate::config_types::BluetoothAddressList;
+use crate::config_types::{BluetoothAddressList, ExtraProtoFields, StageTimeouts};
+use crate::error::ProxyError;
+use crate::event_clock::EventClock;
+use crate::handshake_events::{self, Direction, HandshakeEvent, Outcome};
 use crate::sdr_ui;
 use crate::web::AppState;
+use crate::wifi_credentials::{apply_wpa_key_policy, check_wifi_key, select_wifi_configs};
 use anyhow::anyhow;
 use backon::{ExponentialBuilder, Retryable};
 use bluer::{
-    rfcomm::{Profile, ProfileHandle, Role, Stream},
-    Adapter, Address, Uuid,
+    agent::{
+        Agent, AgentHandle, ReqError, ReqResult, RequestAuthorization, RequestConfirmation,
+        RequestPasskey, RequestPinCode, RequestPinCodeFn,
+    },
+    rfcomm::{ConnectRequest, Profile, ProfileHandle, Role, Stream},
+    Adapter, Address, Device, Uuid,
 };
+use futures::FutureExt;
 use futures::StreamExt;
 use simplelog::*;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::OnceLock;
 use std::time::{Duration, Instant};
+use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::broadcast::Receiver as BroadcastReceiver;
 use tokio::sync::broadcast::Sender as BroadcastSender;
+use tokio::sync::mpsc;
 use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
 
 include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
-use protobuf::Message;
+use protobuf::reflect::MessageDescriptor;
+use protobuf::{Enum, Message, MessageFull, UnknownFields};
 use WifiInfoResponse::AccessPointType;
 use WifiInfoResponse::SecurityMode;
+use WifiInfoResponse::WifiBand;
 const HEADER_LEN: usize = 4;
 const STAGES: u8 = 5;
+// WifiVersionRequest + WifiVersionResponse, when the version stage is enabled
+const VERSION_STAGES: u8 = 2;
 const ATTEMPTS: usize = 3;
+// first delay between the retries of a failed handshake stage read, doubled on every retry
+const READ_RETRY_DELAY: Duration = Duration::from_millis(250);
+// ignored connect requests are held unanswered for longer than BlueZ waits for
+// the answer (the 25 s D-Bus timeout): a dropped one would be answered as canceled
+const IGNORED_REQUEST_HOLD: Duration = Duration::from_secs(30);
 
 // module name for logging engine
 const NAME: &str = "<i><bright-black> bluetooth: </>";
@@ -41,9 +68,85 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>
 
 pub const AAWG_PROFILE_UUID: Uuid = Uuid::from_u128(0x4de17a0052cb11e6bdf40800200c9a66);
 pub const BTLE_PROFILE_UUID: Uuid = Uuid::from_u128(0x9b3f6c10a4d2418ea2b90700300de8f4);
-const HSP_HS_UUID: Uuid = Uuid::from_u128(0x0000110800001000800000805f9b34fb);
-const HSP_AG_UUID: Uuid = Uuid::from_u128(0x0000111200001000800000805f9b34fb);
+pub const HSP_HS_UUID: Uuid = Uuid::from_u128(0x0000110800001000800000805f9b34fb);
+pub const HSP_AG_UUID: Uuid = Uuid::from_u128(0x0000111200001000800000805f9b34fb);
 pub const KNOWN_DEVICES_FILE: &str = concat!(crate::base_config_dir!(), "/known_devices");
+pub const LAST_DEVICE_FILE: &str = concat!(crate::base_config_dir!(), "/last_device");
+
+const MAX_FIELD_NUMBER: u32 = (1 << 29) - 1;
+
+/// Adds the configured extra fields to a message. They are not part of the
+/// compiled message (its fields, looked up in `descriptor`, are all set by us),
+/// so they are encoded as its unknown fields.
+fn add_extra_fields(
+    descriptor: &MessageDescriptor,
+    extra: &ExtraProtoFields,
+    fields: &mut UnknownFields,
+) -> Result<()> {
+    let message = descriptor.name();
+    for (field, value) in extra.iter() {
+        let Ok(number) = field.parse::<u32>() else {
+            return Err(if descriptor.field_by_name(field).is_some() {
+                format!("{}.{} is set by aa-proxy-rs", message, field)
+            } else {
+                format!("{} has no field `{}`, use a field number", message, field)
+            }
+            .into());
+        };
+        if let Some(known) = descriptor.field_by_number(number) {
+            return Err(format!(
+                "{} field {} ({}) is set by aa-proxy-rs",
+                message,
+                number,
+                known.name()
+            )
+            .into());
+        }
+        if number == 0 || number > MAX_FIELD_NUMBER || (19000..=19999).contains(&number) {
+            return Err(format!("invalid field number {} for {}", number, message).into());
+        }
+        match value.parse::<u64>() {
+            Ok(varint) => fields.add_varint(number, varint),
+            Err(_) => fields.add_length_delimited(number, value.as_bytes().to_vec()),
+        }
+    }
+    Ok(())
+}
+
+/// validates the configured extra fields of the WiFi messages
+pub fn check_wifi_extra_fields(wifi_config: &WifiConfig) -> Result<()> {
+    add_extra_fields(
+        &WifiStartRequest::WifiStartRequest::descriptor(),
+        &wifi_config.start_extra_fields,
+        &mut UnknownFields::new(),
+    )?;
+    add_extra_fields(
+        &WifiInfoResponse::WifiInfoResponse::descriptor(),
+        &wifi_config.info_extra_fields,
+        &mut UnknownFields::new(),
+    )
+}
+
+fn log_extra_fields(message: &str, extra: &ExtraProtoFields) {
+    for (field, value) in extra.iter() {
+        info!(
+            "{} 🧪 {}: extra field <b>{}</> = <b>{}</>",
+            NAME, message, field, value
+        );
+    }
+}
+
+// when the WiFi credentials were last sent to the phone
+static CREDENTIALS_SENT: std::sync::Mutex<Option<Instant>> = std::sync::Mutex::new(None);
+
+/// time since the WiFi credentials were sent to the phone (once per handshake)
+pub fn take_credentials_sent_elapsed() -> Option<Duration> {
+    CREDENTIALS_SENT
+        .lock()
+        .unwrap()
+        .take()
+        .map(|sent| sent.elapsed())
+}
 
 #[derive(Debug, Clone, PartialEq)]
 #[repr(u16)]
@@ -58,44 +161,178 @@ enum MessageId {
     WifiStartResponse = 7,
 }
 
+/// The phone closed the bluetooth connection in the middle of a handshake stage.
+#[derive(Debug)]
+pub struct PhoneDisconnected {
+    stage: u8,
+    stages: u8,
+    received: usize,
+    expected: usize,
+}
+
+impl std::fmt::Display for PhoneDisconnected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "phone disconnected during stage #{} of {} (received {} of {} bytes)",
+            self.stage, self.stages, self.received, self.expected
+        )
+    }
+}
+
+impl std::error::Error for PhoneDisconnected {}
+
+/// registered HSP profile together with the task accepting its connections
+struct HspRegistration {
+    session: bluer::Session,
+    task: JoinHandle<()>,
+}
+
+/// Settings of a [`Bluetooth::aa_handshake`].
+pub struct HandshakeOptions {
+    /// devices to connect to
+    pub connect: BluetoothAddressList,
+    pub wifi_config: WifiConfig,
+    /// how long to wait for the phone to open the AA profile
+    pub bt_timeout: Duration,
+    /// a stop was requested: don't connect to any device
+    pub stopped: bool,
+    /// keep the profile connection and redo the handshake on it when the session restarts
+    pub quick_reconnect: bool,
+    /// power the adapter off after the handshake
+    pub bt_poweroff: bool,
+    pub bt_poweroff_delay: Duration,
+    pub bt_sco: bool,
+    pub bt_sco_keep_bluetooth_alive: bool,
+    pub extra_connect_policy: ExtraConnectPolicy,
+    /// devices allowed to open the AA profile
+    pub allowlist: BluetoothAddressList,
+    /// `Some` auto-connects to the known devices within this window
+    pub auto_connect_window: Option<Duration>,
+    pub hsp_teardown_timeout: Duration,
+    pub log_wall_clock: bool,
+    /// per-device WiFi credentials
+    pub wifi_credentials_file: Option<PathBuf>,
+    pub wpa_key_policy: WpaKeyPolicy,
+    /// signal `tcp_start` before the phone got the credentials
+    pub early_tcp_start: bool,
+    pub read_retries: u8,
+    pub stage_timeouts: StageTimeouts,
+    /// WiFi version to announce, `None` for the default
+    pub version: Option<i32>,
+    pub rssi_poll_interval: Option<Duration>,
+    pub rssi_warn_dbm: i16,
+    /// wait this long for the phone to connect over TCP after the handshake
+    pub confirm_timeout: Option<Duration>,
+}
+
+/// Notifications and channels a [`Bluetooth::aa_handshake`] shares with the main loop.
+pub struct HandshakeSignals {
+    /// notified when the TCP server may accept the phone
+    pub tcp_start: Arc<Notify>,
+    /// notified when the phone connected over TCP
+    pub md_connected: Arc<Notify>,
+    /// session restarts, for `quick_reconnect`
+    pub need_restart: BroadcastReceiver<Option<Action>>,
+    pub restart_tx: BroadcastSender<Option<Action>>,
+    /// whether the profile connection is kept after the handshake
+    pub profile_connected: Arc<AtomicBool>,
+    /// cancels the wait for the phone
+    pub shutdown: Arc<Notify>,
+}
+
 pub struct Bluetooth {
+    /// D-Bus session the AA Wireless profile is (re)registered on
+    session: bluer::Session,
     adapter: Adapter,
     handle_aa: ProfileHandle,
     btle_handle: Option<bluer::gatt::local::ApplicationHandle>,
     adv_handle: Option<bluer::adv::AdvertisementHandle>,
     current_index: usize,
     dongle_mode: bool,
+    /// RFCOMM channel of the HSP profile, `None` lets BlueZ choose
+    hsp_channel: Option<u16>,
+    /// phone which completed the last handshake before startup, tried first
+    last_device: Option<Address>,
+    /// HSP headset UUID registered by us
+    hsp_hs_uuid: Uuid,
+    /// HSP audio gateway UUID connected on the phone
+    hsp_ag_uuid: Uuid,
+    /// our pairing agent, unregistered on drop
+    _agent: Option<AgentHandle>,
+    /// the AA Wireless profile, for registering it again in `readvertise`
+    aa_profile: Profile,
+    /// RSSI polling of the phone of the current session
+    rssi_poller: Option<JoinHandle<()>>,
+    /// extra connect requests left unanswered by the `ignore` policy
+    ignored_requests: Vec<(Instant, ConnectRequest)>,
 }
 
 // Create and configure the Bluetooth adapter
 pub async fn init(
     btalias: Option<String>,
+    alias_serial_suffix: bool,
+    adapter_id: Option<&str>,
     advertise: bool,
     dongle_mode: bool,
+    rfcomm_channel: u8,
+    hsp_channel: Option<u8>,
+    aa_uuid: Uuid,
+    hsp_hs_uuid: Uuid,
+    hsp_ag_uuid: Uuid,
+    pairing_policy: PairingPolicy,
+    pairing_pin: String,
 ) -> Result<Bluetooth> {
     let session = bluer::Session::new().await?;
-    let adapter = session.default_adapter().await?;
+    let adapter = select_adapter(&session, adapter_id).await?;
 
     // setting BT alias for further use
     let alias = match btalias {
+        Some(btalias) => btalias,
+        None if !alias_serial_suffix => String::from(IDENTITY_NAME),
+        // no (complete) serial number: fall back to the base alias
         None => match get_cpu_serial_number_suffix().await {
-            Ok(suffix) => format!("{}-{}", IDENTITY_NAME, suffix),
-            Err(_) => String::from(IDENTITY_NAME),
+            Ok(suffix) if !suffix.is_empty() => format!("{}-{}", IDENTITY_NAME, suffix),
+            _ => String::from(IDENTITY_NAME),
         },
-        Some(btalias) => btalias,
     };
     info!("{} 🥏 Bluetooth alias: <bold><green>{}</>", NAME, alias);
 
+    // adapter address is informational only: don't fail the whole setup on it
+    let address = match adapter.address().await {
+        Ok(address) => address.to_string(),
+        Err(e) => {
+            warn!(
+                "{} 🥏 adapter <b>{}</> address unavailable, showing as unknown: {}",
+                NAME,
+                adapter.name(),
+                e
+            );
+            String::from("unknown")
+        }
+    };
     info!(
         "{} 🥏 Opened bluetooth adapter <b>{}</> with address <b>{}</b>",
         NAME,
         adapter.name(),
-        adapter.address().await?
+        address
     );
     adapter.set_alias(alias.clone()).await?;
     adapter.set_powered(true).await?;
     adapter.set_pairable(true).await?;
 
+    let agent = match pairing_agent(pairing_policy, pairing_pin) {
+        Some(agent) => {
+            let handle = session.register_agent(agent).await?;
+            info!(
+                "{} 🔐 Pairing agent registered, policy: <b>{}</>",
+                NAME, pairing_policy
+            );
+            Some(handle)
+        }
+        None => None,
+    };
+
     if advertise {
         adapter.set_discoverable(true).await?;
         adapter.set_discoverable_timeout(0).await?;
@@ -103,27 +340,179 @@ pub async fn init(
 
     // AA Wireless profile
     let profile = Profile {
-        uuid: AAWG_PROFILE_UUID,
+        uuid: aa_uuid,
         name: Some("AA Wireless".to_string()),
-        channel: Some(8),
+        channel: Some(rfcomm_channel.into()),
         role: Some(Role::Server),
         require_authentication: Some(false),
         require_authorization: Some(false),
         ..Default::default()
     };
-    let handle_aa = session.register_profile(profile).await?;
-    info!("{} 📱 AA Wireless Profile: registered", NAME);
+    let handle_aa = session
+        .register_profile(profile.clone())
+        .await
+        .map_err(|e| {
+            error!(
+                "{} 📱 AA Wireless Profile: registration on RFCOMM channel {} failed: {}",
+                NAME, rfcomm_channel, e
+            );
+            e
+        })?;
+    if aa_uuid != AAWG_PROFILE_UUID {
+        info!(
+            "{} 📱 AA Wireless Profile: using UUID <b>{}</>",
+            NAME, aa_uuid
+        );
+    }
+    info!(
+        "{} 📱 AA Wireless Profile: registered on RFCOMM channel {}",
+        NAME, rfcomm_channel
+    );
 
     Ok(Bluetooth {
+        session,
         adapter,
         handle_aa,
         btle_handle: None,
         adv_handle: None,
         current_index: 0,
         dongle_mode,
+        hsp_channel: hsp_channel.map(u16::from),
+        last_device: load_last_device(),
+        hsp_hs_uuid,
+        hsp_ag_uuid,
+        _agent: agent,
+        aa_profile: profile,
+        rssi_poller: None,
+        ignored_requests: Vec::new(),
     })
 }
 
+/// whether `device` may open the AA profile: an empty (or wildcard) allowlist allows any
+fn is_allowed(allowlist: &BluetoothAddressList, device: &Address) -> bool {
+    match &allowlist.0 {
+        Some(addrs) if !allowlist.is_wildcard() => addrs.contains(device),
+        _ => true,
+    }
+}
+
+/// Dry run check: looks up the adapter without changing its state, returns its name.
+pub async fn check_adapter(adapter_id: Option<&str>) -> Result<String> {
+    let session = bluer::Session::new().await?;
+    let adapter = select_adapter(&session, adapter_id).await?;
+    Ok(adapter.name().to_string())
+}
+
+/// Opens the adapter with the name (e.g. `hci1`) or MAC address `id`,
+/// or the default adapter when `id` is not set.
+async fn select_adapter(session: &bluer::Session, id: Option<&str>) -> Result<Adapter> {
+    let Some(id) = id else {
+        return Ok(session.default_adapter().await?);
+    };
+    let wanted = id.trim().parse::<Address>().ok();
+    let names = session.adapter_names().await?;
+    for name in &names {
+        let adapter = session.adapter(name)?;
+        if name.as_str() == id.trim() {
+            return Ok(adapter);
+        }
+        if wanted.is_some() && adapter.address().await.ok() == wanted {
+            return Ok(adapter);
+        }
+    }
+    Err(format!(
+        "bluetooth adapter {} not found, available adapters: {}",
+        id,
+        names.join(", ")
+    )
+    .into())
+}
+
+async fn accept_confirmation(req: RequestConfirmation) -> ReqResult<()> {
+    info!(
+        "{} 🔐 pairing: accepting <b>{}</> (passkey {:06})",
+        NAME, req.device, req.passkey
+    );
+    Ok(())
+}
+
+async fn reject_confirmation(req: RequestConfirmation) -> ReqResult<()> {
+    warn!(
+        "{} 🔐 pairing: rejecting <b>{}</>, confirmation without a PIN (passkey {:06})",
+        NAME, req.device, req.passkey
+    );
+    Err(ReqError::Rejected)
+}
+
+async fn accept_authorization(req: RequestAuthorization) -> ReqResult<()> {
+    info!("{} 🔐 pairing: authorizing <b>{}</>", NAME, req.device);
+    Ok(())
+}
+
+async fn send_passkey(req: RequestPasskey, passkey: Option<u32>) -> ReqResult<u32> {
+    match passkey {
+        Some(passkey) => {
+            info!(
+                "{} 🔐 pairing: sending passkey to <b>{}</>",
+                NAME, req.device
+            );
+            Ok(passkey)
+        }
+        None => {
+            warn!(
+                "{} 🔐 pairing: rejecting <b>{}</>, configured PIN is not a valid passkey",
+                NAME, req.device
+            );
+            Err(ReqError::Rejected)
+        }
+    }
+}
+
+async fn send_pin_code(req: RequestPinCode, pin: String) -> ReqResult<String> {
+    info!(
+        "{} 🔐 pairing: sending PIN code to <b>{}</>",
+        NAME, req.device
+    );
+    Ok(pin)
+}
+
+/// Pairing agent deciding every pairing request by `policy`, or `None` to leave
+/// pairing to the system agent. Each decision is logged with the device address.
+fn pairing_agent(policy: PairingPolicy, pin: String) -> Option<Agent> {
+    let passkey = pin.parse::<u32>().ok().filter(|p| *p <= 999_999);
+    // PIN code of legacy pairing
+    let pin = if pin.is_empty() {
+        String::from("0000")
+    } else {
+        pin
+    };
+    let request_pin_code: RequestPinCodeFn =
+        Box::new(move |req| Box::pin(send_pin_code(req, pin.clone())));
+    match policy {
+        PairingPolicy::System => None,
+        PairingPolicy::Accept => Some(Agent {
+            request_default: true,
+            request_pin_code: Some(request_pin_code),
+            request_confirmation: Some(Box::new(|req| Box::pin(accept_confirmation(req)))),
+            request_authorization: Some(Box::new(|req| Box::pin(accept_authorization(req)))),
+            ..Default::default()
+        }),
+        PairingPolicy::Pin => Some(Agent {
+            request_default: true,
+            request_pin_code: Some(request_pin_code),
+            request_passkey: Some(Box::new(move |req| Box::pin(send_passkey(req, passkey)))),
+            request_confirmation: Some(Box::new(|req| Box::pin(reject_confirmation(req)))),
+            ..Default::default()
+        }),
+    }
+}
+
+/// External "phone present" trigger (SIGUSR1 or the `/phone-present` web endpoint)
+pub fn phone_present_trigger() -> &'static Notify {
+    static TRIGGER: OnceLock<Notify> = OnceLock::new();
+    TRIGGER.get_or_init(Notify::new)
+}
+
 pub async fn get_cpu_serial_number_suffix() -> Result<String> {
     let mut serial = String::new();
     let contents = tokio::fs::read_to_string("/sys/firmware/devicetree/base/serial-number").await?;
@@ -203,11 +592,279 @@ fn save_known_device(addr: Address) {
     }
 }
 
+/// protobuf `SecurityMode` announced to the phone for the configured AP security
+fn security_mode(security: WifiSecurity) -> SecurityMode {
+    match security {
+        WifiSecurity::Open => SecurityMode::OPEN,
+        WifiSecurity::Wpa2 => SecurityMode::WPA2_PERSONAL,
+        WifiSecurity::Wpa3 => SecurityMode::WPA3_PERSONAL,
+        WifiSecurity::Wpa2Wpa3Mixed => SecurityMode::WPA2_WPA3_PERSONAL,
+    }
+}
+
+/// WifiInfoResponse message with the credentials of `wifi_config`
+fn wifi_info_response(wifi_config: WifiConfig) -> Result<WifiInfoResponse::WifiInfoResponse> {
+    let mut info = WifiInfoResponse::WifiInfoResponse::new();
+    info!(
+        "{} 🛜 Sending Host SSID and Password: {}, {}",
+        NAME, wifi_config.ssid, wifi_config.wpa_key
+    );
+    info.set_ssid(wifi_config.ssid);
+    // the key is a required field, an open AP sends it empty
+    info.set_key(match wifi_config.security {
+        WifiSecurity::Open => String::new(),
+        _ => wifi_config.wpa_key,
+    });
+    info.set_bssid(wifi_config.bssid);
+    info.set_security_mode(security_mode(wifi_config.security));
+    info.set_access_point_type(AccessPointType::DYNAMIC);
+    // optional fields, left out unless announcing the band is enabled
+    if let Some(band) = wifi_config.band {
+        info!(
+            "{} 🛜 Announcing AP band: <b>{} GHz</>, channel: <b>{}</>",
+            NAME,
+            band,
+            wifi_config
+                .channel
+                .map_or(String::from("unknown"), |c| c.to_string())
+        );
+        info.set_band(wifi_band(&band));
+    }
+    if let Some(channel) = wifi_config.channel {
+        info.set_channel(channel.into());
+    }
+    add_extra_fields(
+        &WifiInfoResponse::WifiInfoResponse::descriptor(),
+        &wifi_config.info_extra_fields,
+        info.mut_unknown_fields(),
+    )?;
+    log_extra_fields("WifiInfoResponse", &wifi_config.info_extra_fields);
+
+    Ok(info)
+}
+
+/// logs the bluetooth RSSI of the phone, warning when it is below `warn_dbm`
+async fn log_rssi(device: &Device, warn_dbm: i16) {
+    match device.rssi().await {
+        Ok(Some(rssi)) if rssi < warn_dbm => warn!(
+            "{} 📶 phone RSSI: <b>{} dBm</> is below {} dBm, expect connection drops",
+            NAME, rssi, warn_dbm
+        ),
+        Ok(Some(rssi)) => info!("{} 📶 phone RSSI: <b>{} dBm</>", NAME, rssi),
+        Ok(None) => debug!("{} 📶 phone RSSI not reported by the adapter", NAME),
+        Err(e) => debug!("{} 📶 reading phone RSSI failed: {}", NAME, e),
+    }
+}
+
+/// logs the RSSI of the phone every `interval` for as long as it stays connected
+fn poll_rssi(device: Device, interval: Duration, warn_dbm: i16) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // the first tick completes immediately, the RSSI at connect is already logged
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if !device.is_connected().await.unwrap_or(false) {
+                debug!("{} 📶 phone disconnected, RSSI polling stopped", NAME);
+                break;
+            }
+            log_rssi(&device, warn_dbm).await;
+        }
+    });
+}
+
+/// Load the address of the phone which completed the last bluetooth handshake.
+fn load_last_device() -> Option<Address> {
+    let contents = std::fs::read_to_string(LAST_DEVICE_FILE).ok()?;
+    match contents.trim().parse::<Address>() {
+        Ok(addr) if addr != Address::any() => Some(addr),
+        _ => {
+            warn!(
+                "{} last_device: ignoring invalid contents: {}",
+                NAME,
+                contents.trim()
+            );
+            None
+        }
+    }
+}
+
+/// Remember the phone which completed the bluetooth handshake, so it is tried first next time.
+fn save_last_device(addr: Address) {
+    if addr == Address::any() || load_last_device() == Some(addr) {
+        return;
+    }
+    match std::fs::write(LAST_DEVICE_FILE, format!("{}\n", addr)) {
+        Ok(_) => debug!("{} 💾 Saved {} as the last connected device", NAME, addr),
+        Err(e) => warn!("{} last_device: failed to write {}: {}", NAME, addr, e),
+    }
+}
+
+/// Moves `last` to the front of `addresses` (if present), keeping the order of the others.
+fn prioritize_device(addresses: &mut Vec<Address>, last: Option<Address>) {
+    if let Some(pos) = last.and_then(|last| addresses.iter().position(|a| *a == last)) {
+        let addr = addresses.remove(pos);
+        addresses.insert(0, addr);
+    }
+}
+
+/// protobuf `WifiBand` of a `band` config value
+fn wifi_band(band: &str) -> WifiBand {
+    match band.trim() {
+        "2.4" => WifiBand::BAND_2_4_GHZ,
+        "5" => WifiBand::BAND_5_GHZ,
+        "6" => WifiBand::BAND_6_GHZ,
+        _ => WifiBand::BAND_UNKNOWN,
+    }
+}
+
+/// sends a handshake stage message, publishing the outcome to `handshake_events` subscribers
+/// and the embedder's `events` channel
 async fn send_message(
     stream: &mut Stream,
     stage: u8,
+    stages: u8,
+    id: MessageId,
+    message: impl Message,
+    clock: &EventClock,
+    events: Option<&mpsc::Sender<HandshakeEvent>>,
+) -> Result<usize> {
+    handshake_events::emit(
+        events,
+        HandshakeEvent::StageStarted {
+            stage,
+            stages,
+            message_id: format!("{:?}", id),
+            direction: Direction::Send,
+        },
+    );
+    let res = send_frame(stream, stage, stages, id.clone(), message, clock).await;
+    handshake_events::publish(stage, stages, &id, Direction::Send, clock.elapsed(), &res);
+    handshake_events::emit_completed(
+        events,
+        stage,
+        stages,
+        &id,
+        Direction::Send,
+        clock.elapsed(),
+        &res,
+    );
+    res
+}
+
+/// reads a handshake stage message within `stage_timeout` (if any),
+/// publishing the outcome to `handshake_events` subscribers and the embedder's `events` channel
+async fn read_message(
+    stream: &mut Stream,
+    stage: u8,
+    stages: u8,
+    id: MessageId,
+    started: Instant,
+    clock: &EventClock,
+    stage_timeout: Option<Duration>,
+    events: Option<&mpsc::Sender<HandshakeEvent>>,
+) -> Result<usize> {
+    handshake_events::emit(
+        events,
+        HandshakeEvent::StageStarted {
+            stage,
+            stages,
+            message_id: format!("{:?}", id),
+            direction: Direction::Receive,
+        },
+    );
+    let read = read_frame(stream, stage, stages, id.clone(), started, clock);
+    let res = match stage_timeout {
+        Some(limit) => match timeout(limit, read).await {
+            Ok(res) => res,
+            Err(_) => Err(ProxyError::HandshakeTimeout {
+                stage,
+                stages,
+                frame: format!("{:?}", id),
+                after: limit,
+            }
+            .into()),
+        },
+        None => read.await,
+    };
+    handshake_events::publish(
+        stage,
+        stages,
+        &id,
+        Direction::Receive,
+        clock.elapsed(),
+        &res,
+    );
+    handshake_events::emit_completed(
+        events,
+        stage,
+        stages,
+        &id,
+        Direction::Receive,
+        clock.elapsed(),
+        &res,
+    );
+    res
+}
+
+/// reads a handshake stage message, retrying up to `retries` times with exponential
+/// backoff on I/O errors before the first byte of the frame; a phone-reported failure
+/// (e.g. in `WifiConnectStatus`), a phone disconnect, a stage timeout or an error after
+/// part of the frame was consumed (the stream would be out of sync) is returned right away
+async fn read_message_retry(
+    stream: &mut Stream,
+    stage: u8,
+    stages: u8,
+    id: MessageId,
+    started: Instant,
+    clock: &EventClock,
+    retries: u8,
+    stage_timeout: Option<Duration>,
+    events: Option<&mpsc::Sender<HandshakeEvent>>,
+) -> Result<usize> {
+    let mut delay = READ_RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        match read_message(
+            stream,
+            stage,
+            stages,
+            id.clone(),
+            started,
+            clock,
+            stage_timeout,
+            events,
+        )
+        .await
+        {
+            Err(e) if attempt < retries && e.downcast_ref::<std::io::Error>().is_some() => {
+                attempt += 1;
+                warn!(
+                    "{} 📨 stage #{} of {}: reading <yellow>{:?}</> frame failed: {}, retry {}/{} in {} ms",
+                    NAME,
+                    stage,
+                    stages,
+                    id,
+                    e,
+                    attempt,
+                    retries,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            res => return res,
+        }
+    }
+}
+
+async fn send_frame(
+    stream: &mut Stream,
+    stage: u8,
+    stages: u8,
     id: MessageId,
     message: impl Message,
+    clock: &EventClock,
 ) -> Result<usize> {
     let mut packet: Vec<u8> = vec![];
     let mut data = message.write_to_bytes()?;
@@ -220,8 +877,12 @@ async fn send_message(
     packet.append(&mut data);
 
     info!(
-        "{} 📨 stage #{} of {}: Sending <yellow>{:?}</> frame to phone...",
-        NAME, stage, STAGES, id
+        "{} 📨 stage #{} of {}: Sending <yellow>{:?}</> frame to phone... {}",
+        NAME,
+        stage,
+        stages,
+        id,
+        clock.stamp()
     );
 
     // Ensure the full packet is written
@@ -230,14 +891,16 @@ async fn send_message(
     Ok(packet.len())
 }
 
-async fn read_message(
+async fn read_frame(
     stream: &mut Stream,
     stage: u8,
+    stages: u8,
     id: MessageId,
     started: Instant,
+    clock: &EventClock,
 ) -> Result<usize> {
     let mut header = [0u8; HEADER_LEN];
-    stream.read_exact(&mut header).await?;
+    read_stage_bytes(stream, &mut header, 0, stage, stages).await?;
     debug!("received header bytes: {:02X?}", header);
     let elapsed = started.elapsed();
 
@@ -252,28 +915,45 @@ async fn read_message(
         );
     }
     info!(
-        "{} 📨 stage #{} of {}: Received <yellow>{:?}</> frame from phone (⏱️ {} ms)",
+        "{} 📨 stage #{} of {}: Received <yellow>{:?}</> frame from phone (⏱️ {} ms) {}",
         NAME,
         stage,
-        STAGES,
+        stages,
         id,
         (elapsed.as_secs() * 1_000) + (elapsed.subsec_nanos() / 1_000_000) as u64,
+        clock.stamp(),
     );
 
     // read and discard the remaining bytes
     if len > 0 {
         let mut buf = vec![0; len];
-        let n = stream.read_exact(&mut buf).await?;
+        let n = read_stage_bytes(stream, &mut buf, HEADER_LEN, stage, stages).await?;
         debug!("remaining {} bytes: {:02X?}", n, buf);
 
         // analyzing WifiConnectStatus
         // this is a frame where phone cannot connect to WiFi:
-        // [08, FD, FF, FF, FF, FF, FF, FF, FF, FF, 01] -> which is -i64::MAX
+        // [08, FD, FF, FF, FF, FF, FF, FF, FF, FF, 01] -> status -3 (incorrect credentials)
         // and this is where all is fine:
         // [08, 00]
-        if id == MessageId::WifiConnectStatus && n >= 2 {
-            if buf[1] != 0 {
-                return Err("phone cannot connect to our WiFi AP...".into());
+        if id == MessageId::WifiConnectStatus {
+            match wifi_connect_status(&buf) {
+                Some(0) => (),
+                Some(status) => {
+                    return Err(Box::new(ProxyError::WifiRejected {
+                        status: Some(status.into()),
+                        reason: format!(
+                            "{} (status {})",
+                            wifi_connect_status_reason(status),
+                            status
+                        ),
+                    }));
+                }
+                None => {
+                    return Err(Box::new(ProxyError::WifiRejected {
+                        status: None,
+                        reason: "undecodable status".into(),
+                    }));
+                }
             }
         }
     }
@@ -281,7 +961,137 @@ async fn read_message(
     Ok(HEADER_LEN + len)
 }
 
+/// Fills `buf` like `read_exact`, but the phone closing the connection (EOF or reset),
+/// even after a partial read, fails right away with [`PhoneDisconnected`] instead of an
+/// I/O error which would be retried. `consumed` is the number of frame bytes read before
+/// `buf`; any other I/O error once the frame has been started is returned as
+/// [`ProxyError::FrameInterrupted`], which isn't retried either.
+async fn read_stage_bytes(
+    stream: &mut (impl AsyncRead + Unpin),
+    buf: &mut [u8],
+    consumed: usize,
+    stage: u8,
+    stages: u8,
+) -> Result<usize> {
+    use std::io::ErrorKind;
+    let mut received = 0;
+    while received < buf.len() {
+        match stream.read(&mut buf[received..]).await {
+            Ok(0) => {}
+            Ok(n) => {
+                received += n;
+                continue;
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::UnexpectedEof
+                        | ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::NotConnected
+                        | ErrorKind::BrokenPipe
+                ) => {}
+            Err(e) if consumed + received == 0 => return Err(e.into()),
+            Err(source) => {
+                return Err(Box::new(ProxyError::FrameInterrupted {
+                    stage,
+                    stages,
+                    received: consumed + received,
+                    source,
+                }))
+            }
+        }
+        return Err(Box::new(PhoneDisconnected {
+            stage,
+            stages,
+            received,
+            expected: buf.len(),
+        }));
+    }
+    Ok(received)
+}
+
+/// status of a WifiConnectStatus payload: 0 is success, negative values are errors;
+/// `None` if the payload can't be decoded
+fn wifi_connect_status(payload: &[u8]) -> Option<i32> {
+    WifiConnectStatus::WifiConnectStatus::parse_from_bytes(payload)
+        .ok()
+        .map(|message| message.status())
+}
+
+fn is_wifi_rejected(err: &(dyn std::error::Error + Send + Sync)) -> bool {
+    matches!(
+        err.downcast_ref::<ProxyError>(),
+        Some(ProxyError::WifiRejected { .. })
+    )
+}
+
+/// human-readable reason of a WifiConnectStatus error status
+fn wifi_connect_status_reason(status: i32) -> &'static str {
+    use WifiConnectStatus::WifiConnectionStatus::*;
+    match WifiConnectStatus::WifiConnectionStatus::from_i32(status) {
+        Some(NO_COMPATIBLE_VERSION) => "no compatible protocol version",
+        Some(WIFI_INACCESSIBLE_CHANNEL) => "WiFi channel inaccessible for the phone",
+        Some(WIFI_INCORRECT_CREDENTIALS) => "incorrect WiFi credentials (password mismatch?)",
+        Some(PROJECTION_ALREADY_STARTED) => "projection already started",
+        Some(WIFI_DISABLED) => "WiFi disabled",
+        Some(WIFI_NOT_YET_STARTED) => "WiFi not yet started",
+        Some(INVALID_HOST) => "invalid host",
+        Some(NO_SUPPORTED_WIFI_CHANNELS) => "no supported WiFi channels",
+        Some(INSTRUCT_USER_TO_CHECK_THE_PHONE) => "check the phone for a prompt",
+        Some(PHONE_WIFI_DISABLED) => "WiFi disabled on the phone",
+        Some(WIFI_NETWORK_UNAVAILABLE) => "WiFi network unavailable (SSID not found)",
+        Some(SUCCESS) | None => "unknown error",
+    }
+}
+
 impl Bluetooth {
+    /// Stops the RSSI polling of the last session's phone.
+    pub fn stop_rssi_poll(&mut self) {
+        if let Some(poller) = self.rssi_poller.take() {
+            poller.abort();
+        }
+    }
+
+    /// Makes the adapter visible to the phone again after a session without a
+    /// full `init`: the adapter and pairing agent are reused, only the AA Wireless
+    /// profile and the BLE advertisement are registered again. The next
+    /// `aa_handshake` then waits on the fresh profile handle.
+    ///
+    /// The old registration stays in place until the new one succeeded, so on an
+    /// error the phone can still connect through it and the caller can try again.
+    pub async fn readvertise(&mut self, advertise: bool) -> Result<()> {
+        self.adapter.set_powered(true).await?;
+        if advertise {
+            self.set_discoverable().await?;
+        }
+
+        // a fresh handle drops stale connect requests; the old one is
+        // unregistered when it's replaced
+        for attempt in 1..=3 {
+            match self.session.register_profile(self.aa_profile.clone()).await {
+                Ok(handle) => {
+                    info!("{} 📱 AA Wireless Profile: registered again", NAME);
+                    self.handle_aa = handle;
+                    break;
+                }
+                Err(e) if attempt < 3 => {
+                    debug!(
+                        "{} 📱 AA Wireless Profile: registering again failed (attempt {}): {}",
+                        NAME, attempt, e
+                    );
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if self.adv_handle.is_some() {
+            self.advertise_ble().await;
+        }
+        Ok(())
+    }
+
     pub async fn start_ble(&mut self, state: AppState, enable_btle: bool) -> Result<()> {
         // --- Start BLE GATT server first ---
         if enable_btle {
@@ -296,6 +1106,12 @@ impl Bluetooth {
             }
         }
 
+        self.advertise_ble().await;
+        Ok(())
+    }
+
+    /// (re)starts the BLE advertisement
+    async fn advertise_ble(&mut self) {
         // --- Prepare UUIDs ---
         let mut uuids: std::collections::BTreeSet<bluer::Uuid> = std::collections::BTreeSet::new();
         uuids.insert(BTLE_PROFILE_UUID);
@@ -311,7 +1127,16 @@ impl Bluetooth {
                 advertisement_type: bluer::adv::Type::Peripheral,
                 service_uuids: uuids.clone(),
                 discoverable: Some(true), // temporarily true for stable discovery
-                local_name: Some(self.adapter.alias().await?),
+                local_name: match self.adapter.alias().await {
+                    Ok(alias) => Some(alias),
+                    Err(e) => {
+                        warn!(
+                            "{} 📣 adapter alias unavailable, advertising without local name: {}",
+                            NAME, e
+                        );
+                        None
+                    }
+                },
                 ..Default::default()
             };
 
@@ -385,24 +1210,78 @@ impl Bluetooth {
                 }
             }
         }
+    }
 
+    /// Keeps the adapter powered off until the phone-present trigger fires,
+    /// then powers it up and makes it discoverable for `window`.
+    pub async fn wait_for_phone_present(&mut self, window: Duration) -> Result<()> {
+        let _ = self.adapter.set_discoverable(false).await;
+        self.adapter.set_powered(false).await?;
+        info!(
+            "{} 💤 bluetooth idle (adapter off), waiting for phone-present trigger...",
+            NAME
+        );
+        phone_present_trigger().notified().await;
+        info!("{} 📡 phone-present trigger received", NAME);
+
+        self.adapter.set_powered(true).await?;
+        self.adapter.set_pairable(true).await?;
+        self.adapter.set_discoverable(true).await?;
+        self.adapter
+            .set_discoverable_timeout(window.as_secs() as u32)
+            .await?;
+        info!(
+            "{} 📣 bluetooth powered up and discoverable for {} seconds",
+            NAME,
+            window.as_secs()
+        );
         Ok(())
     }
 
-    async fn get_aa_profile_connection(
+    /// Handles connect requests on the AA profile which were queued while
+    /// the previous session was running, so a stale one is not accepted now.
+    fn handle_extra_connect_requests(&mut self, policy: ExtraConnectPolicy) {
+        // BlueZ has given up on these by now
+        self.ignored_requests
+            .retain(|(since, _)| since.elapsed() < IGNORED_REQUEST_HOLD);
+        if policy == ExtraConnectPolicy::Keep {
+            return;
+        }
+        while let Some(Some(req)) = self.handle_aa.next().now_or_never() {
+            match policy {
+                ExtraConnectPolicy::Ignore => {
+                    warn!(
+                        "{} 📱 AA Wireless Profile: ignoring extra connect request from: <b>{}</>",
+                        NAME,
+                        req.device()
+                    );
+                    self.ignored_requests.push((Instant::now(), req));
+                }
+                _ => {
+                    warn!(
+                        "{} 📱 AA Wireless Profile: rejecting extra connect request from: <b>{}</>",
+                        NAME,
+                        req.device()
+                    );
+                    req.reject(ReqError::Rejected);
+                }
+            }
+        }
+    }
+
+    /// Actively connects to the configured (or known-good) devices, so the
+    /// phone opens the AA profile connection itself.
+    async fn connect_to_devices(
         &mut self,
         connect: BluetoothAddressList,
-        bt_timeout: Duration,
         stopped: bool,
-    ) -> Result<(Address, Stream)> {
-        info!("{} ⏳ Waiting for phone to connect via bluetooth...", NAME);
-
+    ) -> Result<()> {
         // try to connect to saved devices or provided one via command line
         if let Some(addresses_to_connect) = connect.0 {
             if !stopped {
                 let adapter_cloned = self.adapter.clone();
 
-                let addresses: Vec<Address> = if addresses_to_connect
+                let mut addresses: Vec<Address> = if addresses_to_connect
                     .iter()
                     .any(|addr| *addr == Address::any())
                 {
@@ -417,6 +1296,8 @@ impl Bluetooth {
                 } else {
                     addresses_to_connect
                 };
+                // the phone of the previous run is the most likely one to be around
+                prioritize_device(&mut addresses, self.last_device);
                 // exit if we don't have anything to connect to
                 if !addresses.is_empty() {
                     info!("{} 🧲 Attempting to start an AndroidAuto session via bluetooth with the following devices, in this order: {:?}", NAME, addresses);
@@ -426,6 +1307,7 @@ impl Bluetooth {
                                 &adapter_cloned,
                                 &addresses,
                                 self.current_index,
+                                self.hsp_ag_uuid,
                             )
                             .await?;
 
@@ -492,10 +1374,97 @@ impl Bluetooth {
                 }
             }
         }
+        Ok(())
+    }
+
+    /// `auto` connect mode: when bonded devices exist, try to connect to them
+    /// (like with `connect`) for `window`, then fall back to advertising and
+    /// waiting for the phone to connect on its own.
+    async fn auto_connect(&mut self, window: Duration, stopped: bool) -> Result<()> {
+        if stopped {
+            return Ok(());
+        }
+        let mut bonded = vec![];
+        for addr in self.adapter.device_addresses().await? {
+            if let Ok(device) = self.adapter.device(addr) {
+                if let Ok(true) = device.is_paired().await {
+                    bonded.push(addr);
+                }
+            }
+        }
+        if bonded.is_empty() {
+            info!(
+                "{} 🧭 bt_connect_mode = auto: no bonded devices, advertising and waiting for a phone",
+                NAME
+            );
+            return self.set_discoverable().await;
+        }
 
-        let req = timeout(bt_timeout, self.handle_aa.next())
-            .await?
-            .expect("received no connect request");
+        info!(
+            "{} 🧭 bt_connect_mode = auto: {} bonded device(s) found, trying to connect for {} seconds",
+            NAME,
+            bonded.len(),
+            window.as_secs()
+        );
+        match timeout(
+            window,
+            self.connect_to_devices(BluetoothAddressList(Some(bonded)), stopped),
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(_) => {
+                warn!(
+                    "{} 🧭 bt_connect_mode = auto: no bonded device connected within {} seconds, falling back to advertising",
+                    NAME,
+                    window.as_secs()
+                );
+                self.set_discoverable().await
+            }
+        }
+    }
+
+    async fn set_discoverable(&self) -> Result<()> {
+        self.adapter.set_discoverable(true).await?;
+        self.adapter.set_discoverable_timeout(0).await?;
+        Ok(())
+    }
+
+    async fn get_aa_profile_connection(
+        &mut self,
+        connect: BluetoothAddressList,
+        bt_timeout: Duration,
+        stopped: bool,
+        extra_connect_policy: ExtraConnectPolicy,
+        allowlist: BluetoothAddressList,
+        auto_connect_window: Option<Duration>,
+    ) -> Result<(Address, Stream)> {
+        self.handle_extra_connect_requests(extra_connect_policy);
+        info!("{} ⏳ Waiting for phone to connect via bluetooth...", NAME);
+
+        match auto_connect_window {
+            Some(window) => self.auto_connect(window, stopped).await?,
+            None => self.connect_to_devices(connect, stopped).await?,
+        }
+
+        // reject devices not on the allowlist and keep waiting for an allowed
+        // one, all within the same `bt_timeout`
+        let deadline = tokio::time::Instant::now() + bt_timeout;
+        let handle_aa = &mut self.handle_aa;
+        let req = loop {
+            let req = tokio::time::timeout_at(deadline, handle_aa.next())
+                .await?
+                .expect("received no connect request");
+            if is_allowed(&allowlist, req.device()) {
+                break req;
+            }
+            warn!(
+                "{} 📱 AA Wireless Profile: rejecting connect request from device not on the allowlist: <b>{}</>",
+                NAME,
+                req.device()
+            );
+            req.reject(ReqError::Rejected);
+        };
         info!(
             "{} 📱 AA Wireless Profile: connect from: <b>{}</>",
             NAME,
@@ -511,6 +1480,7 @@ impl Bluetooth {
         adapter: &Adapter,
         addresses: &Vec<Address>,
         start_index: usize,
+        hsp_ag_uuid: Uuid,
     ) -> Result<usize> {
         let n = addresses.len();
 
@@ -556,7 +1526,7 @@ impl Bluetooth {
                     NAME, addr, dev_name, j, ATTEMPTS
                 );
                 if let Ok(true) = device.is_paired().await {
-                    match device.connect_profile(&HSP_AG_UUID).await {
+                    match device.connect_profile(&hsp_ag_uuid).await {
                         Ok(_) => {
                             info!(
                                 "{} 🔗 Successfully connected to device: {}{}",
@@ -579,73 +1549,275 @@ impl Bluetooth {
         Err(anyhow!("Unable to connect to the provided addresses").into())
     }
 
-    async fn send_params(wifi_config: WifiConfig, stream: &mut Stream) -> Result<()> {
-        use WifiInfoResponse::WifiInfoResponse;
+    /// `wifi_configs` are the WiFi profiles to offer, in order: when the phone
+    /// reports it cannot join one, the next one is sent in a new WifiInfoResponse.
+    /// `early_tcp_start` is notified as soon as the credentials are sent,
+    /// `read_retries` is the number of retries of a failed stage read
+    /// and `stage_timeouts` bound the wait for every stage read.
+    /// With a `version`, the phone's WifiVersionRequest is answered with it
+    /// right after the WifiStartRequest.
+    async fn send_params(
+        wifi_configs: Vec<WifiConfig>,
+        stream: &mut Stream,
+        clock: &EventClock,
+        early_tcp_start: Option<&Notify>,
+        read_retries: u8,
+        stage_timeouts: &StageTimeouts,
+        version: Option<i32>,
+        events: Option<&mpsc::Sender<HandshakeEvent>>,
+    ) -> Result<()> {
         use WifiStartRequest::WifiStartRequest;
+        use WifiVersionResponse::WifiVersionResponse;
+        let stages = match version {
+            Some(_) => STAGES + VERSION_STAGES,
+            None => STAGES,
+        };
         let mut stage = 1;
         let mut started;
+        let profiles = wifi_configs.len();
+        let first = wifi_configs.first().ok_or("no WiFi profile to send")?;
 
         info!("{} 📲 Sending parameters via bluetooth to phone...", NAME);
         let mut start_req = WifiStartRequest::new();
-        info!(
-            "{} 🛜 Sending Host IP Address: {}",
-            NAME, wifi_config.ip_addr
-        );
-        start_req.set_ip_address(wifi_config.ip_addr);
-        start_req.set_port(wifi_config.port);
-        send_message(stream, stage, MessageId::WifiStartRequest, start_req).await?;
-        stage += 1;
-        started = Instant::now();
-        read_message(stream, stage, MessageId::WifiInfoRequest, started).await?;
+        info!("{} 🛜 Sending Host IP Address: {}", NAME, first.ip_addr);
+        start_req.set_ip_address(first.ip_addr.clone());
+        start_req.set_port(first.port);
+        add_extra_fields(
+            &WifiStartRequest::descriptor(),
+            &first.start_extra_fields,
+            start_req.mut_unknown_fields(),
+        )?;
+        log_extra_fields("WifiStartRequest", &first.start_extra_fields);
+        send_message(
+            stream,
+            stage,
+            stages,
+            MessageId::WifiStartRequest,
+            start_req,
+            clock,
+            events,
+        )
+        .await?;
+
+        if let Some(version) = version {
+            stage += 1;
+            started = Instant::now();
+            read_message_retry(
+                stream,
+                stage,
+                stages,
+                MessageId::WifiVersionRequest,
+                started,
+                clock,
+                read_retries,
+                stage_timeouts.get(stage),
+                events,
+            )
+            .await?;
+            let mut version_resp = WifiVersionResponse::new();
+            info!("{} 🛜 Sending WiFi version: {}", NAME, version);
+            version_resp.set_version(version);
+            stage += 1;
+            send_message(
+                stream,
+                stage,
+                stages,
+                MessageId::WifiVersionResponse,
+                version_resp,
+                clock,
+                events,
+            )
+            .await?;
+        }
 
-        let mut info = WifiInfoResponse::new();
-        info!(
-            "{} 🛜 Sending Host SSID and Password: {}, {}",
-            NAME, wifi_config.ssid, wifi_config.wpa_key
-        );
-        info.set_ssid(wifi_config.ssid);
-        info.set_key(wifi_config.wpa_key);
-        info.set_bssid(wifi_config.bssid);
-        info.set_security_mode(SecurityMode::WPA2_PERSONAL);
-        info.set_access_point_type(AccessPointType::DYNAMIC);
-        stage += 1;
-        send_message(stream, stage, MessageId::WifiInfoResponse, info).await?;
         stage += 1;
         started = Instant::now();
-        read_message(stream, stage, MessageId::WifiStartResponse, started).await?;
-        stage += 1;
-        started = Instant::now();
-        read_message(stream, stage, MessageId::WifiConnectStatus, started).await?;
+        read_message_retry(
+            stream,
+            stage,
+            stages,
+            MessageId::WifiInfoRequest,
+            started,
+            clock,
+            read_retries,
+            stage_timeouts.get(stage),
+            events,
+        )
+        .await?;
+
+        let info_stage = stage + 1;
+        for (i, wifi_config) in wifi_configs.into_iter().enumerate() {
+            if profiles > 1 {
+                info!(
+                    "{} 🛜 trying WiFi profile {}/{}: SSID <b>{}</>",
+                    NAME,
+                    i + 1,
+                    profiles,
+                    wifi_config.ssid
+                );
+            }
+            stage = info_stage;
+            send_message(
+                stream,
+                stage,
+                stages,
+                MessageId::WifiInfoResponse,
+                wifi_info_response(wifi_config)?,
+                clock,
+                events,
+            )
+            .await?;
+            *CREDENTIALS_SENT.lock().unwrap() = Some(Instant::now());
+            if i == 0 {
+                if let Some(tcp_start) = early_tcp_start {
+                    info!(
+                        "{} 🛰️ credentials sent, starting TCP accept early {}",
+                        NAME,
+                        clock.stamp()
+                    );
+                    tcp_start.notify_one();
+                }
+                stage = info_stage + 1;
+                started = Instant::now();
+                read_message_retry(
+                    stream,
+                    stage,
+                    stages,
+                    MessageId::WifiStartResponse,
+                    started,
+                    clock,
+                    read_retries,
+                    stage_timeouts.get(stage),
+                    events,
+                )
+                .await?;
+            }
+            stage = info_stage + 2;
+            started = Instant::now();
+            match read_message_retry(
+                stream,
+                stage,
+                stages,
+                MessageId::WifiConnectStatus,
+                started,
+                clock,
+                read_retries,
+                stage_timeouts.get(stage),
+                events,
+            )
+            .await
+            {
+                Ok(_) => return Ok(()),
+                Err(e) if is_wifi_rejected(&e) && i + 1 < profiles => {
+                    warn!(
+                        "{} 🛜 WiFi profile {}/{} failed: {}, trying the next one",
+                        NAME,
+                        i + 1,
+                        profiles,
+                        e
+                    );
+                }
+                Err(e) => {
+                    if is_wifi_rejected(&e) && profiles > 1 {
+                        warn!("{} 🛜 all {} WiFi profiles failed", NAME, profiles);
+                    }
+                    return Err(e);
+                }
+            }
+        }
 
-        Ok(())
+        unreachable!("the last WiFi profile always returns")
     }
 
     /// Drop HSP session here - this unregisters the profile from BlueZ.
-    /// We do it explicitly with a small delay to give BlueZ time to clean up.
-    async fn unregister_hsp(hsp_session: Option<bluer::Session>) {
-        if let Some(sess) = hsp_session {
+    /// Then the HSP task gets up to `teardown_timeout` to finish on its own
+    /// (giving BlueZ time to clean up), otherwise it is aborted.
+    async fn unregister_hsp(hsp: Option<HspRegistration>, teardown_timeout: Duration) {
+        if let Some(HspRegistration { session, mut task }) = hsp {
             info!("{} 🎧 Headset Profile (HSP): unregistering ...", NAME);
-            drop(sess);
-            tokio::time::sleep(Duration::from_millis(80)).await;
-            info!("{} 🎧 Headset Profile (HSP): unregistered", NAME);
+            drop(session);
+            if !teardown_timeout.is_zero() && timeout(teardown_timeout, &mut task).await.is_ok() {
+                info!("{} 🎧 Headset Profile (HSP): unregistered cleanly", NAME);
+            } else {
+                task.abort();
+                info!(
+                    "{} 🎧 Headset Profile (HSP): unregistered, task force-aborted after {} ms",
+                    NAME,
+                    teardown_timeout.as_millis()
+                );
+            }
+        }
+    }
+
+    /// powers the adapter off after `delay`, letting the phone finish disconnecting
+    async fn power_off(adapter: &Adapter, delay: Duration) {
+        if !delay.is_zero() {
+            info!(
+                "{} 💤 powering off the adapter in {} ms",
+                NAME,
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
         }
+        let _ = adapter.set_powered(false).await;
     }
 
+    /// Waits for the phone on the AA Wireless profile and hands it the WiFi
+    /// credentials. The `events` channel gets exactly one terminal
+    /// [`HandshakeEvent::Succeeded`] or [`HandshakeEvent::Failed`] event, also
+    /// when the handshake fails before the phone connected.
     pub async fn aa_handshake(
         &mut self,
-        connect: BluetoothAddressList,
-        wifi_config: WifiConfig,
-        tcp_start: Arc<Notify>,
-        bt_timeout: Duration,
-        stopped: bool,
-        quick_reconnect: bool,
-        bt_poweroff: bool,
-        bt_sco: bool,
-        bt_sco_keep_bluetooth_alive: bool,
-        mut need_restart: BroadcastReceiver<Option<Action>>,
-        restart_tx: BroadcastSender<Option<Action>>,
-        profile_connected: Arc<AtomicBool>,
-    ) -> Result<()> {
+        options: HandshakeOptions,
+        signals: HandshakeSignals,
+        events: Option<mpsc::Sender<HandshakeEvent>>,
+    ) -> std::result::Result<(), ProxyError> {
+        let mut outcome = Outcome::new(events.clone());
+        let res = self.handshake(options, signals, events, &mut outcome).await;
+        outcome.finish(&res);
+        res
+    }
+
+    async fn handshake(
+        &mut self,
+        options: HandshakeOptions,
+        signals: HandshakeSignals,
+        events: Option<mpsc::Sender<HandshakeEvent>>,
+        outcome: &mut Outcome,
+    ) -> std::result::Result<(), ProxyError> {
+        let HandshakeOptions {
+            connect,
+            wifi_config,
+            bt_timeout,
+            stopped,
+            quick_reconnect,
+            bt_poweroff,
+            bt_poweroff_delay,
+            bt_sco,
+            bt_sco_keep_bluetooth_alive,
+            extra_connect_policy,
+            allowlist,
+            auto_connect_window,
+            hsp_teardown_timeout,
+            log_wall_clock,
+            wifi_credentials_file,
+            wpa_key_policy,
+            early_tcp_start,
+            read_retries,
+            stage_timeouts,
+            version,
+            rssi_poll_interval,
+            rssi_warn_dbm,
+            confirm_timeout,
+        } = options;
+        let HandshakeSignals {
+            tcp_start,
+            md_connected,
+            mut need_restart,
+            restart_tx,
+            profile_connected,
+            shutdown,
+        } = signals;
         if bt_poweroff {
             let _ = self.adapter.set_powered(true).await;
         }
@@ -657,8 +1829,9 @@ impl Bluetooth {
         if !self.dongle_mode {
             let session = bluer::Session::new().await?;
             let profile = Profile {
-                uuid: HSP_HS_UUID,
+                uuid: self.hsp_hs_uuid,
                 name: Some("HSP HS".to_string()),
+                channel: self.hsp_channel,
                 require_authentication: Some(false),
                 require_authorization: Some(false),
                 ..Default::default()
@@ -672,7 +1845,7 @@ impl Bluetooth {
                     // accept and immediately drop the HSP control stream so Android Auto
                     // Bluetooth handshakes are not affected. The SCO/eSCO audio socket is
                     // handled separately by the bt_sco listener.
-                    tokio::spawn(async move {
+                    let task = tokio::spawn(async move {
                         let mut h = handle;
                         loop {
                             let req = match h.next().await {
@@ -722,14 +1895,18 @@ impl Bluetooth {
                     });
 
                     // Keep handle for unregister
-                    hsp_handle = Some(session);
+                    hsp_handle = Some(HspRegistration { session, task });
                 }
-                Err(e) => {
-                    warn!(
+                Err(e) => match self.hsp_channel {
+                    Some(channel) => warn!(
+                        "{} 🎧 Headset Profile (HSP) registering error on RFCOMM channel {}: {}, ignoring",
+                        NAME, channel, e
+                    ),
+                    None => warn!(
                         "{} 🎧 Headset Profile (HSP) registering error: {}, ignoring",
                         NAME, e
-                    );
-                }
+                    ),
+                },
             }
         }
 
@@ -737,9 +1914,41 @@ impl Bluetooth {
         let is_wildcard_connect = connect.is_wildcard();
 
         // Use the provided session and adapter instead of creating new ones
-        let (address, mut stream) = self
-            .get_aa_profile_connection(connect, bt_timeout, stopped)
-            .await?;
+        let connection = self.get_aa_profile_connection(
+            connect,
+            bt_timeout,
+            stopped,
+            extra_connect_policy,
+            allowlist,
+            auto_connect_window,
+        );
+        // a shutdown drops the connection attempts and the HSP task,
+        // so nothing keeps holding the adapter
+        let (address, mut stream) = tokio::select! {
+            res = connection => res?,
+            _ = shutdown.notified() => {
+                info!(
+                    "{} 🛑 shutdown requested, no longer waiting for the phone",
+                    NAME
+                );
+                Self::unregister_hsp(hsp_handle.take(), Duration::ZERO).await;
+                return Err(ProxyError::HandshakeCancelled);
+            }
+        };
+        let clock = EventClock::new(log_wall_clock);
+        info!(
+            "{} 📱 AA Wireless Profile: connected {}",
+            NAME,
+            clock.stamp()
+        );
+        handshake_events::emit(events.as_ref(), HandshakeEvent::Connected(address));
+        if let Ok(device) = self.adapter.device(address) {
+            log_rssi(&device, rssi_warn_dbm).await;
+            self.stop_rssi_poll();
+            if let Some(interval) = rssi_poll_interval {
+                self.rssi_poller = Some(poll_rssi(device, interval, rssi_warn_dbm));
+            }
+        }
 
         let phone_name = match self.adapter.device(address) {
             Ok(device) => device.name().await.ok().flatten(),
@@ -747,13 +1956,55 @@ impl Bluetooth {
         };
         sdr_ui::set_current_phone_from_bt(&address.to_string(), phone_name);
 
-        Self::send_params(wifi_config.clone(), &mut stream).await?;
+        // per-device credentials and fallback profiles, if configured
+        let wifi_configs = match wifi_credentials_file {
+            Some(ref path) => select_wifi_configs(path, address, &wifi_config),
+            None => vec![wifi_config],
+        };
+        let wifi_configs = wifi_configs
+            .into_iter()
+            .map(|wifi_config| {
+                check_wifi_key(&wifi_config)?;
+                apply_wpa_key_policy(wifi_config, wpa_key_policy)
+            })
+            .collect::<std::result::Result<Vec<_>, String>>()
+            .map_err(ProxyError::InvalidWifiConfig)?;
+
+        // registered before the phone gets the credentials,
+        // so an early TCP connection can't be missed
+        let md_connected = md_connected.notified();
+        tokio::pin!(md_connected);
+        if confirm_timeout.is_some() {
+            md_connected.as_mut().enable();
+        }
+
+        let res = Self::send_params(
+            wifi_configs.clone(),
+            &mut stream,
+            &clock,
+            early_tcp_start.then_some(&*tcp_start),
+            read_retries,
+            &stage_timeouts,
+            version,
+            events.as_ref(),
+        )
+        .await;
+        outcome.finish(&res);
+        res?;
+        info!(
+            "{} 📲 Bluetooth handshake completed {}",
+            NAME,
+            clock.stamp()
+        );
 
         // Record this device as a known-good AA device (only when using wildcard connect)
         if is_wildcard_connect {
             save_known_device(address);
         }
-        tcp_start.notify_one();
+        save_last_device(address);
+        if !early_tcp_start {
+            tcp_start.notify_one();
+        }
 
         if quick_reconnect {
             // keep the bluetooth profile connection alive
@@ -820,9 +2071,24 @@ impl Bluetooth {
                     }
 
                     // now restart handshake with the same params
-                    match Self::send_params(wifi_config.clone(), &mut stream).await {
+                    let clock = EventClock::new(log_wall_clock);
+                    let res = Self::send_params(
+                        wifi_configs.clone(),
+                        &mut stream,
+                        &clock,
+                        early_tcp_start.then_some(&*tcp_start),
+                        read_retries,
+                        &stage_timeouts,
+                        version,
+                        events.as_ref(),
+                    )
+                    .await;
+                    handshake_events::emit_result(events.as_ref(), &res);
+                    match res {
                         Ok(_) => {
-                            tcp_start.notify_one();
+                            if !early_tcp_start {
+                                tcp_start.notify_one();
+                            }
                             continue;
                         }
                         Err(e) => {
@@ -837,7 +2103,7 @@ impl Bluetooth {
                 }
                 // we are now disconnected, redo bluetooth connection
                 profile_connected.store(false, Ordering::Relaxed);
-                Self::unregister_hsp(hsp_session).await;
+                Self::unregister_hsp(hsp_session, hsp_teardown_timeout).await;
                 // main loop could now wait so send an event to restart
                 let _ = restart_tx.send(None);
             }));
@@ -866,7 +2132,7 @@ impl Bluetooth {
                 // this is not quick_reconnect. The task only keeps BT alive while
                 // the current AA session is alive, then cleans up on restart/stop.
                 let mut held_stream = stream;
-                let _held_hsp_session = hsp_session;
+                let held_hsp_session = hsp_session;
 
                 match keepalive_restart_rx.recv().await {
                     Ok(action) => {
@@ -896,15 +2162,10 @@ impl Bluetooth {
                     }
                 }
 
-                if let Some(sess) = _held_hsp_session {
-                    info!("{} 🎧 Headset Profile (HSP): unregistering ...", NAME);
-                    drop(sess);
-                    tokio::time::sleep(Duration::from_millis(80)).await;
-                    info!("{} 🎧 Headset Profile (HSP): unregistered", NAME);
-                }
+                Self::unregister_hsp(held_hsp_session, hsp_teardown_timeout).await;
 
                 if bt_poweroff {
-                    let _ = adapter_cloned.set_powered(false).await;
+                    Self::power_off(&adapter_cloned, bt_poweroff_delay).await;
                 }
 
                 profile_connected.store(false, Ordering::Relaxed);
@@ -923,15 +2184,179 @@ impl Bluetooth {
             // --- UNREGISTER HSP ---
             //
             if !self.dongle_mode {
-                Self::unregister_hsp(hsp_handle.take()).await;
+                Self::unregister_hsp(hsp_handle.take(), hsp_teardown_timeout).await;
             }
             if bt_poweroff {
-                let _ = self.adapter.set_powered(false).await;
+                Self::power_off(&self.adapter, bt_poweroff_delay).await;
             }
         }
 
-        info!("{} 🚀 Bluetooth launch sequence completed", NAME);
+        if let Some(confirm_timeout) = confirm_timeout {
+            // the phone connecting over TCP is the actual proof of success
+            if timeout(confirm_timeout, md_connected).await.is_err() {
+                warn!(
+                    "{} ⚠️ Bluetooth launch sequence finished, but the phone did not connect over TCP within {} s",
+                    NAME,
+                    confirm_timeout.as_secs()
+                );
+                return Ok(());
+            }
+            info!(
+                "{} 🚀 Bluetooth launch sequence completed, phone connected over TCP",
+                NAME
+            );
+        } else {
+            info!("{} 🚀 Bluetooth launch sequence completed", NAME);
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wifi_connect_status_is_decoded() {
+        assert_eq!(wifi_connect_status(&[0x08, 0x00]), Some(0));
+        let failure = [
+            0x08, 0xFD, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01,
+        ];
+        assert_eq!(wifi_connect_status(&failure), Some(-3));
+        assert_eq!(
+            wifi_connect_status_reason(-3),
+            "incorrect WiFi credentials (password mismatch?)"
+        );
+        assert_eq!(wifi_connect_status_reason(-42), "unknown error");
+        // no status field
+        assert_eq!(wifi_connect_status(&[0x10, 0x00]), Some(0));
+        // truncated varint
+        assert_eq!(wifi_connect_status(&[0x08, 0xFD]), None);
+    }
+
+    #[test]
+    fn extra_fields_cannot_override_compiled_fields() {
+        let descriptor = WifiInfoResponse::WifiInfoResponse::descriptor();
+        let extra = |field: &str, value: &str| {
+            ExtraProtoFields(Some(vec![(field.to_string(), value.to_string())]))
+        };
+        let mut fields = UnknownFields::new();
+
+        let err = add_extra_fields(&descriptor, &extra("ssid", "x"), &mut fields).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "WifiInfoResponse.ssid is set by aa-proxy-rs"
+        );
+        let err = add_extra_fields(&descriptor, &extra("7", "1"), &mut fields).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "WifiInfoResponse field 7 (band) is set by aa-proxy-rs"
+        );
+        assert!(add_extra_fields(&descriptor, &extra("nope", "1"), &mut fields).is_err());
+
+        add_extra_fields(&descriptor, &extra("20", "1"), &mut fields).unwrap();
+        assert_eq!(fields.get(20), Some(protobuf::UnknownValueRef::Varint(1)));
+    }
+
+    #[test]
+    fn last_device_is_moved_to_the_front() {
+        let a: Address = "AA:BB:CC:DD:EE:01".parse().unwrap();
+        let b: Address = "AA:BB:CC:DD:EE:02".parse().unwrap();
+        let c: Address = "AA:BB:CC:DD:EE:03".parse().unwrap();
+
+        let mut addresses = vec![a, b, c];
+        prioritize_device(&mut addresses, Some(c));
+        assert_eq!(addresses, vec![c, a, b]);
+
+        // unknown or missing last device leaves the order untouched
+        let other: Address = "AA:BB:CC:DD:EE:04".parse().unwrap();
+        prioritize_device(&mut addresses, Some(other));
+        prioritize_device(&mut addresses, None);
+        assert_eq!(addresses, vec![c, a, b]);
+    }
+
+    #[tokio::test]
+    async fn phone_disconnect_is_detected_mid_frame() {
+        // EOF after 2 of the 4 header bytes
+        let mut stream: &[u8] = &[0x00, 0x02];
+        let mut header = [0u8; HEADER_LEN];
+        let err = read_stage_bytes(&mut stream, &mut header, 0, 3, 5)
+            .await
+            .unwrap_err();
+        let disconnected = err.downcast_ref::<PhoneDisconnected>().unwrap();
+        assert_eq!(disconnected.received, 2);
+        assert_eq!(
+            err.to_string(),
+            "phone disconnected during stage #3 of 5 (received 2 of 4 bytes)"
+        );
+
+        // clean EOF before the frame
+        let mut stream: &[u8] = &[];
+        let err = read_stage_bytes(&mut stream, &mut header, 0, 1, 5)
+            .await
+            .unwrap_err();
+        assert!(err.is::<PhoneDisconnected>());
+
+        let mut stream: &[u8] = &[0x00, 0x02, 0x00, 0x07, 0xFF];
+        assert_eq!(
+            read_stage_bytes(&mut stream, &mut header, 0, 1, 5)
+                .await
+                .unwrap(),
+            HEADER_LEN
+        );
+        assert_eq!(header, [0x00, 0x02, 0x00, 0x07]);
+    }
+
+    /// yields `data`, then fails every read with a (retryable) I/O error
+    struct FailingReader(&'static [u8]);
+
+    impl AsyncRead for FailingReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            if self.0.is_empty() {
+                return std::task::Poll::Ready(Err(std::io::Error::other("read failed")));
+            }
+            let n = self.0.len().min(buf.remaining());
+            buf.put_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn only_errors_before_the_frame_are_retryable() {
+        let mut header = [0u8; HEADER_LEN];
+
+        // nothing of the frame read yet: a plain I/O error, so the read is retried
+        let err = read_stage_bytes(&mut FailingReader(&[]), &mut header, 0, 1, 5)
+            .await
+            .unwrap_err();
+        assert!(err.is::<std::io::Error>());
+
+        // error after part of the header
+        let err = read_stage_bytes(&mut FailingReader(&[0x00, 0x02]), &mut header, 0, 1, 5)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProxyError>(),
+            Some(ProxyError::FrameInterrupted { received: 2, .. })
+        ));
+
+        // error at the start of the body, the header is already consumed
+        let mut body = [0u8; 2];
+        let err = read_stage_bytes(&mut FailingReader(&[]), &mut body, HEADER_LEN, 1, 5)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProxyError>(),
+            Some(ProxyError::FrameInterrupted {
+                received: HEADER_LEN,
+                ..
+            })
+        ));
+    }
+}
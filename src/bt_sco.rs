@@ -1,5 +1,5 @@
 use crate::bt_sco_echo::{self, BtScoEchoSettings};
-use crate::config::BtScoMediaBridgeResampler;
+use crate::config::{BtScoCodec, BtScoMediaBridgeResampler};
 use simplelog::*;
 use std::collections::{BTreeMap, VecDeque};
 use std::io;
@@ -21,10 +21,14 @@ const SOL_BLUETOOTH: libc::c_int = 274;
 
 const SCO_OPTIONS: libc::c_int = 0x01;
 const SCO_CONNINFO: libc::c_int = 0x02;
+const BT_DEFER_SETUP: libc::c_int = 7;
 const BT_VOICE: libc::c_int = 11;
 const BT_SNDMTU: libc::c_int = 12;
 const BT_RCVMTU: libc::c_int = 13;
 
+const BT_VOICE_TRANSPARENT: u16 = 0x0003;
+const BT_VOICE_CVSD_16BIT: u16 = 0x0060;
+
 /// Target size for bridge chunks: about 20ms of 48kHz stereo s16le PCM.
 /// The SCO packet cadence produces 60-byte input packets, so actual chunks are
 /// usually 22.5ms / 4320 bytes after the 6x stereo expansion.
@@ -67,6 +71,9 @@ pub struct BtScoOptions {
     pub sco_uplink_ring_capacity: usize,
     /// Microphone echo handling settings for SCO uplink.
     pub echo_settings: BtScoEchoSettings,
+    /// Preferred codec for incoming SCO connections. Anything but `Auto` defers
+    /// the connection setup so the voice setting can be applied before accepting.
+    pub codec: BtScoCodec,
 }
 
 #[repr(C)]
@@ -245,15 +252,25 @@ pub fn spawn(options: BtScoOptions) -> io::Result<thread::JoinHandle<()>> {
 
 fn run(options: BtScoOptions) -> io::Result<()> {
     let listener = create_sco_listener()?;
+    let deferred = options.codec != BtScoCodec::Auto && enable_defer_setup(listener);
 
     debug!(
-        "{} listening for incoming SCO/eSCO audio, bridge_aa_media_pcm={}, media_ring_capacity={}, bridge_sco_uplink_pcm={}, uplink_ring_capacity={}",
+        "{} listening for incoming SCO/eSCO audio, codec={}, bridge_aa_media_pcm={}, media_ring_capacity={}, bridge_sco_uplink_pcm={}, uplink_ring_capacity={}",
         NAME,
+        options.codec,
         options.bridge_aa_media_pcm,
         effective_ring_capacity(options.bridge_ring_capacity),
         options.bridge_sco_uplink_pcm,
         effective_sco_uplink_capacity(options.sco_uplink_ring_capacity)
     );
+    if options.codec == BtScoCodec::Msbc
+        && (options.bridge_aa_media_pcm || options.bridge_sco_uplink_pcm)
+    {
+        warn!(
+            "{} mSBC audio is not decoded: the SCO media/mic bridges only work on CVSD connections",
+            NAME
+        );
+    }
 
     loop {
         let mut peer: SockAddrSco = unsafe { mem::zeroed() };
@@ -278,6 +295,11 @@ fn run(options: BtScoOptions) -> io::Result<()> {
             continue;
         }
 
+        if deferred {
+            accept_with_codec(fd, options.codec);
+        }
+        let linear_pcm = log_negotiated_codec(fd, options.codec);
+
         let generation = SCO_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
         clear_aa_pcm_queue();
         SCO_CONNECTED.store(true, Ordering::SeqCst);
@@ -292,7 +314,7 @@ fn run(options: BtScoOptions) -> io::Result<()> {
         );
         log_sco_socket_info(fd);
 
-        handle_sco_connection(fd, &options, generation);
+        handle_sco_connection(fd, &options, generation, linear_pcm);
 
         SCO_CONNECTED.store(false, Ordering::SeqCst);
         clear_aa_pcm_queue();
@@ -341,8 +363,91 @@ fn create_sco_listener() -> io::Result<RawFd> {
     Ok(fd)
 }
 
-fn handle_sco_connection(fd: RawFd, options: &BtScoOptions, generation: u64) {
+/// Makes incoming SCO connections wait for the first read, so the voice setting
+/// can still be changed on the accepted socket. Returns false if unsupported.
+fn enable_defer_setup(listener: RawFd) -> bool {
+    match setsockopt_value::<libc::c_int>(listener, SOL_BLUETOOTH, BT_DEFER_SETUP, 1) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(
+                "{} BT_DEFER_SETUP unavailable, keeping the default SCO codec: {}",
+                NAME, e
+            );
+            false
+        }
+    }
+}
+
+fn voice_setting(codec: BtScoCodec) -> Option<u16> {
+    match codec {
+        BtScoCodec::Auto => None,
+        BtScoCodec::Cvsd => Some(BT_VOICE_CVSD_16BIT),
+        BtScoCodec::Msbc => Some(BT_VOICE_TRANSPARENT),
+    }
+}
+
+fn is_transparent(setting: u16) -> bool {
+    setting & 0x0003 == BT_VOICE_TRANSPARENT
+}
+
+/// Applies the preferred voice setting to a deferred SCO connection and accepts it.
+fn accept_with_codec(fd: RawFd, codec: BtScoCodec) {
+    if let Some(setting) = voice_setting(codec) {
+        if let Err(e) = setsockopt_value(fd, SOL_BLUETOOTH, BT_VOICE, BtVoice { setting }) {
+            warn!(
+                "{} unable to request the {} SCO codec (kernel/adapter support?), accepting with the default: {}",
+                NAME, codec, e
+            );
+        }
+    }
+
+    // on a deferred socket the first read accepts the connection and returns 0
+    let mut byte = 0u8;
+    let n = unsafe { libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+    if n < 0 {
+        warn!(
+            "{} deferred SCO accept failed: {}",
+            NAME,
+            io::Error::last_os_error()
+        );
+    }
+}
+
+/// Logs the codec in use and returns true if the socket carries linear PCM.
+fn log_negotiated_codec(fd: RawFd, requested: BtScoCodec) -> bool {
+    match getsockopt_value::<BtVoice>(fd, SOL_BLUETOOTH, BT_VOICE) {
+        Ok(v) => {
+            let transparent = is_transparent(v.setting);
+            info!(
+                "{} SCO codec: <b>{}</> (requested: {}, BT_VOICE=0x{:04x})",
+                NAME,
+                if transparent {
+                    "mSBC/transparent"
+                } else {
+                    "CVSD"
+                },
+                requested,
+                v.setting
+            );
+            !transparent
+        }
+        Err(e) => {
+            debug!("{} SCO codec unknown, BT_VOICE unavailable: {}", NAME, e);
+            true
+        }
+    }
+}
+
+fn handle_sco_connection(fd: RawFd, options: &BtScoOptions, generation: u64, linear_pcm: bool) {
     let started = Instant::now();
+    let bridge_aa_media_pcm = options.bridge_aa_media_pcm && linear_pcm;
+    let bridge_sco_uplink_pcm = options.bridge_sco_uplink_pcm && linear_pcm;
+    if !linear_pcm && (options.bridge_aa_media_pcm || options.bridge_sco_uplink_pcm) {
+        warn!(
+            "{} SCO connection carries transparent (mSBC) data, media/mic bridges skipped",
+            NAME
+        );
+    }
 
     let mut buf = [0u8; 2048];
     let mut stats = ScoStats::default();
@@ -418,9 +523,11 @@ fn handle_sco_connection(fd: RawFd, options: &BtScoOptions, generation: u64) {
             );
         }
 
-        bt_sco_echo::observe_downlink_sco_8k_mono(&buf[..n]);
+        if linear_pcm {
+            bt_sco_echo::observe_downlink_sco_8k_mono(&buf[..n]);
+        }
 
-        if options.bridge_aa_media_pcm {
+        if bridge_aa_media_pcm {
             sco_s16le_mono_8k_to_aa_pcm_s16le_stereo_48k(
                 &buf[..n],
                 &mut aa_pcm_chunk,
@@ -436,7 +543,7 @@ fn handle_sco_connection(fd: RawFd, options: &BtScoOptions, generation: u64) {
             }
         }
 
-        if options.bridge_sco_uplink_pcm {
+        if bridge_sco_uplink_pcm {
             let (uplink, from_mic) = match pop_sco_uplink_frame(n) {
                 Some(frame) => (frame, true),
                 None => (vec![0u8; n], false),
@@ -497,7 +604,7 @@ fn handle_sco_connection(fd: RawFd, options: &BtScoOptions, generation: u64) {
         }
     }
 
-    if options.bridge_aa_media_pcm && !aa_pcm_chunk.is_empty() {
+    if bridge_aa_media_pcm && !aa_pcm_chunk.is_empty() {
         debug!(
             "{} dropping trailing partial AA PCM chunk on SCO disconnect: generation={}, bytes={}",
             NAME,
@@ -832,6 +939,29 @@ fn getsockopt_value<T: Copy + Default>(
     }
 }
 
+fn setsockopt_value<T: Copy>(
+    fd: RawFd,
+    level: libc::c_int,
+    optname: libc::c_int,
+    value: T,
+) -> io::Result<()> {
+    let rc = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            optname,
+            &value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+
+    if rc < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
 fn getsockopt_int(fd: RawFd, level: libc::c_int, optname: libc::c_int) -> io::Result<i32> {
     getsockopt_value::<libc::c_int>(fd, level, optname)
 }
@@ -939,6 +1069,14 @@ fn format_bdaddr(addr: BdAddr) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn codec_preference_maps_to_voice_setting() {
+        assert_eq!(voice_setting(BtScoCodec::Auto), None);
+        assert_eq!(voice_setting(BtScoCodec::Cvsd), Some(0x0060));
+        assert!(is_transparent(voice_setting(BtScoCodec::Msbc).unwrap()));
+        assert!(!is_transparent(BT_VOICE_CVSD_16BIT));
+    }
+
     #[test]
     fn sco_converter_expands_8k_mono_to_48k_stereo() {
         // Two input samples: 0x0001 and 0x0002.
@@ -0,0 +1,408 @@
+use crate::mitm::{Packet, ProxyType, ENCRYPTED, FRAME_TYPE_FIRST, HEADER_LENGTH};
+use chrono::Local;
+use simplelog::*;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> capture: </>";
+
+// Just a generic Result type to ease error handling for us. Errors in multithreaded
+// async contexts needs some extra restrictions
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// File magic of the session capture format (version 2).
+///
+/// The file consists of this magic followed by records, each made of:
+/// `u64` microseconds since session start, `u8` direction, `u8` channel,
+/// `u8` flags, `u8` final length presence, `u32` final length,
+/// `u8` message id presence, `u16` message id,
+/// `u32` payload length and the payload itself (all big-endian).
+///
+/// The message id is present for the first frame of a message whose payload
+/// was captured in plaintext (MITM mode or unencrypted frames).
+pub const CAPTURE_MAGIC: &[u8; 8] = b"AAPXCAP2";
+/// version 1 files have no message id in the records; they are still readable
+const CAPTURE_MAGIC_V1: &[u8; 8] = b"AAPXCAP1";
+pub const CAPTURE_FILE_SUFFIX: &str = ".aacap";
+// records waiting for the writer thread; when full, new records are dropped
+const CAPTURE_QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CaptureDirection {
+    /// Packet forwarded towards the head unit (sent by the MD side of the proxy).
+    ToHeadUnit = 0,
+    /// Packet forwarded towards the mobile device (sent by the HU side of the proxy).
+    ToMobileDevice = 1,
+}
+
+impl CaptureDirection {
+    /// direction of packets which a proxy of given type passes to the opposite side
+    pub fn from_sender(proxy_type: ProxyType) -> Self {
+        match proxy_type {
+            ProxyType::MobileDevice => Self::ToHeadUnit,
+            ProxyType::HeadUnit => Self::ToMobileDevice,
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::ToHeadUnit),
+            1 => Some(Self::ToMobileDevice),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord {
+    pub timestamp: Duration,
+    pub direction: CaptureDirection,
+    pub channel: u8,
+    pub flags: u8,
+    pub final_length: Option<u32>,
+    pub message_id: Option<u16>,
+    pub payload: Vec<u8>,
+}
+
+impl CaptureRecord {
+    pub fn to_packet(&self) -> Packet {
+        Packet {
+            channel: self.channel,
+            flags: self.flags,
+            final_length: self.final_length,
+            payload: self.payload.clone(),
+        }
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&(self.timestamp.as_micros() as u64).to_be_bytes())?;
+        w.write_all(&[
+            self.direction as u8,
+            self.channel,
+            self.flags,
+            self.final_length.is_some() as u8,
+        ])?;
+        w.write_all(&self.final_length.unwrap_or(0).to_be_bytes())?;
+        w.write_all(&[self.message_id.is_some() as u8])?;
+        w.write_all(&self.message_id.unwrap_or(0).to_be_bytes())?;
+        w.write_all(&(self.payload.len() as u32).to_be_bytes())?;
+        w.write_all(&self.payload)
+    }
+
+    /// reads next record, returns `None` on a clean end of file
+    fn read_from<R: Read>(r: &mut R, version: u8) -> io::Result<Option<Self>> {
+        let mut ts = [0u8; 8];
+        match r.read_exact(&mut ts) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut hdr = [0u8; 8];
+        r.read_exact(&mut hdr)?;
+        let direction = CaptureDirection::from_u8(hdr[0]).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid capture direction: {}", hdr[0]),
+            )
+        })?;
+        let final_length = u32::from_be_bytes(hdr[4..8].try_into().unwrap());
+        let mut message_id = None;
+        if version >= 2 {
+            let mut id = [0u8; 3];
+            r.read_exact(&mut id)?;
+            if id[0] != 0 {
+                message_id = Some(u16::from_be_bytes([id[1], id[2]]));
+            }
+        }
+        let mut len = [0u8; 4];
+        r.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        let mut payload = vec![0u8; len];
+        r.read_exact(&mut payload)?;
+
+        Ok(Some(Self {
+            timestamp: Duration::from_micros(u64::from_be_bytes(ts)),
+            direction,
+            channel: hdr[1],
+            flags: hdr[2],
+            final_length: if hdr[3] != 0 {
+                Some(final_length)
+            } else {
+                None
+            },
+            message_id,
+            payload,
+        }))
+    }
+}
+
+/// Per-session capture handle. Cloned into both proxy directions; records are
+/// passed to a dedicated writer thread so capturing never blocks the hot path.
+#[derive(Clone)]
+pub struct SessionCapture {
+    tx: Sender<CaptureRecord>,
+    started: Instant,
+    dropped: Arc<AtomicUsize>,
+    /// packets are captured decrypted (MITM mode)
+    decrypted: bool,
+}
+
+impl SessionCapture {
+    /// creates a new capture file in `dir` and starts the writer thread;
+    /// `decrypted` tells if the captured packets are plaintext (MITM mode)
+    pub fn start(dir: &Path, decrypted: bool) -> io::Result<(Self, PathBuf)> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "session_{}{}",
+            Local::now().format("%Y%m%d_%H%M%S"),
+            CAPTURE_FILE_SUFFIX
+        ));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writer.write_all(CAPTURE_MAGIC)?;
+
+        let (tx, rx) = mpsc::channel(CAPTURE_QUEUE_CAPACITY);
+        let thread_path = path.clone();
+        std::thread::spawn(move || capture_writer(rx, writer, thread_path));
+
+        Ok((
+            Self {
+                tx,
+                started: Instant::now(),
+                dropped: Arc::new(AtomicUsize::new(0)),
+                decrypted,
+            },
+            path,
+        ))
+    }
+
+    /// queues a packet for writing; never waits for the writer thread
+    pub fn record(&self, direction: CaptureDirection, pkt: &Packet) {
+        let record = CaptureRecord {
+            timestamp: self.started.elapsed(),
+            direction,
+            channel: pkt.channel,
+            flags: pkt.flags,
+            final_length: pkt.final_length,
+            message_id: self.message_id(pkt),
+            payload: pkt.payload.clone(),
+        };
+        if self.tx.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// message id of a first frame with a plaintext payload
+    fn message_id(&self, pkt: &Packet) -> Option<u16> {
+        if pkt.flags & FRAME_TYPE_FIRST == 0 || (!self.decrypted && pkt.flags & ENCRYPTED != 0) {
+            return None;
+        }
+        Some(u16::from_be_bytes([
+            *pkt.payload.first()?,
+            *pkt.payload.get(1)?,
+        ]))
+    }
+
+    /// number of records which were dropped because the writer was too slow
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn capture_writer(mut rx: Receiver<CaptureRecord>, mut writer: BufWriter<File>, path: PathBuf) {
+    let mut count: usize = 0;
+    while let Some(record) = rx.blocking_recv() {
+        if let Err(e) = record.write_to(&mut writer) {
+            error!("{} write error to {}: {}", NAME, path.display(), e);
+            return;
+        }
+        count += 1;
+    }
+    if let Err(e) = writer.flush() {
+        error!("{} flush error to {}: {}", NAME, path.display(), e);
+    }
+    info!(
+        "{} 💾 capture closed: <b>{}</> ({} packets)",
+        NAME,
+        path.display(),
+        count
+    );
+}
+
+/// reads the whole capture file into memory
+pub fn read_capture(path: &Path) -> Result<Vec<CaptureRecord>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    let version = match &magic {
+        CAPTURE_MAGIC => 2,
+        CAPTURE_MAGIC_V1 => 1,
+        _ => return Err(format!("{}: not an aa-proxy-rs capture file", path.display()).into()),
+    };
+
+    let mut records = vec![];
+    while let Some(record) = CaptureRecord::read_from(&mut reader, version)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// writes records as a capture file
+pub fn write_capture(path: &Path, records: &[CaptureRecord]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(CAPTURE_MAGIC)?;
+    for record in records {
+        record.write_to(&mut writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Converts a session capture into a DHU replay file: only the HU-directed
+/// stream is kept and timestamps are rebased to the first kept packet.
+pub fn export_dhu_replay(input: &Path, output: &Path) -> Result<usize> {
+    let records = to_dhu_replay(read_capture(input)?);
+    if records.is_empty() {
+        return Err(format!("{}: no HU-directed packets found", input.display()).into());
+    }
+    write_capture(output, &records)?;
+    Ok(records.len())
+}
+
+fn to_dhu_replay(records: Vec<CaptureRecord>) -> Vec<CaptureRecord> {
+    let mut records: Vec<CaptureRecord> = records
+        .into_iter()
+        .filter(|r| r.direction == CaptureDirection::ToHeadUnit)
+        .collect();
+    if let Some(base) = records.first().map(|r| r.timestamp) {
+        for record in records.iter_mut() {
+            record.timestamp -= base;
+        }
+    }
+    records
+}
+
+/// Plays a DHU replay file into the HU side of the proxy, acting as the MD.
+///
+/// `tx` feeds the HU proxy (in place of the MD proxy), `rx` receives whatever
+/// the HU proxy would send to the phone; it is drained and discarded.
+/// Drained bytes are added to `bytes_drained`, as if written to the phone.
+/// The first record is the version response, which is sent only after the
+/// DHU's version request arrives. The remaining records keep their original timing,
+/// unless `fast` is set: then they are sent as fast as the HU proxy takes them.
+pub async fn dhu_replay(
+    path: PathBuf,
+    tx: Sender<Packet>,
+    mut rx: Receiver<Packet>,
+    bytes_drained: Arc<AtomicUsize>,
+    fast: bool,
+) -> Result<()> {
+    let records = read_capture(&path)?;
+    info!(
+        "{} ▶️ DHU replay: loaded {} packets from <b>{}</>{}",
+        NAME,
+        records.len(),
+        path.display(),
+        if fast { " (fast)" } else { "" }
+    );
+    let mut records = records.into_iter();
+
+    // version exchange
+    rx.recv().await.ok_or("DHU replay: HU channel hung up")?;
+    let version = records.next().ok_or("DHU replay: capture is empty")?;
+    tx.send(version.to_packet()).await?;
+
+    // drain everything the HU proxy wants to send to the (non-existing) phone,
+    // counting it as written so the stall detection keeps working
+    tokio::spawn(async move {
+        while let Some(pkt) = rx.recv().await {
+            bytes_drained.fetch_add(HEADER_LENGTH + pkt.payload.len(), Ordering::Relaxed);
+        }
+    });
+
+    let mut first: Option<(Instant, Duration)> = None;
+    let mut sent: usize = 1;
+    for record in records {
+        if !fast {
+            let (base, ts0) = *first.get_or_insert((Instant::now(), record.timestamp));
+            let due = base + record.timestamp.saturating_sub(ts0);
+            tokio::time::sleep_until(due.into()).await;
+        }
+        tx.send(record.to_packet()).await?;
+        sent += 1;
+    }
+
+    info!("{} ⏹️ DHU replay: finished, {} packets sent", NAME, sent);
+    Err("DHU replay finished".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(ms: u64, direction: CaptureDirection, final_length: Option<u32>) -> CaptureRecord {
+        CaptureRecord {
+            timestamp: Duration::from_millis(ms),
+            direction,
+            channel: 3,
+            flags: 0x0b,
+            final_length,
+            message_id: Some(0x8001),
+            payload: vec![0x80, 0x01, 0xaa, 0xbb],
+        }
+    }
+
+    #[test]
+    fn record_roundtrip() {
+        let records = vec![
+            record(5, CaptureDirection::ToHeadUnit, None),
+            record(7, CaptureDirection::ToMobileDevice, Some(65536)),
+        ];
+        let mut buf = vec![];
+        for r in &records {
+            r.write_to(&mut buf).unwrap();
+        }
+
+        let mut cursor = io::Cursor::new(buf);
+        let mut decoded = vec![];
+        while let Some(r) = CaptureRecord::read_from(&mut cursor, 2).unwrap() {
+            decoded.push(r);
+        }
+        assert_eq!(decoded, records);
+    }
+
+    #[test]
+    fn version_1_records_have_no_message_id() {
+        let mut buf = vec![];
+        buf.extend_from_slice(&5000u64.to_be_bytes());
+        buf.extend_from_slice(&[0, 3, 0x0b, 0, 0, 0, 0, 0]);
+        buf.extend_from_slice(&2u32.to_be_bytes());
+        buf.extend_from_slice(&[0x80, 0x01]);
+
+        let r = CaptureRecord::read_from(&mut io::Cursor::new(buf), 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.timestamp, Duration::from_millis(5));
+        assert_eq!(r.channel, 3);
+        assert_eq!(r.message_id, None);
+        assert_eq!(r.payload, vec![0x80, 0x01]);
+    }
+
+    #[test]
+    fn dhu_replay_keeps_hu_stream_only() {
+        let replay = to_dhu_replay(vec![
+            record(10, CaptureDirection::ToMobileDevice, None),
+            record(20, CaptureDirection::ToHeadUnit, None),
+            record(35, CaptureDirection::ToHeadUnit, None),
+        ]);
+        assert_eq!(replay.len(), 2);
+        assert_eq!(replay[0].timestamp, Duration::ZERO);
+        assert_eq!(replay[1].timestamp, Duration::from_millis(15));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::config_types::{
-    BluetoothAddressList, EvConnectorTypes, HexdumpLevel, InjectClusterCodecResolution,
-    InjectDisplayTypes, UsbId,
+    BluetoothAddressList, EvConnectorTypes, ExtraProtoFields, HexdumpLevel,
+    InjectClusterCodecResolution, InjectDisplayTypes, ModelDpiMap, ProfileUuid, ProtocolVersion,
+    ServiceIdList, StageTimeouts, UsbId,
 };
 use indexmap::IndexMap;
 use serde::de::{Deserializer, Error as DeError};
@@ -8,6 +9,7 @@ use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use simplelog::*;
 use std::io::Error;
+use std::net::{IpAddr, Ipv4Addr};
 use std::process::Command;
 use std::{fmt::Display, fs, io, path::PathBuf, str::FromStr, sync::Arc};
 use tokio::sync::RwLock;
@@ -29,10 +31,51 @@ pub const TCP_DHU_PORT: i32 = 5277;
 pub const DEFAULT_WASM_HOOKS_DIR: &str = "/data/wasm-hooks";
 pub const DEFAULT_CRASH_DIR: &str = "/data/aa-proxy-rs/crashes";
 pub const DEFAULT_SDR_UI_OVERRIDE_FILE: &str = "/data/aa-proxy-rs/sdr-ui-overrides.toml";
+pub const DEFAULT_FUNCTIONFS_DIR: &str = "/dev/usb-ffs/aa";
+pub const DEFAULT_USB_ACCESSORY_PATH: &str = "/dev/usb_accessory";
 
 pub type SharedConfig = Arc<RwLock<AppConfig>>;
 pub type SharedConfigJson = Arc<RwLock<ConfigJson>>;
 
+/// Placeholder logged instead of a secret value.
+pub const REDACTED: &str = "<redacted>";
+// any config key containing one of these words (split on `_`) holds a secret
+const SECRET_WORDS: &[&str] = &[
+    "passphrase",
+    "password",
+    "passwd",
+    "pin",
+    "psk",
+    "secret",
+    "token",
+];
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key.ends_with("wpa_key") || key.split('_').any(|word| SECRET_WORDS.contains(&word))
+}
+
+// replaces the values of secret keys, at any nesting level
+fn redact_value(key: &str, value: &mut serde_json::Value) {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                redact_value(k, v);
+            }
+        }
+        Value::Array(items) => {
+            for v in items.iter_mut() {
+                redact_value(key, v);
+            }
+        }
+        Value::Null => {}
+        Value::String(s) if s.is_empty() => {}
+        _ if is_secret_key(key) => *value = Value::String(REDACTED.to_string()),
+        _ => {}
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Action {
     Reconnect,
@@ -47,6 +90,35 @@ pub struct WifiConfig {
     pub ssid: String,
     pub bssid: String,
     pub wpa_key: String,
+    /// security mode announced to the phone in the WifiInfoResponse
+    pub security: WifiSecurity,
+    /// AP channel announced in the WifiInfoResponse, omitted when `None`
+    pub channel: Option<u8>,
+    /// AP band (`2.4`, `5` or `6`) announced in the WifiInfoResponse, omitted when `None`
+    pub band: Option<String>,
+    /// extra fields of the WifiStartRequest message
+    pub start_extra_fields: ExtraProtoFields,
+    /// extra fields of the WifiInfoResponse message
+    pub info_extra_fields: ExtraProtoFields,
+}
+
+impl WifiConfig {
+    /// one-line summary for logs, without the WPA key
+    pub fn redacted(&self) -> String {
+        format!(
+            "ip_addr = {:?}, port = {}, ssid = {:?}, bssid = {:?}, wpa_key = {:?}, security = {}, channel = {:?}, band = {:?}, start_extra_fields = {:?}, info_extra_fields = {:?}",
+            self.ip_addr,
+            self.port,
+            self.ssid,
+            self.bssid,
+            if self.wpa_key.is_empty() { "" } else { REDACTED },
+            self.security,
+            self.channel,
+            self.band,
+            self.start_extra_fields.to_string(),
+            self.info_extra_fields.to_string()
+        )
+    }
 }
 
 pub fn empty_string_as_none<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
@@ -151,6 +223,57 @@ impl Display for BtScoMediaBridgeResampler {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HuUsbTransport {
+    /// `/dev/usb_accessory` character device (f_accessory gadget function)
+    Accessory,
+    /// FunctionFS bulk endpoint files in `functionfs_dir`
+    Functionfs,
+}
+
+impl Default for HuUsbTransport {
+    fn default() -> Self {
+        Self::Accessory
+    }
+}
+
+impl Display for HuUsbTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Accessory => "accessory",
+            Self::Functionfs => "functionfs",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BtScoCodec {
+    /// leave the voice setting to the kernel/controller default
+    Auto,
+    /// CVSD, 8 kHz linear PCM on the socket
+    Cvsd,
+    /// mSBC wideband, transparent data on the socket
+    Msbc,
+}
+
+impl Default for BtScoCodec {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
+impl Display for BtScoCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Cvsd => "cvsd",
+            Self::Msbc => "msbc",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum BtScoMicEchoControl {
@@ -173,6 +296,196 @@ impl Display for BtScoMicEchoControl {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MitmInitPolicy {
+    Passthrough,
+    Fail,
+}
+
+impl Default for MitmInitPolicy {
+    fn default() -> Self {
+        Self::Passthrough
+    }
+}
+
+impl Display for MitmInitPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Passthrough => "passthrough",
+            Self::Fail => "fail",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BtConnectMode {
+    Static,
+    Auto,
+}
+
+impl Default for BtConnectMode {
+    fn default() -> Self {
+        Self::Static
+    }
+}
+
+impl Display for BtConnectMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Static => "static",
+            Self::Auto => "auto",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtraConnectPolicy {
+    Keep,
+    Reject,
+    Ignore,
+}
+
+impl Default for ExtraConnectPolicy {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+impl Display for ExtraConnectPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Keep => "keep",
+            Self::Reject => "reject",
+            Self::Ignore => "ignore",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairingPolicy {
+    System,
+    Accept,
+    Pin,
+}
+
+impl Default for PairingPolicy {
+    fn default() -> Self {
+        Self::System
+    }
+}
+
+impl Display for PairingPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::System => "system",
+            Self::Accept => "accept",
+            Self::Pin => "pin",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WpaKeyPolicy {
+    Reject,
+    Truncate,
+}
+
+impl Default for WpaKeyPolicy {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+impl Display for WpaKeyPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Reject => "reject",
+            Self::Truncate => "truncate",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WifiSecurity {
+    /// open AP without a password, for isolated test setups only
+    Open,
+    Wpa2,
+    Wpa3,
+    #[serde(rename = "wpa2-wpa3")]
+    Wpa2Wpa3Mixed,
+}
+
+impl Default for WifiSecurity {
+    fn default() -> Self {
+        Self::Wpa2
+    }
+}
+
+impl Display for WifiSecurity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Open => "open",
+            Self::Wpa2 => "wpa2",
+            Self::Wpa3 => "wpa3",
+            Self::Wpa2Wpa3Mixed => "wpa2-wpa3",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BandCheck {
+    Off,
+    Warn,
+    Reconnect,
+}
+
+impl Default for BandCheck {
+    fn default() -> Self {
+        Self::Off
+    }
+}
+
+impl Display for BandCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Off => "off",
+            Self::Warn => "warn",
+            Self::Reconnect => "reconnect",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsUnits {
+    Binary,
+    Decimal,
+    Bytes,
+}
+
+impl Default for StatsUnits {
+    fn default() -> Self {
+        Self::Binary
+    }
+}
+
+impl Display for StatsUnits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Binary => "binary",
+            Self::Decimal => "decimal",
+            Self::Bytes => "bytes",
+        })
+    }
+}
+
 fn webserver_default_bind() -> Option<String> {
     Some("0.0.0.0:80".into())
 }
@@ -211,6 +524,25 @@ pub struct AppConfig {
     pub pkt_debug: bool,
     pub hexdump_level: HexdumpLevel,
     pub disable_console_debug: bool,
+    /// Add absolute wall-clock timestamps to key connection/session log events
+    /// (relative monotonic times are always logged).
+    pub log_wall_clock: bool,
+    /// Log every effective config value at startup, with secrets redacted.
+    pub log_config: bool,
+    /// Log the routine idle/restart lines (waiting for the phone, accept
+    /// timeouts) after 1, 5 and then every 15 minutes while nothing changes.
+    pub idle_log_backoff: bool,
+    /// Emit a versioned JSON `SessionEnded` record at the end of each session,
+    /// to the log and to the event bus (`session_ended` topic).
+    pub session_summary: bool,
+    /// File the `session_summary` records are appended to as JSON lines,
+    /// `-` for stdout. Empty disables it.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub session_summary_file: Option<PathBuf>,
+    /// Warn when an identical frame repeats this many times in a row on one
+    /// channel within `dup_frame_window_ms` (0 disables the detector).
+    pub dup_frame_threshold: u16,
+    pub dup_frame_window_ms: u16,
     /// Enable additional packet debug filtering on top of `hexdump_level`.
     pub pkt_debug_filter_enabled: bool,
     /// Packet debug proxy filter: `both`, `hu`, or `md`.
@@ -233,9 +565,48 @@ pub struct AppConfig {
     pub pkt_debug_filter_max_payload_bytes: usize,
     pub legacy: bool,
     pub quick_reconnect: bool,
+    /// Start accepting the phone TCP connection as soon as the WiFi
+    /// credentials are sent, instead of at the end of the BT handshake.
+    pub early_tcp_start: bool,
+    /// Wait up to this many seconds for the phone TCP connection before the
+    /// Bluetooth launch sequence is logged as completed; 0 doesn't wait.
+    pub bt_confirm_connection_secs: u16,
+    /// How many times a failed bluetooth handshake stage read is retried
+    /// (with exponential backoff) before the handshake is aborted.
+    pub bt_handshake_read_retries: u8,
+    /// Timeout of every bluetooth handshake stage read [seconds], either one
+    /// value for all stages or a comma separated list per stage.
+    pub bt_stage_timeout_secs: StageTimeouts,
+    /// Answer the phone's WifiVersionRequest with this version in an extra
+    /// bluetooth handshake stage; 0 skips the version stage.
+    pub bt_wifi_version: u16,
+    /// Poll the phone's bluetooth RSSI every this many seconds while it stays
+    /// connected; 0 only logs it once when the phone connects.
+    pub bt_rssi_poll_secs: u16,
+    /// Warn when the phone's bluetooth RSSI is below this level [dBm].
+    pub bt_rssi_warn_dbm: i16,
     pub bt_poweroff: bool,
+    /// Grace delay before `bt_poweroff` powers the adapter off [milliseconds].
+    pub bt_poweroff_delay_ms: u16,
     pub connect: BluetoothAddressList,
+    /// `static`: use `connect` as configured, `auto`: connect to bonded devices
+    /// when there are any and fall back to advertising after the connect window.
+    pub bt_connect_mode: BtConnectMode,
+    /// How long `auto` mode tries to connect to bonded devices [seconds].
+    pub bt_auto_connect_window_secs: u16,
+    /// Keep the bluetooth adapter off until an external "phone present" trigger
+    /// (SIGUSR1 or POST `/phone-present`) arrives.
+    pub bt_phone_present_trigger: bool,
+    /// How long the adapter stays discoverable after the trigger [seconds].
+    pub bt_trigger_discoverable_secs: u16,
+    /// Unix socket streaming bluetooth handshake stage events as JSON lines.
+    /// Empty disables it.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub bt_handshake_events_socket: Option<PathBuf>,
     pub logfile: PathBuf,
+    /// Number of recent log lines kept in memory and served by the `/health`
+    /// endpoint (0 disables the buffer).
+    pub log_buffer_lines: usize,
     /// Enable writing Rust panic reports to disk.
     pub crash_handler_enabled: bool,
     /// Directory where panic reports are written.
@@ -246,40 +617,236 @@ pub struct AppConfig {
     pub sdr_ui_override_autocreate_profiles: bool,
     /// TOML file that stores per-vehicle and optional per-phone SDR UI overrides.
     pub sdr_ui_override_file: PathBuf,
+    /// Interval of the transfer statistics in the log [seconds], 0 = disabled;
+    /// never shorter than the monitor poll interval.
     pub stats_interval: u16,
+    /// Units of the periodic transfer statistics: binary (KiB, MiB),
+    /// decimal (kB, MB) or raw byte counts.
+    pub stats_units: StatsUnits,
+    /// How often the transfer monitor checks stats, stalls and pending actions
+    /// [milliseconds]; 0 derives it from `stats_interval` and `timeout_secs`.
+    pub monitor_poll_ms: u16,
+    /// Number of consecutive transfer stalls only warned about before the
+    /// session is failed; 0 fails on the first stall.
+    pub stall_leniency: u8,
+    /// Stall timeout of the phone -> car direction [seconds]; 0 uses `timeout_secs`.
+    pub stall_timeout_to_hu_secs: u16,
+    /// Stall timeout of the car -> phone direction [seconds]; 0 uses `timeout_secs`.
+    pub stall_timeout_to_md_secs: u16,
+    /// Minimum phone -> car throughput of the session watchdog [bytes/s];
+    /// 0 disables the watchdog.
+    pub watchdog_min_bytes_per_sec: u32,
+    /// Number of consecutive watchdog intervals below the minimum throughput
+    /// after which the session is restarted.
+    pub watchdog_intervals: u8,
+    /// Recycle (restart) every session after this duration regardless of
+    /// traffic [seconds]; 0 = unlimited.
+    pub max_session_secs: u32,
+    /// Append-only file with per-minute transfer statistics. Empty disables it.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub stats_log_file: Option<PathBuf>,
+    /// Size after which `stats_log_file` is rotated [kilobytes].
+    pub stats_log_max_kb: u32,
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub udc: Option<String>,
     pub iface: String,
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub btalias: Option<String>,
+    /// Append the CPU serial number suffix to the default alias (unused with `btalias`).
+    pub bt_alias_serial_suffix: bool,
+    /// Bluetooth adapter to use, by name (`hci1`) or MAC address; unset uses the default one.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub bt_adapter: Option<String>,
     pub timeout_secs: u16,
+    /// Address the MD/DHU TCP listeners bind to, IPv4 or IPv6
+    /// (`::` listens on both families).
+    pub tcp_bind_addr: IpAddr,
+    /// Address of the DHU TCP listener when it differs from `tcp_bind_addr`.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub tcp_dhu_bind_addr: Option<IpAddr>,
+    /// TCP port of the phone (MD) listener, announced to the phone in the bluetooth handshake.
+    pub tcp_md_port: u16,
+    /// TCP port of the DHU emulator listener.
+    pub tcp_dhu_port: u16,
+    /// Set SO_REUSEADDR on the MD/DHU TCP listeners.
+    pub tcp_reuse_addr: bool,
+    /// Accept backlog of the MD/DHU TCP listeners.
+    pub tcp_listen_backlog: u16,
+    /// Idle time before the first TCP keepalive probe on the MD/DHU streams [s], 0 = keepalive disabled.
+    pub tcp_keepalive_idle_secs: u16,
+    /// Interval between the TCP keepalive probes [s].
+    pub tcp_keepalive_interval_secs: u16,
+    /// Unanswered TCP keepalive probes before the connection is dropped.
+    pub tcp_keepalive_count: u8,
+    /// Read buffer size of the HU (car) side reader [KiB], rounded up to whole pages.
+    pub hu_read_buffer_kb: u16,
+    /// Read buffer size of the MD (phone) side reader [KiB], rounded up to whole pages.
+    pub md_read_buffer_kb: u16,
+    /// Capacity [packets] of each of the proxy/reader queues between the HU and MD sides.
+    pub proxy_queue_capacity: u16,
+    /// Submission queue size of the io_uring ring (0 = tokio-uring default).
+    pub uring_entries: u32,
+    /// Maximum of io_uring reads/writes in flight (0 = unlimited).
+    pub max_inflight_ops: u16,
+    /// Collect small consecutive frames for up to this long [ms] before writing
+    /// them at once (control and input frames are written right away). 0 disables it.
+    pub write_coalesce_ms: u16,
+    /// Collected frames are written as soon as they reach this size [KiB].
+    pub write_coalesce_kb: u16,
     #[serde(
         default = "webserver_default_bind",
         deserialize_with = "empty_string_as_none"
     )]
     pub webserver: Option<String>,
+    /// Port of the dedicated Prometheus metrics endpoint (0 = disabled,
+    /// needs the `metrics` feature).
+    pub metrics_port: u16,
     pub bt_timeout_secs: u16,
+    /// Handling of AA profile connect requests which arrived while a session was
+    /// already running: `keep` (leave queued), `reject` or `ignore` (leave
+    /// unanswered until they time out).
+    pub bt_extra_connect_policy: ExtraConnectPolicy,
+    /// Phones allowed to open the AA Wireless profile; empty allows any phone.
+    pub bt_allowlist: BluetoothAddressList,
+    /// After a session only register the AA Wireless profile and the BLE
+    /// advertisement again, reusing the adapter setup.
+    pub bt_readvertise: bool,
+    /// Pairing requests: `system` leaves them to the BlueZ/system agent,
+    /// `accept` accepts every pairing, `pin` answers with `bt_pairing_pin`.
+    pub bt_pairing_policy: PairingPolicy,
+    /// Fixed PIN/passkey for the `pin` policy, also answered to legacy PIN code
+    /// requests with `accept`; empty uses `0000`.
+    pub bt_pairing_pin: String,
+    /// How long to wait for the HSP profile task to finish after unregistering
+    /// it [milliseconds]; 0 aborts the task immediately.
+    pub bt_hsp_teardown_timeout_ms: u16,
+    /// RFCOMM channel of the AA Wireless bluetooth profile.
+    pub bt_rfcomm_channel: u8,
+    /// RFCOMM channel of the HSP profile; 0 lets BlueZ choose a free one.
+    pub bt_hsp_rfcomm_channel: u8,
+    /// Service UUID of the AA Wireless profile; empty uses the standard one.
+    pub bt_aa_profile_uuid: ProfileUuid,
+    /// HSP headset UUID registered on the adapter; empty uses the standard one.
+    pub bt_hsp_hs_uuid: ProfileUuid,
+    /// HSP audio gateway UUID connected on the phone; empty uses the standard one.
+    pub bt_hsp_ag_uuid: ProfileUuid,
     pub mitm: bool,
+    /// What to do when the MITM subsystem cannot be initialized at session start
+    /// (missing keys/certificates, SSL context errors): `passthrough` or `fail`.
+    pub mitm_init_policy: MitmInitPolicy,
+    /// Initialize the SSL/crypto contexts at startup instead of during the
+    /// first session handshake.
+    pub mitm_ssl_prewarm: bool,
+    /// Lowest negotiated protocol version (`major.minor`) accepted in MITM mode.
+    /// Empty accepts any version.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub min_protocol_version: Option<ProtocolVersion>,
+    /// Highest negotiated protocol version (`major.minor`) accepted in MITM mode.
+    /// Empty accepts any version.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub max_protocol_version: Option<ProtocolVersion>,
     pub dpi: u16,
+    /// Per head unit model DPI (`model=dpi,...`), overriding `dpi` for the
+    /// listed models.
+    pub dpi_by_model: ModelDpiMap,
     pub audio_max_unacked: u8,
     pub add_vendor_channel: bool,
     pub remove_tap_restriction: bool,
     pub video_in_motion: bool,
     pub disable_media_sink: bool,
     pub disable_tts_sink: bool,
+    /// Frames per second the projected video is capped to by dropping frames,
+    /// 0 = off.
+    pub video_fps_cap: u8,
     pub developer_mode: bool,
     #[serde(default, deserialize_with = "empty_string_as_none")]
     pub wired: Option<UsbId>,
     pub dhu: bool,
+    /// How the HU side USB gadget endpoint is exposed.
+    pub hu_usb_transport: HuUsbTransport,
+    /// FunctionFS mount point used with `hu_usb_transport = "functionfs"`.
+    /// `ep0` must already be configured (descriptors written and kept open) by
+    /// the gadget setup; `ep1` is the bulk OUT (HU -> proxy) and `ep2` the bulk
+    /// IN (proxy -> HU) endpoint.
+    pub functionfs_dir: PathBuf,
+    /// Character device of the f_accessory gadget function, for
+    /// `hu_usb_transport = accessory`.
+    pub usb_accessory_path: PathBuf,
+    /// Keep the USB accessory (HU) device open across wireless phone
+    /// reconnects while it stays healthy, instead of reopening it per session.
+    /// A new session starts with the HU's next version request.
+    pub hu_usb_keep_open: bool,
+    /// When a wireless phone connection fails while the HU side is still healthy,
+    /// wait this long [seconds] for the phone to reconnect over TCP, without a
+    /// new bluetooth handshake, before doing a full restart. The HU side session
+    /// is restarted either way. 0 disables the quick reconnect.
+    pub md_quick_reconnect_secs: u16,
+    /// How many times opening the USB accessory device is retried.
+    pub usb_accessory_open_retries: u8,
+    /// Initial delay between USB accessory open retries, doubled on every
+    /// attempt [milliseconds].
+    pub usb_accessory_open_retry_ms: u16,
+    /// At the end of a session let the proxy tasks return by themselves and
+    /// wait for them to release the streams, instead of aborting them.
+    pub cooperative_shutdown: bool,
+    /// Directory where each proxied session is captured to a `.aacap` file.
+    /// Empty disables capturing.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub capture_dir: Option<PathBuf>,
+    /// File where the configuration negotiated in the last working session
+    /// is saved and logged on the next startup. Empty disables the checkpoint.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub last_known_good_file: Option<PathBuf>,
+    /// DHU replay file (see `--export-dhu-replay`). When set together with `dhu`,
+    /// the recorded HU-directed stream is played to the DHU instead of a phone.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub dhu_replay_file: Option<PathBuf>,
+    /// Play `dhu_replay_file` as fast as the DHU accepts it, ignoring the recorded timing.
+    pub dhu_replay_fast: bool,
+    /// For headless testing: when no phone completes the bluetooth handshake
+    /// within this time [seconds], play `dhu_replay_file` to the DHU instead.
+    /// 0 disables the fallback.
+    pub dhu_fallback_secs: u16,
+    /// Development only: number of additional DHUs accepted after the first one,
+    /// mirroring the MD stream (see `dhu_fanout`). Requires mitm. 0 disables it.
+    pub dhu_mirrors: u8,
+    /// PEM certificate (chain) of the DHU listener; together with `dhu_tls_key`
+    /// the DHU connection is TLS encrypted. Empty keeps plain TCP.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub dhu_tls_cert: Option<PathBuf>,
+    /// PEM private key of `dhu_tls_cert`.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub dhu_tls_key: Option<PathBuf>,
+    /// PEM CA file; when set, the DHU has to present a client certificate signed by it.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub dhu_tls_client_ca: Option<PathBuf>,
+    /// Unix domain socket the DHU listener is bound to instead of `tcp_dhu_port`.
+    /// Empty keeps TCP.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub dhu_unix_socket: Option<PathBuf>,
+    /// Unix domain socket the MD listener is bound to instead of `tcp_md_port`,
+    /// for a local relay of the phone connection. Empty keeps TCP.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub md_unix_socket: Option<PathBuf>,
     /// Optional direct TCP address for Android Auto Head Unit Server on the MD/phone side.
     /// Empty keeps the normal USB/Bluetooth/Wi-Fi MD transport behavior.
     pub aa_server_tcp_addr: String,
     pub ev: bool,
     pub odometer: bool,
     pub tire_pressure: bool,
+    /// Inject GPS position and vehicle speed (see `sensor_feed`) when the HU
+    /// doesn't provide them.
+    pub sensor_injection: bool,
+    /// NMEA 0183 source (serial device, FIFO or file) feeding `sensor_injection`.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub sensor_nmea_source: Option<PathBuf>,
     pub remove_bluetooth: bool,
     pub remove_wifi: bool,
     pub inject_display_types: InjectDisplayTypes,
+    /// Service ids kept in the ServiceDiscoveryResponse (empty keeps all).
+    pub sdr_service_allow: ServiceIdList,
+    /// Service ids removed from the ServiceDiscoveryResponse.
+    pub sdr_service_deny: ServiceIdList,
     pub inject_add_input_sources: bool,
     pub inject_cluster_display_id: u16,
     pub inject_cluster_width_margin: u16,
@@ -310,10 +877,29 @@ pub struct AppConfig {
     pub usb_serial_console: bool,
     pub wifi_version: u16,
     pub band: String,
+    /// Check via hostapd that the phone joined the configured `band`.
+    pub band_check: BandCheck,
     pub country_code: String,
     pub channel: u8,
+    /// Announce `band` and `channel` to the phone in the bluetooth WifiInfoResponse.
+    pub wifi_announce_band: bool,
     pub ssid: String,
     pub wpa_passphrase: String,
+    /// TOML file mapping phone MAC addresses to their own SSID/passphrase
+    /// (see `wifi_credentials`); phones without an entry get `ssid`/`wpa_passphrase`.
+    /// Its fallback profiles are offered when the phone can't join the first network.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub wifi_credentials_file: Option<PathBuf>,
+    /// What to do with a WPA passphrase longer than 63 characters before
+    /// it is sent to the phone.
+    pub wpa_key_policy: WpaKeyPolicy,
+    /// Security mode announced to the phone in the bluetooth handshake;
+    /// the AP (hostapd) has to be configured to match.
+    pub wifi_security: WifiSecurity,
+    /// Extra fields (`number=value`) sent in the bluetooth WifiStartRequest.
+    pub wifi_start_extra_fields: ExtraProtoFields,
+    /// Extra fields (`number=value`) sent in the bluetooth WifiInfoResponse.
+    pub wifi_info_extra_fields: ExtraProtoFields,
     pub eth_mode: String,
     pub startup_delay: u8,
     pub ble_password: String,
@@ -329,6 +915,15 @@ pub struct AppConfig {
     /// false = forward immediately after cached-IDR preview (lower latency, may artifact)
     pub media_wait_for_live_idr: bool,
     pub collect_speed: bool,
+    /// Split transfer statistics into moving/parked based on the vehicle
+    /// speed from the sensor batches (requires mitm).
+    pub motion_stats: bool,
+    /// Estimate the projected video frame rate and dropped/late frames
+    /// (requires mitm).
+    pub video_stats: bool,
+    /// Measure the phone/car link round-trip latency on ping frames
+    /// (requires mitm).
+    pub latency_stats: bool,
     pub disable_driving_status: bool,
     /// Optional shell command invoked on HU media-key long press.
     ///
@@ -354,6 +949,11 @@ pub struct AppConfig {
     /// should keep routing call audio to aa-proxy-rs instead of dropping BT after
     /// the AA Wi-Fi setup phase.
     pub bt_sco_keep_bluetooth_alive: bool,
+    /// Preferred SCO/eSCO codec, applied to incoming SCO connections through the
+    /// kernel `BT_VOICE` socket option. `auto` keeps the kernel default; `cvsd`
+    /// and `msbc` require kernel and controller SCO support, and the phone still
+    /// has the final say. The SCO bridges only handle CVSD linear PCM.
+    pub bt_sco_codec: BtScoCodec,
     /// Experimental downlink bridge: SCO call audio -> AA PCM media sink.
     /// Disabled by default. Requires `mitm = true`.
     pub bt_sco_media_bridge: bool,
@@ -535,6 +1135,13 @@ impl Default for AppConfig {
             pkt_debug: false,
             hexdump_level: HexdumpLevel::Disabled,
             disable_console_debug: false,
+            log_wall_clock: false,
+            log_config: false,
+            idle_log_backoff: false,
+            session_summary: false,
+            session_summary_file: None,
+            dup_frame_threshold: 0,
+            dup_frame_window_ms: 1000,
             pkt_debug_filter_enabled: false,
             pkt_debug_filter_proxy: "both".to_string(),
             pkt_debug_filter_stages: String::new(),
@@ -547,39 +1154,122 @@ impl Default for AppConfig {
             pkt_debug_filter_max_payload_bytes: 2048,
             legacy: true,
             quick_reconnect: false,
+            early_tcp_start: false,
+            bt_confirm_connection_secs: 0,
+            bt_handshake_read_retries: 3,
+            bt_stage_timeout_secs: StageTimeouts::default(),
+            bt_wifi_version: 0,
+            bt_rssi_poll_secs: 0,
+            bt_rssi_warn_dbm: -80,
             bt_poweroff: false,
+            bt_poweroff_delay_ms: 0,
             connect: BluetoothAddressList::default(),
+            bt_connect_mode: BtConnectMode::default(),
+            bt_auto_connect_window_secs: 30,
+            bt_phone_present_trigger: false,
+            bt_trigger_discoverable_secs: 120,
+            bt_handshake_events_socket: None,
             logfile: "/var/log/aa-proxy-rs.log".into(),
+            log_buffer_lines: 200,
             crash_handler_enabled: true,
             crash_dir: DEFAULT_CRASH_DIR.into(),
             sdr_ui_override_enabled: true,
             sdr_ui_override_autocreate_profiles: true,
             sdr_ui_override_file: DEFAULT_SDR_UI_OVERRIDE_FILE.into(),
             stats_interval: 0,
+            stats_units: StatsUnits::default(),
+            monitor_poll_ms: 100,
+            stall_leniency: 0,
+            stall_timeout_to_hu_secs: 0,
+            stall_timeout_to_md_secs: 0,
+            watchdog_min_bytes_per_sec: 0,
+            watchdog_intervals: 3,
+            max_session_secs: 0,
+            stats_log_file: None,
+            stats_log_max_kb: 512,
             udc: None,
             iface: "wlan0".to_string(),
             btalias: None,
+            bt_alias_serial_suffix: true,
+            bt_adapter: None,
             timeout_secs: 10,
+            tcp_bind_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            tcp_dhu_bind_addr: None,
+            tcp_md_port: TCP_SERVER_PORT as u16,
+            tcp_dhu_port: TCP_DHU_PORT as u16,
+            tcp_reuse_addr: true,
+            tcp_listen_backlog: 1024,
+            tcp_keepalive_idle_secs: 10,
+            tcp_keepalive_interval_secs: 5,
+            tcp_keepalive_count: 3,
+            hu_read_buffer_kb: 16,
+            md_read_buffer_kb: 16,
+            proxy_queue_capacity: 10,
+            uring_entries: 0,
+            max_inflight_ops: 0,
+            write_coalesce_ms: 0,
+            write_coalesce_kb: 16,
             webserver: webserver_default_bind(),
+            metrics_port: 0,
             bt_timeout_secs: 120,
+            bt_extra_connect_policy: ExtraConnectPolicy::default(),
+            bt_allowlist: BluetoothAddressList(None),
+            bt_readvertise: false,
+            bt_pairing_policy: PairingPolicy::default(),
+            bt_pairing_pin: String::new(),
+            bt_hsp_teardown_timeout_ms: 80,
+            bt_rfcomm_channel: 8,
+            bt_hsp_rfcomm_channel: 0,
+            bt_aa_profile_uuid: ProfileUuid::default(),
+            bt_hsp_hs_uuid: ProfileUuid::default(),
+            bt_hsp_ag_uuid: ProfileUuid::default(),
             mitm: false,
+            mitm_init_policy: MitmInitPolicy::default(),
+            mitm_ssl_prewarm: false,
+            min_protocol_version: None,
+            max_protocol_version: None,
             dpi: 0,
+            dpi_by_model: ModelDpiMap::default(),
             audio_max_unacked: 0,
             add_vendor_channel: true,
             remove_tap_restriction: false,
             video_in_motion: false,
             disable_media_sink: false,
             disable_tts_sink: false,
+            video_fps_cap: 0,
             developer_mode: false,
             wired: None,
             dhu: false,
+            hu_usb_transport: HuUsbTransport::Accessory,
+            functionfs_dir: DEFAULT_FUNCTIONFS_DIR.into(),
+            usb_accessory_path: DEFAULT_USB_ACCESSORY_PATH.into(),
+            hu_usb_keep_open: false,
+            md_quick_reconnect_secs: 0,
+            usb_accessory_open_retries: 5,
+            usb_accessory_open_retry_ms: 200,
+            cooperative_shutdown: false,
+            capture_dir: None,
+            last_known_good_file: None,
+            dhu_replay_file: None,
+            dhu_replay_fast: false,
+            dhu_fallback_secs: 0,
+            dhu_mirrors: 0,
+            dhu_tls_cert: None,
+            dhu_unix_socket: None,
+            md_unix_socket: None,
+            dhu_tls_key: None,
+            dhu_tls_client_ca: None,
             aa_server_tcp_addr: String::new(),
             ev: false,
             odometer: false,
             tire_pressure: false,
+            sensor_injection: false,
+            sensor_nmea_source: None,
             remove_bluetooth: false,
             remove_wifi: false,
             inject_display_types: InjectDisplayTypes::default(),
+            sdr_service_allow: ServiceIdList::default(),
+            sdr_service_deny: ServiceIdList::default(),
             inject_add_input_sources: false,
             inject_cluster_display_id: 1,
             inject_cluster_width_margin: 270,
@@ -615,6 +1305,7 @@ impl Default for AppConfig {
                 }
                 .to_string()
             },
+            band_check: BandCheck::default(),
             country_code: "US".to_string(),
             channel: {
                 if supports_5ghz_wifi().unwrap_or(false) {
@@ -624,8 +1315,14 @@ impl Default for AppConfig {
                     6
                 }
             },
+            wifi_announce_band: false,
             ssid: String::from(IDENTITY_NAME),
             wpa_passphrase: String::from(IDENTITY_NAME),
+            wifi_credentials_file: None,
+            wpa_key_policy: WpaKeyPolicy::default(),
+            wifi_security: WifiSecurity::default(),
+            wifi_start_extra_fields: ExtraProtoFields::default(),
+            wifi_info_extra_fields: ExtraProtoFields::default(),
             eth_mode: String::new(),
             startup_delay: 0,
             ble_password: String::new(),
@@ -633,10 +1330,14 @@ impl Default for AppConfig {
             media_dump_base_port: None,
             media_wait_for_live_idr: true,
             collect_speed: false,
+            motion_stats: false,
+            video_stats: false,
+            latency_stats: false,
             disable_driving_status: false,
             hu_button_handler: None,
             bt_sco: false,
             bt_sco_keep_bluetooth_alive: true,
+            bt_sco_codec: BtScoCodec::Auto,
             bt_sco_media_bridge: false,
             bt_sco_media_bridge_audio_type: BtScoMediaBridgeAudioType::Media,
             bt_sco_media_bridge_gain_percent: 300,
@@ -770,8 +1471,29 @@ impl AppConfig {
         Ok(file_config.unwrap())
     }
 
+    /// Every effective config value as copy-pasteable `key = value` lines,
+    /// with secret values replaced by [`REDACTED`].
+    pub fn redacted(&self) -> String {
+        let mut values = match serde_json::to_value(self) {
+            Ok(values) => values,
+            Err(e) => return format!("# config not serializable: {}", e),
+        };
+        redact_value("", &mut values);
+        let serde_json::Value::Object(map) = values else {
+            return String::new();
+        };
+        map.iter()
+            .map(|(key, value)| match value {
+                // unset optional values are empty strings in the config file
+                serde_json::Value::Null => format!("{} = \"\"", key),
+                value => format!("{} = {}", key, value),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     pub fn save(&self, config_file: PathBuf) {
-        debug!("Saving config: {:?}", self);
+        debug!("Saving config:\n{}", self.redacted());
         let raw = fs::read_to_string(&config_file).unwrap_or_default();
         let mut doc = raw.parse::<DocumentMut>().unwrap_or_else(|_| {
             // if the file doesn't exists or there is parse error, create a new one
@@ -785,6 +1507,17 @@ impl AppConfig {
         doc["pkt_debug"] = value(self.pkt_debug);
         doc["hexdump_level"] = value(format!("{:?}", self.hexdump_level));
         doc["disable_console_debug"] = value(self.disable_console_debug);
+        doc["log_wall_clock"] = value(self.log_wall_clock);
+        doc["log_config"] = value(self.log_config);
+        doc["idle_log_backoff"] = value(self.idle_log_backoff);
+        doc["session_summary"] = value(self.session_summary);
+        doc["session_summary_file"] = value(
+            self.session_summary_file
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["dup_frame_threshold"] = value(self.dup_frame_threshold as i64);
+        doc["dup_frame_window_ms"] = value(self.dup_frame_window_ms as i64);
         doc["pkt_debug_filter_enabled"] = value(self.pkt_debug_filter_enabled);
         doc["pkt_debug_filter_proxy"] = value(self.pkt_debug_filter_proxy.to_string());
         doc["pkt_debug_filter_stages"] = value(self.pkt_debug_filter_stages.to_string());
@@ -801,9 +1534,27 @@ impl AppConfig {
             value(self.pkt_debug_filter_max_payload_bytes as i64);
         doc["legacy"] = value(self.legacy);
         doc["quick_reconnect"] = value(self.quick_reconnect);
+        doc["early_tcp_start"] = value(self.early_tcp_start);
+        doc["bt_confirm_connection_secs"] = value(self.bt_confirm_connection_secs as i64);
+        doc["bt_handshake_read_retries"] = value(self.bt_handshake_read_retries as i64);
+        doc["bt_stage_timeout_secs"] = value(self.bt_stage_timeout_secs.to_string());
+        doc["bt_wifi_version"] = value(self.bt_wifi_version as i64);
+        doc["bt_rssi_poll_secs"] = value(self.bt_rssi_poll_secs as i64);
+        doc["bt_rssi_warn_dbm"] = value(self.bt_rssi_warn_dbm as i64);
         doc["bt_poweroff"] = value(self.bt_poweroff);
+        doc["bt_poweroff_delay_ms"] = value(self.bt_poweroff_delay_ms as i64);
         doc["connect"] = value(self.connect.to_string());
+        doc["bt_connect_mode"] = value(self.bt_connect_mode.to_string());
+        doc["bt_auto_connect_window_secs"] = value(self.bt_auto_connect_window_secs as i64);
+        doc["bt_phone_present_trigger"] = value(self.bt_phone_present_trigger);
+        doc["bt_trigger_discoverable_secs"] = value(self.bt_trigger_discoverable_secs as i64);
+        doc["bt_handshake_events_socket"] = value(
+            self.bt_handshake_events_socket
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
         doc["logfile"] = value(self.logfile.display().to_string());
+        doc["log_buffer_lines"] = value(self.log_buffer_lines as i64);
         doc["crash_handler_enabled"] = value(self.crash_handler_enabled);
         doc["crash_dir"] = value(self.crash_dir.display().to_string());
         doc["sdr_ui_override_enabled"] = value(self.sdr_ui_override_enabled);
@@ -811,6 +1562,20 @@ impl AppConfig {
             value(self.sdr_ui_override_autocreate_profiles);
         doc["sdr_ui_override_file"] = value(self.sdr_ui_override_file.display().to_string());
         doc["stats_interval"] = value(self.stats_interval as i64);
+        doc["stats_units"] = value(self.stats_units.to_string());
+        doc["monitor_poll_ms"] = value(self.monitor_poll_ms as i64);
+        doc["stall_leniency"] = value(self.stall_leniency as i64);
+        doc["stall_timeout_to_hu_secs"] = value(self.stall_timeout_to_hu_secs as i64);
+        doc["stall_timeout_to_md_secs"] = value(self.stall_timeout_to_md_secs as i64);
+        doc["watchdog_min_bytes_per_sec"] = value(self.watchdog_min_bytes_per_sec as i64);
+        doc["watchdog_intervals"] = value(self.watchdog_intervals as i64);
+        doc["max_session_secs"] = value(self.max_session_secs as i64);
+        doc["stats_log_file"] = value(
+            self.stats_log_file
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["stats_log_max_kb"] = value(self.stats_log_max_kb as i64);
         if let Some(udc) = &self.udc {
             doc["udc"] = value(udc);
         }
@@ -818,29 +1583,135 @@ impl AppConfig {
         if let Some(alias) = &self.btalias {
             doc["btalias"] = value(alias);
         }
+        doc["bt_alias_serial_suffix"] = value(self.bt_alias_serial_suffix);
+        if let Some(adapter) = &self.bt_adapter {
+            doc["bt_adapter"] = value(adapter);
+        }
         doc["timeout_secs"] = value(self.timeout_secs as i64);
+        doc["tcp_bind_addr"] = value(self.tcp_bind_addr.to_string());
+        doc["tcp_dhu_bind_addr"] = value(
+            self.tcp_dhu_bind_addr
+                .map_or(String::new(), |addr| addr.to_string()),
+        );
+        doc["tcp_md_port"] = value(self.tcp_md_port as i64);
+        doc["tcp_dhu_port"] = value(self.tcp_dhu_port as i64);
+        doc["tcp_reuse_addr"] = value(self.tcp_reuse_addr);
+        doc["tcp_listen_backlog"] = value(self.tcp_listen_backlog as i64);
+        doc["tcp_keepalive_idle_secs"] = value(self.tcp_keepalive_idle_secs as i64);
+        doc["tcp_keepalive_interval_secs"] = value(self.tcp_keepalive_interval_secs as i64);
+        doc["tcp_keepalive_count"] = value(self.tcp_keepalive_count as i64);
+        doc["hu_read_buffer_kb"] = value(self.hu_read_buffer_kb as i64);
+        doc["md_read_buffer_kb"] = value(self.md_read_buffer_kb as i64);
+        doc["proxy_queue_capacity"] = value(self.proxy_queue_capacity as i64);
+        doc["uring_entries"] = value(self.uring_entries as i64);
+        doc["max_inflight_ops"] = value(self.max_inflight_ops as i64);
+        doc["write_coalesce_ms"] = value(self.write_coalesce_ms as i64);
+        doc["write_coalesce_kb"] = value(self.write_coalesce_kb as i64);
         if let Some(webserver) = &self.webserver {
             doc["webserver"] = value(webserver);
         }
+        doc["metrics_port"] = value(self.metrics_port as i64);
         doc["bt_timeout_secs"] = value(self.bt_timeout_secs as i64);
+        doc["bt_extra_connect_policy"] = value(self.bt_extra_connect_policy.to_string());
+        doc["bt_allowlist"] = value(self.bt_allowlist.to_string());
+        doc["bt_readvertise"] = value(self.bt_readvertise);
+        doc["bt_pairing_policy"] = value(self.bt_pairing_policy.to_string());
+        doc["bt_pairing_pin"] = value(&self.bt_pairing_pin);
+        doc["bt_hsp_teardown_timeout_ms"] = value(self.bt_hsp_teardown_timeout_ms as i64);
+        doc["bt_rfcomm_channel"] = value(self.bt_rfcomm_channel as i64);
+        doc["bt_hsp_rfcomm_channel"] = value(self.bt_hsp_rfcomm_channel as i64);
+        doc["bt_aa_profile_uuid"] = value(self.bt_aa_profile_uuid.to_string());
+        doc["bt_hsp_hs_uuid"] = value(self.bt_hsp_hs_uuid.to_string());
+        doc["bt_hsp_ag_uuid"] = value(self.bt_hsp_ag_uuid.to_string());
         doc["mitm"] = value(self.mitm);
+        doc["mitm_init_policy"] = value(self.mitm_init_policy.to_string());
+        doc["mitm_ssl_prewarm"] = value(self.mitm_ssl_prewarm);
+        doc["min_protocol_version"] = value(
+            self.min_protocol_version
+                .map_or(String::new(), |v| v.to_string()),
+        );
+        doc["max_protocol_version"] = value(
+            self.max_protocol_version
+                .map_or(String::new(), |v| v.to_string()),
+        );
         doc["dpi"] = value(self.dpi as i64);
+        doc["dpi_by_model"] = value(self.dpi_by_model.to_string());
         doc["audio_max_unacked"] = value(self.audio_max_unacked as i64);
         doc["add_vendor_channel"] = value(self.add_vendor_channel);
         doc["remove_tap_restriction"] = value(self.remove_tap_restriction);
         doc["video_in_motion"] = value(self.video_in_motion);
         doc["disable_media_sink"] = value(self.disable_media_sink);
         doc["disable_tts_sink"] = value(self.disable_tts_sink);
+        doc["video_fps_cap"] = value(self.video_fps_cap as i64);
         doc["developer_mode"] = value(self.developer_mode);
         doc["wired"] = value(self.wired.as_ref().map_or(String::new(), |w| w.to_string()));
         doc["dhu"] = value(self.dhu);
+        doc["hu_usb_transport"] = value(self.hu_usb_transport.to_string());
+        doc["functionfs_dir"] = value(self.functionfs_dir.display().to_string());
+        doc["usb_accessory_path"] = value(self.usb_accessory_path.display().to_string());
+        doc["hu_usb_keep_open"] = value(self.hu_usb_keep_open);
+        doc["md_quick_reconnect_secs"] = value(self.md_quick_reconnect_secs as i64);
+        doc["usb_accessory_open_retries"] = value(self.usb_accessory_open_retries as i64);
+        doc["usb_accessory_open_retry_ms"] = value(self.usb_accessory_open_retry_ms as i64);
+        doc["cooperative_shutdown"] = value(self.cooperative_shutdown);
+        doc["capture_dir"] = value(
+            self.capture_dir
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["last_known_good_file"] = value(
+            self.last_known_good_file
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["dhu_replay_file"] = value(
+            self.dhu_replay_file
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["dhu_replay_fast"] = value(self.dhu_replay_fast);
+        doc["dhu_fallback_secs"] = value(self.dhu_fallback_secs as i64);
+        doc["dhu_mirrors"] = value(self.dhu_mirrors as i64);
+        doc["dhu_tls_cert"] = value(
+            self.dhu_tls_cert
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["dhu_tls_key"] = value(
+            self.dhu_tls_key
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["dhu_tls_client_ca"] = value(
+            self.dhu_tls_client_ca
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["dhu_unix_socket"] = value(
+            self.dhu_unix_socket
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["md_unix_socket"] = value(
+            self.md_unix_socket
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
         doc["aa_server_tcp_addr"] = value(self.aa_server_tcp_addr.to_string());
         doc["ev"] = value(self.ev);
         doc["odometer"] = value(self.odometer);
         doc["tire_pressure"] = value(self.tire_pressure);
+        doc["sensor_injection"] = value(self.sensor_injection);
+        doc["sensor_nmea_source"] = value(
+            self.sensor_nmea_source
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
         doc["remove_bluetooth"] = value(self.remove_bluetooth);
         doc["remove_wifi"] = value(self.remove_wifi);
         doc["inject_display_types"] = value(self.inject_display_types.to_string());
+        doc["sdr_service_allow"] = value(self.sdr_service_allow.to_string());
+        doc["sdr_service_deny"] = value(self.sdr_service_deny.to_string());
         doc["inject_add_input_sources"] = value(self.inject_add_input_sources);
         doc["inject_cluster_display_id"] = value(self.inject_cluster_display_id as i64);
         doc["inject_cluster_width_margin"] = value(self.inject_cluster_width_margin as i64);
@@ -870,10 +1741,21 @@ impl AppConfig {
         doc["usb_serial_console"] = value(self.usb_serial_console);
         doc["wifi_version"] = value(self.wifi_version as i64);
         doc["band"] = value(self.band.to_string());
+        doc["band_check"] = value(self.band_check.to_string());
         doc["country_code"] = value(&self.country_code);
         doc["channel"] = value(self.channel as i64);
+        doc["wifi_announce_band"] = value(self.wifi_announce_band);
         doc["ssid"] = value(&self.ssid);
         doc["wpa_passphrase"] = value(&self.wpa_passphrase);
+        doc["wifi_credentials_file"] = value(
+            self.wifi_credentials_file
+                .as_ref()
+                .map_or(String::new(), |p| p.display().to_string()),
+        );
+        doc["wpa_key_policy"] = value(self.wpa_key_policy.to_string());
+        doc["wifi_security"] = value(self.wifi_security.to_string());
+        doc["wifi_start_extra_fields"] = value(self.wifi_start_extra_fields.to_string());
+        doc["wifi_info_extra_fields"] = value(self.wifi_info_extra_fields.to_string());
         doc["eth_mode"] = value(&self.eth_mode);
         doc["startup_delay"] = value(self.startup_delay as i64);
         doc["ble_password"] = value(&self.ble_password);
@@ -883,12 +1765,16 @@ impl AppConfig {
         }
         doc["media_wait_for_live_idr"] = value(self.media_wait_for_live_idr);
         doc["collect_speed"] = value(self.collect_speed);
+        doc["motion_stats"] = value(self.motion_stats);
+        doc["video_stats"] = value(self.video_stats);
+        doc["latency_stats"] = value(self.latency_stats);
         doc["disable_driving_status"] = value(self.disable_driving_status);
         if let Some(cmd) = &self.hu_button_handler {
             doc["hu_button_handler"] = value(cmd);
         }
         doc["bt_sco"] = value(self.bt_sco);
         doc["bt_sco_keep_bluetooth_alive"] = value(self.bt_sco_keep_bluetooth_alive);
+        doc["bt_sco_codec"] = value(self.bt_sco_codec.to_string());
         doc["bt_sco_media_bridge"] = value(self.bt_sco_media_bridge);
         doc["bt_sco_media_bridge_audio_type"] =
             value(self.bt_sco_media_bridge_audio_type.to_string());
@@ -940,3 +1826,25 @@ impl AppConfig {
         Ok(parsed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_config_hides_secrets() {
+        let cfg = AppConfig {
+            wpa_passphrase: "hunter2-wifi".to_string(),
+            ble_password: "hunter2-ble".to_string(),
+            ..Default::default()
+        };
+        let text = cfg.redacted();
+        assert!(!text.contains("hunter2"));
+        assert!(text.contains(&format!("wpa_passphrase = \"{}\"", REDACTED)));
+        assert!(text.contains("wpa_key_policy = "));
+        assert!(!text.contains(&format!("wpa_key_policy = \"{}\"", REDACTED)));
+
+        assert!(is_secret_key("pairing_pin"));
+        assert!(!is_secret_key("pinned_channels"));
+    }
+}
@@ -2,7 +2,7 @@ use crate::mitm;
 use crate::mitm::protos::DisplayType;
 use crate::mitm::protos::EvConnectorType;
 use crate::mitm::protos::VideoCodecResolutionType;
-use bluer::Address;
+use bluer::{Address, Uuid};
 use serde::{
     de::{self, Visitor},
     Deserialize, Deserializer, Serialize, Serializer,
@@ -319,6 +319,213 @@ impl fmt::Display for InjectDisplayTypes {
     }
 }
 
+/// Comma-separated list of service (channel) ids from the ServiceDiscoveryResponse
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ServiceIdList(pub Option<Vec<i32>>);
+
+impl ServiceIdList {
+    fn to_string_internal(&self) -> String {
+        match &self.0 {
+            Some(ids) => ids
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+            None => "".to_string(),
+        }
+    }
+
+    pub fn contains(&self, id: i32) -> bool {
+        self.0.as_ref().is_some_and(|ids| ids.contains(&id))
+    }
+}
+
+impl<'de> Deserialize<'de> for ServiceIdList {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let mut ids = Vec::new();
+        if !s.is_empty() {
+            for part in s.split(',') {
+                let trimmed = part.trim();
+                if !trimmed.is_empty() {
+                    let id = trimmed.parse::<i32>().map_err(de::Error::custom)?;
+                    if !ids.contains(&id) {
+                        ids.push(id);
+                    }
+                }
+            }
+        }
+
+        if ids.is_empty() {
+            Ok(ServiceIdList(None))
+        } else {
+            Ok(ServiceIdList(Some(ids)))
+        }
+    }
+}
+
+impl Serialize for ServiceIdList {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let s = self.to_string_internal();
+        serializer.serialize_str(&s)
+    }
+}
+
+impl fmt::Display for ServiceIdList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.to_string_internal();
+        write!(f, "{}", s)
+    }
+}
+
+/// `field=value` pairs added to an outgoing protobuf message, where `field`
+/// is the number of a field which is not part of the compiled message.
+/// Numeric values are encoded as varints, anything else as a string.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtraProtoFields(pub Option<Vec<(String, String)>>);
+
+impl ExtraProtoFields {
+    fn to_string_internal(&self) -> String {
+        match &self.0 {
+            Some(fields) => fields
+                .iter()
+                .map(|(field, value)| format!("{}={}", field, value))
+                .collect::<Vec<String>>()
+                .join(","),
+            None => "".to_string(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.0.iter().flatten()
+    }
+}
+
+impl<'de> Deserialize<'de> for ExtraProtoFields {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let mut fields = Vec::new();
+        for part in s.split(',') {
+            let trimmed = part.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            let (field, value) = trimmed
+                .split_once('=')
+                .ok_or_else(|| de::Error::custom(format!("expected field=value: {}", trimmed)))?;
+            fields.push((field.trim().to_string(), value.trim().to_string()));
+        }
+
+        if fields.is_empty() {
+            Ok(ExtraProtoFields(None))
+        } else {
+            Ok(ExtraProtoFields(Some(fields)))
+        }
+    }
+}
+
+impl Serialize for ExtraProtoFields {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let s = self.to_string_internal();
+        serializer.serialize_str(&s)
+    }
+}
+
+impl fmt::Display for ExtraProtoFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.to_string_internal();
+        write!(f, "{}", s)
+    }
+}
+
+/// `model=dpi` pairs: DPI to use for a head unit model, as reported in the
+/// ServiceDiscoveryResponse. Models are matched case-insensitively.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelDpiMap(pub Option<Vec<(String, u16)>>);
+
+impl ModelDpiMap {
+    fn to_string_internal(&self) -> String {
+        match &self.0 {
+            Some(entries) => entries
+                .iter()
+                .map(|(model, dpi)| format!("{}={}", model, dpi))
+                .collect::<Vec<String>>()
+                .join(","),
+            None => "".to_string(),
+        }
+    }
+
+    /// DPI configured for `model`, if any
+    pub fn get(&self, model: &str) -> Option<u16> {
+        let model = model.trim();
+        self.0
+            .iter()
+            .flatten()
+            .find(|(m, _)| m.eq_ignore_ascii_case(model))
+            .map(|(_, dpi)| *dpi)
+    }
+}
+
+impl<'de> Deserialize<'de> for ModelDpiMap {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let mut entries = Vec::new();
+        for part in s.split(',') {
+            let trimmed = part.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            // model names may contain `=`, the DPI can't
+            let (model, dpi) = trimmed
+                .rsplit_once('=')
+                .ok_or_else(|| de::Error::custom(format!("expected model=dpi: {}", trimmed)))?;
+            let dpi = dpi
+                .trim()
+                .parse::<u16>()
+                .map_err(|e| de::Error::custom(format!("invalid DPI for {}: {}", model, e)))?;
+            entries.push((model.trim().to_string(), dpi));
+        }
+
+        if entries.is_empty() {
+            Ok(ModelDpiMap(None))
+        } else {
+            Ok(ModelDpiMap(Some(entries)))
+        }
+    }
+}
+
+impl Serialize for ModelDpiMap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let s = self.to_string_internal();
+        serializer.serialize_str(&s)
+    }
+}
+
+impl fmt::Display for ModelDpiMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.to_string_internal();
+        write!(f, "{}", s)
+    }
+}
+
 #[derive(
     clap::ValueEnum, Default, Debug, PartialEq, PartialOrd, Clone, Copy, Deserialize, Serialize,
 )]
@@ -384,6 +591,149 @@ impl<'de> Deserialize<'de> for UsbId {
     }
 }
 
+/// Android Auto protocol version as `major.minor`, ordered by major then minor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl std::str::FromStr for ProtocolVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (major, minor) = s
+            .trim()
+            .split_once('.')
+            .ok_or_else(|| "Expected format MAJOR.MINOR".to_string())?;
+        let major = major.parse::<u16>().map_err(|e| e.to_string())?;
+        let minor = minor.parse::<u16>().map_err(|e| e.to_string())?;
+        Ok(ProtocolVersion { major, minor })
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Bluetooth service UUID overriding a built-in profile UUID; empty keeps the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProfileUuid(pub Option<Uuid>);
+
+impl ProfileUuid {
+    /// the configured UUID, or `default` when unset
+    pub fn or(&self, default: Uuid) -> Uuid {
+        self.0.unwrap_or(default)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProfileUuid {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let trimmed = s.trim();
+        if trimmed.is_empty() {
+            return Ok(ProfileUuid(None));
+        }
+        trimmed
+            .parse::<Uuid>()
+            .map(|uuid| ProfileUuid(Some(uuid)))
+            .map_err(|e| de::Error::custom(format!("invalid profile UUID '{}': {}", trimmed, e)))
+    }
+}
+
+impl Serialize for ProfileUuid {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for ProfileUuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(uuid) => write!(f, "{}", uuid),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Timeouts of the bluetooth handshake stages in seconds: a single value applies
+/// to every stage, a list gives the timeout of stage 1, 2, ... in order.
+/// 0 (or a stage missing from the list) waits without a timeout.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StageTimeouts(pub Option<Vec<u16>>);
+
+impl StageTimeouts {
+    fn to_string_internal(&self) -> String {
+        match &self.0 {
+            Some(secs) => secs
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+            None => "".to_string(),
+        }
+    }
+
+    /// timeout of the 1-based handshake `stage`, if any
+    pub fn get(&self, stage: u8) -> Option<std::time::Duration> {
+        let secs = match self.0.as_deref()? {
+            [all] => *all,
+            list => *list.get(usize::from(stage).checked_sub(1)?)?,
+        };
+        (secs > 0).then(|| std::time::Duration::from_secs(secs.into()))
+    }
+}
+
+impl<'de> Deserialize<'de> for StageTimeouts {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let mut secs = Vec::new();
+        for part in s.split(',') {
+            let trimmed = part.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            secs.push(trimmed.parse::<u16>().map_err(|e| {
+                de::Error::custom(format!("invalid stage timeout {}: {}", trimmed, e))
+            })?);
+        }
+
+        if secs.is_empty() {
+            Ok(StageTimeouts(None))
+        } else {
+            Ok(StageTimeouts(Some(secs)))
+        }
+    }
+}
+
+impl Serialize for StageTimeouts {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let s = self.to_string_internal();
+        serializer.serialize_str(&s)
+    }
+}
+
+impl fmt::Display for StageTimeouts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.to_string_internal();
+        write!(f, "{}", s)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -430,4 +780,84 @@ mod tests {
             serde_json::to_string(&value).expect("serialize inject cluster codec resolution");
         assert_eq!(serialized, "\"VIDEO_1920x1080\"");
     }
+
+    #[test]
+    fn protocol_version_parses_and_orders() {
+        let v: ProtocolVersion = "1.7".parse().expect("valid protocol version");
+        assert_eq!(v, ProtocolVersion { major: 1, minor: 7 });
+        assert_eq!(v.to_string(), "1.7");
+        assert!(
+            ProtocolVersion {
+                major: 1,
+                minor: 10
+            } > v
+        );
+        assert!(ProtocolVersion { major: 2, minor: 0 } > v);
+        assert!("1".parse::<ProtocolVersion>().is_err());
+    }
+
+    #[test]
+    fn extra_proto_fields_parse_pairs_and_reject_malformed() {
+        let parsed: ExtraProtoFields =
+            serde_json::from_str("\" 3=1, 4=mode \"").expect("valid extra fields");
+        assert_eq!(
+            parsed,
+            ExtraProtoFields(Some(vec![
+                ("3".to_string(), "1".to_string()),
+                ("4".to_string(), "mode".to_string()),
+            ]))
+        );
+        assert_eq!(parsed.to_string(), "3=1,4=mode");
+        assert!(serde_json::from_str::<ExtraProtoFields>("\"3\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<ExtraProtoFields>("\"\"").unwrap(),
+            ExtraProtoFields(None)
+        );
+    }
+
+    #[test]
+    fn model_dpi_map_parse_and_lookup() {
+        let parsed: ModelDpiMap =
+            serde_json::from_str("\"Sync 3=160, MIB2 =140\"").expect("valid model dpi map");
+        assert_eq!(parsed.get("sync 3"), Some(160));
+        assert_eq!(parsed.get("MIB2"), Some(140));
+        assert_eq!(parsed.get("Uconnect"), None);
+        assert_eq!(parsed.to_string(), "Sync 3=160,MIB2=140");
+        assert!(serde_json::from_str::<ModelDpiMap>("\"MIB2=high\"").is_err());
+        assert!(serde_json::from_str::<ModelDpiMap>("\"MIB2\"").is_err());
+    }
+
+    #[test]
+    fn profile_uuid_parse_and_reject_malformed() {
+        let parsed: ProfileUuid =
+            serde_json::from_str("\" 4de17a00-52cb-11e6-bdf4-0800200c9a66 \"")
+                .expect("valid profile uuid");
+        assert_eq!(
+            parsed.0,
+            Some(Uuid::from_u128(0x4de17a0052cb11e6bdf40800200c9a66))
+        );
+        assert_eq!(parsed.to_string(), "4de17a00-52cb-11e6-bdf4-0800200c9a66");
+        assert_eq!(
+            serde_json::from_str::<ProfileUuid>("\"\"").unwrap(),
+            ProfileUuid(None)
+        );
+        assert!(serde_json::from_str::<ProfileUuid>("\"4de17a00-52cb\"").is_err());
+    }
+
+    #[test]
+    fn stage_timeouts_single_value_or_list() {
+        let all: StageTimeouts = serde_json::from_str("\"10\"").expect("valid stage timeout");
+        assert_eq!(all.get(1), Some(std::time::Duration::from_secs(10)));
+        assert_eq!(all.get(5), Some(std::time::Duration::from_secs(10)));
+
+        let list: StageTimeouts =
+            serde_json::from_str("\"5, 0, 5\"").expect("valid stage timeout list");
+        assert_eq!(list.get(1), Some(std::time::Duration::from_secs(5)));
+        assert_eq!(list.get(2), None);
+        assert_eq!(list.get(4), None);
+        assert_eq!(list.to_string(), "5,0,5");
+
+        assert_eq!(StageTimeouts::default().get(1), None);
+        assert!(serde_json::from_str::<StageTimeouts>("\"5,x\"").is_err());
+    }
 }
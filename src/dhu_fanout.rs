@@ -0,0 +1,119 @@
+//! Development only: mirroring the MD stream to additional DHU emulators.
+//!
+//! With `dhu_mirrors` set, extra DHU clients are accepted on the DHU port after
+//! the first one (the primary). Every DHU gets its own HU side proxy with its
+//! own SSL session, so each of them receives the decrypted MD stream
+//! re-encrypted for it. The sensor/input channels and SDR learned by a mirror's
+//! proxy are its own; only the primary's are shared with the rest of the proxy.
+//!
+//! Ordering and merge semantics:
+//! - Packets from the MD are delivered to every DHU in the same order. The
+//!   primary is never slowed down by a mirror: a mirror whose queue is full
+//!   loses the packet.
+//! - The primary drives the session: everything it sends goes to the MD as
+//!   usual (version and SSL handshake, service discovery, channel opens, acks).
+//! - From a mirror only complete (single frame) input reports on the input
+//!   channel are forwarded; all its other frames are dropped, so the phone sees
+//!   exactly one head unit.
+//! - Competing input is merged by arrival time at the proxy, whole frames at a
+//!   time; input reports from different DHUs are never interleaved within a
+//!   frame, but there is no arbitration between them (e.g. concurrent touch
+//!   gestures on two DHUs are both forwarded as they come).
+use crate::mitm::protos::InputMessageId::INPUT_MESSAGE_INPUT_REPORT;
+use crate::mitm::{Packet, FRAME_TYPE_MASK};
+use simplelog::*;
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Mutex;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> dhu-fanout: </>";
+
+// Just a generic Result type to ease error handling for us. Errors in multithreaded
+// async contexts needs some extra restrictions
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Clones every packet headed to the HU to the mirrors, then passes it to the primary.
+pub async fn fan_out(
+    mut rx: Receiver<Packet>,
+    primary: Sender<Packet>,
+    mirrors: Vec<Sender<Packet>>,
+) -> Result<()> {
+    let mut dropped = vec![0u64; mirrors.len()];
+    while let Some(pkt) = rx.recv().await {
+        for (i, mirror) in mirrors.iter().enumerate() {
+            match mirror.try_send(pkt.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    dropped[i] += 1;
+                    if dropped[i].is_power_of_two() {
+                        warn!(
+                            "{} mirror #{} is too slow, {} packets dropped",
+                            NAME,
+                            i + 1,
+                            dropped[i]
+                        );
+                    }
+                }
+                // the mirror is gone, the others go on
+                Err(TrySendError::Closed(_)) => {}
+            }
+        }
+        primary.send(pkt).await?;
+    }
+    Ok(())
+}
+
+/// Forwards the input reports of a mirror to the MD, dropping everything else.
+pub async fn merge_input(
+    mut rx: Receiver<Packet>,
+    tx: Sender<Packet>,
+    input_channel: Arc<Mutex<Option<u8>>>,
+) -> Result<()> {
+    while let Some(pkt) = rx.recv().await {
+        let input_channel = *input_channel.lock().await;
+        if is_input_report(&pkt, input_channel) {
+            tx.send(pkt).await?;
+        }
+    }
+    Ok(())
+}
+
+/// a complete input report on the input channel
+fn is_input_report(pkt: &Packet, input_channel: Option<u8>) -> bool {
+    Some(pkt.channel) == input_channel
+        && pkt.flags & FRAME_TYPE_MASK == FRAME_TYPE_MASK
+        && pkt.payload.get(0..2) == Some(&(INPUT_MESSAGE_INPUT_REPORT as u16).to_be_bytes()[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mitm::{ENCRYPTED, FRAME_TYPE_FIRST};
+
+    fn packet(channel: u8, flags: u8, message_id: u16) -> Packet {
+        Packet {
+            channel,
+            flags,
+            final_length: None,
+            payload: message_id.to_be_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn only_whole_input_reports_are_merged() {
+        let report = INPUT_MESSAGE_INPUT_REPORT as u16;
+        let whole = ENCRYPTED | FRAME_TYPE_MASK;
+        assert!(is_input_report(&packet(5, whole, report), Some(5)));
+        // other channel, unknown input channel, fragment, other message
+        assert!(!is_input_report(&packet(4, whole, report), Some(5)));
+        assert!(!is_input_report(&packet(5, whole, report), None));
+        assert!(!is_input_report(
+            &packet(5, ENCRYPTED | FRAME_TYPE_FIRST, report),
+            Some(5)
+        ));
+        // e.g. a ChannelOpenResponse
+        assert!(!is_input_report(&packet(5, whole, 0x0008), Some(5)));
+    }
+}
@@ -0,0 +1,310 @@
+//! Optional TLS termination in front of the DHU (Desktop Head Unit emulator) listener.
+//!
+//! When a certificate and key are configured, every DHU client is accepted here:
+//! the TLS session is terminated and the decrypted stream relayed to one end of
+//! a socket pair, whose other end is handed to the proxy. Unlike a plaintext
+//! listener, a socket pair can't be connected to by another process, so TLS and
+//! the client certificate check can't be bypassed. The proxy uses its Unix
+//! stream transfer path for it.
+//!
+//! The TLS layer is not added to the transfer path itself: its devices hand
+//! owned buffers straight to io_uring reads and writes, and a TLS record layer in
+//! between would have to be threaded through every transfer of the copy loop (and
+//! the MITM frame handling) for this one listener. OpenSSL is used rather than
+//! rustls because it's already built (vendored) for the MITM of the Android Auto
+//! SSL handshake; rustls would be a second TLS stack in the image.
+use openssl::ssl::{ErrorCode, Ssl, SslAcceptor, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+use simplelog::*;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+use tokio::sync::mpsc::Sender;
+
+use crate::io_uring::TcpKeepalive;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> dhu-tls: </>";
+
+// Just a generic Result type to ease error handling for us. Errors in multithreaded
+// async contexts needs some extra restrictions
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+const BUFFER_LEN: usize = 16 * 1024;
+/// first delay after a failed accept, doubled up to `MAX_ACCEPT_RETRY_DELAY`; errors
+/// like EMFILE persist until a descriptor is released, retrying right away would spin
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Memory BIO for `SslStream`: ciphertext received from the DHU is queued in
+/// `incoming`, ciphertext produced by OpenSSL is collected in `outgoing`.
+/// An empty `incoming` reports `WouldBlock`, so OpenSSL asks for more data
+/// (`WANT_READ`) instead of treating it as EOF.
+#[derive(Default)]
+struct TlsBuf {
+    incoming: VecDeque<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Read for TlsBuf {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.incoming.is_empty() {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        self.incoming.read(buf)
+    }
+}
+
+impl Write for TlsBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.outgoing.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Builds the TLS acceptor from PEM files. With `client_ca` set, the DHU has to
+/// present a certificate signed by one of its CAs.
+pub fn acceptor(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<SslAcceptor> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+    builder.set_certificate_chain_file(cert)?;
+    builder.set_private_key_file(key, SslFiletype::PEM)?;
+    builder.check_private_key()?;
+    if let Some(ca) = client_ca {
+        builder.set_ca_file(ca)?;
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    Ok(builder.build())
+}
+
+/// Accepts TLS clients on `listener` and hands each of them to the proxy through
+/// `clients`, as the proxy's end of a socket pair the decrypted stream is
+/// relayed to. The socket options are applied to the DHU connection.
+pub async fn serve(
+    listener: TcpListener,
+    clients: Sender<StdUnixStream>,
+    acceptor: SslAcceptor,
+    keepalive: Option<TcpKeepalive>,
+) {
+    let mut retry_delay = ACCEPT_RETRY_DELAY;
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(client) => {
+                retry_delay = ACCEPT_RETRY_DELAY;
+                client
+            }
+            Err(e) => {
+                error!(
+                    "{} accept failed: {}, retrying in {} ms",
+                    NAME,
+                    e,
+                    retry_delay.as_millis()
+                );
+                tokio::time::sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(MAX_ACCEPT_RETRY_DELAY);
+                continue;
+            }
+        };
+        if let Err(e) = stream.set_nodelay(true) {
+            warn!("{} DHU <u>{}</u>: TCP_NODELAY: {}", NAME, addr, e);
+        }
+        if let Some(Err(e)) = keepalive.map(|keepalive| keepalive.apply(&stream)) {
+            warn!("{} DHU <u>{}</u>: TCP keepalive: {}", NAME, addr, e);
+        }
+        let ssl = match Ssl::new(acceptor.context()) {
+            Ok(ssl) => ssl,
+            Err(e) => {
+                error!("{} unable to create SSL session: {}", NAME, e);
+                continue;
+            }
+        };
+        let clients = clients.clone();
+        tokio::spawn(async move {
+            if let Err(e) = relay(stream, addr, ssl, clients).await {
+                warn!("{} 🔒 DHU <u>{}</u>: {}", NAME, addr, e);
+            }
+        });
+    }
+}
+
+/// writes the pending ciphertext to the DHU
+async fn flush(ssl: &mut SslStream<TlsBuf>, stream: &mut TcpStream) -> Result<()> {
+    let outgoing = std::mem::take(&mut ssl.get_mut().outgoing);
+    if !outgoing.is_empty() {
+        stream.write_all(&outgoing).await?;
+    }
+    Ok(())
+}
+
+async fn relay(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    ssl: Ssl,
+    clients: Sender<StdUnixStream>,
+) -> Result<()> {
+    let mut ssl = SslStream::new(ssl, TlsBuf::default())?;
+    let mut tls_buf = vec![0u8; BUFFER_LEN];
+
+    // TLS handshake
+    loop {
+        match ssl.accept() {
+            Ok(()) => break,
+            Err(e) if e.code() == ErrorCode::WANT_READ => {
+                flush(&mut ssl, &mut stream).await?;
+                let n = stream.read(&mut tls_buf).await?;
+                if n == 0 {
+                    return Err("connection closed during TLS handshake".into());
+                }
+                ssl.get_mut().incoming.extend(&tls_buf[..n]);
+            }
+            Err(e) => {
+                // send a possible alert before giving up
+                let _ = flush(&mut ssl, &mut stream).await;
+                return Err(format!("TLS handshake failed: {}", e).into());
+            }
+        }
+    }
+    flush(&mut ssl, &mut stream).await?;
+    info!(
+        "{} 🔒 DHU <u>{}</u> connected, negotiated: <b>{}</> <b><blue>{}</>",
+        NAME,
+        addr,
+        ssl.ssl().version_str(),
+        ssl.ssl()
+            .current_cipher()
+            .map_or("unknown", |cipher| cipher.name()),
+    );
+
+    let (ours, theirs) = StdUnixStream::pair()?;
+    ours.set_nonblocking(true)?;
+    let mut plain = UnixStream::from_std(ours)?;
+    clients
+        .send(theirs)
+        .await
+        .map_err(|_| "the proxy doesn't accept DHU clients anymore")?;
+    let mut plain_buf = vec![0u8; BUFFER_LEN];
+    let mut decrypted = vec![0u8; BUFFER_LEN];
+    loop {
+        tokio::select! {
+            n = stream.read(&mut tls_buf) => {
+                let n = n?;
+                if n == 0 {
+                    break;
+                }
+                ssl.get_mut().incoming.extend(&tls_buf[..n]);
+                loop {
+                    match ssl.ssl_read(&mut decrypted) {
+                        Ok(n) => plain.write_all(&decrypted[..n]).await?,
+                        Err(e) if e.code() == ErrorCode::WANT_READ => break,
+                        Err(e) if e.code() == ErrorCode::ZERO_RETURN => {
+                            debug!("{} DHU <u>{}</u> sent close_notify", NAME, addr);
+                            return Ok(());
+                        }
+                        Err(e) => return Err(Box::new(e)),
+                    }
+                }
+                // post-handshake messages (e.g. key updates) may need a reply
+                flush(&mut ssl, &mut stream).await?;
+            }
+            n = plain.read(&mut plain_buf) => {
+                let n = n?;
+                if n == 0 {
+                    break;
+                }
+                ssl.ssl_write(&plain_buf[..n])?;
+                flush(&mut ssl, &mut stream).await?;
+            }
+        }
+    }
+    let _ = ssl.shutdown();
+    let _ = flush(&mut ssl, &mut stream).await;
+    debug!("{} DHU <u>{}</u> relay closed", NAME, addr);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::SslConnector;
+    use openssl::x509::{X509NameBuilder, X509};
+
+    /// writes a self-signed certificate and its key as PEM files
+    fn self_signed(dir: &Path) -> (std::path::PathBuf, std::path::PathBuf) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, cert.build().to_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[tokio::test]
+    async fn tls_client_is_handed_to_the_proxy_through_a_socket_pair() {
+        let dir = std::env::temp_dir().join(format!("aa-proxy-dhu-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = self_signed(&dir);
+        let acceptor = acceptor(&cert, &key, None).unwrap();
+
+        // the proxy's side echoing everything back
+        let (clients_tx, mut clients_rx) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(async move {
+            let s = clients_rx.recv().await.unwrap();
+            s.set_nonblocking(true).unwrap();
+            let mut s = UnixStream::from_std(s).unwrap();
+            let mut buf = [0u8; 64];
+            loop {
+                let n = s.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                s.write_all(&buf[..n]).await.unwrap();
+            }
+        });
+
+        let front = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let front_addr = front.local_addr().unwrap();
+        tokio::spawn(serve(front, clients_tx, acceptor, None));
+
+        let echoed = tokio::task::spawn_blocking(move || {
+            let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+            connector.set_ca_file(&cert).unwrap();
+            let stream = std::net::TcpStream::connect(front_addr).unwrap();
+            let mut tls = connector.build().connect("localhost", stream).unwrap();
+            tls.write_all(b"hello dhu").unwrap();
+            let mut buf = [0u8; 9];
+            tls.read_exact(&mut buf).unwrap();
+            buf
+        })
+        .await
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(&echoed, b"hello dhu");
+    }
+}
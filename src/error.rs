@@ -0,0 +1,163 @@
+//! Error type returned by the public entry points of the proxy
+//! ([`io_loop`](crate::io_uring::io_loop) and
+//! [`Bluetooth::aa_handshake`](crate::bluetooth::Bluetooth::aa_handshake)).
+//!
+//! Internally the modules keep using boxed errors; failures worth telling apart
+//! are boxed as a [`ProxyError`] where they happen and recovered by the
+//! `From<Box<dyn Error>>` conversion at the public boundary, so `?` keeps
+//! working everywhere.
+use crate::bluetooth::PhoneDisconnected;
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use thiserror::Error;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+#[derive(Error, Debug)]
+pub enum ProxyError {
+    /// a shutdown interrupted the wait for the phone to connect
+    #[error("bluetooth handshake cancelled by shutdown")]
+    HandshakeCancelled,
+    #[error(
+        "stage #{stage} of {stages}: timed out after {} s waiting for {frame} frame",
+        .after.as_secs()
+    )]
+    HandshakeTimeout {
+        stage: u8,
+        stages: u8,
+        frame: String,
+        after: Duration,
+    },
+    #[error(transparent)]
+    PhoneDisconnected(#[from] PhoneDisconnected),
+    /// an I/O error after part of a handshake frame was read; retrying would
+    /// leave the stream out of sync
+    #[error("stage #{stage} of {stages}: frame read failed after {received} bytes: {source}")]
+    FrameInterrupted {
+        stage: u8,
+        stages: u8,
+        received: usize,
+        source: io::Error,
+    },
+    /// the phone reported in its `WifiConnectStatus` that it cannot join the WiFi AP
+    #[error("phone cannot connect to our WiFi AP: {reason}")]
+    WifiRejected { status: Option<i64>, reason: String },
+    #[error("invalid WiFi configuration: {0}")]
+    InvalidWifiConfig(String),
+    #[error("MITM initialization failed: {0}")]
+    MitmInit(String),
+    #[error("TCP accept failed: {0}")]
+    TcpAccept(io::Error),
+    /// a listener can't be bound, e.g. no interface has the configured address
+    #[error("cannot bind the {listener} listener to {addr}: {source}")]
+    ListenerBind {
+        listener: &'static str,
+        addr: SocketAddr,
+        source: io::Error,
+    },
+    /// the USB accessory device node doesn't exist
+    #[error("USB accessory device {path} doesn't exist, the USB gadget (f_accessory function) isn't configured")]
+    UsbGadgetNotConfigured { path: String },
+    #[error("cannot open USB device {path}: {source}")]
+    UsbOpen { path: String, source: io::Error },
+    /// no data transferred within the stall timeout; the stalled directions
+    /// with the time since their last progress
+    #[error(
+        "unexpected transfer stall: {}",
+        stalled_directions(.phone_to_car, .car_to_phone)
+    )]
+    TransferStall {
+        phone_to_car: Option<Duration>,
+        car_to_phone: Option<Duration>,
+    },
+    /// the phone -> car throughput stayed below the watchdog minimum
+    #[error(
+        "throughput below the watchdog minimum for {intervals} intervals, last: {bytes_per_sec} bytes/s"
+    )]
+    LowThroughput { bytes_per_sec: u64, intervals: u8 },
+    #[error("bluetooth error: {0}")]
+    Bluetooth(#[from] bluer::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Other(BoxError),
+}
+
+fn stalled_directions(phone_to_car: &Option<Duration>, car_to_phone: &Option<Duration>) -> String {
+    [
+        ("phone -> car", *phone_to_car),
+        ("car -> phone", *car_to_phone),
+    ]
+    .into_iter()
+    .filter_map(|(direction, idle)| {
+        idle.map(|idle| format!("{} (no data for {} s)", direction, idle.as_secs()))
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+impl ProxyError {
+    /// wraps any other error, e.g. for `.map_err(ProxyError::other)?`
+    pub fn other(err: impl Into<BoxError>) -> Self {
+        ProxyError::from(err.into())
+    }
+}
+
+impl From<BoxError> for ProxyError {
+    /// recovers the [`ProxyError`] (or a known error type) from a boxed error
+    fn from(err: BoxError) -> Self {
+        let err = match err.downcast::<ProxyError>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<PhoneDisconnected>() {
+            Ok(err) => return ProxyError::PhoneDisconnected(*err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<bluer::Error>() {
+            Ok(err) => return ProxyError::Bluetooth(*err),
+            Err(err) => err,
+        };
+        match err.downcast::<io::Error>() {
+            Ok(err) => ProxyError::Io(*err),
+            Err(err) => ProxyError::Other(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxed_errors_are_recovered() {
+        let boxed: BoxError = Box::new(ProxyError::TransferStall {
+            phone_to_car: None,
+            car_to_phone: Some(Duration::from_secs(12)),
+        });
+        let err = ProxyError::from(boxed);
+        assert!(matches!(
+            err,
+            ProxyError::TransferStall {
+                phone_to_car: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "unexpected transfer stall: car -> phone (no data for 12 s)"
+        );
+
+        let boxed: BoxError = Box::new(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(matches!(
+            ProxyError::from(boxed),
+            ProxyError::Io(e) if e.kind() == io::ErrorKind::BrokenPipe
+        ));
+
+        let err = ProxyError::other("handling failed");
+        assert!(matches!(err, ProxyError::Other(_)));
+        assert_eq!(err.to_string(), "handling failed");
+    }
+}
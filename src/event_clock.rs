@@ -0,0 +1,55 @@
+use chrono::{DateTime, Local};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Monotonic start of a connection or session paired with the wall-clock
+/// time it was taken at, for annotating key log events.
+///
+/// The relative time always comes from the monotonic clock; the absolute
+/// timestamp is derived from the pairing, so it is not affected by clock
+/// adjustments (e.g. NTP) happening in the middle of a session.
+#[derive(Debug, Clone, Copy)]
+pub struct EventClock {
+    started: Instant,
+    started_wall: SystemTime,
+    wall_clock: bool,
+}
+
+impl EventClock {
+    /// `wall_clock` enables the absolute timestamps in [`EventClock::stamp`]
+    pub fn new(wall_clock: bool) -> Self {
+        Self {
+            started: Instant::now(),
+            started_wall: SystemTime::now(),
+            wall_clock,
+        }
+    }
+
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// wall-clock time of an instant measured against this clock
+    pub fn wall_time(&self, at: Instant) -> DateTime<Local> {
+        DateTime::<Local>::from(self.started_wall + at.saturating_duration_since(self.started))
+    }
+
+    /// log annotation for the current moment, e.g. `[+1.234s]` or
+    /// `[+1.234s @ 2025-08-25 15:24:43.123]` when absolute timestamps are enabled
+    pub fn stamp(&self) -> String {
+        let now = Instant::now();
+        let relative = now.saturating_duration_since(self.started).as_secs_f64();
+        if self.wall_clock {
+            format!(
+                "[+{:.3}s @ {}]",
+                relative,
+                self.wall_time(now).format("%Y-%m-%d %H:%M:%S%.3f")
+            )
+        } else {
+            format!("[+{:.3}s]", relative)
+        }
+    }
+}
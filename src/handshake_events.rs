@@ -0,0 +1,301 @@
+//! Bluetooth handshake stage events published to local subscribers.
+//!
+//! Clients connected to the Unix socket receive one JSON object per line
+//! for every stage frame sent to or received from the phone, e.g.:
+//! ```json
+//! {"version":1,"stage":2,"stages":5,"message_id":"WifiInfoRequest","direction":"receive","elapsed_ms":412,"result":"ok","error":null}
+//! ```
+//!
+//! Applications embedding the crate can instead pass an `mpsc` channel to
+//! `Bluetooth::aa_handshake` and receive the typed [`HandshakeEvent`]s.
+use bluer::Address;
+use serde::Serialize;
+use simplelog::*;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixListener;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> handshake: </>";
+
+// Just a generic Result type to ease error handling for us. Errors in multithreaded
+// async contexts needs some extra restrictions
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// bumped on incompatible changes of [`StageEvent`]
+pub const SCHEMA_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageResult {
+    Ok,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageEvent {
+    pub version: u8,
+    pub stage: u8,
+    pub stages: u8,
+    pub message_id: String,
+    pub direction: Direction,
+    /// time since the start of the handshake
+    pub elapsed_ms: u64,
+    pub result: StageResult,
+    pub error: Option<String>,
+}
+
+/// Handshake progress sent to the channel given to `Bluetooth::aa_handshake`.
+#[derive(Debug, Clone)]
+pub enum HandshakeEvent {
+    /// the phone connected the AA Wireless profile
+    Connected(Address),
+    StageStarted {
+        stage: u8,
+        stages: u8,
+        message_id: String,
+        direction: Direction,
+    },
+    StageCompleted {
+        stage: u8,
+        stages: u8,
+        message_id: String,
+        direction: Direction,
+        /// time since the start of the handshake
+        elapsed: Duration,
+        error: Option<String>,
+    },
+    /// the phone got the WiFi credentials and reported it joined the WiFi
+    Succeeded,
+    Failed(String),
+}
+
+static EVENTS: OnceLock<broadcast::Sender<StageEvent>> = OnceLock::new();
+
+/// publishes a stage event; no-op until [`serve`] was started
+pub fn publish<T, E: std::fmt::Display>(
+    stage: u8,
+    stages: u8,
+    message_id: impl std::fmt::Debug,
+    direction: Direction,
+    elapsed: Duration,
+    result: &std::result::Result<T, E>,
+) {
+    let Some(tx) = EVENTS.get() else {
+        return;
+    };
+    let _ = tx.send(StageEvent {
+        version: SCHEMA_VERSION,
+        stage,
+        stages,
+        message_id: format!("{:?}", message_id),
+        direction,
+        elapsed_ms: elapsed.as_millis() as u64,
+        result: match result {
+            Ok(_) => StageResult::Ok,
+            Err(_) => StageResult::Error,
+        },
+        error: result.as_ref().err().map(|e| e.to_string()),
+    });
+}
+
+/// sends `event` to the `events` channel (if any); the handshake never waits
+/// for a full channel, the event is dropped instead
+pub fn emit(events: Option<&mpsc::Sender<HandshakeEvent>>, event: HandshakeEvent) {
+    if let Some(tx) = events {
+        if let Err(mpsc::error::TrySendError::Full(event)) = tx.try_send(event) {
+            debug!("{} 🧭 events channel full, dropped {:?}", NAME, event);
+        }
+    }
+}
+
+/// sends the [`HandshakeEvent::StageCompleted`] event of a stage `result`
+pub fn emit_completed<T, E: std::fmt::Display>(
+    events: Option<&mpsc::Sender<HandshakeEvent>>,
+    stage: u8,
+    stages: u8,
+    message_id: impl std::fmt::Debug,
+    direction: Direction,
+    elapsed: Duration,
+    result: &std::result::Result<T, E>,
+) {
+    emit(
+        events,
+        HandshakeEvent::StageCompleted {
+            stage,
+            stages,
+            message_id: format!("{:?}", message_id),
+            direction,
+            elapsed,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        },
+    );
+}
+
+/// sends the terminal [`HandshakeEvent::Succeeded`] or [`HandshakeEvent::Failed`] event
+pub fn emit_result<T, E: std::fmt::Display>(
+    events: Option<&mpsc::Sender<HandshakeEvent>>,
+    result: &std::result::Result<T, E>,
+) {
+    emit(
+        events,
+        match result {
+            Ok(_) => HandshakeEvent::Succeeded,
+            Err(e) => HandshakeEvent::Failed(e.to_string()),
+        },
+    );
+}
+
+/// Sends the terminal event of a handshake exactly once, whether it ends in a
+/// stage or fails earlier, e.g. while waiting for the phone to connect.
+pub struct Outcome {
+    events: Option<mpsc::Sender<HandshakeEvent>>,
+    reported: bool,
+}
+
+impl Outcome {
+    pub fn new(events: Option<mpsc::Sender<HandshakeEvent>>) -> Self {
+        Self {
+            events,
+            reported: false,
+        }
+    }
+
+    /// sends the terminal event of `result`, unless one was already sent
+    pub fn finish<T, E: std::fmt::Display>(&mut self, result: &std::result::Result<T, E>) {
+        if !self.reported {
+            self.reported = true;
+            emit_result(self.events.as_ref(), result);
+        }
+    }
+}
+
+/// Accepts subscribers on the Unix socket at `path` and streams them the events.
+pub async fn serve(path: PathBuf) -> Result<()> {
+    // remove a stale socket left by a previous run
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    let tx = EVENTS.get_or_init(|| broadcast::channel(64).0);
+    info!(
+        "{} 🧭 handshake events socket listening on <b>{}</>",
+        NAME,
+        path.display()
+    );
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut rx = tx.subscribe();
+        debug!("{} 🧭 handshake events subscriber connected", NAME);
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("{} 🧭 slow subscriber: {} events skipped", NAME, n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let mut line = match serde_json::to_string(&event) {
+                    Ok(line) => line,
+                    Err(_) => continue,
+                };
+                line.push('\n');
+                if stream.write_all(line.as_bytes()).await.is_err() {
+                    debug!("{} 🧭 handshake events subscriber disconnected", NAME);
+                    return;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(rx: &mut mpsc::Receiver<HandshakeEvent>) -> Vec<String> {
+        let mut events = vec![];
+        while let Ok(event) = rx.try_recv() {
+            events.push(match event {
+                HandshakeEvent::Connected(_) => "connected".to_string(),
+                HandshakeEvent::StageStarted { stage, .. } => format!("started {}", stage),
+                HandshakeEvent::StageCompleted { stage, error, .. } => {
+                    format!("completed {} {:?}", stage, error)
+                }
+                HandshakeEvent::Succeeded => "succeeded".to_string(),
+                HandshakeEvent::Failed(e) => format!("failed {}", e),
+            });
+        }
+        events
+    }
+
+    #[test]
+    fn failure_before_the_phone_connected_is_terminal() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut outcome = Outcome::new(Some(tx));
+        outcome.finish::<(), _>(&Err("timed out waiting for the phone"));
+        assert_eq!(
+            received(&mut rx),
+            ["failed timed out waiting for the phone"]
+        );
+    }
+
+    #[test]
+    fn stage_result_is_the_only_terminal_event() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let events = Some(tx.clone());
+        let mut outcome = Outcome::new(Some(tx));
+
+        let phone: Address = "AA:BB:CC:DD:EE:01".parse().unwrap();
+        emit(events.as_ref(), HandshakeEvent::Connected(phone));
+        emit(
+            events.as_ref(),
+            HandshakeEvent::StageStarted {
+                stage: 1,
+                stages: 5,
+                message_id: "WifiStartRequest".into(),
+                direction: Direction::Send,
+            },
+        );
+        let res: std::result::Result<(), &str> = Err("phone disconnected");
+        emit_completed(
+            events.as_ref(),
+            1,
+            5,
+            "WifiStartRequest",
+            Direction::Send,
+            Duration::ZERO,
+            &res,
+        );
+        outcome.finish(&res);
+        // the error returned by `aa_handshake` doesn't add a second one
+        outcome.finish::<(), _>(&Err("phone disconnected"));
+        assert_eq!(
+            received(&mut rx),
+            [
+                "connected",
+                "started 1",
+                "completed 1 Some(\"phone disconnected\")",
+                "failed phone disconnected"
+            ]
+        );
+
+        let (tx, mut rx) = mpsc::channel(8);
+        let mut outcome = Outcome::new(Some(tx));
+        outcome.finish::<(), &str>(&Ok(()));
+        outcome.finish::<(), _>(&Err("TCP confirmation failed"));
+        assert_eq!(received(&mut rx), ["succeeded"]);
+    }
+}
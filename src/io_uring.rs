@@ -4,25 +4,32 @@ use crate::script_wasm::ScriptRegistry;
 type ScriptRegistry = ();
 use crate::mitm::SharedServiceDiscoveryResponse;
 use crate::web::ServerEvent;
+use backon::{ExponentialBuilder, Retryable};
 use bytesize::ByteSize;
 use core::net::SocketAddr;
 use humantime::format_duration;
 use mac_address::MacAddress;
+use openssl::ssl::SslAcceptor;
 use simplelog::*;
+use socket2::{Domain, SockRef, Socket, Type};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
 use std::marker::PhantomData;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, BorrowedFd};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::fs::File as TokioFile;
 use tokio::io::{self, copy_bidirectional, AsyncBufReadExt, BufReader};
+use tokio::net::TcpListener as TokioTcpListener;
 use tokio::net::TcpStream as TokioTcpStream;
-use tokio::process::Command;
 use tokio::sync::broadcast::Sender as BroadcastSender;
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::{Receiver, Sender, WeakSender};
 use tokio::sync::{mpsc, Mutex, Notify, RwLock};
 use tokio::task::JoinHandle;
 use tokio::time::{sleep, timeout};
@@ -32,6 +39,7 @@ use tokio_uring::fs::File;
 use tokio_uring::fs::OpenOptions;
 use tokio_uring::net::TcpListener;
 use tokio_uring::net::TcpStream;
+use tokio_uring::net::{UnixListener, UnixStream};
 use tokio_uring::BufResult;
 use tokio_uring::UnsubmittedWrite;
 use tokio_util::sync::CancellationToken;
@@ -43,28 +51,71 @@ const NAME: &str = "<i><bright-black> proxy: </>";
 // async contexts needs some extra restrictions
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
-const USB_ACCESSORY_PATH: &str = "/dev/usb_accessory";
+const FUNCTIONFS_EP_OUT: &str = "ep1";
+const FUNCTIONFS_EP_IN: &str = "ep2";
+/// default read buffer size of both directions (16 KiB)
 pub const BUFFER_LEN: usize = 16 * 1024;
+// accepted range of the per-direction read buffers [KiB]
+const READ_BUFFER_KB_RANGE: std::ops::RangeInclusive<u16> = 1..=1024;
 const TCP_CLIENT_TIMEOUT: Duration = Duration::new(30, 0);
+// pauses between logged idle cycles with `idle_log_backoff`, the last one repeats
+const IDLE_LOG_INTERVALS: [Duration; 3] = [
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(15 * 60),
+];
+// band check reconnects in a row before the phone is left on the wrong band
+const MAX_BAND_RECONNECTS: u8 = 3;
+// how long the cooperative shutdown waits for the session tasks to return
+const SESSION_STOP_TIMEOUT: Duration = Duration::from_secs(2);
 const COMP_APP_TCP_PORT: u16 = 9999;
 const COMP_APP_TCP_PORT_WS: u16 = 9998;
 const COMP_APP_TCP_PORT_SWUPDATE: u16 = 9997;
 // Original queue depth was 10. Keep this small to avoid queue-induced latency.
-const MITM_QUEUE_CAPACITY: usize = 10;
-
-use crate::config::{Action, SharedConfig};
-use crate::config::{TCP_DHU_PORT, TCP_SERVER_PORT};
+// Default of `proxy_queue_capacity`, used as is by the benchmark.
+pub(crate) const MITM_QUEUE_CAPACITY: usize = 10;
+// proxy queues are sampled this often for the saturation warning
+const QUEUE_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+// number of samples per saturation report (10 s)
+const QUEUE_REPORT_SAMPLES: u32 = 100;
+// share of the samples at capacity above which a queue is reported
+const QUEUE_FULL_WARN_RATIO: f64 = 0.1;
+
+use crate::bluetooth;
+use crate::capture::{self, SessionCapture};
+use crate::config::{
+    Action, AppConfig, BandCheck, HuUsbTransport, MitmInitPolicy, SharedConfig, StatsUnits,
+};
+use crate::dhu_fanout;
+use crate::dhu_tls;
+use crate::error::ProxyError;
 use crate::ev::spawn_ev_client_task;
 use crate::ev::BatteryData;
 use crate::ev::EvTaskCommand;
+use crate::event_clock::EventClock;
+use crate::last_known_good::{self, LastKnownGood};
+use crate::latency_stats;
 use crate::mitm::endpoint_reader;
+use crate::mitm::is_version_request;
 use crate::mitm::media_tcp_server;
+use crate::mitm::mitm_init_check;
 use crate::mitm::proxy;
+use crate::mitm::ssl_prewarm;
+use crate::mitm::take_negotiated_protocol_version;
+use crate::mitm::DuplicateFrameDetector;
 use crate::mitm::MediaSink;
 use crate::mitm::Packet;
 use crate::mitm::ProxyType;
+use crate::mitm::{reset_transmission_state, reset_vehicle_motion, vehicle_motion, Motion};
+use crate::mitm::{resume_transfer, set_transfer_pause_limit, transfer_paused};
+use crate::mitm::{set_max_inflight_ops, MIN_INFLIGHT_OPS};
+use crate::sensor_feed;
+use crate::session_summary::{self, Negotiated, SessionEnded};
+use crate::stats_log::StatsLog;
 use crate::usb_stream;
 use crate::usb_stream::{UsbStreamRead, UsbStreamWrite};
+use crate::video_stats;
+use crate::wifi_band;
 
 // tokio_uring::fs::File and tokio_uring::net::TcpStream are using different
 // read and write calls:
@@ -100,26 +151,710 @@ impl Endpoint<TcpStream> for TcpStream {
     }
 }
 
+/// FunctionFS bulk endpoint pair of the HU side gadget function.
+/// Unlike the accessory character device, each direction has its own file.
+pub struct FunctionFs {
+    /// bulk OUT endpoint: data from the HU
+    ep_out: File,
+    /// bulk IN endpoint: data to the HU
+    ep_in: File,
+}
+
+impl FunctionFs {
+    /// Opens `ep1` (OUT) and `ep2` (IN) in the FunctionFS mount `dir`.
+    /// `ep0` has to be configured by the gadget setup beforehand.
+    pub async fn open(dir: &Path) -> io::Result<Self> {
+        let ep_out = OpenOptions::new()
+            .read(true)
+            .open(dir.join(FUNCTIONFS_EP_OUT))
+            .await?;
+        let ep_in = OpenOptions::new()
+            .write(true)
+            .open(dir.join(FUNCTIONFS_EP_IN))
+            .await?;
+        Ok(Self { ep_out, ep_in })
+    }
+}
+
+impl Endpoint<FunctionFs> for FunctionFs {
+    async fn read<T: BoundedBufMut>(&self, buf: T) -> BufResult<usize, T> {
+        self.ep_out.read_at(buf, 0).await
+    }
+    fn write<T: BoundedBuf>(&self, buf: T) -> UnsubmittedWrite<T> {
+        self.ep_in.write_at(buf, 0)
+    }
+}
+
+/// HU side device of a session
+enum HuDevice {
+    Tcp(Rc<TcpStream>),
+    Unix(Rc<UnixStream>),
+    Usb(Rc<File>),
+    FunctionFs(Rc<FunctionFs>),
+}
+
+impl HuDevice {
+    /// closes a DHU connection, the USB devices are left as they are
+    fn shutdown(&self) {
+        match self {
+            HuDevice::Tcp(stream) => {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+            }
+            HuDevice::Unix(stream) => {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+            }
+            HuDevice::Usb(_) | HuDevice::FunctionFs(_) => {}
+        }
+    }
+}
+
+// frames held for the next session between two sessions of a kept accessory
+const KEPT_ACCESSORY_HELD_FRAMES: usize = 16;
+
+/// USB accessory (HU) kept open across MD sessions (`hu_usb_keep_open`).
+/// The device outlives the sessions: its reader is not a session task, it
+/// keeps reading between the sessions so no read is ever given up, and passes
+/// the frames to the attached session.
+struct KeptAccessory {
+    file: Rc<File>,
+    // device node it was opened from
+    path: PathBuf,
+    sessions: mpsc::UnboundedSender<Option<Sender<Packet>>>,
+    // cancelled when the device reader ended (I/O error or EOF)
+    failed: CancellationToken,
+    reader: JoinHandle<()>,
+}
+
+impl KeptAccessory {
+    fn new(
+        file: Rc<File>,
+        path: PathBuf,
+        buffer_len: usize,
+        queue_capacity: usize,
+        dup_detector: Option<DuplicateFrameDetector>,
+    ) -> Self {
+        let (frames_tx, frames_rx) = mpsc::channel(queue_capacity);
+        let (sessions, sessions_rx) = mpsc::unbounded_channel();
+        let failed = CancellationToken::new();
+        let reader = endpoint_reader(
+            IoDevice::EndpointIo(file.clone()),
+            frames_tx,
+            true,
+            buffer_len,
+            dup_detector,
+            CancellationToken::new(),
+        );
+        let done = failed.clone();
+        let reader = tokio_uring::spawn(async move {
+            if let Err(e) = tokio::try_join!(reader, forward_to_session(frames_rx, sessions_rx)) {
+                warn!("{} 📂 kept USB accessory reader ended: {}", NAME, e);
+            }
+            done.cancel();
+        });
+        Self {
+            file,
+            path,
+            sessions,
+            failed,
+            reader,
+        }
+    }
+
+    /// passes the HU frames to a session, from the HU's version request on
+    fn attach(&self, tx: Sender<Packet>) {
+        let _ = self.sessions.send(Some(tx));
+    }
+
+    /// the session ended, its remaining frames are dropped
+    fn detach(&self) {
+        let _ = self.sessions.send(None);
+    }
+
+    /// the device can serve another session: it is still read without errors
+    fn healthy(&self) -> bool {
+        !self.failed.is_cancelled()
+    }
+
+    /// session task ending the session when the device fails
+    fn watch(&self) -> impl Future<Output = Result<()>> {
+        let failed = self.failed.clone();
+        async move {
+            failed.cancelled().await;
+            Err("kept USB accessory failed".into())
+        }
+    }
+}
+
+impl Drop for KeptAccessory {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Passes the frames of a kept accessory to the attached session. A session
+/// only gets frames from the HU's version request on: what the HU still
+/// sends in a previous session is dropped, so the protocol state never
+/// carries over. A version request arriving between sessions is held for
+/// the next one.
+async fn forward_to_session(
+    mut frames: Receiver<Packet>,
+    mut sessions: mpsc::UnboundedReceiver<Option<Sender<Packet>>>,
+) -> Result<()> {
+    let mut session: Option<Sender<Packet>> = None;
+    // the HU restarted the protocol since the last session ended
+    let mut started = false;
+    let mut held: VecDeque<Packet> = VecDeque::new();
+    loop {
+        tokio::select! {
+            // a detach is handled before the frames read after it
+            biased;
+            next = sessions.recv() => {
+                // gone with the `KeptAccessory`
+                let Some(next) = next else {
+                    return Ok(());
+                };
+                if next.is_none() {
+                    started = false;
+                    held.clear();
+                }
+                session = next;
+            }
+            pkt = frames.recv() => {
+                // the reader ended, its result tells why
+                let Some(pkt) = pkt else {
+                    return Ok(());
+                };
+                if is_version_request(&pkt) {
+                    started = true;
+                    held.clear();
+                }
+                if !started {
+                    debug!("{} 📂 dropping a frame of the previous HU session", NAME);
+                    continue;
+                }
+                if held.len() == KEPT_ACCESSORY_HELD_FRAMES {
+                    // not a restart waiting for its reply, start over
+                    started = false;
+                    held.clear();
+                    continue;
+                }
+                held.push_back(pkt);
+            }
+        }
+        if let Some(tx) = session.clone() {
+            while let Some(pkt) = held.pop_front() {
+                if tx.send(pkt).await.is_err() {
+                    // the session ended before it was detached
+                    session = None;
+                    started = false;
+                    held.clear();
+                }
+            }
+        }
+    }
+}
+
+/// MD side stream of a wireless session
+enum MdStream {
+    Tcp(Rc<TcpStream>),
+    /// `md_unix_socket`
+    Unix(Rc<UnixStream>),
+}
+
+impl MdStream {
+    fn shutdown(&self) {
+        let _ = match self {
+            MdStream::Tcp(stream) => stream.shutdown(std::net::Shutdown::Both),
+            MdStream::Unix(stream) => stream.shutdown(std::net::Shutdown::Both),
+        };
+    }
+}
+
+/// listener accepting the MD or DHU connections
+enum SocketListener {
+    Tcp(TcpListener),
+    /// `md_unix_socket`/`dhu_unix_socket`
+    Unix(UnixSocketListener),
+    /// DHU TLS: decrypted clients handed over by the TLS front-end, each as
+    /// one end of a socket pair no other process can connect to
+    Tls(mpsc::Receiver<std::os::unix::net::UnixStream>),
+}
+
+impl SocketListener {
+    /// transport name for logging
+    fn kind(&self) -> &'static str {
+        match self {
+            SocketListener::Tcp(_) => "TCP",
+            SocketListener::Unix(_) => "Unix socket",
+            SocketListener::Tls(_) => "TLS",
+        }
+    }
+}
+
+/// Unix domain socket listener, its socket file is removed when dropped
+struct UnixSocketListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl UnixSocketListener {
+    fn bind(path: &Path) -> io::Result<Self> {
+        // a socket left over by a previous run would fail the bind
+        remove_socket_file(path)?;
+        Ok(Self {
+            listener: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        let _ = remove_socket_file(&self.path);
+    }
+}
+
+/// removes `path` when it is a socket, anything else is left alone
+fn remove_socket_file(path: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Removes the socket files of the MD/DHU Unix socket listeners, for a process
+/// exit which doesn't drop them.
+pub fn remove_unix_sockets(cfg: &AppConfig) {
+    for path in [&cfg.md_unix_socket, &cfg.dhu_unix_socket]
+        .into_iter()
+        .flatten()
+    {
+        let _ = remove_socket_file(path);
+    }
+}
+
 pub enum IoDevice<A: Endpoint<A>> {
     UsbReader(Rc<RefCell<UsbStreamRead>>, PhantomData<A>),
     UsbWriter(Rc<RefCell<UsbStreamWrite>>, PhantomData<A>),
     EndpointIo(Rc<A>),
     TcpStreamIo(Rc<TcpStream>),
+    UnixStreamIo(Rc<UnixStream>),
+    FunctionFsIo(Rc<FunctionFs>),
+}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// memory page size, 4 KiB when it can't be determined
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// Read buffer size for one direction in bytes, rounded up to a multiple of the
+/// page size; out of range values fall back to `BUFFER_LEN`
+/// optional duplicate frame detection of a reader (`dup_frame_threshold`)
+fn dup_frame_detector(config: &AppConfig) -> Option<DuplicateFrameDetector> {
+    (config.dup_frame_threshold > 0).then(|| {
+        DuplicateFrameDetector::new(
+            config.dup_frame_threshold.into(),
+            Duration::from_millis(config.dup_frame_window_ms.into()),
+        )
+    })
+}
+
+pub(crate) fn read_buffer_len(kb: u16, side: &str) -> usize {
+    let len = if READ_BUFFER_KB_RANGE.contains(&kb) {
+        usize::from(kb) * 1024
+    } else {
+        warn!(
+            "{} ⚙️ {} read buffer of {} KiB is out of range ({}-{} KiB), using {} KiB",
+            NAME,
+            side,
+            kb,
+            READ_BUFFER_KB_RANGE.start(),
+            READ_BUFFER_KB_RANGE.end(),
+            BUFFER_LEN / 1024
+        );
+        BUFFER_LEN
+    };
+    let rounded = len.next_multiple_of(page_size());
+    if rounded != len {
+        info!(
+            "{} ⚙️ {} read buffer of {} KiB rounded up to {} KiB (page size)",
+            NAME,
+            side,
+            len / 1024,
+            rounded / 1024
+        );
+    }
+    rounded
+}
+
+/// Polling interval of `transfer_monitor`: the configured one, or when `poll_ms`
+/// is 0, derived as the gcd of the stats and stall intervals. It is never
+/// coarser than the stall timeout.
+fn monitor_poll_interval(
+    poll_ms: u16,
+    stats_interval: Option<Duration>,
+    read_timeout: Duration,
+) -> Duration {
+    let interval = if poll_ms == 0 {
+        let ms = match stats_interval {
+            Some(stats) => gcd(stats.as_millis(), read_timeout.as_millis()),
+            None => read_timeout.as_millis(),
+        };
+        Duration::from_millis(ms as u64)
+    } else {
+        Duration::from_millis(poll_ms.into())
+    };
+    if interval > read_timeout {
+        warn!(
+            "{} ⚙️ monitor poll interval {} ms is coarser than the stall timeout, using {} ms",
+            NAME,
+            interval.as_millis(),
+            read_timeout.as_millis()
+        );
+        return read_timeout;
+    }
+    interval.max(Duration::from_millis(10))
+}
+
+/// The statistics are computed when `transfer_monitor` wakes up, so a
+/// `stats_interval` shorter than its poll interval is raised to it.
+fn stats_report_interval(
+    stats_interval: Option<Duration>,
+    poll_interval: Duration,
+) -> Option<Duration> {
+    match stats_interval {
+        Some(interval) if interval < poll_interval => {
+            warn!(
+                "{} ⚙️ stats_interval {} is shorter than the monitor poll interval, using {}",
+                NAME,
+                format_duration(interval),
+                format_duration(poll_interval)
+            );
+            Some(poll_interval)
+        }
+        interval => interval,
+    }
+}
+
+/// bytes transferred since the last report (from its `last_total`) and the
+/// speed over `elapsed` [bytes/s]
+fn transfer_rate(total: usize, last_total: usize, elapsed: Duration) -> (u64, u64) {
+    let delta = total.saturating_sub(last_total) as u64;
+    let speed = match elapsed.as_secs_f64() {
+        secs if secs > 0.0 => (delta as f64 / secs).round() as u64,
+        _ => 0,
+    };
+    (delta, speed)
+}
+
+/// time and bytes transferred in one motion state
+#[derive(Debug, Default, Clone, Copy)]
+struct MotionCounters {
+    time: Duration,
+    phone_to_car: usize,
+    car_to_phone: usize,
+}
+
+impl std::fmt::Display for MotionCounters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, phone -> car {}, car -> phone {}",
+            format_duration(Duration::from_secs(self.time.as_secs())),
+            ByteSize::b(self.phone_to_car as u64).to_string_as(true),
+            ByteSize::b(self.car_to_phone as u64).to_string_as(true),
+        )
+    }
+}
+
+/// Session transfer split by the vehicle motion state (`motion_stats` option)
+#[derive(Debug, Default)]
+struct MotionStats {
+    moving: MotionCounters,
+    parked: MotionCounters,
+}
+
+impl MotionStats {
+    fn add(&mut self, motion: Motion, time: Duration, phone_to_car: usize, car_to_phone: usize) {
+        let counters = match motion {
+            Motion::Moving => &mut self.moving,
+            Motion::Parked => &mut self.parked,
+            Motion::Unknown => return,
+        };
+        counters.time += time;
+        counters.phone_to_car += phone_to_car;
+        counters.car_to_phone += car_to_phone;
+    }
+}
+
+impl std::fmt::Display for MotionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "🚗 moving: {} | 🅿️ parked: {}", self.moving, self.parked)
+    }
+}
+
+/// Warns when one of the proxy queues is frequently at capacity,
+/// so `proxy_queue_capacity` can be tuned.
+async fn queue_pressure_monitor(
+    queues: Vec<(&'static str, WeakSender<Packet>)>,
+    capacity: usize,
+) -> Result<()> {
+    let mut full = vec![0u32; queues.len()];
+    let mut samples = 0;
+    let mut interval = tokio::time::interval(QUEUE_SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        for (count, (_, queue)) in full.iter_mut().zip(queues.iter()) {
+            // not used in this session (e.g. no MD side in DHU replay mode)
+            let Some(queue) = queue.upgrade() else {
+                continue;
+            };
+            if queue.capacity() == 0 {
+                *count += 1;
+            }
+        }
+        samples += 1;
+        if samples < QUEUE_REPORT_SAMPLES {
+            continue;
+        }
+        for (count, (name, _)) in full.iter().zip(queues.iter()) {
+            let ratio = f64::from(*count) / f64::from(samples);
+            if ratio >= QUEUE_FULL_WARN_RATIO {
+                warn!(
+                    "{} 🚧 {} queue was full in {:.0}% of the samples (capacity {}), consider raising <b>proxy_queue_capacity</>",
+                    NAME,
+                    name,
+                    ratio * 100.0,
+                    capacity
+                );
+            }
+        }
+        full.fill(0);
+        samples = 0;
+    }
+}
+
+/// stall detection of one transfer direction
+struct StallCheck {
+    timeout: Duration,
+    checked: Instant,
+    bytes_last: usize,
+    /// last check which saw the `bytes` total advance (or rearm)
+    progressed: Instant,
+}
+
+impl StallCheck {
+    fn new(timeout: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            timeout,
+            checked: now,
+            bytes_last: 0,
+            progressed: now,
+        }
+    }
+
+    /// `None` until `timeout` elapsed since the last check, then whether
+    /// the `bytes` total didn't advance in the meantime
+    fn check(&mut self, bytes: usize, now: Instant) -> Option<bool> {
+        if now.duration_since(self.checked) <= self.timeout {
+            return None;
+        }
+        let stalled = bytes == self.bytes_last;
+        self.checked = now;
+        self.bytes_last = bytes;
+        if !stalled {
+            self.progressed = now;
+        }
+        Some(stalled)
+    }
+
+    /// starts a new timeout window, e.g. after a transfer pause
+    fn rearm(&mut self, bytes: usize, now: Instant) {
+        self.checked = now;
+        self.bytes_last = bytes;
+        self.progressed = now;
+    }
+
+    /// time without progress, as seen by the checks
+    fn idle(&self, now: Instant) -> Duration {
+        now.duration_since(self.progressed)
+    }
+}
+
+/// Counts consecutive `window`s with a throughput below `min_bytes_per_sec`.
+struct ThroughputWatchdog {
+    window: Duration,
+    min_bytes_per_sec: u64,
+    intervals: u8,
+    checked: Instant,
+    bytes_last: usize,
+    low: u8,
+}
+
+impl ThroughputWatchdog {
+    fn new(window: Duration, min_bytes_per_sec: u64, intervals: u8) -> Self {
+        Self {
+            window,
+            min_bytes_per_sec,
+            intervals: intervals.max(1),
+            checked: Instant::now(),
+            bytes_last: 0,
+            low: 0,
+        }
+    }
+
+    /// `None` until `window` elapsed since the last check, then the throughput
+    /// of the window and whether it was below the minimum
+    fn check(&mut self, bytes: usize, now: Instant) -> Option<(u64, bool)> {
+        let elapsed = now.duration_since(self.checked);
+        if elapsed < self.window {
+            return None;
+        }
+        let speed = ((bytes - self.bytes_last) as f64 / elapsed.as_secs_f64()).round() as u64;
+        self.checked = now;
+        self.bytes_last = bytes;
+        let low = speed < self.min_bytes_per_sec;
+        self.low = if low { self.low.saturating_add(1) } else { 0 };
+        Some((speed, low))
+    }
+
+    /// whether enough consecutive windows were below the minimum
+    fn expired(&self) -> bool {
+        self.low >= self.intervals
+    }
+
+    /// starts a new window, e.g. after a transfer pause
+    fn rearm(&mut self, bytes: usize, now: Instant) {
+        self.checked = now;
+        self.bytes_last = bytes;
+    }
+}
+
+/// byte count of the periodic statistics in the configured units
+fn format_bytes(bytes: u64, units: StatsUnits) -> String {
+    match units {
+        StatsUnits::Binary => ByteSize::b(bytes).to_string_as(true),
+        StatsUnits::Decimal => ByteSize::b(bytes).to_string_as(false),
+        StatsUnits::Bytes => bytes.to_string(),
+    }
+}
+
+/// Live transfer statistics of a running session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferStats {
+    /// total bytes of the session written to the car (phone -> car)
+    pub usb_bytes: usize,
+    /// total bytes of the session written to the phone (car -> phone)
+    pub tcp_bytes: usize,
+    /// phone -> car speed since the previous stats [bytes/s]
+    pub usb_speed: u64,
+    /// car -> phone speed since the previous stats [bytes/s]
+    pub tcp_speed: u64,
+    pub session_duration: Duration,
 }
 
+/// Subscription to [`TransferStats`] for applications embedding [`io_loop`]:
+/// at the start of every session a new receiver is sent to `sessions`, which
+/// yields the stats of that session every `interval` and ends with it. A session
+/// whose receiver doesn't fit into `sessions` (not drained) has no stats.
+#[derive(Debug, Clone)]
+pub struct TransferStatsSubscription {
+    pub sessions: Sender<Receiver<TransferStats>>,
+    pub interval: Duration,
+}
+
+/// per-session sender of [`TransferStats`], fed by `transfer_monitor`
+struct TransferStatsStream {
+    tx: Sender<TransferStats>,
+    interval: Duration,
+    started: Instant,
+    /// previous stats: (time, usb bytes, tcp bytes)
+    last: (Instant, usize, usize),
+}
+
+impl TransferStatsStream {
+    fn new(tx: Sender<TransferStats>, interval: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            tx,
+            interval,
+            started: now,
+            last: (now, 0, 0),
+        }
+    }
+
+    /// sends the stats once `interval` elapsed; `false` when the receiver is gone
+    fn sample(&mut self, usb_bytes: usize, tcp_bytes: usize, now: Instant) -> bool {
+        let (sent, usb_last, tcp_last) = self.last;
+        let elapsed = now.duration_since(sent);
+        if elapsed < self.interval {
+            return true;
+        }
+        let speed = |bytes: usize| (bytes as f64 / elapsed.as_secs_f64()).round() as u64;
+        let stats = TransferStats {
+            usb_bytes,
+            tcp_bytes,
+            usb_speed: speed(usb_bytes - usb_last),
+            tcp_speed: speed(tcp_bytes - tcp_last),
+            session_duration: now.duration_since(self.started),
+        };
+        self.last = (now, usb_bytes, tcp_bytes);
+        // a slow consumer misses stats instead of delaying the monitor
+        !matches!(
+            self.tx.try_send(stats),
+            Err(mpsc::error::TrySendError::Closed(_))
+        )
+    }
+}
+
+/// `stall_timeouts` are the phone -> car (USB) and car -> phone (TCP) stall timeouts,
+/// `transfer_stats` gets the live stats (the stream ends when the monitor does)
 async fn transfer_monitor(
     stats_interval: Option<Duration>,
     usb_bytes_written: Arc<AtomicUsize>,
     tcp_bytes_written: Arc<AtomicUsize>,
-    read_timeout: Duration,
+    stall_timeouts: (Duration, Duration),
+    poll_interval: Duration,
+    mut stats_log: Option<StatsLog>,
+    motion_stats: Option<Arc<std::sync::Mutex<MotionStats>>>,
     config: SharedConfig,
+    mut transfer_stats: Option<TransferStatsStream>,
 ) -> Result<()> {
+    // the stats stream is sampled at least as often as it is due
+    let poll_interval = match transfer_stats {
+        Some(ref stats) => poll_interval.min(stats.interval),
+        None => poll_interval,
+    };
+    let mut motion_sample = (Instant::now(), 0, 0);
     let mut usb_bytes_out_last: usize = 0;
     let mut tcp_bytes_out_last: usize = 0;
-    let mut stall_usb_bytes_last: usize = 0;
-    let mut stall_tcp_bytes_last: usize = 0;
     let mut report_time = Instant::now();
-    let mut stall_check = Instant::now();
+    let mut usb_stall_check = StallCheck::new(stall_timeouts.0);
+    let mut tcp_stall_check = StallCheck::new(stall_timeouts.1);
+    let (stall_leniency, stats_units, mut watchdog) = {
+        let cfg = config.read().await;
+        let watchdog = (cfg.watchdog_min_bytes_per_sec > 0).then(|| {
+            ThroughputWatchdog::new(
+                stats_interval.unwrap_or(stall_timeouts.0),
+                cfg.watchdog_min_bytes_per_sec.into(),
+                cfg.watchdog_intervals,
+            )
+        });
+        (cfg.stall_leniency, cfg.stats_units, watchdog)
+    };
+    let mut lenient_stalls: u8 = 0;
 
     info!(
         "{} ⚙️ Showing transfer statistics: <b><blue>{}</>",
@@ -129,63 +864,128 @@ async fn transfer_monitor(
             None => "disabled".to_string(),
         }
     );
+    debug!(
+        "{} ⚙️ transfer monitor poll interval: {}",
+        NAME,
+        format_duration(poll_interval)
+    );
 
     loop {
         // load current total transfer from AtomicUsize:
         let usb_bytes_out = usb_bytes_written.load(Ordering::Relaxed);
         let tcp_bytes_out = tcp_bytes_written.load(Ordering::Relaxed);
 
+        // moving/parked split, by the motion state in the last poll interval
+        if let Some(ref motion_stats) = motion_stats {
+            let (sampled, usb_last, tcp_last) = motion_sample;
+            motion_stats.lock().unwrap().add(
+                vehicle_motion(),
+                sampled.elapsed(),
+                usb_bytes_out - usb_last,
+                tcp_bytes_out - tcp_last,
+            );
+            motion_sample = (Instant::now(), usb_bytes_out, tcp_bytes_out);
+        }
+
+        // per-minute rolling log
+        if let Some(ref mut stats_log) = stats_log {
+            stats_log.sample(usb_bytes_out, tcp_bytes_out);
+        }
+        session_summary::sample_throughput(usb_bytes_out, tcp_bytes_out);
+        #[cfg(feature = "metrics")]
+        crate::metrics::sample_transfer(usb_bytes_out, tcp_bytes_out);
+        if let Some(ref mut stats) = transfer_stats {
+            if !stats.sample(usb_bytes_out, tcp_bytes_out, Instant::now()) {
+                debug!("{} transfer stats receiver dropped", NAME);
+                transfer_stats = None;
+            }
+        }
+
         // Stats printing
         if stats_interval.is_some() && report_time.elapsed() > stats_interval.unwrap() {
-            // compute USB transfer
-            usb_bytes_out_last = usb_bytes_out - usb_bytes_out_last;
-            let usb_transferred_total = ByteSize::b(usb_bytes_out.try_into().unwrap());
-            let usb_transferred_last = ByteSize::b(usb_bytes_out_last.try_into().unwrap());
-            let usb_speed: u64 =
-                (usb_bytes_out_last as f64 / report_time.elapsed().as_secs_f64()).round() as u64;
-            let usb_speed = ByteSize::b(usb_speed);
-
-            // compute TCP transfer
-            tcp_bytes_out_last = tcp_bytes_out - tcp_bytes_out_last;
-            let tcp_transferred_total = ByteSize::b(tcp_bytes_out.try_into().unwrap());
-            let tcp_transferred_last = ByteSize::b(tcp_bytes_out_last.try_into().unwrap());
-            let tcp_speed: u64 =
-                (tcp_bytes_out_last as f64 / report_time.elapsed().as_secs_f64()).round() as u64;
-            let tcp_speed = ByteSize::b(tcp_speed);
+            let elapsed = report_time.elapsed();
+            let (usb_delta, usb_speed) = transfer_rate(usb_bytes_out, usb_bytes_out_last, elapsed);
+            let (tcp_delta, tcp_speed) = transfer_rate(tcp_bytes_out, tcp_bytes_out_last, elapsed);
 
             info!(
                 "{} {} {: >9} ({: >9}/s), {: >9} total | {} {: >9} ({: >9}/s), {: >9} total",
                 NAME,
                 "phone -> car 🔺",
-                usb_transferred_last.to_string_as(true),
-                usb_speed.to_string_as(true),
-                usb_transferred_total.to_string_as(true),
+                format_bytes(usb_delta, stats_units),
+                format_bytes(usb_speed, stats_units),
+                format_bytes(usb_bytes_out as u64, stats_units),
                 "car -> phone 🔻",
-                tcp_transferred_last.to_string_as(true),
-                tcp_speed.to_string_as(true),
-                tcp_transferred_total.to_string_as(true),
+                format_bytes(tcp_delta, stats_units),
+                format_bytes(tcp_speed, stats_units),
+                format_bytes(tcp_bytes_out as u64, stats_units),
             );
+            if let Some(ref motion_stats) = motion_stats {
+                info!("{} {}", NAME, motion_stats.lock().unwrap());
+            }
+            if let Some(video) = video_stats::period_summary() {
+                info!("{} {}", NAME, video);
+            }
+            if let Some(latency) = latency_stats::period_summary() {
+                info!("{} {}", NAME, latency);
+            }
 
-            // save values for next iteration
+            // totals of this report, the next deltas are counted from them
             report_time = Instant::now();
             usb_bytes_out_last = usb_bytes_out;
             tcp_bytes_out_last = tcp_bytes_out;
         }
 
-        // transfer stall detection
-        if stall_check.elapsed() > read_timeout {
-            // compute delta since last check
-            stall_usb_bytes_last = usb_bytes_out - stall_usb_bytes_last;
-            stall_tcp_bytes_last = tcp_bytes_out - stall_tcp_bytes_last;
-
-            if stall_usb_bytes_last == 0 || stall_tcp_bytes_last == 0 {
-                return Err("unexpected transfer stall".into());
+        // transfer stall detection, every direction within its own timeout;
+        // a paused transfer is not a stall, the windows start again on resume
+        let now = Instant::now();
+        if transfer_paused().is_some() {
+            usb_stall_check.rearm(usb_bytes_out, now);
+            tcp_stall_check.rearm(tcp_bytes_out, now);
+            if let Some(ref mut watchdog) = watchdog {
+                watchdog.rearm(usb_bytes_out, now);
+            }
+        }
+        let usb_stall = usb_stall_check.check(usb_bytes_out, now);
+        let tcp_stall = tcp_stall_check.check(tcp_bytes_out, now);
+        if usb_stall.is_some() || tcp_stall.is_some() {
+            let stalled = match (usb_stall == Some(true), tcp_stall == Some(true)) {
+                (true, true) => Some("phone -> car and car -> phone"),
+                (true, false) => Some("phone -> car"),
+                (false, true) => Some("car -> phone"),
+                (false, false) => None,
+            };
+            if let Some(direction) = stalled {
+                if lenient_stalls >= stall_leniency {
+                    return Err(Box::new(ProxyError::TransferStall {
+                        phone_to_car: (usb_stall == Some(true)).then(|| usb_stall_check.idle(now)),
+                        car_to_phone: (tcp_stall == Some(true)).then(|| tcp_stall_check.idle(now)),
+                    }));
+                }
+                lenient_stalls += 1;
+                warn!(
+                    "{} ⚠️ transfer stall ({}) tolerated ({}/{}), giving it one more window",
+                    NAME, direction, lenient_stalls, stall_leniency
+                );
+            } else {
+                // only consecutive stalls count
+                lenient_stalls = 0;
             }
+        }
 
-            // save values for next iteration
-            stall_check = Instant::now();
-            stall_usb_bytes_last = usb_bytes_out;
-            stall_tcp_bytes_last = tcp_bytes_out;
+        // throughput watchdog, for sessions alive but too slow to be usable
+        if let Some(ref mut watchdog) = watchdog {
+            if let Some((speed, true)) = watchdog.check(usb_bytes_out, now) {
+                if watchdog.expired() {
+                    return Err(Box::new(ProxyError::LowThroughput {
+                        bytes_per_sec: speed,
+                        intervals: watchdog.low,
+                    }));
+                }
+                warn!(
+                    "{} ⚠️ phone -> car throughput {} bytes/s below the watchdog minimum ({}/{})",
+                    NAME, speed, watchdog.low, watchdog.intervals
+                );
+            }
         }
 
         // check pending action
@@ -198,7 +998,7 @@ async fn transfer_monitor(
             return Err(format!("action request: {:?}", action).into());
         }
 
-        sleep(Duration::from_millis(100)).await;
+        sleep(poll_interval).await;
     }
 }
 
@@ -210,6 +1010,145 @@ async fn flatten<T>(handle: &mut JoinHandle<Result<T>>) -> Result<T> {
     }
 }
 
+/// Cooperative end of the session tasks (`cooperative_shutdown` option).
+/// The endpoint readers and proxies get the [`SessionStop::token`] and return at
+/// a safe point, between whole frames; the other tasks, which only pass packets
+/// between channels or watch counters, are just dropped at their next await.
+/// Either way they release the streams/files they hold, and [`SessionStop::stop`]
+/// waits until every one of them is gone.
+struct SessionStop {
+    token: CancellationToken,
+    // one clone lives in every wrapped task, so `all_done` yields `None`
+    // when the last of them has been dropped
+    alive: mpsc::Sender<()>,
+    all_done: mpsc::Receiver<()>,
+}
+
+impl SessionStop {
+    fn new() -> Self {
+        let (alive, all_done) = mpsc::channel(1);
+        Self {
+            token: CancellationToken::new(),
+            alive,
+            all_done,
+        }
+    }
+
+    /// token for the tasks returning on their own, see [`SessionStop::track`]
+    fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// a task given the [`SessionStop::token`], which returns at a safe point
+    fn track<F>(&self, fut: F) -> impl Future<Output = Result<()>>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let alive = self.alive.clone();
+        async move {
+            let _alive = alive;
+            fut.await
+        }
+    }
+
+    /// a task without frames in flight, dropped as soon as the session is stopped
+    fn wrap<F>(&self, fut: F) -> impl Future<Output = Result<()>>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let token = self.token.clone();
+        let alive = self.alive.clone();
+        async move {
+            let _alive = alive;
+            tokio::select! {
+                res = fut => res,
+                _ = token.cancelled() => Ok(()),
+            }
+        }
+    }
+
+    /// signals the tasks to return and waits for all of them to release
+    /// their resources; `false` when some are still running after `max_wait`
+    async fn stop(self, max_wait: Duration) -> bool {
+        let Self {
+            token,
+            alive,
+            mut all_done,
+        } = self;
+        token.cancel();
+        drop(alive);
+        timeout(max_wait, all_done.recv()).await.is_ok()
+    }
+}
+
+/// latency between the WiFi credentials sent over bluetooth and the phone
+/// TCP connection, for comparing `early_tcp_start` on/off
+fn log_credentials_to_connect() {
+    if let Some(elapsed) = bluetooth::take_credentials_sent_elapsed() {
+        info!(
+            "{} ⏱️ phone connected <b>{} ms</> after WiFi credentials were sent",
+            NAME,
+            elapsed.as_millis()
+        );
+    }
+}
+
+/// creates a TCP listener, setting the socket options before bind
+fn bind_listener(addr: SocketAddr, reuse_addr: bool, backlog: u16) -> io::Result<TcpListener> {
+    Ok(TcpListener::from_std(bind_std_listener(
+        addr, reuse_addr, backlog,
+    )?))
+}
+
+fn bind_std_listener(
+    addr: SocketAddr,
+    reuse_addr: bool,
+    backlog: u16,
+) -> io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    // allows rebinding while connections from the previous run are in TIME_WAIT
+    socket.set_reuse_address(reuse_addr)?;
+    // the IPv6 wildcard accepts IPv4 clients too (as IPv4-mapped addresses)
+    if addr.ip() == IpAddr::V6(Ipv6Addr::UNSPECIFIED) {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(backlog.into())?;
+    Ok(socket.into())
+}
+
+/// TCP keepalive of the MD/DHU connections, lets the kernel drop a silently dead
+/// peer; complements the stall detection of the transfer loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TcpKeepalive {
+    idle: u16,
+    interval: u16,
+    count: u8,
+}
+
+impl TcpKeepalive {
+    /// `None` when keepalive is disabled
+    fn from_config(cfg: &AppConfig) -> Option<Self> {
+        (cfg.tcp_keepalive_idle_secs > 0).then(|| TcpKeepalive {
+            idle: cfg.tcp_keepalive_idle_secs,
+            interval: cfg.tcp_keepalive_interval_secs.max(1),
+            count: cfg.tcp_keepalive_count.max(1),
+        })
+    }
+
+    pub(crate) fn apply(&self, stream: &impl AsRawFd) -> io::Result<()> {
+        // the io_uring streams only expose the raw fd, which stays open while
+        // `stream` is borrowed
+        let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+        SockRef::from(&fd).set_tcp_keepalive(
+            &socket2::TcpKeepalive::new()
+                .with_time(Duration::from_secs(self.idle.into()))
+                .with_interval(Duration::from_secs(self.interval.into()))
+                .with_retries(self.count.into()),
+        )
+    }
+}
+
 async fn tcp_bridge(remote_addr: &str, local_addr: &str, cancel: CancellationToken) {
     loop {
         debug!(
@@ -306,9 +1245,9 @@ async fn tcp_bridge(remote_addr: &str, local_addr: &str, cancel: CancellationTok
     }
 }
 
-/// Async lookup MAC from IPv4 using /proc/net/arp
+/// Async lookup MAC from IPv4 (also IPv4-mapped IPv6) using /proc/net/arp
 pub async fn mac_from_ipv4(addr: SocketAddr) -> io::Result<Option<MacAddress>> {
-    let ip = match addr.ip() {
+    let ip = match addr.ip().to_canonical() {
         IpAddr::V4(v4) => v4,
         IpAddr::V6(_) => return Ok(None),
     };
@@ -332,11 +1271,198 @@ pub async fn mac_from_ipv4(addr: SocketAddr) -> io::Result<Option<MacAddress>> {
     Ok(None)
 }
 
+/// Opens the USB accessory device, retrying up to `retries` times with an
+/// exponential backoff starting at `retry_delay` while the node is not ready
+/// (e.g. right after the gadget was bound).
+async fn open_usb_accessory(
+    path: &Path,
+    retries: u8,
+    retry_delay: Duration,
+) -> std::result::Result<File, ProxyError> {
+    let open = || async {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(false)
+            .open(path)
+            .await
+    };
+    let retry_policy = ExponentialBuilder::default()
+        .with_min_delay(retry_delay)
+        .with_max_delay(retry_delay * 8)
+        .with_max_times(retries.into());
+
+    open.retry(retry_policy)
+        .sleep(tokio::time::sleep)
+        .notify(|err: &io::Error, dur: Duration| {
+            warn!(
+                "{} 📂 USB accessory not ready: {}, retrying in {} ms",
+                NAME,
+                err,
+                dur.as_millis()
+            );
+        })
+        .await
+        .map_err(|source| match source.kind() {
+            io::ErrorKind::NotFound => ProxyError::UsbGadgetNotConfigured {
+                path: path.display().to_string(),
+            },
+            _ => ProxyError::UsbOpen {
+                path: path.display().to_string(),
+                source,
+            },
+        })
+}
+
+/// Rate limits the routine logging of the idle/restart cycle while nothing
+/// changes: the first cycle is logged, later ones only after exponentially
+/// increasing pauses (see `IDLE_LOG_INTERVALS`).
+struct IdleLogGate {
+    enabled: bool,
+    last_logged: Option<Instant>,
+    step: usize,
+    suppressed: u32,
+}
+
+impl IdleLogGate {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            last_logged: None,
+            step: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Called once per idle cycle: `Some(suppressed cycles)` when this cycle
+    /// should be logged, `None` to stay quiet.
+    fn check(&mut self, now: Instant) -> Option<u32> {
+        if !self.enabled {
+            return Some(0);
+        }
+        if let Some(last) = self.last_logged {
+            let pause = IDLE_LOG_INTERVALS[self.step.min(IDLE_LOG_INTERVALS.len() - 1)];
+            if now.duration_since(last) < pause {
+                self.suppressed += 1;
+                return None;
+            }
+            self.step += 1;
+        }
+        self.last_logged = Some(now);
+        Some(std::mem::take(&mut self.suppressed))
+    }
+
+    /// Something changed (a connection arrived): log the next cycle again.
+    fn reset(&mut self) {
+        self.last_logged = None;
+        self.step = 0;
+        self.suppressed = 0;
+    }
+}
+
+/// next client handed over by the DHU TLS front-end
+async fn tls_client(
+    clients: &mut mpsc::Receiver<std::os::unix::net::UnixStream>,
+) -> io::Result<UnixStream> {
+    match clients.recv().await {
+        Some(stream) => Ok(UnixStream::from_std(stream)),
+        None => Err(io::Error::other("TLS front-end stopped")),
+    }
+}
+
+/// Waits for a client on a Unix socket listener or from the TLS front-end,
+/// `name` is used for logging.
+async fn unix_wait_for_connection(
+    accept: impl Future<Output = io::Result<UnixStream>>,
+    name: &str,
+    log_timeout: bool,
+) -> Result<UnixStream> {
+    let stream = match timeout(TCP_CLIENT_TIMEOUT, accept)
+        .await
+        .map_err(|e| std::io::Error::other(e))
+    {
+        Ok(Ok(stream)) => stream,
+        Err(e) | Ok(Err(e)) => {
+            if log_timeout {
+                error!("{} 📵 {} server: {}, restarting...", NAME, name, e);
+            } else {
+                debug!("{} 📵 {} server: {}, restarting...", NAME, name, e);
+            }
+            return Err(Box::new(e));
+        }
+    };
+    info!("{} 📳 {} server: new client connected", NAME, name);
+    Ok(stream)
+}
+
+/// Waits for a DHU to connect on the TCP or Unix socket listener.
+async fn dhu_wait_for_connection(
+    listener: &mut SocketListener,
+    keepalive: Option<TcpKeepalive>,
+    log_timeout: bool,
+) -> Result<HuDevice> {
+    match listener {
+        SocketListener::Tcp(listener) => {
+            let (stream, _, _) =
+                tcp_wait_for_connection(listener, false, keepalive, log_timeout).await?;
+            Ok(HuDevice::Tcp(Rc::new(stream)))
+        }
+        SocketListener::Unix(listener) => {
+            let accept = listener.listener.accept();
+            let stream = unix_wait_for_connection(accept, "DHU Unix socket", log_timeout).await?;
+            Ok(HuDevice::Unix(Rc::new(stream)))
+        }
+        SocketListener::Tls(clients) => {
+            let stream =
+                unix_wait_for_connection(tls_client(clients), "DHU TLS", log_timeout).await?;
+            Ok(HuDevice::Unix(Rc::new(stream)))
+        }
+    }
+}
+
+/// Waits for the MD on the TCP or Unix socket listener. Returns the phone's MAC
+/// address (only known over TCP) for the later disassociation, and the token
+/// stopping the companion bridges of the session.
+async fn md_wait_for_connection(
+    listener: &mut SocketListener,
+    keepalive: Option<TcpKeepalive>,
+    log_timeout: bool,
+) -> Result<(MdStream, Option<MacAddress>, CancellationToken)> {
+    match listener {
+        SocketListener::Tcp(listener) => {
+            let (stream, ip, cancel) =
+                tcp_wait_for_connection(listener, true, keepalive, log_timeout).await?;
+            let mac = mac_from_ipv4(ip).await.unwrap_or(None);
+            Ok((MdStream::Tcp(Rc::new(stream)), mac, cancel))
+        }
+        SocketListener::Unix(listener) => {
+            let accept = listener.listener.accept();
+            let stream = unix_wait_for_connection(accept, "MD Unix socket", log_timeout).await?;
+            Ok((
+                MdStream::Unix(Rc::new(stream)),
+                None,
+                CancellationToken::new(),
+            ))
+        }
+        SocketListener::Tls(clients) => {
+            let stream =
+                unix_wait_for_connection(tls_client(clients), "MD TLS", log_timeout).await?;
+            Ok((
+                MdStream::Unix(Rc::new(stream)),
+                None,
+                CancellationToken::new(),
+            ))
+        }
+    }
+}
+
 /// Asynchronously wait for an inbound TCP connection
 /// returning TcpStream of first client connected
 async fn tcp_wait_for_connection(
     listener: &mut TcpListener,
     start_companion_bridges: bool,
+    keepalive: Option<TcpKeepalive>,
+    log_timeout: bool,
 ) -> Result<(TcpStream, SocketAddr, CancellationToken)> {
     let retval = listener.accept();
     let (stream, addr) = match timeout(TCP_CLIENT_TIMEOUT, retval)
@@ -345,8 +1471,12 @@ async fn tcp_wait_for_connection(
     {
         Ok(Ok((stream, addr))) => (stream, addr),
         Err(e) | Ok(Err(e)) => {
-            error!("{} 📵 TCP server: {}, restarting...", NAME, e);
-            return Err(Box::new(e));
+            if log_timeout {
+                error!("{} 📵 TCP server: {}, restarting...", NAME, e);
+            } else {
+                debug!("{} 📵 TCP server: {}, restarting...", NAME, e);
+            }
+            return Err(Box::new(ProxyError::TcpAccept(e)));
         }
     };
     info!(
@@ -354,8 +1484,16 @@ async fn tcp_wait_for_connection(
         NAME, addr
     );
 
+    // disable Nagle algorithm, so segments are always sent as soon as possible,
+    // even if there is only a small amount of data
+    stream.set_nodelay(true)?;
+    if let Some(keepalive) = keepalive {
+        keepalive.apply(&stream)?;
+    }
+
     // One CancellationToken per session — cancelled when the session ends,
-    // which stops all tcp_bridge tasks spawned for this client.
+    // which stops all tcp_bridge tasks spawned for this client. Nothing may
+    // fail from here on, or the bridges would outlive the token's owner.
     let cancel = CancellationToken::new();
 
     // this is creating a reverse tcp bridge for Android.
@@ -373,7 +1511,7 @@ async fn tcp_wait_for_connection(
                 // FIXME use port configured by user for webserver
                 // or ignore when webserver disabled...
                 tcp_bridge(
-                    &format!("{}:{}", addr.ip(), COMP_APP_TCP_PORT),
+                    &SocketAddr::new(addr.ip(), COMP_APP_TCP_PORT).to_string(),
                     "127.0.0.1:80",
                     c,
                 )
@@ -396,7 +1534,7 @@ async fn tcp_wait_for_connection(
             // FIXME use port configured by user for webserver
             // or ignore when webserver disabled...
             tcp_bridge(
-                &format!("{}:{}", addr.ip(), COMP_APP_TCP_PORT_WS),
+                &SocketAddr::new(addr.ip(), COMP_APP_TCP_PORT_WS).to_string(),
                 "127.0.0.1:80",
                 c,
             )
@@ -413,7 +1551,7 @@ async fn tcp_wait_for_connection(
             // FIXME use port configured by user for webserver
             // or ignore when webserver disabled...
             tcp_bridge(
-                &format!("{}:{}", addr.ip(), COMP_APP_TCP_PORT_SWUPDATE),
+                &SocketAddr::new(addr.ip(), COMP_APP_TCP_PORT_SWUPDATE).to_string(),
                 "127.0.0.1:8080",
                 c,
             )
@@ -426,10 +1564,6 @@ async fn tcp_wait_for_connection(
         );
     }
 
-    // disable Nagle algorithm, so segments are always sent as soon as possible,
-    // even if there is only a small amount of data
-    stream.set_nodelay(true)?;
-
     Ok((stream, addr, cancel))
 }
 
@@ -437,7 +1571,10 @@ async fn tcp_wait_for_connection(
 /// This is used only when `aa_server_tcp_addr` is set. It intentionally does
 /// not start the companion reverse TCP bridges, because there is no inbound
 /// phone client address in this mode.
-async fn tcp_connect_to_aa_server(addr: &str) -> Result<TcpStream> {
+async fn tcp_connect_to_aa_server(
+    addr: &str,
+    keepalive: Option<TcpKeepalive>,
+) -> Result<TcpStream> {
     let addr = addr.trim();
     let socket_addr: SocketAddr = addr.parse().map_err(|e| {
         std::io::Error::new(
@@ -471,6 +1608,9 @@ async fn tcp_connect_to_aa_server(addr: &str) -> Result<TcpStream> {
     };
 
     stream.set_nodelay(true)?;
+    if let Some(keepalive) = keepalive {
+        keepalive.apply(&stream)?;
+    }
     info!(
         "{} 📳 MD direct TCP: connected to Android Auto Head Unit Server at <u>{}</u>",
         NAME, socket_addr
@@ -479,9 +1619,108 @@ async fn tcp_connect_to_aa_server(addr: &str) -> Result<TcpStream> {
     Ok(stream)
 }
 
+/// TLS acceptor for the DHU listener, when `dhu_tls_cert` and `dhu_tls_key` are configured
+fn dhu_tls_acceptor(cfg: &AppConfig) -> Result<Option<SslAcceptor>> {
+    match (&cfg.dhu_tls_cert, &cfg.dhu_tls_key) {
+        (Some(cert), Some(key)) => {
+            let acceptor = dhu_tls::acceptor(cert, key, cfg.dhu_tls_client_ca.as_deref())
+                .map_err(|e| format!("DHU TLS setup failed: {}", e))?;
+            info!(
+                "{} 🔒 DHU TLS enabled, client certificate verification: <b>{}</>",
+                NAME,
+                cfg.dhu_tls_client_ca.is_some()
+            );
+            Ok(Some(acceptor))
+        }
+        (None, None) => Ok(None),
+        _ => Err("both dhu_tls_cert and dhu_tls_key are needed for DHU TLS".into()),
+    }
+}
+
+/// Dry run checks of the proxy setup: binds (and releases) the MD/DHU listeners
+/// and checks the DHU TLS files and the HU device. Returns found problems.
+pub fn check_setup(cfg: &AppConfig) -> Vec<String> {
+    let mut problems = vec![];
+    let tcp_only = cfg.md_unix_socket.is_none() && cfg.dhu_unix_socket.is_none();
+    if tcp_only && cfg.tcp_md_port == cfg.tcp_dhu_port {
+        problems.push(format!(
+            "tcp_md_port and tcp_dhu_port are both set to {}",
+            cfg.tcp_md_port
+        ));
+    }
+    let dhu_bind_addr = cfg.tcp_dhu_bind_addr.unwrap_or(cfg.tcp_bind_addr);
+    for (name, ip, port, unix_socket) in [
+        (
+            "MD",
+            cfg.tcp_bind_addr,
+            cfg.tcp_md_port,
+            &cfg.md_unix_socket,
+        ),
+        ("DHU", dhu_bind_addr, cfg.tcp_dhu_port, &cfg.dhu_unix_socket),
+    ] {
+        if unix_socket.is_some() {
+            continue;
+        }
+        let addr = SocketAddr::new(ip, port);
+        match bind_std_listener(addr, cfg.tcp_reuse_addr, cfg.tcp_listen_backlog) {
+            Ok(_) => info!(
+                "{} ✅ {} listener can be bound to <u>{}</u>",
+                NAME, name, addr
+            ),
+            Err(e) => problems.push(format!("{} listener {}: {}", name, addr, e)),
+        }
+    }
+    if let Err(e) = dhu_tls_acceptor(cfg) {
+        problems.push(e.to_string());
+    }
+    if cfg.md_unix_socket.is_some() && cfg.md_unix_socket == cfg.dhu_unix_socket {
+        problems.push("md_unix_socket and dhu_unix_socket are the same path".to_string());
+    }
+    for (name, unix_socket) in [
+        ("md_unix_socket", &cfg.md_unix_socket),
+        ("dhu_unix_socket", &cfg.dhu_unix_socket),
+    ] {
+        let Some(path) = unix_socket else {
+            continue;
+        };
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        if dir.is_some_and(|dir| !dir.is_dir()) {
+            problems.push(format!(
+                "directory of {} {} doesn't exist",
+                name,
+                path.display()
+            ));
+        }
+    }
+    if let Some(ref path) = cfg.dhu_replay_file {
+        if !path.exists() {
+            problems.push(format!("dhu_replay_file {} doesn't exist", path.display()));
+        }
+    }
+    if !cfg.dhu {
+        // the gadget may not be set up yet before the first start
+        let (name, path) = match cfg.hu_usb_transport {
+            HuUsbTransport::Functionfs => ("FunctionFS directory", cfg.functionfs_dir.as_path()),
+            HuUsbTransport::Accessory => ("USB accessory device", cfg.usb_accessory_path.as_path()),
+        };
+        if path.exists() {
+            info!("{} ✅ {} present: <u>{}</u>", NAME, name, path.display());
+        } else {
+            warn!(
+                "{} {} {} doesn't exist (yet), it is created by the USB gadget setup",
+                NAME,
+                name,
+                path.display()
+            );
+        }
+    }
+    problems
+}
+
 pub async fn io_loop(
     need_restart: BroadcastSender<Option<Action>>,
     tcp_start: Arc<Notify>,
+    md_connected: Arc<Notify>,
     config: SharedConfig,
     tx: Arc<Mutex<Option<Sender<Packet>>>>,
     sensor_channel: Arc<Mutex<Option<u8>>>,
@@ -492,20 +1731,144 @@ pub async fn io_loop(
     usb_connected: Arc<AtomicBool>,
     script_registry: Option<Arc<ScriptRegistry>>,
     ws_event_tx: BroadcastSender<ServerEvent>,
-) -> Result<()> {
+    transfer_stats: Option<TransferStatsSubscription>,
+    md_port: u16,
+    dhu_port: u16,
+    queue_capacity: usize,
+) -> std::result::Result<(), ProxyError> {
     let shared_config = config.clone();
     #[allow(unused_variables)]
     let (client_handler, ev_tx) = spawn_ev_client_task().await;
 
     // prepare/bind needed TCP listeners
-    info!("{} 🛰️ Starting TCP server for MD...", NAME);
-    let bind_addr = format!("0.0.0.0:{}", TCP_SERVER_PORT).parse().unwrap();
-    let mut md_listener = Some(TcpListener::bind(bind_addr).unwrap());
-    info!("{} 🛰️ MD TCP server bound to: <u>{}</u>", NAME, bind_addr);
-    info!("{} 🛰️ Starting TCP server for DHU...", NAME);
-    let bind_addr = format!("0.0.0.0:{}", TCP_DHU_PORT).parse().unwrap();
-    let mut dhu_listener = Some(TcpListener::bind(bind_addr).unwrap());
-    info!("{} 🛰️ DHU TCP server bound to: <u>{}</u>", NAME, bind_addr);
+    let (reuse_addr, backlog, md_bind_ip, dhu_bind_ip) = {
+        let cfg = config.read().await;
+        (
+            cfg.tcp_reuse_addr,
+            cfg.tcp_listen_backlog,
+            cfg.tcp_bind_addr,
+            cfg.tcp_dhu_bind_addr.unwrap_or(cfg.tcp_bind_addr),
+        )
+    };
+    info!(
+        "{} 🛰️ TCP listeners: SO_REUSEADDR: <b>{}</>, accept backlog: <b>{}</>",
+        NAME, reuse_addr, backlog
+    );
+    let (md_unix_socket, dhu_unix_socket) = {
+        let cfg = config.read().await;
+        (cfg.md_unix_socket.clone(), cfg.dhu_unix_socket.clone())
+    };
+    let mut md_listener = match md_unix_socket {
+        Some(path) => {
+            let listener = UnixSocketListener::bind(&path)?;
+            info!(
+                "{} 🛰️ MD Unix socket server bound to: <u>{}</u>",
+                NAME,
+                path.display()
+            );
+            SocketListener::Unix(listener)
+        }
+        None => {
+            info!("{} 🛰️ Starting TCP server for MD...", NAME);
+            let bind_addr = SocketAddr::new(md_bind_ip, md_port);
+            let listener = bind_listener(bind_addr, reuse_addr, backlog).map_err(|source| {
+                ProxyError::ListenerBind {
+                    listener: "MD",
+                    addr: bind_addr,
+                    source,
+                }
+            })?;
+            info!("{} 🛰️ MD TCP server bound to: <u>{}</u>", NAME, bind_addr);
+            SocketListener::Tcp(listener)
+        }
+    };
+    if dhu_unix_socket.is_none() {
+        info!("{} 🛰️ Starting TCP server for DHU...", NAME);
+    }
+    let bind_addr = SocketAddr::new(dhu_bind_ip, dhu_port);
+    let dhu_bind_error = |source| ProxyError::ListenerBind {
+        listener: "DHU",
+        addr: bind_addr,
+        source,
+    };
+    let tls_acceptor = dhu_tls_acceptor(&config.read().await)?;
+    let mut dhu_listener = match (dhu_unix_socket, tls_acceptor) {
+        (Some(path), tls_acceptor) => {
+            if tls_acceptor.is_some() {
+                warn!(
+                    "{} ⚠️ DHU TLS is not used with the DHU Unix socket listener",
+                    NAME
+                );
+            }
+            let listener = UnixSocketListener::bind(&path)?;
+            info!(
+                "{} 🛰️ DHU Unix socket server bound to: <u>{}</u>",
+                NAME,
+                path.display()
+            );
+            SocketListener::Unix(listener)
+        }
+        (None, Some(acceptor)) => {
+            // the decrypted clients are only reachable through the TLS front-end
+            let tls_listener =
+                bind_std_listener(bind_addr, reuse_addr, backlog).map_err(dhu_bind_error)?;
+            tls_listener.set_nonblocking(true)?;
+            let (clients_tx, clients_rx) = mpsc::channel(1);
+            tokio::spawn(dhu_tls::serve(
+                TokioTcpListener::from_std(tls_listener)?,
+                clients_tx,
+                acceptor,
+                TcpKeepalive::from_config(&config.read().await),
+            ));
+            info!("{} 🔒 DHU TLS server bound to: <u>{}</u>", NAME, bind_addr);
+            SocketListener::Tls(clients_rx)
+        }
+        (None, None) => {
+            let listener = bind_listener(bind_addr, reuse_addr, backlog).map_err(dhu_bind_error)?;
+            info!("{} 🛰️ DHU TCP server bound to: <u>{}</u>", NAME, bind_addr);
+            SocketListener::Tcp(listener)
+        }
+    };
+
+    info!(
+        "{} ⚙️ proxy queue capacity: <b>{}</> packets",
+        NAME, queue_capacity
+    );
+    {
+        let cfg = config.read().await;
+        if cfg.max_inflight_ops > 0 {
+            set_max_inflight_ops(cfg.max_inflight_ops.into());
+            info!(
+                "{} ⚙️ io_uring operations in flight: max <b>{}</>",
+                NAME,
+                usize::from(cfg.max_inflight_ops).max(MIN_INFLIGHT_OPS)
+            );
+        }
+        if cfg.dhu_fallback_secs > 0 && cfg.dhu_replay_file.is_none() {
+            warn!(
+                "{} ⚠️ dhu_fallback_secs is set without dhu_replay_file, DHU fallback disabled",
+                NAME
+            );
+        }
+    }
+
+    // initialize SSL contexts ahead of the first session
+    {
+        let cfg = config.read().await;
+        if cfg.mitm && cfg.mitm_ssl_prewarm {
+            match ssl_prewarm() {
+                Ok(elapsed) => info!(
+                    "{} 🔒 SSL contexts pre-initialized in {} ms",
+                    NAME,
+                    elapsed.as_millis()
+                ),
+                Err(e) => warn!(
+                    "{} 🔒 SSL pre-initialization failed, initializing per session: {}",
+                    NAME, e
+                ),
+            }
+        }
+    }
 
     // create media tap sinks once — they persist across reconnects (requires mitm=true)
     let persistent_media_sinks: HashMap<u8, MediaSink> = {
@@ -542,9 +1905,30 @@ pub async fn io_loop(
         map
     };
 
+    // USB accessory (HU) kept open across MD sessions with `hu_usb_keep_open`
+    let mut persistent_hu_usb: Option<KeptAccessory> = None;
+    let mut idle_log = IdleLogGate::new(false);
+    // consecutive disassociations of the phone because of a band mismatch
+    let mut band_reconnects: u8 = 0;
+    // waiting for the phone to reconnect on the still bound MD listener
+    let mut quick_reconnect = false;
+
     loop {
         // reload new config
         let config = config.read().await.clone();
+        idle_log.enabled = config.idle_log_backoff;
+        // routine logging of this (possibly idle) cycle
+        let log_idle = match idle_log.check(Instant::now()) {
+            Some(suppressed) if suppressed > 0 => {
+                info!(
+                    "{} 💤 still idle, {} similar cycles were not logged",
+                    NAME, suppressed
+                );
+                true
+            }
+            Some(_) => true,
+            None => false,
+        };
 
         // generate Durations from configured seconds
         let stats_interval = {
@@ -555,20 +1939,70 @@ pub async fn io_loop(
             }
         };
         let read_timeout = Duration::from_secs(config.timeout_secs.into());
+        let max_session = match config.max_session_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs.into())),
+        };
+        // per direction stall timeouts, 0 falls back to `timeout_secs`
+        let stall_timeout = |secs: u16| match secs {
+            0 => read_timeout,
+            secs => Duration::from_secs(secs.into()),
+        };
+        let stall_timeouts = (
+            stall_timeout(config.stall_timeout_to_hu_secs),
+            stall_timeout(config.stall_timeout_to_md_secs),
+        );
+
+        // validate MITM setup before any device is connected, so failures
+        // are reported deterministically instead of in the middle of a stream
+        if config.mitm && !config.runtime_mitm_failed {
+            if let Err(e) = mitm_init_check().await {
+                error!("{} 🔴 MITM initialization failed: {}", NAME, e);
+                match config.mitm_init_policy {
+                    MitmInitPolicy::Fail => {
+                        return Err(ProxyError::MitmInit(e.to_string()));
+                    }
+                    MitmInitPolicy::Passthrough => {
+                        warn!(
+                            "{} ⚠️ mitm_init_policy = passthrough: continuing without MITM",
+                            NAME
+                        );
+                        shared_config.write().await.runtime_mitm_failed = true;
+                    }
+                }
+            }
+        }
 
         let mut client_mac: Option<MacAddress> = None;
-        let mut md_tcp = None;
+        let mut md_stream = None;
         let mut md_usb = None;
-        let mut hu_tcp = None;
+        let mut hu_dhu = None;
         let mut hu_usb = None;
+        let mut hu_ffs = None;
+        // development only: additional DHUs mirroring the MD stream
+        let mut dhu_mirrors = Vec::new();
         let mut usb_used = false;
         // CancellationToken for tcp_bridge tasks spawned for this session
         let mut bridge_cancel: Option<CancellationToken> = None;
 
+        let keepalive = TcpKeepalive::from_config(&config);
         let aa_server_tcp_addr = config.aa_server_tcp_addr.trim().to_string();
         let aa_server_tcp_enabled = !aa_server_tcp_addr.is_empty();
+        // recorded session played to the DHU instead of a phone
+        let mut dhu_replay = if config.dhu {
+            config.dhu_replay_file.clone()
+        } else {
+            None
+        };
 
-        if aa_server_tcp_enabled {
+        if let Some(ref path) = dhu_replay {
+            info!(
+                "{} ▶️ DHU replay mode enabled, no phone will be used: <u>{}</u>",
+                NAME,
+                path.display()
+            );
+            usb_connected.store(false, Ordering::Relaxed);
+        } else if aa_server_tcp_enabled {
             // Direct Android Auto Head Unit Server mode replaces the MD/phone-side
             // USB/Bluetooth/Wi-Fi transport only. Do not connect yet: open the
             // HU/DHU side first, then create a fresh MD TCP connection immediately
@@ -579,6 +2013,36 @@ pub async fn io_loop(
                 NAME, aa_server_tcp_addr
             );
             usb_connected.store(false, Ordering::Relaxed);
+        } else if quick_reconnect {
+            quick_reconnect = false;
+            // the listener is still bound and the phone still knows where to connect
+            let window = Duration::from_secs(config.md_quick_reconnect_secs.into());
+            info!(
+                "{} 🔁 waiting up to {} for the phone to reconnect...",
+                NAME,
+                format_duration(window)
+            );
+            match timeout(
+                window,
+                md_wait_for_connection(&mut md_listener, keepalive, true),
+            )
+            .await
+            {
+                Ok(Ok((s, mac, cancel))) => {
+                    md_stream = Some(s);
+                    md_connected.notify_waiters();
+                    client_mac = mac;
+                    bridge_cancel = Some(cancel);
+                }
+                _ => {
+                    warn!(
+                        "{} 🔁 phone did not reconnect, falling back to a full restart",
+                        NAME
+                    );
+                    let _ = need_restart.send(None);
+                    continue;
+                }
+            }
         } else if config.wired.is_some() {
             info!("{} 💤 waiting for USB or bluetooth handshake...", NAME);
 
@@ -590,79 +2054,218 @@ pub async fn io_loop(
                     }
                     tokio::time::sleep(Duration::from_millis(100)).await;
                 }
-            };
+            };
+
+            tokio::select! {
+                usb_res = usb_future => {
+                    info!("{} 🔌 USB device connected, disabling wireless...", NAME);
+                    usb_connected.store(true, Ordering::Relaxed);
+                    usb_used = true;
+                    md_usb = Some(usb_res);
+                }
+                _ = tcp_start.notified() => {
+                    if log_idle {
+                        info!("{} 🛰️ MD {} server: listening for phone connection...", NAME, md_listener.kind());
+                    }
+                    if let Ok((s, mac, cancel)) = md_wait_for_connection(&mut md_listener, keepalive, log_idle).await {
+                        md_stream = Some(s);
+                        md_connected.notify_waiters();
+                        log_credentials_to_connect();
+                        client_mac = mac;
+                        bridge_cancel = Some(cancel);
+                    } else {
+                        let _ = need_restart.send(None);
+                        continue;
+                    }
+                }
+            }
+        } else {
+            if log_idle {
+                info!("{} 💤 waiting for bluetooth handshake...", NAME);
+            }
+            // headless testing: give up waiting for a phone and replay to the DHU
+            let fallback = match config.dhu_replay_file {
+                Some(ref path) if config.dhu_fallback_secs > 0 => Some(path),
+                _ => None,
+            };
+            let handshake_done = match fallback {
+                Some(_) => timeout(
+                    Duration::from_secs(config.dhu_fallback_secs.into()),
+                    tcp_start.notified(),
+                )
+                .await
+                .is_ok(),
+                None => {
+                    tcp_start.notified().await;
+                    true
+                }
+            };
+
+            if !handshake_done {
+                let path = fallback.unwrap();
+                warn!(
+                    "{} ⚠️ no phone within {} s, falling back to DHU replay mode: <u>{}</u>",
+                    NAME,
+                    config.dhu_fallback_secs,
+                    path.display()
+                );
+                dhu_replay = Some(path.clone());
+                usb_connected.store(false, Ordering::Relaxed);
+            } else {
+                if log_idle {
+                    info!(
+                        "{} 🛰️ MD {} server: listening for phone connection...",
+                        NAME,
+                        md_listener.kind()
+                    );
+                }
+                if let Ok((s, mac, cancel)) =
+                    md_wait_for_connection(&mut md_listener, keepalive, log_idle).await
+                {
+                    md_stream = Some(s);
+                    md_connected.notify_waiters();
+                    log_credentials_to_connect();
+                    // MAC address of the connected client for later disassociation
+                    client_mac = mac;
+                    usb_connected.store(false, Ordering::Relaxed);
+                    bridge_cancel = Some(cancel);
+                } else {
+                    // notify main loop to restart
+                    let _ = need_restart.send(None);
+                    continue;
+                }
+            }
+        }
 
-            tokio::select! {
-                usb_res = usb_future => {
-                    info!("{} 🔌 USB device connected, disabling wireless...", NAME);
-                    usb_connected.store(true, Ordering::Relaxed);
-                    usb_used = true;
-                    md_usb = Some(usb_res);
-                }
-                _ = tcp_start.notified() => {
-                    info!("{} 🛰️ MD TCP server: listening for phone connection...", NAME);
-                    if let Ok((s, ip, cancel)) = tcp_wait_for_connection(&mut md_listener.as_mut().unwrap(), true).await {
-                        md_tcp = Some(s);
-                        client_mac = mac_from_ipv4(ip).await.unwrap_or(None);
-                        bridge_cancel = Some(cancel);
-                    } else {
-                        let _ = need_restart.send(None);
-                        continue;
+        // make sure the phone is not on the other radio of a dual-band AP
+        if let Some(mac) = client_mac.filter(|_| config.band_check != BandCheck::Off) {
+            match wifi_band::check_client_band(mac, config.band.trim()).await {
+                Some(iface)
+                    if config.band_check == BandCheck::Reconnect
+                        && band_reconnects < MAX_BAND_RECONNECTS =>
+                {
+                    band_reconnects += 1;
+                    info!(
+                        "{} 📶 disassociating WiFi client to reconnect: {} (attempt {} of {})",
+                        NAME, mac, band_reconnects, MAX_BAND_RECONNECTS
+                    );
+                    if !wifi_band::disassociate(&iface, mac).await {
+                        warn!("{} 📶 hostapd failed to disassociate {}", NAME, mac);
                     }
+                    if let Some(cancel) = bridge_cancel.take() {
+                        cancel.cancel();
+                    }
+                    let _ = need_restart.send(None);
+                    continue;
                 }
+                Some(_) if config.band_check == BandCheck::Reconnect => warn!(
+                    "{} 📶 client {} is still on the wrong band after {} reconnects, continuing",
+                    NAME, mac, MAX_BAND_RECONNECTS
+                ),
+                Some(_) => {}
+                None => band_reconnects = 0,
             }
-        } else {
-            info!("{} 💤 waiting for bluetooth handshake...", NAME);
-            tcp_start.notified().await;
+        }
 
-            info!(
-                "{} 🛰️ MD TCP server: listening for phone connection...",
-                NAME
-            );
-            if let Ok((s, ip, cancel)) =
-                tcp_wait_for_connection(&mut md_listener.as_mut().unwrap(), true).await
-            {
-                md_tcp = Some(s);
-                // Get MAC address of the connected client for later disassociation
-                client_mac = mac_from_ipv4(ip).await.unwrap_or(None);
-                usb_connected.store(false, Ordering::Relaxed);
-                bridge_cancel = Some(cancel);
+        if config.dhu || dhu_replay.is_some() {
+            if log_idle {
+                info!(
+                    "{} 🛰️ DHU {} server: listening for `Desktop Head Unit` connection...",
+                    NAME,
+                    dhu_listener.kind()
+                );
+            }
+            if let Ok(hu) = dhu_wait_for_connection(&mut dhu_listener, keepalive, log_idle).await {
+                hu_dhu = Some(hu);
             } else {
                 // notify main loop to restart
                 let _ = need_restart.send(None);
                 continue;
             }
-        }
-
-        if config.dhu {
+            if config.dhu_mirrors > 0 && dhu_replay.is_none() {
+                if !matches!(dhu_listener, SocketListener::Tcp(_)) {
+                    warn!(
+                        "{} 🪞 dhu_mirrors requires the plain DHU TCP listener, DHU mirroring disabled",
+                        NAME
+                    );
+                } else if config.mitm && !config.runtime_mitm_failed {
+                    for i in 1..=config.dhu_mirrors {
+                        info!(
+                            "{} 🪞 DHU TCP server: waiting for mirror DHU #{} of {}...",
+                            NAME, i, config.dhu_mirrors
+                        );
+                        match dhu_wait_for_connection(&mut dhu_listener, keepalive, true).await {
+                            Ok(HuDevice::Tcp(s)) => dhu_mirrors.push(s),
+                            _ => {
+                                warn!(
+                                    "{} 🪞 continuing with {} mirror DHU(s)",
+                                    NAME,
+                                    dhu_mirrors.len()
+                                );
+                                break;
+                            }
+                        }
+                    }
+                } else {
+                    warn!(
+                        "{} 🪞 dhu_mirrors requires mitm = true, DHU mirroring disabled",
+                        NAME
+                    );
+                }
+            }
+        } else if config.hu_usb_transport == HuUsbTransport::Functionfs {
             info!(
-                "{} 🛰️ DHU TCP server: listening for `Desktop Head Unit` connection...",
-                NAME
+                "{} 📂 Opening FunctionFS endpoints: <u>{}</u>",
+                NAME,
+                config.functionfs_dir.display()
             );
-            if let Ok((s, _, _)) =
-                tcp_wait_for_connection(&mut dhu_listener.as_mut().unwrap(), false).await
-            {
-                hu_tcp = Some(s);
-            } else {
-                // notify main loop to restart
-                let _ = need_restart.send(None);
-                continue;
+            match FunctionFs::open(&config.functionfs_dir).await {
+                Ok(ffs) => hu_ffs = Some(Rc::new(ffs)),
+                Err(e) => {
+                    error!("{} 🔴 Error opening FunctionFS endpoints: {}", NAME, e);
+                    // notify main loop to restart
+                    let _ = need_restart.send(None);
+                    continue;
+                }
             }
+        } else if let Some(kept) = persistent_hu_usb
+            .as_ref()
+            .filter(|kept| config.hu_usb_keep_open && kept.path == config.usb_accessory_path)
+        {
+            info!(
+                "{} 📂 Reusing open USB accessory device: <u>{}</u>",
+                NAME,
+                kept.path.display()
+            );
+            hu_usb = Some(kept.file.clone());
         } else {
             info!(
                 "{} 📂 Opening USB accessory device: <u>{}</u>",
-                NAME, USB_ACCESSORY_PATH
+                NAME,
+                config.usb_accessory_path.display()
             );
-            match OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(false)
-                .open(USB_ACCESSORY_PATH)
-                .await
+            match open_usb_accessory(
+                &config.usb_accessory_path,
+                config.usb_accessory_open_retries,
+                Duration::from_millis(config.usb_accessory_open_retry_ms.into()),
+            )
+            .await
             {
-                Ok(s) => hu_usb = Some(s),
+                Ok(s) => {
+                    let s = Rc::new(s);
+                    if config.hu_usb_keep_open {
+                        persistent_hu_usb = Some(KeptAccessory::new(
+                            s.clone(),
+                            config.usb_accessory_path.clone(),
+                            read_buffer_len(config.hu_read_buffer_kb, "HU"),
+                            queue_capacity,
+                            dup_frame_detector(&config),
+                        ));
+                    }
+                    hu_usb = Some(s);
+                }
                 Err(e) => {
-                    error!("{} 🔴 Error opening USB accessory: {}", NAME, e);
+                    error!("{} 🔴 {}", NAME, e);
                     // notify main loop to restart
                     let _ = need_restart.send(None);
                     continue;
@@ -670,10 +2273,10 @@ pub async fn io_loop(
             }
         }
 
-        if aa_server_tcp_enabled {
-            match tcp_connect_to_aa_server(&aa_server_tcp_addr).await {
+        if aa_server_tcp_enabled && dhu_replay.is_none() {
+            match tcp_connect_to_aa_server(&aa_server_tcp_addr, keepalive).await {
                 Ok(s) => {
-                    md_tcp = Some(s);
+                    md_stream = Some(MdStream::Tcp(Rc::new(s)));
                 }
                 Err(e) => {
                     error!(
@@ -687,8 +2290,14 @@ pub async fn io_loop(
             }
         }
 
-        info!("{} ♾️ Starting to proxy data between HU and MD...", NAME);
-        let started = Instant::now();
+        // both sides are connected, the idle cycle is over
+        idle_log.reset();
+        let clock = EventClock::new(config.log_wall_clock);
+        info!(
+            "{} ♾️ Starting to proxy data between HU and MD... {}",
+            NAME,
+            clock.stamp()
+        );
 
         // `read` and `write` take owned buffers (more on that later), and
         // there's no "per-socket" buffer, so they actually take `&self`.
@@ -705,18 +2314,22 @@ pub async fn io_loop(
         let mut reader_hu;
         let mut reader_md;
         // these will be used for cleanup
-        let mut md_tcp_stream = None;
-        let mut hu_tcp_stream = None;
+        let mut md_socket = None;
 
         // MITM/proxy mpsc channels:
         // Keep enough in-flight capacity so reader tasks do not stall under bursty
         // media traffic and starve control-channel forwarding.
-        let (tx_hu, rx_md): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(MITM_QUEUE_CAPACITY);
-        let (tx_md, rx_hu): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(MITM_QUEUE_CAPACITY);
-        let (txr_hu, rxr_md): (Sender<Packet>, Receiver<Packet>) =
-            mpsc::channel(MITM_QUEUE_CAPACITY);
-        let (txr_md, rxr_hu): (Sender<Packet>, Receiver<Packet>) =
-            mpsc::channel(MITM_QUEUE_CAPACITY);
+        let (tx_hu, rx_md): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(queue_capacity);
+        let (tx_md, rx_hu): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(queue_capacity);
+        let (txr_hu, rxr_md): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(queue_capacity);
+        let (txr_md, rxr_hu): (Sender<Packet>, Receiver<Packet>) = mpsc::channel(queue_capacity);
+        // watched for saturation, without keeping the queues open
+        let queues = vec![
+            ("car -> phone", tx_hu.downgrade()),
+            ("phone -> car", tx_md.downgrade()),
+            ("car reader", txr_hu.downgrade()),
+            ("phone reader", txr_md.downgrade()),
+        ];
 
         // selecting I/O device for reading and writing
         // and creating desired objects for proxy functions
@@ -726,46 +2339,215 @@ pub async fn io_loop(
         let md_w;
         let mut usb_dev = None;
         // MD transfer device
-        if let Some(md) = md_usb {
+        if dhu_replay.is_some() {
+            // no MD device, the replay task is feeding the HU side
+            md_r = None;
+            md_w = None;
+        } else if let Some(md) = md_usb {
             // MD over wired USB
             let (dev, usb_r, usb_w) = md;
             usb_dev = Some(dev);
             let usb_r = Rc::new(RefCell::new(usb_r));
             let usb_w = Rc::new(RefCell::new(usb_w));
-            md_r = IoDevice::UsbReader(usb_r, PhantomData::<TcpStream>);
-            md_w = IoDevice::UsbWriter(usb_w, PhantomData::<TcpStream>);
+            md_r = Some(IoDevice::UsbReader(usb_r, PhantomData::<TcpStream>));
+            md_w = Some(IoDevice::UsbWriter(usb_w, PhantomData::<TcpStream>));
         } else {
-            // MD using TCP stream (wireless)
-            let md = Rc::new(md_tcp.unwrap());
-            md_r = IoDevice::EndpointIo(md.clone());
-            md_w = IoDevice::EndpointIo(md.clone());
-            md_tcp_stream = Some(md.clone());
+            let md = md_stream.unwrap();
+            match md {
+                MdStream::Tcp(ref s) => {
+                    // MD using TCP stream (wireless)
+                    md_r = Some(IoDevice::EndpointIo(s.clone()));
+                    md_w = Some(IoDevice::EndpointIo(s.clone()));
+                }
+                MdStream::Unix(ref s) => {
+                    // MD relayed over a Unix domain socket
+                    md_r = Some(IoDevice::UnixStreamIo(s.clone()));
+                    md_w = Some(IoDevice::UnixStreamIo(s.clone()));
+                }
+            }
+            md_socket = Some(md);
         }
         // HU transfer device
-        if let Some(hu) = hu_usb {
-            // HU connected directly via USB
-            let hu = Rc::new(hu);
-            hu_r = IoDevice::EndpointIo(hu.clone());
-            hu_w = IoDevice::EndpointIo(hu.clone());
+        let hu_device = if let Some(hu) = hu_ffs {
+            HuDevice::FunctionFs(hu)
+        } else if let Some(hu) = hu_usb {
+            HuDevice::Usb(hu)
         } else {
-            // Head Unit Emulator via TCP
-            let hu = Rc::new(hu_tcp.unwrap());
-            hu_r = IoDevice::TcpStreamIo(hu.clone());
-            hu_w = IoDevice::TcpStreamIo(hu.clone());
-            hu_tcp_stream = Some(hu.clone());
+            hu_dhu.unwrap()
+        };
+        match hu_device {
+            HuDevice::FunctionFs(ref hu) => {
+                // HU connected via FunctionFS endpoints
+                hu_r = IoDevice::FunctionFsIo(hu.clone());
+                hu_w = IoDevice::FunctionFsIo(hu.clone());
+            }
+            HuDevice::Usb(ref hu) => {
+                // HU connected directly via USB
+                hu_r = IoDevice::EndpointIo(hu.clone());
+                hu_w = IoDevice::EndpointIo(hu.clone());
+            }
+            HuDevice::Tcp(ref hu) => {
+                // Head Unit Emulator via TCP
+                hu_r = IoDevice::TcpStreamIo(hu.clone());
+                hu_w = IoDevice::TcpStreamIo(hu.clone());
+            }
+            HuDevice::Unix(ref hu) => {
+                // Head Unit Emulator via Unix domain socket
+                hu_r = IoDevice::UnixStreamIo(hu.clone());
+                hu_w = IoDevice::UnixStreamIo(hu.clone());
+            }
         }
 
-        // handling battery in JSON
-        if config.mitm && config.ev {
+        // handling battery in JSON and injected sensor data
+        if config.mitm && (config.ev || config.sensor_injection) {
             let mut tx_lock = tx.lock().await;
             *tx_lock = Some(tx_hu.clone());
         }
 
+        // optional capture of the whole session; message ids are only decoded
+        // when the payloads are decrypted (not in passthrough mode)
+        let decrypted = config.mitm && !shared_config.read().await.runtime_mitm_failed;
+        let session_capture = match config.capture_dir {
+            Some(ref dir) => match SessionCapture::start(dir, decrypted) {
+                Ok((c, path)) => {
+                    info!(
+                        "{} 💾 Capturing session to: <u>{}</u>",
+                        NAME,
+                        path.display()
+                    );
+                    Some(c)
+                }
+                Err(e) => {
+                    error!(
+                        "{} 🔴 Cannot start session capture in {}: {}",
+                        NAME,
+                        dir.display(),
+                        e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+
+        // lets the session tasks below return on their own at the end
+        let session_stop = SessionStop::new();
+        let queue_monitor =
+            tokio::spawn(session_stop.wrap(queue_pressure_monitor(queues, queue_capacity)));
+        // video channels are learned again from this session's SDR
+        video_stats::reset();
+        latency_stats::reset(config.latency_stats);
+        session_summary::reset();
+        // sensors are injected again once this session's SDR advertises them
+        sensor_feed::set_injected_sensors(false, false);
+        // a pause never carries over, its limit is announced again by the phone
+        if let Some(paused) = resume_transfer() {
+            info!(
+                "{} ▶️ transfer resumed at session start, was paused for {} ms",
+                NAME,
+                paused.as_millis()
+            );
+        }
+        set_transfer_pause_limit(None);
+        reset_transmission_state();
+        #[cfg(feature = "metrics")]
+        crate::metrics::reset_channel_labels();
+
         // dedicated reading threads:
-        reader_hu = tokio_uring::spawn(endpoint_reader(hu_r, txr_hu, true));
-        reader_md = tokio_uring::spawn(endpoint_reader(md_r, txr_md, false));
+        // optional duplicate frame detection on both readers
+        let dup_detector = || dup_frame_detector(&config);
+        let hu_buffer_len = read_buffer_len(config.hu_read_buffer_kb, "HU");
+        let md_buffer_len = read_buffer_len(config.md_read_buffer_kb, "MD");
+        info!(
+            "{} ⚙️ read buffers: HU: <b>{} KiB</>, MD: <b>{} KiB</>",
+            NAME,
+            hu_buffer_len / 1024,
+            md_buffer_len / 1024
+        );
+        // mirror DHUs get their own HU side proxy; tasks are aborted with the session
+        let mut mirror_tasks = Vec::new();
+        let mut mirror_streams = Vec::new();
+        let rx_hu = if dhu_mirrors.is_empty() {
+            rx_hu
+        } else {
+            let (primary_tx, primary_rx) = mpsc::channel(queue_capacity);
+            let mut mirror_txs = Vec::new();
+            for stream in dhu_mirrors.drain(..) {
+                let (mirror_tx, mirror_rx) = mpsc::channel(queue_capacity);
+                let (mirror_txr, mirror_rxr) = mpsc::channel(queue_capacity);
+                let (mirror_out_tx, mirror_out_rx) = mpsc::channel(queue_capacity);
+                // the channels and SDR learned from the mirror's own SDR stay
+                // with its proxy, the primary's are what the MD knows
+                let mirror_sensor_channel = Arc::new(Mutex::new(None));
+                let mirror_input_channel = Arc::new(Mutex::new(None));
+                let mirror_sdr: SharedServiceDiscoveryResponse = Arc::new(RwLock::new(None));
+                mirror_tasks.push(tokio_uring::spawn(session_stop.track(endpoint_reader(
+                    IoDevice::<TcpStream>::TcpStreamIo(stream.clone()),
+                    mirror_txr,
+                    true,
+                    hu_buffer_len,
+                    None,
+                    session_stop.token(),
+                ))));
+                mirror_tasks.push(tokio_uring::spawn(session_stop.track(proxy(
+                    ProxyType::HeadUnit,
+                    IoDevice::<TcpStream>::TcpStreamIo(stream.clone()),
+                    Arc::new(AtomicUsize::new(0)),
+                    mirror_out_tx,
+                    mirror_rx,
+                    mirror_rxr,
+                    shared_config.clone(),
+                    mirror_sensor_channel,
+                    mirror_input_channel,
+                    last_battery.clone(),
+                    last_speed.clone(),
+                    mirror_sdr,
+                    ev_tx.clone(),
+                    None,
+                    script_registry.clone(),
+                    HashMap::new(),
+                    ws_event_tx.clone(),
+                    None,
+                    session_stop.token(),
+                ))));
+                mirror_tasks.push(tokio::spawn(session_stop.wrap(dhu_fanout::merge_input(
+                    mirror_out_rx,
+                    tx_hu.clone(),
+                    input_channel.clone(),
+                ))));
+                mirror_txs.push(mirror_tx);
+                mirror_streams.push(stream);
+            }
+            info!(
+                "{} 🪞 mirroring the MD stream to {} additional DHU(s)",
+                NAME,
+                mirror_streams.len()
+            );
+            mirror_tasks.push(tokio::spawn(
+                session_stop.wrap(dhu_fanout::fan_out(rx_hu, primary_tx, mirror_txs)),
+            ));
+            primary_rx
+        };
+        // a kept accessory is read by its own reader, which outlives the session
+        let kept_hu = persistent_hu_usb
+            .as_ref()
+            .filter(|_| matches!(hu_device, HuDevice::Usb(_)));
+        reader_hu = match kept_hu {
+            Some(kept) => {
+                kept.attach(txr_hu);
+                tokio_uring::spawn(session_stop.wrap(kept.watch()))
+            }
+            None => tokio_uring::spawn(session_stop.track(endpoint_reader(
+                hu_r,
+                txr_hu,
+                true,
+                hu_buffer_len,
+                dup_detector(),
+                session_stop.token(),
+            ))),
+        };
         // main processing threads:
-        from_file = tokio_uring::spawn(proxy(
+        from_file = tokio_uring::spawn(session_stop.track(proxy(
             ProxyType::HeadUnit,
             hu_w,
             file_bytes.clone(),
@@ -783,39 +2565,102 @@ pub async fn io_loop(
             script_registry.clone(),
             persistent_media_sinks.clone(),
             ws_event_tx.clone(),
-        ));
-        from_stream = tokio_uring::spawn(proxy(
-            ProxyType::MobileDevice,
-            md_w,
-            stream_bytes.clone(),
-            tx_md.clone(),
-            rx_md,
-            rxr_hu,
-            shared_config.clone(),
-            sensor_channel.clone(),
-            input_channel.clone(),
-            last_battery.clone(),
-            last_speed.clone(),
-            last_service_discovery_response.clone(),
-            ev_tx.clone(),
-            Some(tx_md.clone()),
-            script_registry.clone(),
-            persistent_media_sinks.clone(),
-            ws_event_tx.clone(),
-        ));
+            session_capture.clone(),
+            session_stop.token(),
+        )));
+        if let (Some(md_r), Some(md_w)) = (md_r, md_w) {
+            reader_md = tokio_uring::spawn(session_stop.track(endpoint_reader(
+                md_r,
+                txr_md,
+                false,
+                md_buffer_len,
+                dup_detector(),
+                session_stop.token(),
+            )));
+            from_stream = tokio_uring::spawn(session_stop.track(proxy(
+                ProxyType::MobileDevice,
+                md_w,
+                stream_bytes.clone(),
+                tx_md.clone(),
+                rx_md,
+                rxr_hu,
+                shared_config.clone(),
+                sensor_channel.clone(),
+                input_channel.clone(),
+                last_battery.clone(),
+                last_speed.clone(),
+                last_service_discovery_response.clone(),
+                ev_tx.clone(),
+                Some(tx_md.clone()),
+                script_registry.clone(),
+                persistent_media_sinks.clone(),
+                ws_event_tx.clone(),
+                session_capture.clone(),
+                session_stop.token(),
+            )));
+        } else {
+            // DHU replay: the replay task is taking the place of the whole MD side
+            reader_md = tokio_uring::spawn(session_stop.wrap(capture::dhu_replay(
+                dhu_replay.clone().unwrap(),
+                tx_md.clone(),
+                rx_md,
+                stream_bytes.clone(),
+                config.dhu_replay_fast,
+            )));
+            from_stream = tokio_uring::spawn(std::future::pending::<Result<()>>());
+        }
+
+        // moving/parked split of this session's transfer
+        let motion_stats = config.motion_stats.then(|| {
+            reset_vehicle_motion();
+            Arc::new(std::sync::Mutex::new(MotionStats::default()))
+        });
 
         // Thread for monitoring transfer
-        let mut monitor = tokio::spawn(transfer_monitor(
+        let stats_log = config
+            .stats_log_file
+            .clone()
+            .map(|path| StatsLog::new(path, u64::from(config.stats_log_max_kb) * 1024));
+        #[cfg(feature = "metrics")]
+        crate::metrics::start_session(file_bytes.clone(), stream_bytes.clone());
+        let mut session_transfer_stats = None;
+        if let Some(ref subscription) = transfer_stats {
+            let (stats_tx, stats_rx) = mpsc::channel(16);
+            // a subscriber not taking the sessions must not hold up this one
+            match subscription.sessions.try_send(stats_rx) {
+                Ok(()) => {
+                    session_transfer_stats =
+                        Some(TransferStatsStream::new(stats_tx, subscription.interval))
+                }
+                Err(mpsc::error::TrySendError::Full(_)) => warn!(
+                    "{} transfer stats subscriber is not keeping up, no stats for this session",
+                    NAME
+                ),
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    debug!("{} transfer stats subscriber is gone", NAME)
+                }
+            }
+        }
+        let poll_interval = monitor_poll_interval(
+            config.monitor_poll_ms,
             stats_interval,
-            file_bytes,
-            stream_bytes,
-            read_timeout,
+            stall_timeouts.0.min(stall_timeouts.1),
+        );
+        let mut monitor = tokio::spawn(session_stop.wrap(transfer_monitor(
+            stats_report_interval(stats_interval, poll_interval),
+            file_bytes.clone(),
+            stream_bytes.clone(),
+            stall_timeouts,
+            poll_interval,
+            stats_log,
+            motion_stats.clone(),
             shared_config.clone(),
-        ));
+            session_transfer_stats,
+        )));
 
         // Background task to interrupt wireless session if USB is plugged in
         let wired_clone = config.wired.clone();
-        let mut usb_monitor = tokio::spawn(async move {
+        let mut usb_monitor = tokio::spawn(session_stop.wrap(async move {
             if let Some(wired) = wired_clone {
                 if !usb_used {
                     loop {
@@ -828,17 +2673,41 @@ pub async fn io_loop(
             }
             let pending: std::future::Pending<Result<()>> = std::future::pending();
             pending.await
-        });
+        }));
 
         // Stop as soon as one of them errors
-        let res = tokio::try_join!(
-            flatten(&mut reader_hu),
-            flatten(&mut reader_md),
-            flatten(&mut from_file),
-            flatten(&mut from_stream),
-            flatten(&mut monitor),
-            flatten(&mut usb_monitor)
-        );
+        let joined = async {
+            tokio::try_join!(
+                flatten(&mut reader_hu),
+                flatten(&mut reader_md),
+                flatten(&mut from_file),
+                flatten(&mut from_stream),
+                flatten(&mut monitor),
+                flatten(&mut usb_monitor)
+            )
+            .map(|_| ())
+        };
+        // or recycle the session after `max_session_secs`, the tasks are aborted below
+        let (res, recycled) = match max_session {
+            Some(limit) => match timeout(limit, joined).await {
+                Ok(res) => (res, false),
+                Err(_) => {
+                    info!(
+                        "{} ♻️ maximum session duration of {} reached, recycling the session",
+                        NAME,
+                        format_duration(limit)
+                    );
+                    (Ok(()), true)
+                }
+            },
+            None => (joined.await, false),
+        };
+        let end_reason = match res {
+            Ok(_) if recycled => "max session duration reached".to_string(),
+            Ok(_) => "completed".to_string(),
+            Err(ref e) => e.to_string(),
+        };
+        let session_failed = res.is_err();
         if let Err(e) = res {
             error!("{} 🔴 Connection error: {}", NAME, e);
             if let Some(dev) = usb_dev {
@@ -847,26 +2716,101 @@ pub async fn io_loop(
             }
         }
 
+        // Quick MD reconnect: the session failed on the wireless phone side (the
+        // HU side tasks were still running), so the phone is accepted again on
+        // the bound listener without a new bluetooth handshake. The HU side
+        // session is restarted as for every session.
+        let md_side_failed = !reader_hu.is_finished() && !from_file.is_finished();
+        if config.md_quick_reconnect_secs > 0
+            && session_failed
+            && md_side_failed
+            && md_socket.is_some()
+            && !aa_server_tcp_enabled
+            && shared_config.read().await.action_requested.is_none()
+        {
+            info!(
+                "{} 🔁 phone connection lost, accepting it again without a bluetooth handshake",
+                NAME
+            );
+            quick_reconnect = true;
+        }
+
+        // a pause must not carry over to the next session
+        if let Some(paused) = resume_transfer() {
+            info!(
+                "{} ▶️ transfer resumed at session end, was paused for {} ms",
+                NAME,
+                paused.as_millis()
+            );
+        }
+
         // Cancel all tcp_bridge tasks spawned for this session before cleanup
         if let Some(cancel) = bridge_cancel.take() {
             cancel.cancel();
         }
 
+        // With cooperative shutdown the tasks return on their own, dropping
+        // the `Rc<TcpStream>`/`Rc<File>` they hold before we go on
+        if config.cooperative_shutdown {
+            let started = Instant::now();
+            if session_stop.stop(SESSION_STOP_TIMEOUT).await {
+                debug!(
+                    "{} session tasks stopped in {} ms",
+                    NAME,
+                    started.elapsed().as_millis()
+                );
+            } else {
+                warn!(
+                    "{} session tasks did not stop within {:?}, aborting them",
+                    NAME, SESSION_STOP_TIMEOUT
+                );
+            }
+        }
+
+        // Health check of a kept-open USB accessory, on its own state: it is
+        // reused for the next session while its reader runs without errors,
+        // the device node is still present and no action (restart/reboot/stop)
+        // was requested. Otherwise it is reopened, as it is when
+        // `usb_accessory_path` was changed in the meantime.
+        if let Some(ref kept) = persistent_hu_usb {
+            kept.detach();
+            let healthy = kept.healthy();
+            let node_present = kept.path.exists();
+            let action = shared_config.read().await.action_requested.is_some();
+            if !healthy || !node_present || action || !config.hu_usb_keep_open {
+                info!(
+                    "{} 📂 USB accessory will be reopened (healthy: {}, device present: {}, action requested: {})",
+                    NAME, healthy, node_present, action
+                );
+                persistent_hu_usb = None;
+            } else {
+                info!(
+                    "{} 📂 USB accessory healthy, keeping it open for the next session",
+                    NAME
+                );
+            }
+        }
+
         // Make sure the reference count drops to zero and the socket is
         // freed by aborting both tasks (which both hold a `Rc<TcpStream>`
-        // for each direction)
+        // for each direction); no-op for tasks which already returned
         reader_hu.abort();
         reader_md.abort();
         from_file.abort();
         from_stream.abort();
         monitor.abort();
         usb_monitor.abort();
+        queue_monitor.abort();
+        for task in mirror_tasks {
+            task.abort();
+        }
 
         // make sure TCP connections are closed before next connection attempts
-        if let Some(stream) = md_tcp_stream {
-            let _ = stream.shutdown(std::net::Shutdown::Both);
+        if let Some(md) = md_socket {
+            md.shutdown();
         }
-        if let Some(stream) = hu_tcp_stream {
+        hu_device.shutdown();
+        for stream in mirror_streams {
             let _ = stream.shutdown(std::net::Shutdown::Both);
         }
 
@@ -875,12 +2819,12 @@ pub async fn io_loop(
         // or when the stop_on_disconnect option was used.
         // Otherwise, the WiFi/AA connection remains hanging and the phone
         // won't switch back to the regular WiFi.
-        if let Some(mac) = client_mac {
+        // Not while waiting for a quick reconnect of the same phone.
+        if let Some(mac) = client_mac.filter(|_| !quick_reconnect) {
             info!("{} disassociating WiFi client: {}", NAME, mac);
-
-            let _ = Command::new("/usr/bin/hostapd_cli")
-                .args(&["disassociate", &mac.to_string()])
-                .spawn();
+            if !wifi_band::disassociate(&config.iface, mac).await {
+                warn!("{} hostapd failed to disassociate {}", NAME, mac);
+            }
         }
 
         // set webserver context EV stuff to None
@@ -892,18 +2836,96 @@ pub async fn io_loop(
         *ic_lock = None;
         // stop EV battery logger if neded
         if config.ev_battery_logger.is_some() {
-            ev_tx.send(EvTaskCommand::Stop).await?;
+            ev_tx
+                .send(EvTaskCommand::Stop)
+                .await
+                .map_err(ProxyError::other)?;
         }
 
+        #[cfg(feature = "metrics")]
+        crate::metrics::end_session();
         info!(
-            "{} ⌛ session time: {}",
+            "{} ⌛ session time: {} {}",
             NAME,
-            format_duration(started.elapsed()).to_string()
+            format_duration(clock.elapsed()).to_string(),
+            clock.stamp()
         );
+        if let Some(motion_stats) = motion_stats {
+            info!("{} ⌛ {}", NAME, motion_stats.lock().unwrap());
+        }
+        if let Some(video) = video_stats::period_summary() {
+            info!("{} ⌛ {}", NAME, video);
+        }
+        if let Some(latency) = latency_stats::period_summary() {
+            info!("{} ⌛ {}", NAME, latency);
+        }
+        // remember what a working session negotiated
+        let protocol_version = take_negotiated_protocol_version();
+        if config.session_summary {
+            let summary = SessionEnded::new(
+                clock.elapsed(),
+                file_bytes.load(Ordering::Relaxed),
+                stream_bytes.load(Ordering::Relaxed),
+                end_reason,
+                client_mac.map(|mac| mac.to_string()),
+                Negotiated {
+                    protocol_version: protocol_version.map(|v| v.to_string()),
+                    mitm: config.mitm && !shared_config.read().await.runtime_mitm_failed,
+                    transport: if config.dhu || dhu_replay.is_some() {
+                        "dhu"
+                    } else if usb_used {
+                        "usb"
+                    } else {
+                        "wireless"
+                    },
+                },
+                video_stats::summary(),
+            );
+            let json = summary.to_json();
+            info!("{} ⌛ session summary: {}", NAME, json);
+            if let Some(ref path) = config.session_summary_file {
+                if let Err(e) = summary.append_to(path) {
+                    warn!(
+                        "{} cannot write session summary to {}: {}",
+                        NAME,
+                        path.display(),
+                        e
+                    );
+                }
+            }
+            let _ = ws_event_tx.send(ServerEvent {
+                topic: session_summary::TOPIC.to_string(),
+                payload: json,
+            });
+        }
+        if let Some(ref path) = config.last_known_good_file {
+            if clock.elapsed() >= last_known_good::MIN_SESSION_DURATION {
+                if let Some(ref sdr) = *last_service_discovery_response.read().await {
+                    let cfg = shared_config.read().await.clone();
+                    let lkg =
+                        LastKnownGood::from_session(&cfg, sdr, protocol_version, clock.elapsed());
+                    last_known_good::checkpoint(path, &lkg);
+                }
+            }
+        }
+        if let Some(ref c) = session_capture {
+            if c.dropped() > 0 {
+                warn!(
+                    "{} 💾 session capture: {} packets dropped (writer too slow)",
+                    NAME,
+                    c.dropped()
+                );
+            }
+        }
+        // closes the capture file once the proxy tasks are gone
+        drop(session_capture);
         // obtain action for passing it to broadcast sender
         let action = shared_config.read().await.action_requested.clone();
         // stream(s) closed, notify main loop to restart
-        let _ = need_restart.send(action);
+        // (unless only the MD connection is accepted again)
+        if !quick_reconnect {
+            let _ = need_restart.send(action);
+        }
 
         // Reset usb_connected so main loop can resume wireless broadcasting
         usb_connected.store(false, Ordering::Relaxed);
@@ -911,6 +2933,378 @@ pub async fn io_loop(
 
     #[allow(unreachable_code)]
     // terminate ev client handler
-    ev_tx.send(EvTaskCommand::Terminate).await?;
-    client_handler.await?;
+    ev_tx
+        .send(EvTaskCommand::Terminate)
+        .await
+        .map_err(ProxyError::other)?;
+    client_handler.await.map_err(ProxyError::other)?;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mitm::{ENCRYPTED, FRAME_TYPE_MASK};
+
+    #[test]
+    fn stop_releases_a_reader_parked_in_a_read() {
+        tokio_uring::start(async {
+            let (ours, _theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+            let stream = Rc::new(UnixStream::from_std(ours));
+            let (tx, _rx) = mpsc::channel(4);
+
+            let session_stop = SessionStop::new();
+            let reader = tokio_uring::spawn(session_stop.track(endpoint_reader(
+                IoDevice::<TcpStream>::UnixStreamIo(stream.clone()),
+                tx,
+                true,
+                64,
+                None,
+                session_stop.token(),
+            )));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert_eq!(Rc::strong_count(&stream), 2);
+
+            // returns well before the timeout, without falling back to abort
+            let started = Instant::now();
+            assert!(session_stop.stop(SESSION_STOP_TIMEOUT).await);
+            assert!(started.elapsed() < Duration::from_millis(200));
+            assert_eq!(Rc::strong_count(&stream), 1);
+            assert!(reader.await.unwrap().is_ok());
+        });
+    }
+
+    #[test]
+    fn functionfs_endpoints_read_and_write() {
+        use std::ffi::CString;
+        use std::io::{Read, Write};
+        use std::os::unix::ffi::OsStrExt;
+
+        // a pair of fifos standing in for the bulk endpoints
+        let dir = std::env::temp_dir().join(format!("aa-proxy-ffs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for ep in [FUNCTIONFS_EP_OUT, FUNCTIONFS_EP_IN] {
+            let path = dir.join(ep);
+            let _ = std::fs::remove_file(&path);
+            let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+            assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        }
+        // host side of both endpoints, opened read-write so opening never blocks
+        let host = |ep| {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(dir.join(ep))
+                .unwrap()
+        };
+        let mut host_out = host(FUNCTIONFS_EP_OUT);
+        let mut host_in = host(FUNCTIONFS_EP_IN);
+
+        tokio_uring::start(async {
+            let ffs = FunctionFs::open(&dir).await.unwrap();
+
+            host_out.write_all(b"from hu").unwrap();
+            let (n, buf) = ffs.read(vec![0u8; 64]).await;
+            assert_eq!(&buf[..n.unwrap()], b"from hu");
+
+            let (n, _) = ffs.write(b"to hu".to_vec()).submit().await;
+            assert_eq!(n.unwrap(), 5);
+            let mut buf = [0u8; 5];
+            host_in.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"to hu");
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn kept_accessory_passes_frames_from_the_version_request_on() {
+        use std::ffi::CString;
+        use std::io::Write;
+        use std::os::unix::ffi::OsStrExt;
+
+        // a fifo standing in for the accessory, the HU writes to it
+        let path = std::env::temp_dir().join(format!("aa-proxy-kept-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let open = || {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap()
+        };
+        let mut hu = open();
+        async fn next_packet(rx: &mut Receiver<Packet>) -> Packet {
+            timeout(Duration::from_secs(1), rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+        }
+        let version_request = [0, FRAME_TYPE_MASK, 0, 6, 0, 1, 0, 1, 0, 7];
+        let ping = [0, FRAME_TYPE_MASK | ENCRYPTED, 0, 2, 0, 11];
+
+        tokio_uring::start(async {
+            let kept =
+                KeptAccessory::new(Rc::new(File::from_std(open())), path.clone(), 64, 4, None);
+
+            // first session
+            let (tx, mut rx) = mpsc::channel(4);
+            kept.attach(tx);
+            hu.write_all(&version_request).unwrap();
+            assert!(is_version_request(&next_packet(&mut rx).await));
+            hu.write_all(&ping).unwrap();
+            assert_eq!(next_packet(&mut rx).await.channel, 0);
+            drop(rx);
+            kept.detach();
+
+            // the HU is still in the first session, then restarts the protocol
+            // before the next session is attached
+            hu.write_all(&ping).unwrap();
+            hu.write_all(&version_request).unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert!(kept.healthy());
+
+            let (tx, mut rx) = mpsc::channel(4);
+            kept.attach(tx);
+            assert!(is_version_request(&next_packet(&mut rx).await));
+            hu.write_all(&ping).unwrap();
+            assert!(!is_version_request(&next_packet(&mut rx).await));
+            assert!(kept.healthy());
+        });
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn usb_accessory_open_is_retried_until_the_node_appears() {
+        let path = std::env::temp_dir().join(format!("aa-proxy-accessory-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        tokio_uring::start(async {
+            // not created at all: the gadget isn't configured
+            let err = open_usb_accessory(&path, 0, Duration::from_millis(10))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, ProxyError::UsbGadgetNotConfigured { .. }));
+
+            // created by the "gadget setup" while retrying
+            let node = path.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(30));
+                std::fs::write(node, b"").unwrap();
+            });
+            assert!(open_usb_accessory(&path, 5, Duration::from_millis(20))
+                .await
+                .is_ok());
+        });
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn md_is_accepted_on_a_unix_socket_which_is_removed_on_drop() {
+        let path = std::env::temp_dir().join(format!("aa-proxy-md-{}.sock", std::process::id()));
+
+        tokio_uring::start(async {
+            let mut listener = SocketListener::Unix(UnixSocketListener::bind(&path).unwrap());
+            let client = UnixStream::connect(&path).await.unwrap();
+            let (md, mac, _) = md_wait_for_connection(&mut listener, None, true)
+                .await
+                .unwrap();
+            assert_eq!(mac, None);
+            let MdStream::Unix(md) = md else {
+                panic!("MD not accepted on the Unix socket");
+            };
+
+            client.write_all(b"from md".to_vec()).await.0.unwrap();
+            let (n, buf) = md.read(vec![0u8; 64]).await;
+            assert_eq!(&buf[..n.unwrap()], b"from md");
+            md.write_all(b"to md".to_vec()).await.0.unwrap();
+            let (n, buf) = client.read(vec![0u8; 64]).await;
+            assert_eq!(&buf[..n.unwrap()], b"to md");
+
+            drop(listener);
+            assert!(!path.exists());
+        });
+    }
+
+    #[test]
+    fn idle_log_gate_backs_off_and_resets() {
+        let mut gate = IdleLogGate::new(true);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(gate.check(at(0)), Some(0));
+        assert_eq!(gate.check(at(30)), None);
+        assert_eq!(gate.check(at(60)), Some(1));
+        assert_eq!(gate.check(at(300)), None);
+        assert_eq!(gate.check(at(360)), Some(1));
+        // capped at the last interval
+        assert_eq!(gate.check(at(360 + 900)), Some(0));
+        assert_eq!(gate.check(at(360 + 1800)), Some(0));
+
+        gate.reset();
+        assert_eq!(gate.check(at(2200)), Some(0));
+        assert_eq!(gate.check(at(2210)), None);
+
+        let mut disabled = IdleLogGate::new(false);
+        assert_eq!(disabled.check(at(0)), Some(0));
+        assert_eq!(disabled.check(at(1)), Some(0));
+    }
+
+    #[test]
+    fn transfer_stats_are_sent_per_interval() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut stream = TransferStatsStream::new(tx, Duration::from_secs(2));
+        let start = stream.started;
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(stream.sample(1000, 10, at(1)));
+        assert!(rx.try_recv().is_err());
+        assert!(stream.sample(4000, 40, at(2)));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            TransferStats {
+                usb_bytes: 4000,
+                tcp_bytes: 40,
+                usb_speed: 2000,
+                tcp_speed: 20,
+                session_duration: Duration::from_secs(2),
+            }
+        );
+
+        drop(rx);
+        assert!(!stream.sample(8000, 80, at(4)));
+    }
+
+    #[test]
+    fn stall_is_checked_once_per_timeout() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut check = StallCheck {
+            timeout: Duration::from_secs(10),
+            checked: start,
+            bytes_last: 0,
+            progressed: start,
+        };
+
+        assert_eq!(check.check(0, at(5)), None);
+        assert_eq!(check.check(100, at(11)), Some(false));
+        assert_eq!(check.check(100, at(15)), None);
+        assert_eq!(check.check(100, at(22)), Some(true));
+        assert_eq!(check.idle(at(22)), Duration::from_secs(11));
+        assert_eq!(check.check(150, at(33)), Some(false));
+        assert_eq!(check.idle(at(33)), Duration::ZERO);
+    }
+
+    #[test]
+    fn stats_are_formatted_in_configured_units() {
+        assert_eq!(format_bytes(1_572_864, StatsUnits::Binary), "1.5 MiB");
+        assert_eq!(format_bytes(1_500_000, StatsUnits::Decimal), "1.5 MB");
+        assert_eq!(format_bytes(1_500_000, StatsUnits::Bytes), "1500000");
+    }
+
+    #[test]
+    fn transfer_rate_is_computed_per_report() {
+        // totals sampled at 2 s, 5 s and 5.5 s
+        let mut last_total = 0;
+        for (total, elapsed, expected) in [
+            (4_000_000, Duration::from_secs(2), (4_000_000, 2_000_000)),
+            (4_300_000, Duration::from_secs(3), (300_000, 100_000)),
+            (4_300_001, Duration::from_millis(500), (1, 2)),
+        ] {
+            assert_eq!(transfer_rate(total, last_total, elapsed), expected);
+            last_total = total;
+        }
+        assert_eq!(transfer_rate(10, 0, Duration::ZERO), (10, 0));
+    }
+
+    #[test]
+    fn stats_interval_is_not_shorter_than_the_poll_interval() {
+        let secs = Duration::from_secs;
+        assert_eq!(stats_report_interval(None, secs(5)), None);
+        assert_eq!(
+            stats_report_interval(Some(secs(10)), secs(5)),
+            Some(secs(10))
+        );
+        assert_eq!(stats_report_interval(Some(secs(1)), secs(5)), Some(secs(5)));
+    }
+
+    #[test]
+    fn watchdog_expires_after_consecutive_slow_windows() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut watchdog = ThroughputWatchdog::new(Duration::from_secs(10), 1000, 2);
+        watchdog.rearm(0, start);
+
+        assert_eq!(watchdog.check(500, at(5)), None);
+        assert_eq!(watchdog.check(500, at(10)), Some((50, true)));
+        assert!(!watchdog.expired());
+        // a healthy window starts counting again
+        assert_eq!(watchdog.check(20_500, at(20)), Some((2000, false)));
+        assert_eq!(watchdog.check(21_000, at(30)), Some((50, true)));
+        assert!(!watchdog.expired());
+        assert_eq!(watchdog.check(21_000, at(40)), Some((0, true)));
+        assert!(watchdog.expired());
+    }
+
+    #[test]
+    fn keepalive_is_set_on_streams() {
+        let disabled = AppConfig {
+            tcp_keepalive_idle_secs: 0,
+            ..Default::default()
+        };
+        assert_eq!(TcpKeepalive::from_config(&disabled), None);
+        let cfg = AppConfig {
+            tcp_keepalive_idle_secs: 7,
+            ..Default::default()
+        };
+        let keepalive = TcpKeepalive::from_config(&cfg).unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        keepalive.apply(&stream).unwrap();
+        let socket = SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(7));
+        assert_eq!(
+            socket.keepalive_interval().unwrap(),
+            Duration::from_secs(cfg.tcp_keepalive_interval_secs.into())
+        );
+        assert_eq!(
+            socket.keepalive_retries().unwrap(),
+            u32::from(cfg.tcp_keepalive_count)
+        );
+    }
+
+    #[test]
+    fn paused_transfer_does_not_stall() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut check = StallCheck::new(Duration::from_secs(10));
+        check.rearm(100, start);
+
+        // paused for 30 s: the monitor rearms the check on every poll
+        for secs in [5, 15, 30] {
+            check.rearm(100, at(secs));
+            assert_eq!(check.check(100, at(secs)), None);
+        }
+        // the timeout window starts again on resume
+        assert_eq!(check.check(100, at(35)), None);
+        assert_eq!(check.check(100, at(41)), Some(true));
+    }
+
+    #[test]
+    fn read_buffer_len_is_page_aligned() {
+        let page = page_size();
+        assert_eq!(read_buffer_len(16, "HU"), BUFFER_LEN.next_multiple_of(page));
+        assert_eq!(read_buffer_len(1, "HU"), 1024usize.next_multiple_of(page));
+        assert_eq!(read_buffer_len(0, "MD"), BUFFER_LEN.next_multiple_of(page));
+        assert_eq!(
+            read_buffer_len(2048, "MD"),
+            BUFFER_LEN.next_multiple_of(page)
+        );
+        assert_eq!(read_buffer_len(1024, "MD") % page, 0);
+    }
 }
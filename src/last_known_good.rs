@@ -0,0 +1,207 @@
+use crate::config::AppConfig;
+use crate::config_types::ProtocolVersion;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use simplelog::*;
+use std::path::Path;
+use std::time::Duration;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> lkg: </>";
+
+// Just a generic Result type to ease error handling for us. Errors in multithreaded
+// async contexts needs some extra restrictions
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// sessions shorter than this are not considered working
+pub const MIN_SESSION_DURATION: Duration = Duration::from_secs(30);
+
+/// video mode announced in the final ServiceDiscoveryResponse
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VideoMode {
+    pub resolution: String,
+    pub density: Option<u64>,
+}
+
+/// Configuration negotiated during the last working session.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LastKnownGood {
+    pub saved_at: String,
+    pub session_secs: u64,
+    pub protocol_version: Option<ProtocolVersion>,
+    pub band: String,
+    pub dpi: u16,
+    pub video: Vec<VideoMode>,
+    pub mitm: bool,
+    /// MITM transforms which were enabled while the session ran
+    pub transforms: Vec<String>,
+}
+
+/// video modes from the protobuf JSON form of the ServiceDiscoveryResponse
+fn video_modes(sdr: &serde_json::Value) -> Vec<VideoMode> {
+    let services = sdr["services"].as_array().map_or(&[][..], |s| s.as_slice());
+    services
+        .iter()
+        .filter_map(|svc| svc["mediaSinkService"]["videoConfigs"].as_array())
+        .flatten()
+        .map(|cfg| VideoMode {
+            resolution: cfg["codecResolution"]
+                .as_str()
+                .unwrap_or("unknown")
+                .to_string(),
+            density: cfg["density"].as_u64(),
+        })
+        .collect()
+}
+
+/// names of the MITM transforms applied with the given config
+fn transforms(cfg: &AppConfig) -> Vec<String> {
+    [
+        ("dpi", cfg.dpi > 0),
+        ("remove_tap_restriction", cfg.remove_tap_restriction),
+        ("video_in_motion", cfg.video_in_motion),
+        ("disable_media_sink", cfg.disable_media_sink),
+        ("disable_tts_sink", cfg.disable_tts_sink),
+        ("developer_mode", cfg.developer_mode),
+        ("add_vendor_channel", cfg.add_vendor_channel),
+        ("remove_bluetooth", cfg.remove_bluetooth),
+        ("remove_wifi", cfg.remove_wifi),
+        ("ev", cfg.ev),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name.to_string())
+    .collect()
+}
+
+impl LastKnownGood {
+    /// builds the checkpoint from the state observed in a finished session
+    pub fn from_session(
+        cfg: &AppConfig,
+        sdr: &serde_json::Value,
+        protocol_version: Option<ProtocolVersion>,
+        session: Duration,
+    ) -> Self {
+        let mitm = cfg.mitm && !cfg.runtime_mitm_failed;
+        Self {
+            saved_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            session_secs: session.as_secs(),
+            protocol_version,
+            band: cfg.band.clone(),
+            dpi: cfg.dpi,
+            video: video_modes(sdr),
+            mitm,
+            transforms: if mitm { transforms(cfg) } else { vec![] },
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&raw)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // write to a temporary file first, so a power cut cannot leave a corrupt file
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn summary(&self) -> String {
+        let video = self
+            .video
+            .iter()
+            .map(|v| match v.density {
+                Some(density) => format!("{}@{}dpi", v.resolution, density),
+                None => v.resolution.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "protocol: {}, band: {}, dpi: {}, video: [{}], mitm: {}, transforms: [{}] (session of {}s, saved {})",
+            self.protocol_version
+                .map_or("unknown".to_string(), |v| v.to_string()),
+            self.band,
+            self.dpi,
+            video,
+            self.mitm,
+            self.transforms.join(", "),
+            self.session_secs,
+            self.saved_at,
+        )
+    }
+}
+
+/// logs the checkpoint saved by a previous run; absent or corrupt files are only reported
+pub fn log_last_known_good(path: &Path) {
+    if !path.exists() {
+        info!(
+            "{} 📌 no last known good config yet ({})",
+            NAME,
+            path.display()
+        );
+        return;
+    }
+    match LastKnownGood::load(path) {
+        Ok(lkg) => info!("{} 📌 last known good: {}", NAME, lkg.summary()),
+        Err(e) => warn!(
+            "{} 📌 ignoring unreadable last known good config {}: {}",
+            NAME,
+            path.display(),
+            e
+        ),
+    }
+}
+
+/// saves the checkpoint and logs the result
+pub fn checkpoint(path: &Path, lkg: &LastKnownGood) {
+    match lkg.save(path) {
+        Ok(()) => info!(
+            "{} 📌 saved last known good config to <b>{}</>",
+            NAME,
+            path.display()
+        ),
+        Err(e) => warn!(
+            "{} 📌 cannot save last known good config to {}: {}",
+            NAME,
+            path.display(),
+            e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_modes_from_sdr_json() {
+        let sdr = serde_json::json!({
+            "services": [
+                { "id": 1, "sensorSourceService": {} },
+                { "id": 3, "mediaSinkService": { "videoConfigs": [
+                    { "codecResolution": "VIDEO_1920x1080", "density": 240 },
+                    { "codecResolution": "VIDEO_1280x720" }
+                ] } }
+            ]
+        });
+        assert_eq!(
+            video_modes(&sdr),
+            vec![
+                VideoMode {
+                    resolution: "VIDEO_1920x1080".into(),
+                    density: Some(240)
+                },
+                VideoMode {
+                    resolution: "VIDEO_1280x720".into(),
+                    density: None
+                },
+            ]
+        );
+        assert!(video_modes(&serde_json::Value::Null).is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+//! Round-trip latency of the phone and car links, measured on ping frames.
+//!
+//! Both sides periodically send a PingRequest on the control channel which the
+//! other side answers with a PingResponse carrying the same timestamp. The time
+//! between the request leaving the proxy and the matching response coming back
+//! is the round trip through the link of the answering side (one-way delay is
+//! estimated as half of it). The frames are only observed, never modified, but
+//! this needs the decrypted stream, so nothing is measured in passthrough mode.
+use crate::mitm::protos::ControlMessageType::*;
+use crate::mitm::protos::*;
+use crate::mitm::{Packet, ProxyType, FRAME_TYPE_FIRST};
+use protobuf::{Enum, Message};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// number of samples in the rolling average
+const WINDOW: usize = 32;
+/// pings not answered within this time are forgotten
+const PENDING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct LinkLatency {
+    samples: VecDeque<Duration>,
+    period_max: Duration,
+    max: Duration,
+    count: u64,
+}
+
+impl LinkLatency {
+    fn sample(&mut self, rtt: Duration) {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+        self.period_max = self.period_max.max(rtt);
+        self.max = self.max.max(rtt);
+        self.count += 1;
+    }
+
+    fn summary(&self) -> Option<LinkSummary> {
+        let last = *self.samples.back()?;
+        let avg = self.samples.iter().sum::<Duration>() / self.samples.len() as u32;
+        Some(LinkSummary {
+            samples: self.count,
+            last_rtt_ms: ms(last),
+            avg_rtt_ms: ms(avg),
+            avg_one_way_ms: ms(avg) / 2.0,
+            period_max_rtt_ms: ms(self.period_max),
+            max_rtt_ms: ms(self.max),
+        })
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_micros() as f64 / 1000.0
+}
+
+#[derive(Debug, Default)]
+struct LatencyStats {
+    /// pings in flight: (side which sent the request, timestamp) -> seen at
+    pending: HashMap<(ProxyType, i64), Instant>,
+    /// answered by the phone
+    md: LinkLatency,
+    /// answered by the car
+    hu: LinkLatency,
+}
+
+impl LatencyStats {
+    fn request(&mut self, from: ProxyType, timestamp: i64, now: Instant) {
+        self.pending
+            .retain(|_, seen| now.duration_since(*seen) < PENDING_TIMEOUT);
+        self.pending.insert((from, timestamp), now);
+    }
+
+    fn response(&mut self, from: ProxyType, timestamp: i64, now: Instant) {
+        let requester = match from {
+            ProxyType::HeadUnit => ProxyType::MobileDevice,
+            ProxyType::MobileDevice => ProxyType::HeadUnit,
+        };
+        let Some(seen) = self.pending.remove(&(requester, timestamp)) else {
+            return;
+        };
+        let rtt = now.duration_since(seen);
+        match from {
+            ProxyType::HeadUnit => self.hu.sample(rtt),
+            ProxyType::MobileDevice => self.md.sample(rtt),
+        }
+    }
+
+    fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            phone: self.md.summary(),
+            car: self.hu.summary(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkSummary {
+    pub samples: u64,
+    pub last_rtt_ms: f64,
+    /// rolling average over the last samples
+    pub avg_rtt_ms: f64,
+    /// estimated as half of the average round trip
+    pub avg_one_way_ms: f64,
+    /// maximum since the last periodic report
+    pub period_max_rtt_ms: f64,
+    pub max_rtt_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySummary {
+    pub phone: Option<LinkSummary>,
+    pub car: Option<LinkSummary>,
+}
+
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "⏱️ latency (round trip):")?;
+        for (name, link) in [("phone", &self.phone), ("car", &self.car)] {
+            match link {
+                Some(l) => write!(
+                    f,
+                    " {}: {:.1} ms avg (one-way ~{:.1} ms), {:.1} ms last, max {:.1} ms (period {:.1} ms);",
+                    name,
+                    l.avg_rtt_ms,
+                    l.avg_one_way_ms,
+                    l.last_rtt_ms,
+                    l.max_rtt_ms,
+                    l.period_max_rtt_ms
+                )?,
+                None => write!(f, " {}: no pings;", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// None when latency stats are disabled
+static LATENCY_STATS: Mutex<Option<LatencyStats>> = Mutex::new(None);
+
+/// forgets the previous session, measuring the next one when `enabled`
+pub fn reset(enabled: bool) {
+    *LATENCY_STATS.lock().unwrap() = enabled.then(LatencyStats::default);
+}
+
+/// feeds a decrypted packet received from the `proxy_type` side
+pub fn observe(proxy_type: ProxyType, pkt: &Packet) {
+    // pings are single frame messages on the control channel
+    if pkt.channel != 0 || pkt.flags & FRAME_TYPE_FIRST == 0 || pkt.payload.len() < 2 {
+        return;
+    }
+    let message_id: i32 = u16::from_be_bytes([pkt.payload[0], pkt.payload[1]]).into();
+    let data = &pkt.payload[2..];
+    let now = Instant::now();
+    let mut lock = LATENCY_STATS.lock().unwrap();
+    let Some(stats) = lock.as_mut() else {
+        return;
+    };
+    match ControlMessageType::from_i32(message_id) {
+        Some(MESSAGE_PING_REQUEST) => {
+            if let Ok(ping) = PingRequest::parse_from_bytes(data) {
+                stats.request(proxy_type, ping.timestamp(), now);
+            }
+        }
+        Some(MESSAGE_PING_RESPONSE) => {
+            if let Ok(pong) = PingResponse::parse_from_bytes(data) {
+                stats.response(proxy_type, pong.timestamp(), now);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// summary for the periodic statistics; starts a new max period
+pub fn period_summary() -> Option<LatencySummary> {
+    let mut lock = LATENCY_STATS.lock().unwrap();
+    let stats = lock.as_mut()?;
+    let summary = stats.summary();
+    stats.md.period_max = Duration::ZERO;
+    stats.hu.period_max = Duration::ZERO;
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_matched_to_requests_of_the_other_side() {
+        let mut stats = LatencyStats::default();
+        let t0 = Instant::now();
+        // HU pings the phone, phone pings the HU
+        stats.request(ProxyType::HeadUnit, 1, t0);
+        stats.request(ProxyType::MobileDevice, 1, t0);
+        stats.response(ProxyType::MobileDevice, 1, t0 + Duration::from_millis(40));
+        stats.response(ProxyType::HeadUnit, 1, t0 + Duration::from_millis(10));
+        // unknown timestamp is ignored
+        stats.response(ProxyType::MobileDevice, 7, t0 + Duration::from_millis(90));
+        stats.request(ProxyType::HeadUnit, 2, t0);
+        stats.response(ProxyType::MobileDevice, 2, t0 + Duration::from_millis(20));
+
+        let summary = stats.summary();
+        let phone = summary.phone.unwrap();
+        assert_eq!(phone.samples, 2);
+        assert_eq!(phone.avg_rtt_ms, 30.0);
+        assert_eq!(phone.avg_one_way_ms, 15.0);
+        assert_eq!(phone.max_rtt_ms, 40.0);
+        assert_eq!(phone.last_rtt_ms, 20.0);
+        assert_eq!(summary.car.unwrap().avg_rtt_ms, 10.0);
+        assert!(stats.pending.is_empty());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod aoa;
+pub mod benchmark;
 pub mod bluetooth;
 pub mod bt_helper;
 pub mod bt_sco;
@@ -6,25 +7,44 @@ pub mod bt_sco_echo;
 pub mod bt_sco_media_bridge;
 pub mod btle;
 pub mod button;
+pub mod capture;
 pub mod config;
 pub mod config_types;
 pub mod crash;
 pub mod device_info;
+pub mod dhu_fanout;
+pub mod dhu_tls;
 pub mod display;
+pub mod error;
 pub mod ev;
+pub mod event_clock;
+pub mod handshake_events;
 pub mod hu_input;
 pub mod io_uring;
+pub mod last_known_good;
+pub mod latency_stats;
 pub mod led;
+pub mod log_buffer;
 pub mod media_tap;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod mitm;
 pub mod mitm_prettyprint;
 pub mod mpegts;
+pub mod packet_filter;
 #[cfg(feature = "wasm-scripting")]
 pub mod script_wasm;
 pub mod sdr_ui;
+pub mod sensor_feed;
+pub mod session_summary;
+pub mod stats_log;
 pub mod usb_gadget;
 pub mod usb_stream;
 pub mod vendor_ext;
+pub mod video_cap;
+pub mod video_stats;
 #[cfg(feature = "wasm-scripting")]
 pub mod wasm_config;
 pub mod web;
+pub mod wifi_band;
+pub mod wifi_credentials;
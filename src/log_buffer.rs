@@ -0,0 +1,87 @@
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+use regex::Regex;
+use simplelog::{Config, SharedLogger};
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+
+/// Bounded in-memory buffer with the most recent log lines, so they can be
+/// fetched over the network (see the `/health` endpoint) without shell access.
+struct LogRing {
+    lines: Mutex<VecDeque<String>>,
+    capacity: usize,
+    markup: Regex,
+}
+
+static LOG_RING: OnceLock<LogRing> = OnceLock::new();
+
+/// `log` sink feeding the ring buffer, combined with the other loggers
+pub struct RingLogger {
+    level: LevelFilter,
+}
+
+impl RingLogger {
+    /// creates the logger keeping last `capacity` lines; `None` when disabled
+    pub fn new(level: LevelFilter, capacity: usize) -> Option<Box<RingLogger>> {
+        if capacity == 0 {
+            return None;
+        }
+        LOG_RING.get_or_init(|| LogRing {
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            // simplelog/paris markup like `<b>`, `<bright-black>` or `</>`
+            markup: Regex::new(r"</?[a-z\-]*>").unwrap(),
+        });
+        Some(Box::new(RingLogger { level }))
+    }
+}
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let Some(ring) = LOG_RING.get() else {
+            return;
+        };
+        let msg = record.args().to_string();
+        let line = format!(
+            "{} [{}] {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            record.level(),
+            ring.markup.replace_all(&msg, "").trim()
+        );
+        let mut lines = ring.lines.lock().unwrap();
+        if lines.len() >= ring.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for RingLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+/// recent log lines, oldest first (empty when the buffer is disabled)
+pub fn recent_lines() -> Vec<String> {
+    LOG_RING.get().map_or(vec![], |ring| {
+        ring.lines.lock().unwrap().iter().cloned().collect()
+    })
+}
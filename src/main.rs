@@ -1,17 +1,23 @@
-use aa_proxy_rs::bluetooth;
+use aa_proxy_rs::benchmark;
+use aa_proxy_rs::bluetooth::{self, HandshakeOptions, HandshakeSignals};
 use aa_proxy_rs::bt_sco::{self, BtScoOptions};
 use aa_proxy_rs::bt_sco_echo::BtScoEchoSettings;
 use aa_proxy_rs::button::button_handler;
+use aa_proxy_rs::capture;
 use aa_proxy_rs::config::SharedConfig;
 use aa_proxy_rs::config::SharedConfigJson;
 use aa_proxy_rs::config::WifiConfig;
-use aa_proxy_rs::config::{Action, AppConfig};
-use aa_proxy_rs::config::{DEFAULT_WLAN_ADDR, TCP_SERVER_PORT};
+use aa_proxy_rs::config::DEFAULT_WLAN_ADDR;
+use aa_proxy_rs::config::{Action, AppConfig, BtConnectMode};
 use aa_proxy_rs::crash;
 use aa_proxy_rs::device_info;
+use aa_proxy_rs::error::ProxyError;
 use aa_proxy_rs::ev::BatteryData;
-use aa_proxy_rs::io_uring::io_loop;
+use aa_proxy_rs::handshake_events;
+use aa_proxy_rs::io_uring::{self, io_loop};
+use aa_proxy_rs::last_known_good;
 use aa_proxy_rs::led::{LedColor, LedManager, LedMode};
+use aa_proxy_rs::log_buffer::RingLogger;
 use aa_proxy_rs::mitm::send_byebye;
 use aa_proxy_rs::mitm::OdometerData;
 use aa_proxy_rs::mitm::Packet;
@@ -25,10 +31,12 @@ use aa_proxy_rs::script_wasm::{ScriptParameters, ScriptRegistry};
 use aa_proxy_rs::wasm_config::WasmConfigStore;
 #[cfg(not(feature = "wasm-scripting"))]
 type ScriptRegistry = ();
+use aa_proxy_rs::sensor_feed;
 use aa_proxy_rs::usb_gadget::uevent_listener;
 use aa_proxy_rs::usb_gadget::UsbGadgetState;
 use aa_proxy_rs::web;
 use aa_proxy_rs::web::ServerEvent;
+use aa_proxy_rs::wifi_credentials;
 use clap::Parser;
 use humantime::format_duration;
 use simplelog::*;
@@ -93,6 +101,47 @@ struct Args {
     /// Generate hostapd config and exit
     #[clap(short = 'o', long)]
     generate_hostapd: bool,
+    /// Convert a session capture into a DHU replay file and exit
+    #[clap(long, num_args = 2, value_names = ["CAPTURE", "OUTPUT"])]
+    export_dhu_replay: Option<Vec<PathBuf>>,
+    /// Run a synthetic throughput benchmark for given number of seconds and exit
+    #[clap(long, value_name = "SECONDS")]
+    benchmark: Option<u64>,
+    /// Validate the config, adapters and listeners without advertising or proxying, and exit
+    #[clap(long)]
+    dry_run: bool,
+}
+
+/// Checks the config and the environment as a startup would, but without
+/// advertising or proxying. Listeners are bound and released right away.
+fn dry_run(cfg: &AppConfig) -> Result<Vec<String>> {
+    let mut problems = io_uring::check_setup(cfg);
+
+    // wireless phones need the WiFi credentials and the bluetooth handshake
+    let aa_server_tcp_enabled = !cfg.aa_server_tcp_addr.trim().is_empty();
+    if cfg.wired.is_none() && !aa_server_tcp_enabled {
+        match init_wifi_config(cfg) {
+            Ok(wifi_config) => info!("{} ✅ WiFi config: {}", NAME, wifi_config.redacted()),
+            Err(e) => problems.push(format!("WiFi config: {}", e)),
+        }
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        match runtime.block_on(bluetooth::check_adapter(cfg.bt_adapter.as_deref())) {
+            Ok(name) => info!("{} ✅ bluetooth adapter: <b>{}</>", NAME, name),
+            Err(e) => problems.push(format!("bluetooth: {}", e)),
+        }
+    }
+
+    if let Some(ref bindaddr) = cfg.webserver {
+        match bindaddr.parse::<SocketAddr>() {
+            Ok(addr) => match std::net::TcpListener::bind(addr) {
+                Ok(_) => info!("{} ✅ webserver can be bound to <u>{}</u>", NAME, addr),
+                Err(e) => problems.push(format!("webserver {}: {}", addr, e)),
+            },
+            Err(e) => problems.push(format!("webserver address {}: {}", bindaddr, e)),
+        }
+    }
+
+    Ok(problems)
 }
 
 fn init_wifi_config(cfg: &AppConfig) -> Result<WifiConfig> {
@@ -118,16 +167,30 @@ fn init_wifi_config(cfg: &AppConfig) -> Result<WifiConfig> {
         .ok_or("No MAC address found")?
         .to_string();
 
-    Ok(WifiConfig {
+    let wifi_config = WifiConfig {
         ip_addr,
-        port: TCP_SERVER_PORT,
+        port: cfg.tcp_md_port.into(),
         ssid: cfg.ssid.clone(),
         bssid,
         wpa_key: cfg.wpa_passphrase.clone(),
-    })
+        security: cfg.wifi_security,
+        channel: cfg.wifi_announce_band.then_some(cfg.channel),
+        band: cfg.wifi_announce_band.then(|| cfg.band.clone()),
+        start_extra_fields: cfg.wifi_start_extra_fields.clone(),
+        info_extra_fields: cfg.wifi_info_extra_fields.clone(),
+    };
+    bluetooth::check_wifi_extra_fields(&wifi_config)?;
+    wifi_credentials::check_wifi_key(&wifi_config)?;
+
+    Ok(wifi_config)
 }
 
-fn logging_init(debug: bool, disable_console_debug: bool, log_path: &PathBuf) {
+fn logging_init(
+    debug: bool,
+    disable_console_debug: bool,
+    log_path: &PathBuf,
+    log_buffer_lines: usize,
+) {
     let conf = ConfigBuilder::new()
         .set_time_format_custom(format_description!(
             "[year]-[month]-[day], [hour]:[minute]:[second].[subsecond digits:3]"
@@ -157,6 +220,11 @@ fn logging_init(debug: bool, disable_console_debug: bool, log_path: &PathBuf) {
     );
     loggers.push(console_logger);
 
+    // in-memory buffer with recent lines for the /health endpoint
+    if let Some(ring_logger) = RingLogger::new(requested_level, log_buffer_lines) {
+        loggers.push(ring_logger);
+    }
+
     let mut logfile_error: Option<String> = None;
     let logfile = OpenOptions::new().create(true).append(true).open(&log_path);
     match logfile {
@@ -235,6 +303,9 @@ async fn clean_disconnect_and_exit(
         tokio::time::sleep(Duration::from_millis(50)).await;
     }
 
+    // the listeners are not dropped on exit
+    io_uring::remove_unix_sockets(&config.read().await);
+
     info!("{} {}: exiting process after teardown", NAME, reason);
     std::process::exit(0);
 }
@@ -244,6 +315,7 @@ async fn tokio_main(
     config_json: SharedConfigJson,
     restart_tx: BroadcastSender<Option<Action>>,
     tcp_start: Arc<Notify>,
+    md_connected: Arc<Notify>,
     config_file: PathBuf,
     tx: Arc<Mutex<Option<Sender<Packet>>>>,
     sensor_channel: Arc<Mutex<Option<u8>>>,
@@ -262,6 +334,16 @@ async fn tokio_main(
 ) -> Result<()> {
     let accessory_started = Arc::new(Notify::new());
     let accessory_started_cloned = accessory_started.clone();
+    {
+        let cfg = config.read().await;
+        if cfg.sensor_injection {
+            tokio::spawn(sensor_feed::run(
+                tx.clone(),
+                sensor_channel.clone(),
+                cfg.sensor_nmea_source.clone(),
+            ));
+        }
+    }
     let state = web::AppState {
         config: config.clone(),
         config_json: config_json.clone(),
@@ -281,6 +363,8 @@ async fn tokio_main(
     // Handle process-exit signals with a protocol-clean teardown.
     let tx_signal = tx.clone();
     let config_signal = config.clone();
+    let shutdown = Arc::new(Notify::new());
+    let shutdown_signal = shutdown.clone();
     tokio::spawn(async move {
         #[cfg(unix)]
         {
@@ -321,9 +405,31 @@ async fn tokio_main(
             }
         }
 
+        // stop waiting for a phone in the bluetooth handshake
+        shutdown_signal.notify_one();
         clean_disconnect_and_exit(tx_signal, config_signal, "signal exit").await;
     });
 
+    // phone-present trigger via SIGUSR1
+    #[cfg(unix)]
+    if cfg.bt_phone_present_trigger {
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("{} signal handler init failed (SIGUSR1): {}", NAME, e);
+                    return;
+                }
+            };
+            while sigusr1.recv().await.is_some() {
+                info!("{} 📡 received SIGUSR1: phone present", NAME);
+                bluetooth::phone_present_trigger().notify_one();
+            }
+        });
+    }
+
     // LED support
     let mut led_manager = if led_support {
         Some(LedManager::new(100))
@@ -355,6 +461,7 @@ async fn tokio_main(
                 duck_percent: cfg.bt_sco_mic_duck_percent,
                 duck_hold_ms: cfg.bt_sco_mic_duck_hold_ms,
             },
+            codec: cfg.bt_sco_codec,
         }) {
             Ok(_) => {
                 info!(
@@ -397,6 +504,11 @@ async fn tokio_main(
             e
         })
         .ok();
+    if cfg.log_config {
+        if let Some(ref wifi_config) = wifi_config {
+            info!("{} ⚙️ WiFi config: {}", NAME, wifi_config.redacted());
+        }
+    }
     let mut usb = None;
     if !cfg.dhu {
         if cfg.legacy {
@@ -416,6 +528,15 @@ async fn tokio_main(
         });
     }
 
+    // stream bluetooth handshake stages to local subscribers
+    if let Some(path) = cfg.bt_handshake_events_socket.clone() {
+        let _ = tokio::spawn(async move {
+            if let Err(e) = handshake_events::serve(path).await {
+                error!("{} handshake events socket: {}", NAME, e);
+            }
+        });
+    }
+
     // spawn a background task for reboot detection
     let mut config_cloned = config.clone();
     let _ = tokio::spawn(async move {
@@ -434,7 +555,25 @@ async fn tokio_main(
         );
     } else {
         loop {
-            match bluetooth::init(cfg.btalias.clone(), cfg.advertise, cfg.dongle_mode).await {
+            match bluetooth::init(
+                cfg.btalias.clone(),
+                cfg.bt_alias_serial_suffix,
+                cfg.bt_adapter.as_deref(),
+                cfg.advertise,
+                cfg.dongle_mode,
+                cfg.bt_rfcomm_channel,
+                match cfg.bt_hsp_rfcomm_channel {
+                    0 => None,
+                    channel => Some(channel),
+                },
+                cfg.bt_aa_profile_uuid.or(bluetooth::AAWG_PROFILE_UUID),
+                cfg.bt_hsp_hs_uuid.or(bluetooth::HSP_HS_UUID),
+                cfg.bt_hsp_ag_uuid.or(bluetooth::HSP_AG_UUID),
+                cfg.bt_pairing_policy,
+                cfg.bt_pairing_pin.clone(),
+            )
+            .await
+            {
                 Ok(result) => {
                     bluetooth = Some(result);
                     break;
@@ -458,6 +597,7 @@ async fn tokio_main(
     // main connection loop
     let change_usb_order = cfg.change_usb_order;
     let mut need_restart = restart_tx.subscribe();
+    let mut session_ended = false;
     loop {
         if let Some(ref mut leds) = led_manager {
             leds.set_led(LedColor::Green, LedMode::Heartbeat).await;
@@ -483,24 +623,88 @@ async fn tokio_main(
                     || cfg.action_requested == Some(Action::Stop))
             {
                 if let Some(ref mut bluetooth) = bluetooth {
+                    // stay idle until the phone is reported nearby
+                    if cfg.bt_phone_present_trigger && cfg.action_requested != Some(Action::Stop) {
+                        if let Err(e) = bluetooth
+                            .wait_for_phone_present(Duration::from_secs(
+                                cfg.bt_trigger_discoverable_secs.into(),
+                            ))
+                            .await
+                        {
+                            error!("{} bluetooth power up on trigger failed: {}", NAME, e);
+                            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                            continue;
+                        }
+                    }
+                    // a failed re-advertise stays pending and is retried before the next handshake
+                    if cfg.bt_readvertise && session_ended {
+                        match bluetooth.readvertise(cfg.advertise).await {
+                            Ok(()) => session_ended = false,
+                            Err(e) => warn!("{} bluetooth re-advertising failed: {}", NAME, e),
+                        }
+                    }
                     // bluetooth handshake
                     if let Err(e) = bluetooth
                         .aa_handshake(
-                            cfg.connect.clone(),
-                            wifi_conf.clone(),
-                            tcp_start.clone(),
-                            Duration::from_secs(cfg.bt_timeout_secs.into()),
-                            cfg.action_requested == Some(Action::Stop),
-                            cfg.quick_reconnect,
-                            cfg.bt_poweroff,
-                            cfg.bt_sco,
-                            cfg.bt_sco_keep_bluetooth_alive,
-                            restart_tx.subscribe(),
-                            restart_tx.clone(),
-                            profile_connected.clone(),
+                            HandshakeOptions {
+                                connect: cfg.connect.clone(),
+                                wifi_config: wifi_conf.clone(),
+                                bt_timeout: Duration::from_secs(cfg.bt_timeout_secs.into()),
+                                stopped: cfg.action_requested == Some(Action::Stop),
+                                quick_reconnect: cfg.quick_reconnect,
+                                bt_poweroff: cfg.bt_poweroff,
+                                bt_poweroff_delay: Duration::from_millis(
+                                    cfg.bt_poweroff_delay_ms.into(),
+                                ),
+                                bt_sco: cfg.bt_sco,
+                                bt_sco_keep_bluetooth_alive: cfg.bt_sco_keep_bluetooth_alive,
+                                extra_connect_policy: cfg.bt_extra_connect_policy,
+                                allowlist: cfg.bt_allowlist.clone(),
+                                auto_connect_window: match cfg.bt_connect_mode {
+                                    BtConnectMode::Auto => Some(Duration::from_secs(
+                                        cfg.bt_auto_connect_window_secs.into(),
+                                    )),
+                                    BtConnectMode::Static => None,
+                                },
+                                hsp_teardown_timeout: Duration::from_millis(
+                                    cfg.bt_hsp_teardown_timeout_ms.into(),
+                                ),
+                                log_wall_clock: cfg.log_wall_clock,
+                                wifi_credentials_file: cfg.wifi_credentials_file.clone(),
+                                wpa_key_policy: cfg.wpa_key_policy,
+                                early_tcp_start: cfg.early_tcp_start,
+                                read_retries: cfg.bt_handshake_read_retries,
+                                stage_timeouts: cfg.bt_stage_timeout_secs.clone(),
+                                version: match cfg.bt_wifi_version {
+                                    0 => None,
+                                    version => Some(version.into()),
+                                },
+                                rssi_poll_interval: match cfg.bt_rssi_poll_secs {
+                                    0 => None,
+                                    secs => Some(Duration::from_secs(secs.into())),
+                                },
+                                rssi_warn_dbm: cfg.bt_rssi_warn_dbm,
+                                confirm_timeout: match cfg.bt_confirm_connection_secs {
+                                    0 => None,
+                                    secs => Some(Duration::from_secs(secs.into())),
+                                },
+                            },
+                            HandshakeSignals {
+                                tcp_start: tcp_start.clone(),
+                                md_connected: md_connected.clone(),
+                                need_restart: restart_tx.subscribe(),
+                                restart_tx: restart_tx.clone(),
+                                profile_connected: profile_connected.clone(),
+                                shutdown: shutdown.clone(),
+                            },
+                            None,
                         )
                         .await
                     {
+                        if matches!(e, ProxyError::HandshakeCancelled) {
+                            info!("{} {}", NAME, e);
+                            return Ok(());
+                        }
                         error!("{} bluetooth AA handshake error: {}", NAME, e);
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                         continue;
@@ -539,6 +743,11 @@ async fn tokio_main(
             );
         }
 
+        session_ended = true;
+        if let Some(ref mut bluetooth) = bluetooth {
+            bluetooth.stop_rssi_poll();
+        }
+
         // TODO: make proper main loop with cancelation
         // re-read config
         cfg = config.read().await.clone();
@@ -663,13 +872,26 @@ fn main() -> Result<()> {
 
     crash::install_panic_handler(config.crash_dir.clone(), config.crash_handler_enabled);
 
-    logging_init(config.debug, config.disable_console_debug, &config.logfile);
+    logging_init(
+        config.debug,
+        config.disable_console_debug,
+        &config.logfile,
+        config.log_buffer_lines,
+    );
     info!(
         "🛸 <b><blue>aa-proxy-rs</> is starting, build: {}, git: {}-{}",
         env!("BUILD_DATE"),
         env!("GIT_DATE"),
         env!("GIT_HASH")
     );
+    if config.log_config {
+        info!(
+            "{} ⚙️ effective config ({}):\n{}",
+            NAME,
+            args.config.display(),
+            config.redacted()
+        );
+    }
 
     // generate system configs from template and exit
     if args.generate_system_config {
@@ -695,6 +917,47 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    // convert session capture to DHU replay file and exit
+    if let Some(files) = args.export_dhu_replay {
+        match capture::export_dhu_replay(&files[0], &files[1]) {
+            Ok(count) => {
+                info!(
+                    "{} 💾 DHU replay with {} packets saved as: <bold><green>{}</>",
+                    NAME,
+                    count,
+                    files[1].display()
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                error!("{} DHU replay export failed: {}", NAME, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // run synthetic throughput benchmark and exit
+    if let Some(secs) = args.benchmark {
+        if let Err(e) = tokio_uring::start(benchmark::run(config, Duration::from_secs(secs))) {
+            error!("{} benchmark failed: {}", NAME, e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // validate config and environment and exit
+    if args.dry_run {
+        let problems = dry_run(&config)?;
+        if problems.is_empty() {
+            info!("{} ✅ dry run: no problems found", NAME);
+            return Ok(());
+        }
+        for problem in &problems {
+            error!("{} 🔴 dry run: {}", NAME, problem);
+        }
+        std::process::exit(1);
+    }
+
     // show SBC model
     let mut led_support = false;
     let mut button_support = false;
@@ -743,6 +1006,9 @@ fn main() -> Result<()> {
         },
         config.crash_dir.display()
     );
+    if let Some(ref path) = config.last_known_good_file {
+        last_known_good::log_last_known_good(path);
+    }
     info!(
         "{} 🖼️ SDR UI overrides: <b><green>{}</> file=<b><green>{}</> autocreate={}",
         NAME,
@@ -766,8 +1032,30 @@ fn main() -> Result<()> {
     let (restart_tx, _) = broadcast::channel(1);
     let tcp_start = Arc::new(Notify::new());
     let tcp_start_cloned = tcp_start.clone();
+    let md_connected = Arc::new(Notify::new());
+    let md_connected_cloned = md_connected.clone();
     #[cfg(feature = "wasm-scripting")]
     let wasm_hooks_dir = config.wasm_hooks_dir.clone();
+    let uring_entries = config.uring_entries;
+    let (md_port, dhu_port) = (config.tcp_md_port, config.tcp_dhu_port);
+    // a zero capacity queue is not possible
+    let queue_capacity = usize::from(config.proxy_queue_capacity).max(1);
+    if md_port == dhu_port {
+        return Err(format!(
+            "tcp_md_port and tcp_dhu_port are both set to {}, the MD and DHU listeners need their own ports",
+            md_port
+        )
+        .into());
+    }
+    if config.metrics_port != 0 {
+        #[cfg(feature = "metrics")]
+        aa_proxy_rs::metrics::spawn_server(config.metrics_port);
+        #[cfg(not(feature = "metrics"))]
+        warn!(
+            "{} metrics_port is set, but this build has no `metrics` feature",
+            NAME
+        );
+    }
     let config = Arc::new(RwLock::new(config));
     let config_json = Arc::new(RwLock::new(config_json));
     let config_cloned = config.clone();
@@ -822,6 +1110,7 @@ fn main() -> Result<()> {
             config_json.clone(),
             restart_tx_cloned,
             tcp_start,
+            md_connected,
             args.config.clone(),
             tx_cloned,
             sensor_channel_cloned,
@@ -842,9 +1131,29 @@ fn main() -> Result<()> {
     });
 
     // start tokio_uring runtime simultaneously
-    let _ = tokio_uring::start(io_loop(
+    let mut uring_builder = tokio_uring::builder();
+    let ring_size = match uring_entries {
+        0 => "default".to_string(),
+        entries => {
+            uring_builder.entries(entries);
+            entries.to_string()
+        }
+    };
+    let uring_runtime = match tokio_uring::Runtime::new(&uring_builder) {
+        Ok(rt) => rt,
+        Err(e) => {
+            error!(
+                "{} 🔴 kernel rejected the io_uring ring ({} entries): {}",
+                NAME, ring_size, e
+            );
+            return Err(e.into());
+        }
+    };
+    info!("{} ⚙️ io_uring ring: <b>{}</> entries", NAME, ring_size);
+    let res = uring_runtime.block_on(io_loop(
         restart_tx,
         tcp_start_cloned,
+        md_connected_cloned,
         config,
         tx,
         sensor_channel,
@@ -855,12 +1164,19 @@ fn main() -> Result<()> {
         usb_connected,
         script_registry.clone(),
         ws_event_tx.clone(),
+        None,
+        md_port,
+        dhu_port,
+        queue_capacity,
     ));
+    if let Err(ref e) = res {
+        error!("{} 🔴 proxy failed: {}", NAME, e);
+    }
 
     info!(
         "🚩 aa-proxy-rs terminated, running time: {}",
         format_duration(started.elapsed()).to_string()
     );
 
-    Ok(())
+    Ok(res?)
 }
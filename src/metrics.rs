@@ -0,0 +1,340 @@
+//! Prometheus-style per-channel counters served on `/metrics`.
+//!
+//! Channels are labeled by the service type from the ServiceDiscoveryResponse,
+//! so the label set is fixed no matter how the HU numbers its channels.
+//! The transfer totals and speeds of the running session are exported too,
+//! optionally also on a dedicated port (see [`spawn_server`]).
+use crate::mitm::protos::Service;
+use crate::mitm::protos::ServiceDiscoveryResponse;
+use crate::mitm::ProxyType;
+use crate::mitm_prettyprint::pkt_debug_service_kind_for_service;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Request, Response, Server};
+use simplelog::*;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> metrics: </>";
+
+const CONTROL_LABEL: &str = "control";
+const UNKNOWN_LABEL: &str = "unknown";
+
+/// byte counters of the running session, shared with `transfer_monitor`
+struct Transfer {
+    usb_bytes_written: Arc<AtomicUsize>,
+    tcp_bytes_written: Arc<AtomicUsize>,
+    started: Instant,
+    /// last speed sample: (time, usb bytes, tcp bytes)
+    sample: (Instant, usize, usize),
+    /// bytes per second of the last sample: (usb, tcp)
+    speed: (u64, u64),
+}
+
+// shortest period a transfer speed is computed over
+const SPEED_PERIOD: Duration = Duration::from_secs(1);
+
+static TRANSFER: Mutex<Option<Transfer>> = Mutex::new(None);
+// sessions started so far, every one after the first is a reconnect
+static SESSIONS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counter {
+    bytes: u64,
+    packets: u64,
+}
+
+// channel id -> label, from the last ServiceDiscoveryResponse
+static CHANNEL_LABELS: Mutex<BTreeMap<u8, &'static str>> = Mutex::new(BTreeMap::new());
+// (channel label, direction) -> counter, for the whole process lifetime
+static COUNTERS: Mutex<BTreeMap<(&'static str, &'static str), Counter>> =
+    Mutex::new(BTreeMap::new());
+
+fn service_label(svc: &Service) -> &'static str {
+    if svc.media_sink_service.is_some() {
+        if svc.media_sink_service.video_configs.is_empty() {
+            "audio"
+        } else {
+            "video"
+        }
+    } else {
+        pkt_debug_service_kind_for_service(svc).as_str()
+    }
+}
+
+/// learns the channel labels of a new session
+pub fn set_channel_labels(msg: &ServiceDiscoveryResponse) {
+    let mut labels = CHANNEL_LABELS.lock().unwrap();
+    labels.clear();
+    for svc in msg.services.iter() {
+        if let Ok(channel) = u8::try_from(svc.id()) {
+            labels.insert(channel, service_label(svc));
+        }
+    }
+}
+
+/// forgets the channel labels, e.g. at the start of a new session
+pub fn reset_channel_labels() {
+    CHANNEL_LABELS.lock().unwrap().clear();
+}
+
+/// counts a packet transmitted by the `proxy_type` side
+pub fn count(proxy_type: ProxyType, channel: u8, bytes: usize) {
+    let label = match channel {
+        0 => CONTROL_LABEL,
+        ch => CHANNEL_LABELS
+            .lock()
+            .unwrap()
+            .get(&ch)
+            .copied()
+            .unwrap_or(UNKNOWN_LABEL),
+    };
+    let direction = match proxy_type {
+        ProxyType::HeadUnit => "to_hu",
+        ProxyType::MobileDevice => "to_md",
+    };
+    let mut counters = COUNTERS.lock().unwrap();
+    let counter = counters.entry((label, direction)).or_default();
+    counter.bytes += bytes as u64;
+    counter.packets += 1;
+}
+
+/// starts exporting the transfer counters of a new session
+pub fn start_session(usb_bytes_written: Arc<AtomicUsize>, tcp_bytes_written: Arc<AtomicUsize>) {
+    SESSIONS.fetch_add(1, Ordering::Relaxed);
+    let now = Instant::now();
+    *TRANSFER.lock().unwrap() = Some(Transfer {
+        usb_bytes_written,
+        tcp_bytes_written,
+        started: now,
+        sample: (now, 0, 0),
+        speed: (0, 0),
+    });
+}
+
+/// stops exporting the transfer counters when the session ends
+pub fn end_session() {
+    *TRANSFER.lock().unwrap() = None;
+}
+
+/// updates the transfer speeds from the totals read by `transfer_monitor`
+pub fn sample_transfer(usb_bytes: usize, tcp_bytes: usize) {
+    let mut transfer = TRANSFER.lock().unwrap();
+    let Some(transfer) = transfer.as_mut() else {
+        return;
+    };
+    let (sampled, usb_last, tcp_last) = transfer.sample;
+    let elapsed = sampled.elapsed();
+    if elapsed < SPEED_PERIOD {
+        return;
+    }
+    let speed = |bytes: usize| (bytes as f64 / elapsed.as_secs_f64()).round() as u64;
+    transfer.speed = (
+        speed(usb_bytes.saturating_sub(usb_last)),
+        speed(tcp_bytes.saturating_sub(tcp_last)),
+    );
+    transfer.sample = (Instant::now(), usb_bytes, tcp_bytes);
+}
+
+fn write_value(out: &mut String, name: &str, typ: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, typ);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn write_transfer(out: &mut String) {
+    let transfer = TRANSFER.lock().unwrap();
+    let (usb_bytes, tcp_bytes, speed, uptime) = match transfer.as_ref() {
+        Some(t) => (
+            t.usb_bytes_written.load(Ordering::Relaxed) as u64,
+            t.tcp_bytes_written.load(Ordering::Relaxed) as u64,
+            t.speed,
+            t.started.elapsed().as_secs(),
+        ),
+        None => (0, 0, (0, 0), 0),
+    };
+    write_value(
+        out,
+        "aa_usb_bytes_written_total",
+        "counter",
+        "Bytes written to the car (phone -> car) in the current session.",
+        usb_bytes,
+    );
+    write_value(
+        out,
+        "aa_tcp_bytes_written_total",
+        "counter",
+        "Bytes written to the phone (car -> phone) in the current session.",
+        tcp_bytes,
+    );
+    write_value(
+        out,
+        "aa_usb_bytes_per_second",
+        "gauge",
+        "Current phone -> car transfer speed.",
+        speed.0,
+    );
+    write_value(
+        out,
+        "aa_tcp_bytes_per_second",
+        "gauge",
+        "Current car -> phone transfer speed.",
+        speed.1,
+    );
+    write_value(
+        out,
+        "aa_session_uptime_seconds",
+        "gauge",
+        "Duration of the current session, 0 without a session.",
+        uptime,
+    );
+    write_value(
+        out,
+        "aa_reconnects_total",
+        "counter",
+        "Sessions started after the first one.",
+        SESSIONS.load(Ordering::Relaxed).saturating_sub(1),
+    );
+}
+
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    counters: &BTreeMap<(&'static str, &'static str), Counter>,
+    value: impl Fn(&Counter) -> u64,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for ((channel, direction), counter) in counters.iter() {
+        let _ = writeln!(
+            out,
+            "{}{{channel=\"{}\",direction=\"{}\"}} {}",
+            name,
+            channel,
+            direction,
+            value(counter)
+        );
+    }
+}
+
+/// all counters in the Prometheus text exposition format
+pub fn render() -> String {
+    let counters = COUNTERS.lock().unwrap();
+    let mut out = String::new();
+    write_metric(
+        &mut out,
+        "aa_channel_bytes_total",
+        "Bytes transmitted per channel type and direction.",
+        &counters,
+        |c| c.bytes,
+    );
+    write_metric(
+        &mut out,
+        "aa_channel_packets_total",
+        "Packets transmitted per channel type and direction.",
+        &counters,
+        |c| c.packets,
+    );
+    drop(counters);
+    write_transfer(&mut out);
+    out
+}
+
+async fn handle(_req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(render()))
+        .unwrap())
+}
+
+/// Serves [`render`] on `port` from a thread with its own tokio runtime,
+/// so scraping never competes with the single-threaded io_uring runtime.
+pub fn spawn_server(port: u16) {
+    let spawned = std::thread::Builder::new()
+        .name("metrics".into())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    error!("{} runtime error: {}", NAME, e);
+                    return;
+                }
+            };
+            runtime.block_on(async move {
+                let addr = SocketAddr::from(([0, 0, 0, 0], port));
+                let make_svc =
+                    make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+                match Server::try_bind(&addr) {
+                    Ok(builder) => {
+                        info!("{} 📈 serving metrics at http://{}/metrics", NAME, addr);
+                        if let Err(e) = builder.serve(make_svc).await {
+                            error!("{} server error: {}", NAME, e);
+                        }
+                    }
+                    Err(e) => error!("{} cannot bind {}: {}", NAME, addr, e),
+                }
+            });
+        });
+    if let Err(e) = spawned {
+        error!("{} cannot start the server thread: {}", NAME, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mitm::protos::MediaSinkService;
+
+    #[test]
+    fn counters_rendered_with_bounded_labels() {
+        let mut msg = ServiceDiscoveryResponse::new();
+        let mut svc = Service::new();
+        svc.set_id(3);
+        let mut media = MediaSinkService::new();
+        media.video_configs.push(Default::default());
+        svc.media_sink_service = Some(media).into();
+        msg.services.push(svc);
+        set_channel_labels(&msg);
+
+        count(ProxyType::HeadUnit, 3, 1000);
+        count(ProxyType::HeadUnit, 3, 500);
+        count(ProxyType::MobileDevice, 0, 10);
+        // not in the SDR
+        count(ProxyType::HeadUnit, 42, 7);
+
+        let text = render();
+        assert!(text.contains("# TYPE aa_channel_bytes_total counter"));
+        assert!(text.contains("aa_channel_bytes_total{channel=\"video\",direction=\"to_hu\"} 1500"));
+        assert!(text.contains("aa_channel_packets_total{channel=\"video\",direction=\"to_hu\"} 2"));
+        assert!(text.contains("aa_channel_bytes_total{channel=\"control\",direction=\"to_md\"} 10"));
+        assert!(text.contains("aa_channel_bytes_total{channel=\"unknown\",direction=\"to_hu\"} 7"));
+    }
+
+    #[test]
+    fn transfer_counters_are_read_from_the_session() {
+        let usb = Arc::new(AtomicUsize::new(0));
+        let tcp = Arc::new(AtomicUsize::new(0));
+        start_session(usb.clone(), tcp.clone());
+        usb.store(4096, Ordering::Relaxed);
+        tcp.store(128, Ordering::Relaxed);
+
+        let text = render();
+        assert!(text.contains(
+            "# TYPE aa_usb_bytes_written_total counter\naa_usb_bytes_written_total 4096\n"
+        ));
+        assert!(text.contains("aa_tcp_bytes_written_total 128\n"));
+        assert!(text.contains("# TYPE aa_usb_bytes_per_second gauge"));
+
+        end_session();
+        assert!(render().contains("aa_usb_bytes_written_total 0\n"));
+    }
+}
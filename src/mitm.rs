@@ -1,5 +1,6 @@
 use crate::bt_sco;
 use crate::bt_sco_media_bridge;
+use crate::capture::{CaptureDirection, SessionCapture};
 use crate::ev::send_ev_data;
 use crate::ev::BatteryData;
 #[cfg(feature = "wasm-scripting")]
@@ -15,28 +16,36 @@ use crate::display::add_display_services;
 use crate::display::emulate_injected_media_packet;
 use crate::display::maybe_emit_pending_injected_focus;
 use crate::display::InjectedMediaState;
+use crate::latency_stats;
+#[cfg(feature = "metrics")]
+use crate::metrics;
 use crate::mitm_prettyprint::{pkt_debug, update_debug_channel_kinds, PacketDebugServiceKind};
+use crate::packet_filter;
 use crate::sdr_ui;
+use crate::sensor_feed;
 use crate::vendor_ext::{
     add_vendor_extension_service, ensure_vendor_channel_open, ensure_vendor_topic_event_bridge,
     handle_vendor_channel_packet, has_vendor_extension_service, is_vendor_channel,
     is_vendor_service_id, mark_vendor_channel_open, VecChannelState, VecTopicEventBridge,
     VecTopicEventRuntime, OUR_VEC_PACKAGE, OUR_VEC_SERVICE_NAME,
 };
+use crate::video_cap::{FrameVerdict, VideoFrameLimiter};
+use crate::video_stats;
 use crate::web::ServerEvent;
 use anyhow::Context;
-use openssl::ssl::{ErrorCode, Ssl, SslContextBuilder, SslFiletype, SslMethod};
+use openssl::ssl::{ErrorCode, Ssl, SslContext, SslContextBuilder, SslFiletype, SslMethod};
 use serde::{Deserialize, Serialize};
 use simplelog::*;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::fmt;
+use std::future::Future;
 use std::io::{Read, Write};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::broadcast::Sender as BroadcastSender;
@@ -44,6 +53,9 @@ use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::RwLock;
 use tokio::time::timeout;
 use tokio_uring::buf::BoundedBuf;
+use tokio_uring::buf::Slice;
+use tokio_uring::BufResult;
+use tokio_util::sync::CancellationToken;
 
 // protobuf stuff:
 include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
@@ -65,11 +77,13 @@ use protos::ControlMessageType::{self, *};
 
 use crate::config::{Action::Stop, AppConfig, BtScoMediaBridgeAudioType, SharedConfig};
 use crate::config_types::HexdumpLevel;
+use crate::config_types::ModelDpiMap;
+use crate::config_types::ProtocolVersion;
+use crate::config_types::ServiceIdList;
 use crate::ev::EvTaskCommand;
 use crate::hu_input::{handle_hu_input, HuInputState};
 use crate::io_uring::Endpoint;
 use crate::io_uring::IoDevice;
-use crate::io_uring::BUFFER_LEN;
 pub use crate::media_tap::{
     media_tcp_server, AudioStreamConfig, MediaSink, MediaStreamInfo, MediaStreamKind,
 };
@@ -162,7 +176,7 @@ pub struct TirePressureData {
     pub pressures_kpa: Vec<f32>,
 }
 
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub enum ProxyType {
     HeadUnit,
     MobileDevice,
@@ -232,6 +246,7 @@ impl SslMemBuf {
     }
 }
 
+#[derive(Clone)]
 pub struct Packet {
     pub channel: u8,
     pub flags: u8,
@@ -281,6 +296,11 @@ impl Packet {
         &self,
         device: &mut IoDevice<A>,
     ) -> std::result::Result<usize, std::io::Error> {
+        write_frame(device, self.to_frame()).await
+    }
+
+    /// header and payload as written to the device
+    fn to_frame(&self) -> Vec<u8> {
         let len = self.payload.len() as u16;
         let mut frame: Vec<u8> = vec![];
         frame.push(self.channel);
@@ -294,22 +314,8 @@ impl Packet {
             frame.push((final_len >> 8) as u8);
             frame.push((final_len & 0xff) as u8);
         }
-        match device {
-            IoDevice::UsbWriter(device, _) => {
-                frame.append(&mut self.payload.clone());
-                let mut dev = device.borrow_mut();
-                dev.write(&frame).await
-            }
-            IoDevice::EndpointIo(device) => {
-                frame.append(&mut self.payload.clone());
-                device.write(frame).submit().await.0
-            }
-            IoDevice::TcpStreamIo(device) => {
-                frame.append(&mut self.payload.clone());
-                device.write(frame).submit().await.0
-            }
-            _ => todo!(),
-        }
+        frame.extend_from_slice(&self.payload);
+        frame
     }
 
     /// decapsulates SSL payload and writes to SslStream
@@ -322,6 +328,108 @@ impl Packet {
     }
 }
 
+/// writes one or more complete frames to the device
+async fn write_frame<A: Endpoint<A>>(
+    device: &mut IoDevice<A>,
+    frame: Vec<u8>,
+) -> std::result::Result<usize, std::io::Error> {
+    match device {
+        IoDevice::UsbWriter(device, _) => {
+            let mut dev = device.borrow_mut();
+            dev.write_all(&frame).await.map(|_| frame.len())
+        }
+        IoDevice::EndpointIo(device) => {
+            let _permit = inflight_permit().await;
+            write_all(|buf| device.write(buf).submit(), frame).await
+        }
+        IoDevice::TcpStreamIo(device) => {
+            let _permit = inflight_permit().await;
+            write_all(|buf| device.write(buf).submit(), frame).await
+        }
+        IoDevice::UnixStreamIo(device) => {
+            let _permit = inflight_permit().await;
+            write_all(|buf| device.write(buf).submit(), frame).await
+        }
+        IoDevice::FunctionFsIo(device) => {
+            let _permit = inflight_permit().await;
+            write_all(|buf| device.write(buf).submit(), frame).await
+        }
+        // a reader half can't be written, fail the session instead of panicking
+        IoDevice::UsbReader(..) => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "write to a read-only USB stream",
+        )),
+    }
+}
+
+/// Write coalescing (`write_coalesce_ms`): small consecutive frames are
+/// collected and written to the device at once, saving write submissions.
+/// The buffer is written when the first collected frame is `max_delay` old,
+/// when it reaches `max_bytes`, or right away for a frame marked to flush.
+struct WriteCoalescer {
+    buf: Vec<u8>,
+    deadline: Option<tokio::time::Instant>,
+    max_delay: Duration,
+    max_bytes: usize,
+}
+
+impl WriteCoalescer {
+    fn new(max_delay: Duration, max_bytes: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(max_bytes),
+            deadline: None,
+            max_delay,
+            max_bytes,
+        }
+    }
+
+    /// when the collected frames have to be written at the latest
+    fn deadline(&self) -> Option<tokio::time::Instant> {
+        self.deadline
+    }
+
+    /// collects the frame, writing the buffer when it's due
+    async fn write<A: Endpoint<A>>(
+        &mut self,
+        device: &mut IoDevice<A>,
+        frame: Vec<u8>,
+        flush_now: bool,
+    ) -> std::result::Result<(), std::io::Error> {
+        if self.buf.is_empty() && (flush_now || frame.len() >= self.max_bytes) {
+            // nothing to merge with, no need to copy it
+            write_frame(device, frame).await?;
+            return Ok(());
+        }
+        if self.buf.is_empty() {
+            self.deadline = Some(tokio::time::Instant::now() + self.max_delay);
+        }
+        self.buf.extend_from_slice(&frame);
+        if flush_now || self.buf.len() >= self.max_bytes {
+            self.flush(device).await?;
+        }
+        Ok(())
+    }
+
+    /// writes the collected frames
+    async fn flush<A: Endpoint<A>>(
+        &mut self,
+        device: &mut IoDevice<A>,
+    ) -> std::result::Result<(), std::io::Error> {
+        self.deadline = None;
+        if !self.buf.is_empty() {
+            let frames = std::mem::replace(&mut self.buf, Vec::with_capacity(self.max_bytes));
+            write_frame(device, frames).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Latency sensitive frames which are not held back by the write coalescing:
+/// control messages and input events.
+fn flush_now(pkt: &Packet, input_channel: Option<u8>) -> bool {
+    pkt.channel == 0 || Some(pkt.channel) == input_channel
+}
+
 impl fmt::Display for Packet {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "packet dump:\n")?;
@@ -534,6 +642,24 @@ fn choose_bt_sco_media_bridge_sink(
     best.map(|(score, channel, cfg, audio_type)| (channel, cfg, audio_type, score))
 }
 
+/// Removes services not in `allow` (when set) or listed in `deny`,
+/// returning the ids of the removed services.
+fn filter_services(
+    msg: &mut ServiceDiscoveryResponse,
+    allow: &ServiceIdList,
+    deny: &ServiceIdList,
+) -> Vec<i32> {
+    let mut removed = vec![];
+    msg.services.retain(|svc| {
+        let keep = (allow.0.is_none() || allow.contains(svc.id())) && !deny.contains(svc.id());
+        if !keep {
+            removed.push(svc.id());
+        }
+        keep
+    });
+    removed
+}
+
 async fn update_last_service_discovery_response(
     proxy_type: ProxyType,
     last_service_discovery_response: &SharedServiceDiscoveryResponse,
@@ -579,6 +705,14 @@ pub async fn pkt_modify_hook(
     script_registry: Option<Arc<ScriptRegistry>>,
     ws_event_tx: BroadcastSender<ServerEvent>,
 ) -> Result<PacketAction> {
+    // registered filters see each packet once, as received from its endpoint
+    if flow == PacketFlow::FromEndpoint {
+        let action = packet_filter::run(proxy_type, pkt);
+        if action != PacketAction::Forward {
+            return Ok(action);
+        }
+    }
+
     // if for some reason we have too small packet, bail out
     if pkt.payload.len() < 2 {
         return Ok(PacketAction::Forward);
@@ -701,6 +835,24 @@ pub async fn pkt_modify_hook(
                 }
                 SENSOR_MESSAGE_BATCH => {
                     if let Ok(mut msg) = SensorBatch::parse_from_bytes(data) {
+                        // real speed, before any spoofing below
+                        if cfg.motion_stats && !msg.speed_data.is_empty() {
+                            set_vehicle_motion(msg.speed_data[0].speed_e3());
+                        }
+                        // real gear/parking brake, before any spoofing below
+                        if let Some(state) = update_transmission_state(&msg) {
+                            debug!(
+                                "{} vehicle: gear={:?}, parking_brake={:?}",
+                                get_name(proxy_type),
+                                state.gear,
+                                state.parking_brake
+                            );
+                            let _ = ws_event_tx.send(ServerEvent {
+                                topic: "vehicle".to_string(),
+                                payload: serde_json::to_string(&state).unwrap_or_default(),
+                            });
+                        }
+
                         if cfg.video_in_motion || cfg.disable_driving_status {
                             // === DRIVING STATUS: must be UNRESTRICTED (0) ===
                             // This is the primary flag AA checks. Value is a bitmask:
@@ -1098,6 +1250,20 @@ pub async fn pkt_modify_hook(
 
     // parsing data
     match control.unwrap_or(MESSAGE_UNEXPECTED_MESSAGE) {
+        MESSAGE_SERVICE_DISCOVERY_REQUEST => {
+            // the phone's ping timeout bounds how long the transfer may be paused
+            if let Ok(msg) = ServiceDiscoveryRequest::parse_from_bytes(data) {
+                if let Some(timeout_ms) = msg.connection_configuration.ping_configuration.timeout_ms
+                {
+                    debug!(
+                        "{} phone ping timeout: {} ms",
+                        get_name(proxy_type),
+                        timeout_ms
+                    );
+                    set_transfer_pause_limit(Some(Duration::from_millis(timeout_ms.into())));
+                }
+            }
+        }
         MESSAGE_BYEBYE_REQUEST => {
             if cfg.stop_on_disconnect && proxy_type == ProxyType::MobileDevice {
                 if let Ok(msg) = ByeByeRequest::parse_from_bytes(data) {
@@ -1217,6 +1383,15 @@ pub async fn pkt_modify_hook(
             // again after SDR rewriting/injected services below.
             update_debug_channel_kinds(ctx, &msg);
 
+            // video channels as advertised by the HU (without injected displays)
+            if cfg.video_stats && proxy_type == ProxyType::HeadUnit {
+                video_stats::set_video_channels(&msg);
+            }
+            #[cfg(feature = "metrics")]
+            if proxy_type == ProxyType::HeadUnit {
+                metrics::set_channel_labels(&msg);
+            }
+
             if let Some(svc) = msg
                 .services
                 .iter()
@@ -1417,7 +1592,11 @@ pub async fn pkt_modify_hook(
             }
 
             // DPI
-            if cfg.dpi > 0 {
+            let (dpi, dpi_source) = match dpi_override() {
+                Some(dpi) => (dpi, "runtime override"),
+                None => dpi_for_head_unit(&cfg.dpi_by_model, cfg.dpi, &msg),
+            };
+            if dpi > 0 {
                 if let Some(svc) = msg
                     .services
                     .iter_mut()
@@ -1427,13 +1606,15 @@ pub async fn pkt_modify_hook(
                     let prev_val = svc.media_sink_service.video_configs[0].density();
                     // set new value
                     svc.media_sink_service.as_mut().unwrap().video_configs[0]
-                        .set_density(cfg.dpi.into());
+                        .set_density(dpi.into());
                     info!(
-                        "{} <yellow>{:?}</>: replacing DPI value: from <b>{}</> to <b>{}</>",
+                        "{} <yellow>{:?}</>: replacing DPI value: from <b>{}</> to <b>{}</> (HU model: {:?}, {})",
                         get_name(proxy_type),
                         control.unwrap(),
                         prev_val,
-                        cfg.dpi
+                        dpi,
+                        head_unit_model(&msg).unwrap_or_default(),
+                        dpi_source
                     );
                 }
             }
@@ -1489,6 +1670,7 @@ pub async fn pkt_modify_hook(
                 || cfg.odometer
                 || cfg.collect_speed
                 || cfg.tire_pressure
+                || cfg.sensor_injection
             {
                 if let Some(svc) = msg
                     .services
@@ -1626,6 +1808,17 @@ pub async fn pkt_modify_hook(
                     .retain(|svc| svc.wifi_projection_service.is_none());
             }
 
+            // user-configured service filtering
+            let removed = filter_services(&mut msg, &cfg.sdr_service_allow, &cfg.sdr_service_deny);
+            if !removed.is_empty() {
+                info!(
+                    "{} <yellow>{:?}</>: removed service ids: {:?}",
+                    get_name(proxy_type),
+                    control.unwrap(),
+                    removed
+                );
+            }
+
             // EV routing features
             if cfg.ev {
                 if let Some(svc) = msg
@@ -1727,6 +1920,37 @@ pub async fn pkt_modify_hook(
                 }
             }
 
+            // GPS position and speed for HUs which don't provide them
+            if cfg.sensor_injection {
+                if let Some(svc) = msg
+                    .services
+                    .iter_mut()
+                    .find(|svc| !svc.sensor_source_service.sensors.is_empty())
+                {
+                    let sensors = &mut svc.sensor_source_service.as_mut().unwrap().sensors;
+                    let has = |t: SensorType| sensors.iter().any(|s| s.sensor_type() == t);
+                    let location = !has(SENSOR_LOCATION);
+                    // the speed sensor was removed on purpose by these
+                    let speed =
+                        !has(SENSOR_SPEED) && !cfg.remove_tap_restriction && !cfg.video_in_motion;
+                    for (sensor_type, add) in [(SENSOR_LOCATION, location), (SENSOR_SPEED, speed)] {
+                        if add {
+                            let mut sensor = Sensor::new();
+                            sensor.set_sensor_type(sensor_type);
+                            sensors.push(sensor);
+                        }
+                    }
+                    sensor_feed::set_injected_sensors(location, speed);
+                    info!(
+                        "{} <yellow>{:?}</>: injected sensors: location: <b>{}</>, speed: <b>{}</>",
+                        get_name(proxy_type),
+                        control.unwrap(),
+                        location,
+                        speed
+                    );
+                }
+            }
+
             let added_services = add_display_services(&mut msg, cfg);
             if added_services > 0 {
                 let before_ids: HashSet<i32> = ctx.hu_service_ids.clone();
@@ -2268,8 +2492,11 @@ async fn ssl_encapsulate(mut mem_buf: SslMemBuf) -> Result<Packet> {
     })
 }
 
-/// creates Ssl for HeadUnit (SSL server) and MobileDevice (SSL client)
-async fn ssl_builder(proxy_type: ProxyType) -> Result<Ssl> {
+// SSL contexts (HU side, MD side) created ahead of the first session by `ssl_prewarm()`
+static PREWARMED_SSL_CONTEXTS: Mutex<Option<(SslContext, SslContext)>> = Mutex::new(None);
+
+/// creates SSL context with our keys and certificates for given proxy side
+fn ssl_context(proxy_type: ProxyType) -> Result<SslContext> {
     let mut ctx_builder = SslContextBuilder::new(SslMethod::tls())?;
 
     // for HU/headunit we need to act as a MD/mobiledevice, so load "md" key and cert
@@ -2287,7 +2514,38 @@ async fn ssl_builder(proxy_type: ProxyType) -> Result<Ssl> {
     ctx_builder.set_min_proto_version(Some(openssl::ssl::SslVersion::TLS1_2))?;
     ctx_builder.set_options(openssl::ssl::SslOptions::NO_TLSV1_3);
 
-    let openssl_ctx = ctx_builder.build();
+    Ok(ctx_builder.build())
+}
+
+/// Initializes the crypto library and both SSL contexts before any phone
+/// connects, so the first session doesn't pay for it during the handshake.
+/// Returns how long the initialization took.
+pub fn ssl_prewarm() -> Result<Duration> {
+    let started = std::time::Instant::now();
+    let hu = ssl_context(ProxyType::HeadUnit)?;
+    let md = ssl_context(ProxyType::MobileDevice)?;
+    // the first Ssl object initializes the remaining lazily created internals
+    Ssl::new(&hu)?;
+    Ssl::new(&md)?;
+    *PREWARMED_SSL_CONTEXTS.lock().unwrap() = Some((hu, md));
+    Ok(started.elapsed())
+}
+
+/// creates Ssl for HeadUnit (SSL server) and MobileDevice (SSL client)
+async fn ssl_builder(proxy_type: ProxyType) -> Result<Ssl> {
+    let prewarmed =
+        PREWARMED_SSL_CONTEXTS
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(hu, md)| match proxy_type {
+                ProxyType::HeadUnit => hu.clone(),
+                ProxyType::MobileDevice => md.clone(),
+            });
+    let openssl_ctx = match prewarmed {
+        Some(ctx) => ctx,
+        None => ssl_context(proxy_type)?,
+    };
     let mut ssl = Ssl::new(&openssl_ctx)?;
     if proxy_type == ProxyType::HeadUnit {
         ssl.set_accept_state(); // SSL server
@@ -2298,13 +2556,166 @@ async fn ssl_builder(proxy_type: ProxyType) -> Result<Ssl> {
     Ok(ssl)
 }
 
+/// validates that everything needed for SSL interception is in place,
+/// returning an error which names exactly what failed to initialize
+pub async fn mitm_init_check() -> Result<()> {
+    for proxy_type in [ProxyType::HeadUnit, ProxyType::MobileDevice] {
+        let prefix = match proxy_type {
+            ProxyType::HeadUnit => "md",
+            ProxyType::MobileDevice => "hu",
+        };
+        for file in [
+            format!("{KEYS_PATH}/{prefix}_cert.pem"),
+            format!("{KEYS_PATH}/{prefix}_key.pem"),
+            format!("{KEYS_PATH}/galroot_cert.pem"),
+        ] {
+            if !std::path::Path::new(&file).exists() {
+                return Err(format!("missing key/certificate file: {}", file).into());
+            }
+        }
+        if let Err(e) = ssl_builder(proxy_type).await {
+            return Err(format!(
+                "SSL context for {:?} side could not be created: {}",
+                proxy_type, e
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Head unit model from the ServiceDiscoveryResponse, preferring the
+/// HeadUnitInfo over the deprecated top-level fields.
+fn head_unit_model(msg: &ServiceDiscoveryResponse) -> Option<String> {
+    let info = msg.headunit_info.as_ref();
+    [
+        info.map(|i| i.head_unit_model()),
+        info.map(|i| i.model()),
+        Some(msg.head_unit_model()),
+        Some(msg.model()),
+    ]
+    .into_iter()
+    .flatten()
+    .map(str::trim)
+    .find(|model| !model.is_empty())
+    .map(str::to_string)
+}
+
+// runtime DPI override, 0 = not set
+static DPI_OVERRIDE: AtomicU16 = AtomicU16::new(0);
+
+/// Overrides the configured DPI without touching the config file (`None` clears it).
+/// It is read when the service discovery response is rewritten, so it only takes
+/// effect on the next negotiation; the phone usually has to reconnect for that.
+pub fn set_dpi_override(dpi: Option<u16>) {
+    DPI_OVERRIDE.store(dpi.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn dpi_override() -> Option<u16> {
+    match DPI_OVERRIDE.load(Ordering::Relaxed) {
+        0 => None,
+        dpi => Some(dpi),
+    }
+}
+
+/// DPI to apply for the head unit (0 = unchanged) and where it comes from:
+/// the `dpi_by_model` entry of the reported model, otherwise the static `dpi`.
+fn dpi_for_head_unit(
+    dpi_by_model: &ModelDpiMap,
+    dpi: u16,
+    msg: &ServiceDiscoveryResponse,
+) -> (u16, &'static str) {
+    match head_unit_model(msg).and_then(|model| dpi_by_model.get(&model)) {
+        Some(model_dpi) => (model_dpi, "dpi_by_model"),
+        None => (dpi, "static dpi"),
+    }
+}
+
+/// Writes the whole frame, resubmitting the remainder after a partial write.
+/// Fails only when a write makes no progress at all.
+async fn write_all<W, F>(mut write: W, mut frame: Vec<u8>) -> std::io::Result<usize>
+where
+    W: FnMut(Slice<Vec<u8>>) -> F,
+    F: Future<Output = BufResult<usize, Slice<Vec<u8>>>>,
+{
+    let total = frame.len();
+    let mut written = 0;
+    while written < total {
+        let (res, buf) = write(frame.slice(written..)).await;
+        frame = buf.into_inner();
+        let n = res?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                format!(
+                    "write made no progress after {} of {} bytes",
+                    written, total
+                ),
+            ));
+        }
+        written += n;
+        if written < total {
+            debug!(
+                "partial write: {} of {} bytes, writing the remaining {}",
+                written,
+                total,
+                total - written
+            );
+        }
+    }
+    Ok(total)
+}
+
+/// reads from a USB gadget endpoint, optionally the frame header first
+async fn read_endpoint<E: Endpoint<E>>(
+    device: &E,
+    rbuf: &mut VecDeque<u8>,
+    mut newdata: Vec<u8>,
+    incremental_read: bool,
+) -> Result<(usize, Vec<u8>)> {
+    let mut n;
+    let len;
+
+    let _permit = inflight_permit().await;
+    if incremental_read {
+        // read header
+        newdata = vec![0u8; HEADER_LENGTH];
+        let retval = device.read(newdata);
+        (n, newdata) = timeout(Duration::from_millis(15000), retval)
+            .await
+            .context("read_input_data/header: EndpointIo timeout")?;
+        let len = n.context("read_input_data/header: EndpointIo read error")?;
+
+        // fill the output/read buffer with the obtained header data
+        rbuf.write(&newdata.clone().slice(..len))?;
+
+        // compute payload size
+        let mut payload_size = (newdata[3] as u16 + ((newdata[2] as u16) << 8)) as usize;
+        if (newdata[1] & FRAME_TYPE_MASK) == FRAME_TYPE_FIRST {
+            // header is 8 bytes; need to read 4 more bytes
+            payload_size += 4;
+        }
+        // prepare buffer for the payload and continue normally
+        newdata = vec![0u8; payload_size];
+    }
+    let retval = device.read(newdata);
+    (n, newdata) = timeout(Duration::from_millis(15000), retval)
+        .await
+        .context("read_input_data: EndpointIo timeout")?;
+    len = n.context("read_input_data: EndpointIo read error")?;
+
+    Ok((len, newdata))
+}
+
 /// reads all available data to VecDeque
 async fn read_input_data<A: Endpoint<A>>(
     rbuf: &mut VecDeque<u8>,
     obj: &mut IoDevice<A>,
     incremental_read: bool,
+    buffer_len: usize,
 ) -> Result<usize> {
-    let mut newdata = vec![0u8; BUFFER_LEN];
+    let mut newdata = vec![0u8; buffer_len];
     let mut n;
     let mut len;
 
@@ -2317,34 +2728,15 @@ async fn read_input_data<A: Endpoint<A>>(
                 .context("read_input_data: UsbReader read error")?;
         }
         IoDevice::EndpointIo(device) => {
-            if incremental_read {
-                // read header
-                newdata = vec![0u8; HEADER_LENGTH];
-                let retval = device.read(newdata);
-                (n, newdata) = timeout(Duration::from_millis(15000), retval)
-                    .await
-                    .context("read_input_data/header: EndpointIo timeout")?;
-                len = n.context("read_input_data/header: EndpointIo read error")?;
-
-                // fill the output/read buffer with the obtained header data
-                rbuf.write(&newdata.clone().slice(..len))?;
-
-                // compute payload size
-                let mut payload_size = (newdata[3] as u16 + ((newdata[2] as u16) << 8)) as usize;
-                if (newdata[1] & FRAME_TYPE_MASK) == FRAME_TYPE_FIRST {
-                    // header is 8 bytes; need to read 4 more bytes
-                    payload_size += 4;
-                }
-                // prepare buffer for the payload and continue normally
-                newdata = vec![0u8; payload_size];
-            }
-            let retval = device.read(newdata);
-            (n, newdata) = timeout(Duration::from_millis(15000), retval)
-                .await
-                .context("read_input_data: EndpointIo timeout")?;
-            len = n.context("read_input_data: EndpointIo read error")?;
+            (len, newdata) =
+                read_endpoint(device.as_ref(), rbuf, newdata, incremental_read).await?;
+        }
+        IoDevice::FunctionFsIo(device) => {
+            (len, newdata) =
+                read_endpoint(device.as_ref(), rbuf, newdata, incremental_read).await?;
         }
         IoDevice::TcpStreamIo(device) => {
+            let _permit = inflight_permit().await;
             let retval = device.read(newdata);
             (n, newdata) = timeout(Duration::from_millis(15000), retval)
                 .await
@@ -2355,6 +2747,17 @@ async fn read_input_data<A: Endpoint<A>>(
                 return Err("read_input_data: TcpStreamIo EOF".into());
             }
         }
+        IoDevice::UnixStreamIo(device) => {
+            let _permit = inflight_permit().await;
+            let retval = device.read(newdata);
+            (n, newdata) = timeout(Duration::from_millis(15000), retval)
+                .await
+                .context("read_input_data: UnixStreamIo timeout")?;
+            len = n.context("read_input_data: UnixStreamIo read error")?;
+            if len == 0 {
+                return Err("read_input_data: UnixStreamIo EOF".into());
+            }
+        }
         _ => todo!(),
     }
     if len > 0 {
@@ -2368,16 +2771,84 @@ fn is_musl() -> bool {
     std::path::Path::new("/lib/ld-musl-riscv64.so.1").exists()
 }
 
+// number of payload bytes hashed by the duplicate frame detector
+const DUP_FRAME_HASH_LEN: usize = 256;
+
+/// Diagnostic for link corruption or a stuck writer: detects identical frames
+/// repeated on the same channel. Only a bounded prefix of each frame is hashed.
+pub struct DuplicateFrameDetector {
+    threshold: u32,
+    window: Duration,
+    // per channel: hash of last frame, number of repeats, time of first occurrence
+    last: HashMap<u8, (u64, u32, std::time::Instant)>,
+}
+
+impl DuplicateFrameDetector {
+    pub fn new(threshold: u32, window: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            last: HashMap::new(),
+        }
+    }
+
+    fn frame_hash(pkt: &Packet) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        pkt.flags.hash(&mut hasher);
+        pkt.final_length.hash(&mut hasher);
+        pkt.payload.len().hash(&mut hasher);
+        pkt.payload[..pkt.payload.len().min(DUP_FRAME_HASH_LEN)].hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Registers a frame; returns the repeat count when it reaches the threshold
+    /// within the window (reported once per run of duplicates).
+    pub fn check(&mut self, pkt: &Packet) -> Option<u32> {
+        let hash = Self::frame_hash(pkt);
+        let now = std::time::Instant::now();
+        match self.last.get_mut(&pkt.channel) {
+            Some(entry) if entry.0 == hash && now.duration_since(entry.2) <= self.window => {
+                entry.1 += 1;
+                (entry.1 == self.threshold).then_some(entry.1)
+            }
+            _ => {
+                self.last.insert(pkt.channel, (hash, 0, now));
+                None
+            }
+        }
+    }
+}
+
 /// main reader thread for a device
 pub async fn endpoint_reader<A: Endpoint<A>>(
     mut device: IoDevice<A>,
     tx: Sender<Packet>,
     hu: bool,
+    buffer_len: usize,
+    mut dup_detector: Option<DuplicateFrameDetector>,
+    stop: CancellationToken,
 ) -> Result<()> {
     let mut rbuf: VecDeque<u8> = VecDeque::new();
     let incremental_read = if !hu && is_musl() { true } else { false };
     loop {
-        read_input_data(&mut rbuf, &mut device, incremental_read).await?;
+        // the session ends: return only between frames, a partially received
+        // one is completed first
+        if stop.is_cancelled() && rbuf.is_empty() {
+            return Ok(());
+        }
+        wait_transfer_resumed().await;
+        if rbuf.is_empty() {
+            // between frames a pending read is given up when the session ends
+            tokio::select! {
+                res = read_input_data(&mut rbuf, &mut device, incremental_read, buffer_len) => {
+                    res?;
+                }
+                _ = stop.cancelled() => return Ok(()),
+            }
+        } else {
+            read_input_data(&mut rbuf, &mut device, incremental_read, buffer_len).await?;
+        }
         // check if we have complete packet available
         loop {
             // Accept packets as soon as we have the complete fixed header.
@@ -2417,6 +2888,21 @@ pub async fn endpoint_reader<A: Endpoint<A>>(
                         final_length,
                         payload: frame,
                     };
+                    if let Some(ref mut detector) = dup_detector {
+                        if let Some(repeats) = detector.check(&pkt) {
+                            warn!(
+                                "{} 🔁 channel <b>{:#04x}</>: identical frame repeated {} times within {} ms (link corruption or stuck writer?)",
+                                get_name(if hu {
+                                    ProxyType::HeadUnit
+                                } else {
+                                    ProxyType::MobileDevice
+                                }),
+                                pkt.channel,
+                                repeats,
+                                detector.window.as_millis()
+                            );
+                        }
+                    }
                     // send packet to main thread for further process
                     tx.send(pkt).await?;
                     // check if we have another packet
@@ -2429,6 +2915,265 @@ pub async fn endpoint_reader<A: Endpoint<A>>(
     }
 }
 
+/// HU's plain version request frame, which (re)starts the AA protocol
+pub fn is_version_request(pkt: &Packet) -> bool {
+    let p = &pkt.payload;
+    pkt.channel == 0
+        && pkt.flags & ENCRYPTED == 0
+        && p.len() >= 2
+        && u16::from_be_bytes([p[0], p[1]]) == MESSAGE_VERSION_REQUEST as u16
+}
+
+/// negotiated protocol version from the MD's version response frame
+fn parse_version_response(pkt: &Packet) -> Option<ProtocolVersion> {
+    let p = &pkt.payload;
+    if p.len() < 6 || u16::from_be_bytes([p[0], p[1]]) != MESSAGE_VERSION_RESPONSE as u16 {
+        return None;
+    }
+    Some(ProtocolVersion {
+        major: u16::from_be_bytes([p[2], p[3]]),
+        minor: u16::from_be_bytes([p[4], p[5]]),
+    })
+}
+
+/// checking the negotiated protocol version against the configured bounds
+fn check_protocol_version(pkt: &Packet, cfg: &AppConfig) -> Result<()> {
+    let bound = |b: Option<ProtocolVersion>| b.map_or("any".to_string(), |v| v.to_string());
+    let bounds_set = cfg.min_protocol_version.is_some() || cfg.max_protocol_version.is_some();
+    let version = parse_version_response(pkt);
+    *NEGOTIATED_PROTOCOL_VERSION.lock().unwrap() = version;
+    let Some(version) = version else {
+        if bounds_set {
+            return Err("unable to detect negotiated protocol version".into());
+        }
+        return Ok(());
+    };
+    info!(
+        "{} 🤝 negotiated protocol version: <b><blue>{}</> (accepted: {} - {})",
+        get_name(ProxyType::MobileDevice),
+        version,
+        bound(cfg.min_protocol_version),
+        bound(cfg.max_protocol_version),
+    );
+    if cfg.min_protocol_version.is_some_and(|min| version < min)
+        || cfg.max_protocol_version.is_some_and(|max| version > max)
+    {
+        return Err(format!(
+            "negotiated protocol version {} is outside of the supported range {} - {}",
+            version,
+            bound(cfg.min_protocol_version),
+            bound(cfg.max_protocol_version),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// vehicle motion state derived from the last observed speed sensor sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Unknown,
+    Parked,
+    Moving,
+}
+
+// speed_e3 (m/s * 1000) from which the vehicle is considered moving (~3.6 km/h)
+const MOVING_SPEED_E3: i32 = 1000;
+static VEHICLE_MOTION: AtomicU8 = AtomicU8::new(Motion::Unknown as u8);
+
+fn set_vehicle_motion(speed_e3: i32) {
+    let motion = if speed_e3.abs() >= MOVING_SPEED_E3 {
+        Motion::Moving
+    } else {
+        Motion::Parked
+    };
+    VEHICLE_MOTION.store(motion as u8, Ordering::Relaxed);
+}
+
+pub fn vehicle_motion() -> Motion {
+    match VEHICLE_MOTION.load(Ordering::Relaxed) {
+        x if x == Motion::Parked as u8 => Motion::Parked,
+        x if x == Motion::Moving as u8 => Motion::Moving,
+        _ => Motion::Unknown,
+    }
+}
+
+/// forgets the motion state, e.g. at the start of a new session
+pub fn reset_vehicle_motion() {
+    VEHICLE_MOTION.store(Motion::Unknown as u8, Ordering::Relaxed);
+}
+
+/// gear and parking brake as last reported by the vehicle;
+/// `None` for vehicles (or sessions) which didn't report them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TransmissionState {
+    pub gear: Option<&'static str>,
+    pub parking_brake: Option<bool>,
+}
+
+static TRANSMISSION_STATE: Mutex<TransmissionState> = Mutex::new(TransmissionState {
+    gear: None,
+    parking_brake: None,
+});
+
+fn gear_name(gear: Gear) -> &'static str {
+    match gear {
+        Gear::GEAR_NEUTRAL => "neutral",
+        Gear::GEAR_1 => "1",
+        Gear::GEAR_2 => "2",
+        Gear::GEAR_3 => "3",
+        Gear::GEAR_4 => "4",
+        Gear::GEAR_5 => "5",
+        Gear::GEAR_6 => "6",
+        Gear::GEAR_7 => "7",
+        Gear::GEAR_8 => "8",
+        Gear::GEAR_9 => "9",
+        Gear::GEAR_10 => "10",
+        Gear::GEAR_DRIVE => "drive",
+        Gear::GEAR_PARK => "park",
+        Gear::GEAR_REVERSE => "reverse",
+    }
+}
+
+/// updates the state from a sensor batch, returning the new state if it changed
+fn update_transmission_state(msg: &SensorBatch) -> Option<TransmissionState> {
+    let mut state = TRANSMISSION_STATE.lock().unwrap();
+    let mut new_state = *state;
+    if let Some(gear) = msg.gear_data.first() {
+        new_state.gear = Some(gear_name(gear.gear()));
+    }
+    if let Some(brake) = msg.parking_brake_data.first() {
+        new_state.parking_brake = Some(brake.parking_brake());
+    }
+    if new_state == *state {
+        return None;
+    }
+    *state = new_state;
+    Some(new_state)
+}
+
+pub fn transmission_state() -> TransmissionState {
+    *TRANSMISSION_STATE.lock().unwrap()
+}
+
+/// forgets the gear/parking brake state, e.g. at the start of a new session
+pub fn reset_transmission_state() {
+    *TRANSMISSION_STATE.lock().unwrap() = TransmissionState::default();
+}
+
+// protocol version negotiated in the most recent MITM session
+static NEGOTIATED_PROTOCOL_VERSION: Mutex<Option<ProtocolVersion>> = Mutex::new(None);
+
+/// takes the protocol version negotiated since the last call (if any)
+pub fn take_negotiated_protocol_version() -> Option<ProtocolVersion> {
+    NEGOTIATED_PROTOCOL_VERSION.lock().unwrap().take()
+}
+
+// bounds the io_uring reads/writes in flight (`max_inflight_ops`); unbounded when unset
+static INFLIGHT_OPS: std::sync::OnceLock<tokio::sync::Semaphore> = std::sync::OnceLock::new();
+
+/// Both endpoint readers keep a read pending all the time, so with fewer
+/// permits the writers would never get one.
+pub const MIN_INFLIGHT_OPS: usize = 3;
+
+/// caps the io_uring operations in flight; can only be set once
+pub fn set_max_inflight_ops(max: usize) {
+    let _ = INFLIGHT_OPS.set(tokio::sync::Semaphore::new(max.max(MIN_INFLIGHT_OPS)));
+}
+
+async fn inflight_permit() -> Option<tokio::sync::SemaphorePermit<'static>> {
+    INFLIGHT_OPS.get()?.acquire().await.ok()
+}
+
+/// Longest pause when the phone didn't announce its ping timeout. While paused,
+/// the pings the phone and the HU exchange on the control channel stay
+/// unanswered, and the phone ends the session once its ping timeout passed; the
+/// phone can announce that timeout in its `ServiceDiscoveryRequest`
+/// (`connection_configuration.ping_configuration.timeout_ms`), which then bounds
+/// the pause instead. This fallback is not a documented protocol value, it's a
+/// conservative guess for phones not announcing one.
+pub const MAX_TRANSFER_PAUSE: Duration = Duration::from_secs(5);
+
+// checked by the readers and writers of the session
+static TRANSFER_PAUSED: AtomicBool = AtomicBool::new(false);
+// the pause is ended on its own after this long [ms]
+static TRANSFER_PAUSE_LIMIT_MS: AtomicU64 = AtomicU64::new(MAX_TRANSFER_PAUSE.as_millis() as u64);
+// when the transfer was paused
+static TRANSFER_PAUSED_SINCE: Mutex<Option<Instant>> = Mutex::new(None);
+
+fn transfer_resumed() -> &'static tokio::sync::Notify {
+    static RESUMED: std::sync::OnceLock<tokio::sync::Notify> = std::sync::OnceLock::new();
+    RESUMED.get_or_init(tokio::sync::Notify::new)
+}
+
+/// Freezes the data flow of the running session (e.g. while reconfiguring the
+/// AP) without ending it: the endpoint readers and the proxy writers wait until
+/// `resume_transfer`, and the stall detection is suspended meanwhile.
+/// Returns false when the transfer was already paused.
+pub fn pause_transfer() -> bool {
+    let mut since = TRANSFER_PAUSED_SINCE.lock().unwrap();
+    if since.is_some() {
+        return false;
+    }
+    *since = Some(Instant::now());
+    TRANSFER_PAUSED.store(true, Ordering::Release);
+    true
+}
+
+/// Resumes a paused transfer, returning how long it was paused.
+pub fn resume_transfer() -> Option<Duration> {
+    let since = TRANSFER_PAUSED_SINCE.lock().unwrap().take()?;
+    TRANSFER_PAUSED.store(false, Ordering::Release);
+    transfer_resumed().notify_waiters();
+    Some(since.elapsed())
+}
+
+/// Sets how long a pause may last before the transfer is resumed on its own;
+/// `None` restores [`MAX_TRANSFER_PAUSE`].
+pub fn set_transfer_pause_limit(limit: Option<Duration>) {
+    let limit = limit.unwrap_or(MAX_TRANSFER_PAUSE);
+    TRANSFER_PAUSE_LIMIT_MS.store(limit.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// how long a pause may last before the transfer is resumed on its own
+pub fn transfer_pause_limit() -> Duration {
+    Duration::from_millis(TRANSFER_PAUSE_LIMIT_MS.load(Ordering::Relaxed))
+}
+
+/// how long the transfer has been paused, `None` while it is running
+pub fn transfer_paused() -> Option<Duration> {
+    TRANSFER_PAUSED_SINCE
+        .lock()
+        .unwrap()
+        .map(|since| since.elapsed())
+}
+
+/// returns right away unless the transfer is paused; a pause longer than
+/// [`transfer_pause_limit`] is ended here, before the phone gives up on the session
+async fn wait_transfer_resumed() {
+    while TRANSFER_PAUSED.load(Ordering::Acquire) {
+        // registered before the check, so a resume in between isn't missed
+        let resumed = transfer_resumed().notified();
+        let Some(paused) = transfer_paused() else {
+            break;
+        };
+        let limit = transfer_pause_limit();
+        if timeout(limit.saturating_sub(paused), resumed)
+            .await
+            .is_err()
+        {
+            // only one of the waiting tasks gets to resume it
+            if let Some(paused) = resume_transfer() {
+                warn!(
+                    "<i><bright-black> mitm: </>⚠️ transfer paused for {} ms, resumed after the {} ms limit",
+                    paused.as_millis(),
+                    limit.as_millis()
+                );
+            }
+        }
+    }
+}
+
 /// checking if there was a true fatal SSL error
 /// Note that the error may not be fatal. For example if the underlying
 /// stream is an asynchronous one then `HandshakeError::WouldBlock` may
@@ -2477,6 +3222,23 @@ fn build_control_reply_on_channel(
     }
 }
 
+/// passes a packet which is about to be sent to the opposite side to the session capture
+fn capture_packet(capture: &Option<SessionCapture>, proxy_type: ProxyType, pkt: &Packet) {
+    if let Some(capture) = capture {
+        capture.record(CaptureDirection::from_sender(proxy_type), pkt);
+    }
+}
+
+/// write coalescing of a proxy task, when enabled
+fn write_coalescer(cfg: &AppConfig) -> Option<WriteCoalescer> {
+    (cfg.write_coalesce_ms > 0).then(|| {
+        WriteCoalescer::new(
+            Duration::from_millis(cfg.write_coalesce_ms.into()),
+            usize::from(cfg.write_coalesce_kb.max(1)) * 1024,
+        )
+    })
+}
+
 /// main thread doing all packet processing of an endpoint/device
 pub async fn proxy<A: Endpoint<A> + 'static>(
     proxy_type: ProxyType,
@@ -2496,6 +3258,8 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
     script_registry: Option<Arc<ScriptRegistry>>,
     media_sinks: HashMap<u8, MediaSink>,
     ws_event_tx: BroadcastSender<ServerEvent>,
+    capture: Option<SessionCapture>,
+    stop: CancellationToken,
 ) -> Result<()> {
     let cfg = config.read().await.clone();
     let passthrough = !cfg.mitm || cfg.runtime_mitm_failed;
@@ -2503,20 +3267,40 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
 
     // in full_frames/passthrough mode we only directly pass packets from one endpoint to the other
     if passthrough {
+        if cfg.video_stats && proxy_type == ProxyType::MobileDevice {
+            warn!(
+                "{} 🎞️ video stats are not available in passthrough mode",
+                get_name(proxy_type)
+            );
+        }
+        if cfg.latency_stats && proxy_type == ProxyType::MobileDevice {
+            warn!(
+                "{} ⏱️ latency stats are not available in passthrough mode",
+                get_name(proxy_type)
+            );
+        }
+        // the input channel is not known without the decrypted service discovery
+        let mut coalescer = write_coalescer(&cfg);
         loop {
+            let flush_at = coalescer.as_ref().and_then(WriteCoalescer::deadline);
             tokio::select! {
             // handling data from opposite device's thread, which needs to be transmitted
             Some(pkt) = rx.recv() => {
                 debug!("{} rx.recv", get_name(proxy_type));
                 let _ = pkt_debug(proxy_type, HexdumpLevel::RawOutput, hex_requested, &pkt, &cfg, None).await;
 
-                pkt.transmit(&mut device)
-                    .await
-                    .with_context(|| format!("proxy/{}: transmit failed", get_name(proxy_type)))?;
+                wait_transfer_resumed().await;
+                match coalescer {
+                    Some(ref mut c) => c.write(&mut device, pkt.to_frame(), flush_now(&pkt, None)).await,
+                    None => pkt.transmit(&mut device).await.map(|_| ()),
+                }
+                .with_context(|| format!("proxy/{}: transmit failed", get_name(proxy_type)))?;
 
                 // Increment byte counters for statistics
                 // fixme: compute final_len for precise stats
                 bytes_written.fetch_add(HEADER_LENGTH + pkt.payload.len(), Ordering::Relaxed);
+                #[cfg(feature = "metrics")]
+                metrics::count(proxy_type, pkt.channel, HEADER_LENGTH + pkt.payload.len());
             }
 
             // handling input data from the reader thread
@@ -2524,8 +3308,29 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
                 debug!("{} rxr.recv", get_name(proxy_type));
                 let _ = pkt_debug(proxy_type, HexdumpLevel::RawOutput, hex_requested, &pkt, &cfg, None).await;
 
+                capture_packet(&capture, proxy_type, &pkt);
                 tx.send(pkt).await?;
             }
+
+            // collected frames are due
+            _ = tokio::time::sleep_until(flush_at.unwrap_or_else(tokio::time::Instant::now)), if flush_at.is_some() => {
+                wait_transfer_resumed().await;
+                if let Some(ref mut c) = coalescer {
+                    c.flush(&mut device)
+                        .await
+                        .with_context(|| format!("proxy/{}: transmit failed", get_name(proxy_type)))?;
+                }
+            }
+
+            // the session ends: only between whole frames, collected ones are written first
+            _ = stop.cancelled() => {
+                if let Some(ref mut c) = coalescer {
+                    c.flush(&mut device)
+                        .await
+                        .with_context(|| format!("proxy/{}: transmit failed", get_name(proxy_type)))?;
+                }
+                return Ok(());
+            }
             }
         }
     }
@@ -2549,6 +3354,10 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
     if proxy_type == ProxyType::HeadUnit {
         // waiting for initial version frame (HU is starting transmission)
         let pkt = rxr.recv().await.ok_or("reader channel hung up")?;
+        // anything else is a HU still in a previous session
+        if !is_version_request(&pkt) {
+            return Err("HU did not restart the AA protocol with a version request".into());
+        }
         let _ = pkt_debug(
             proxy_type,
             HexdumpLevel::DecryptedInput, // the packet is not encrypted
@@ -2559,6 +3368,7 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
         )
         .await;
         // sending to the MD
+        capture_packet(&capture, proxy_type, &pkt);
         tx.send(pkt).await?;
         // waiting for MD reply
         let pkt = rx.recv().await.ok_or("rx channel hung up")?;
@@ -2649,7 +3459,10 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
             None,
         )
         .await;
+        // refuse versions which the MITM transforms were not made for
+        check_protocol_version(&pkt, &cfg)?;
         // sending reply back to the HU
+        capture_packet(&capture, proxy_type, &pkt);
         tx.send(pkt).await?;
 
         // doing SSL handshake
@@ -2733,7 +3546,12 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
     let mut focus_poll = tokio::time::interval(Duration::from_millis(100));
     focus_poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
     focus_poll.tick().await;
+    let mut coalescer = write_coalescer(&cfg);
+    // frames from the MD are capped on their way to the HU
+    let mut video_cap = (proxy_type == ProxyType::HeadUnit && cfg.video_fps_cap > 0)
+        .then(|| VideoFrameLimiter::new(cfg.video_fps_cap));
     loop {
+        let flush_at = coalescer.as_ref().and_then(WriteCoalescer::deadline);
         tokio::select! {
         // handling data from opposite device's thread, which needs to be transmitted
         Some(mut pkt) = rx.recv() => {
@@ -2763,6 +3581,7 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
                         get_name(proxy_type),
                         pkt.channel
                     );
+                    capture_packet(&capture, proxy_type, &pkt);
                     tx.send(pkt).await?;
 
                     maybe_emit_pending_injected_focus(proxy_type, &mut ctx, &cfg, &tx)?;
@@ -2789,6 +3608,28 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
                 continue;
             }
 
+            if let Some(limiter) = video_cap.as_mut().filter(|_| pkt.channel != 0) {
+                match limiter.filter(&pkt, Instant::now()) {
+                    FrameVerdict::Forward => {}
+                    FrameVerdict::Drop => continue,
+                    FrameVerdict::Ack(ack) => {
+                        debug!(
+                            "{} video frame dropped on channel <b>{:#04x}</> ({} so far), acknowledging it to the MD",
+                            get_name(proxy_type),
+                            pkt.channel,
+                            limiter.dropped()
+                        );
+                        // counted like an ack from the HU
+                        if cfg.video_stats {
+                            video_stats::observe(proxy_type, &ack);
+                        }
+                        capture_packet(&capture, proxy_type, &ack);
+                        tx.send(ack).await?;
+                        continue;
+                    }
+                }
+            }
+
             let action = pkt_modify_hook(
                 proxy_type,
                 PacketFlow::ToEndpoint,
@@ -2825,19 +3666,28 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
                         "{} pkt_modify_hook: message has been handled, sending reply packet only...",
                         get_name(proxy_type)
                     );
+                    capture_packet(&capture, proxy_type, &pkt);
                     tx.send(pkt).await?;
                 }
                 PacketAction::Forward => {
                     pkt.encrypt_payload(&mut mem_buf, &mut server).await?;
                     let _ =
                         pkt_debug(proxy_type, HexdumpLevel::RawOutput, hex_requested, &pkt, &cfg, Some(&ctx.debug_channel_kinds)).await;
-                    pkt.transmit(&mut device).await.with_context(|| {
+                    let flush = flush_now(&pkt, ctx.input_channel);
+                    wait_transfer_resumed().await;
+                    match coalescer {
+                        Some(ref mut c) => c.write(&mut device, pkt.to_frame(), flush).await,
+                        None => pkt.transmit(&mut device).await.map(|_| ()),
+                    }
+                    .with_context(|| {
                         format!("proxy/{}: transmit failed", get_name(proxy_type))
                     })?;
 
                     // Increment byte counters for statistics
                     // fixme: compute final_len for precise stats
                     bytes_written.fetch_add(HEADER_LENGTH + pkt.payload.len(), Ordering::Relaxed);
+                    #[cfg(feature = "metrics")]
+                    metrics::count(proxy_type, pkt.channel, HEADER_LENGTH + pkt.payload.len());
                 }
             }
         }
@@ -2847,6 +3697,12 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
             let _ = pkt_debug(proxy_type, HexdumpLevel::RawInput, hex_requested, &pkt, &cfg, Some(&ctx.debug_channel_kinds)).await;
             match pkt.decrypt_payload(&mut mem_buf, &mut server).await {
                 Ok(_) => {
+                    if cfg.video_stats {
+                        video_stats::observe(proxy_type, &pkt);
+                    }
+                    if cfg.latency_stats {
+                        latency_stats::observe(proxy_type, &pkt);
+                    }
                     let action = pkt_modify_hook(
                         proxy_type,
                         PacketFlow::FromEndpoint,
@@ -2876,6 +3732,7 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
                     match action {
                         PacketAction::Drop => {}
                         PacketAction::SendBack | PacketAction::Forward => {
+                            capture_packet(&capture, proxy_type, &pkt);
                             tx.send(pkt).await?;
                         }
                     }
@@ -2887,6 +3744,26 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
         _ = focus_poll.tick(), if proxy_type == ProxyType::HeadUnit => {
             maybe_emit_pending_injected_focus(proxy_type, &mut ctx, &cfg, &tx)?;
         }
+
+        // collected frames are due
+        _ = tokio::time::sleep_until(flush_at.unwrap_or_else(tokio::time::Instant::now)), if flush_at.is_some() => {
+            wait_transfer_resumed().await;
+            if let Some(ref mut c) = coalescer {
+                c.flush(&mut device).await.with_context(|| {
+                    format!("proxy/{}: transmit failed", get_name(proxy_type))
+                })?;
+            }
+        }
+
+        // the session ends: only between whole frames, collected ones are written first
+        _ = stop.cancelled() => {
+            if let Some(ref mut c) = coalescer {
+                c.flush(&mut device).await.with_context(|| {
+                    format!("proxy/{}: transmit failed", get_name(proxy_type))
+                })?;
+            }
+            return Ok(());
+        }
         }
     }
 }
@@ -2894,7 +3771,123 @@ pub async fn proxy<A: Endpoint<A> + 'static>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Read;
+    use std::rc::Rc;
     use tokio::sync::mpsc;
+    use tokio_uring::net::{TcpStream, UnixStream};
+
+    // coalescer writing to one end of a socket pair, the other end reads what was written
+    fn coalescer_device() -> (IoDevice<TcpStream>, std::os::unix::net::UnixStream) {
+        let (ours, theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+        theirs.set_nonblocking(true).unwrap();
+        (
+            IoDevice::UnixStreamIo(Rc::new(UnixStream::from_std(ours))),
+            theirs,
+        )
+    }
+
+    fn written(peer: &mut std::os::unix::net::UnixStream) -> Vec<u8> {
+        let mut buf = vec![0u8; 64];
+        match peer.read(&mut buf) {
+            Ok(n) => buf[..n].to_vec(),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => vec![],
+            Err(e) => panic!("read failed: {}", e),
+        }
+    }
+
+    #[test]
+    fn coalescer_flushes_when_full() {
+        tokio_uring::start(async {
+            let (mut dev, mut peer) = coalescer_device();
+            let mut c = WriteCoalescer::new(Duration::from_secs(60), 8);
+
+            c.write(&mut dev, vec![1; 3], false).await.unwrap();
+            c.write(&mut dev, vec![2; 3], false).await.unwrap();
+            assert!(written(&mut peer).is_empty());
+            assert!(c.deadline().is_some());
+            // reaching max_bytes writes all collected frames at once
+            c.write(&mut dev, vec![3; 3], false).await.unwrap();
+            assert_eq!(written(&mut peer), [[1; 3], [2; 3], [3; 3]].concat());
+            assert_eq!(c.deadline(), None);
+
+            // a frame to flush right away isn't held back
+            c.write(&mut dev, vec![4; 2], true).await.unwrap();
+            assert_eq!(written(&mut peer), vec![4; 2]);
+        });
+    }
+
+    #[test]
+    fn coalescer_flushes_at_the_deadline() {
+        tokio_uring::start(async {
+            let (mut dev, mut peer) = coalescer_device();
+            let max_delay = Duration::from_millis(20);
+            let mut c = WriteCoalescer::new(max_delay, 1024);
+
+            let written_at = tokio::time::Instant::now();
+            c.write(&mut dev, vec![1; 3], false).await.unwrap();
+            c.write(&mut dev, vec![2; 3], false).await.unwrap();
+            // the deadline is set by the first collected frame
+            let deadline = c.deadline().unwrap();
+            assert!(deadline >= written_at + max_delay);
+            assert!(deadline <= tokio::time::Instant::now() + max_delay);
+            assert!(written(&mut peer).is_empty());
+
+            // as done by the proxy loop once the deadline passes
+            tokio::time::sleep_until(deadline).await;
+            c.flush(&mut dev).await.unwrap();
+            assert_eq!(written(&mut peer), [[1; 3], [2; 3]].concat());
+            assert_eq!(c.deadline(), None);
+        });
+    }
+
+    #[test]
+    fn duplicate_frames_reported_once_at_threshold() {
+        let mut detector = DuplicateFrameDetector::new(2, Duration::from_secs(60));
+        let pkt = |channel, byte| Packet {
+            channel,
+            flags: 0x0b,
+            final_length: None,
+            payload: vec![byte; 512],
+        };
+        assert_eq!(detector.check(&pkt(3, 1)), None);
+        // another channel doesn't interfere
+        assert_eq!(detector.check(&pkt(4, 1)), None);
+        assert_eq!(detector.check(&pkt(3, 1)), None);
+        assert_eq!(detector.check(&pkt(3, 1)), Some(2));
+        assert_eq!(detector.check(&pkt(3, 1)), None);
+        // a different frame resets the run
+        assert_eq!(detector.check(&pkt(3, 2)), None);
+        assert_eq!(detector.check(&pkt(3, 2)), None);
+        assert_eq!(detector.check(&pkt(3, 2)), Some(2));
+    }
+
+    #[test]
+    fn filtered_service_discovery_is_well_formed_and_shorter() {
+        let mut msg = ServiceDiscoveryResponse::new();
+        msg.set_display_name("car".into());
+        for id in 1..=4 {
+            let mut svc = Service::new();
+            svc.set_id(id);
+            let mut media = MediaSinkService::new();
+            media.set_audio_type(AUDIO_STREAM_MEDIA);
+            svc.media_sink_service = Some(media).into();
+            msg.services.push(svc);
+        }
+        let original = msg.write_to_bytes().unwrap();
+
+        let allow: ServiceIdList = serde_json::from_str("\"1,2,3\"").unwrap();
+        let deny: ServiceIdList = serde_json::from_str("\"2\"").unwrap();
+        assert_eq!(filter_services(&mut msg, &allow, &deny), vec![2, 4]);
+
+        let filtered = msg.write_to_bytes().unwrap();
+        assert!(filtered.len() < original.len());
+        let parsed = ServiceDiscoveryResponse::parse_from_bytes(&filtered).unwrap();
+        assert_eq!(
+            parsed.services.iter().map(|s| s.id()).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert_eq!(parsed.display_name(), "car");
+    }
 
     fn test_ctx() -> ModifyContext {
         let (ev_tx, _) = mpsc::channel(1);
@@ -2987,4 +3980,211 @@ mod tests {
         );
         assert!(!ctx.media_fragments.contains_key(&0x21));
     }
+
+    #[tokio::test]
+    async fn write_all_completes_after_short_writes() {
+        let frame: Vec<u8> = (0..10).collect();
+        let sink = std::cell::RefCell::new(Vec::new());
+        // fake writer accepting at most 3 bytes per call
+        let short_writer = |buf: Slice<Vec<u8>>| {
+            let n = buf.len().min(3);
+            sink.borrow_mut().extend_from_slice(&buf[..n]);
+            std::future::ready((Ok(n), buf))
+        };
+        assert_eq!(write_all(short_writer, frame.clone()).await.unwrap(), 10);
+        assert_eq!(*sink.borrow(), frame);
+
+        // no progress is an error instead of a silently truncated frame
+        let stuck_writer = |buf: Slice<Vec<u8>>| std::future::ready((Ok(0), buf));
+        let err = write_all(stuck_writer, frame).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn dpi_selected_by_head_unit_model() {
+        let map: ModelDpiMap = serde_json::from_str("\"MIB2=140\"").unwrap();
+        let mut msg = ServiceDiscoveryResponse::new();
+        msg.set_model("legacy".into());
+        assert_eq!(dpi_for_head_unit(&map, 160, &msg), (160, "static dpi"));
+
+        let mut info = HeadUnitInfo::new();
+        info.set_head_unit_model("mib2".to_string());
+        msg.headunit_info = Some(info).into();
+        assert_eq!(head_unit_model(&msg).as_deref(), Some("mib2"));
+        assert_eq!(dpi_for_head_unit(&map, 160, &msg), (140, "dpi_by_model"));
+        // no static dpi: the override still applies
+        assert_eq!(dpi_for_head_unit(&map, 0, &msg), (140, "dpi_by_model"));
+    }
+
+    #[test]
+    fn transmission_state_decoded_from_sensor_batch() {
+        reset_transmission_state();
+        // vehicles which don't report it
+        let mut batch = SensorBatch::new();
+        batch.speed_data.push(Default::default());
+        assert_eq!(update_transmission_state(&batch), None);
+        assert_eq!(transmission_state(), TransmissionState::default());
+
+        let mut gear = GearData::new();
+        gear.set_gear(Gear::GEAR_REVERSE);
+        batch.gear_data.push(gear);
+        let state = update_transmission_state(&batch).expect("gear changed");
+        assert_eq!(state.gear, Some("reverse"));
+        assert_eq!(state.parking_brake, None);
+        // unchanged
+        assert_eq!(update_transmission_state(&batch), None);
+
+        let mut brake = ParkingBrakeData::new();
+        brake.set_parking_brake(true);
+        let mut batch = SensorBatch::new();
+        batch.parking_brake_data.push(brake);
+        let state = update_transmission_state(&batch).expect("brake changed");
+        assert_eq!(state.gear, Some("reverse"));
+        assert_eq!(state.parking_brake, Some(true));
+    }
+
+    #[test]
+    fn only_a_plain_version_request_starts_the_hu_session() {
+        let mut pkt = Packet {
+            channel: 0,
+            flags: FRAME_TYPE_MASK,
+            final_length: None,
+            payload: vec![0, MESSAGE_VERSION_REQUEST as u8, 0, 1, 0, 7],
+        };
+        assert!(is_version_request(&pkt));
+        pkt.flags |= ENCRYPTED;
+        assert!(!is_version_request(&pkt));
+        pkt.flags = FRAME_TYPE_MASK;
+        pkt.channel = 3;
+        assert!(!is_version_request(&pkt));
+        assert!(!is_version_request(&whole_frame(0)));
+    }
+
+    #[tokio::test]
+    async fn paused_transfer_is_resumed_after_the_limit() {
+        set_transfer_pause_limit(Some(Duration::from_millis(50)));
+        assert!(pause_transfer());
+        timeout(Duration::from_secs(2), wait_transfer_resumed())
+            .await
+            .expect("pause not ended at the limit");
+        assert!(transfer_paused().is_none());
+        set_transfer_pause_limit(None);
+        assert_eq!(transfer_pause_limit(), MAX_TRANSFER_PAUSE);
+    }
+
+    fn whole_frame(payload: u8) -> Packet {
+        Packet {
+            channel: 3,
+            flags: FRAME_TYPE_MASK,
+            final_length: None,
+            payload: vec![payload; 2],
+        }
+    }
+
+    #[test]
+    fn reader_stops_only_between_frames() {
+        tokio_uring::start(async {
+            let (dev, mut peer) = coalescer_device();
+            let (tx, mut rx) = mpsc::channel(4);
+            let stop = CancellationToken::new();
+            let frame = whole_frame(1).to_frame();
+
+            // half of a frame received when the session is stopped
+            std::io::Write::write_all(&mut peer, &frame[..3]).unwrap();
+            let reader = tokio_uring::spawn(endpoint_reader(dev, tx, true, 64, None, stop.clone()));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            stop.cancel();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(!reader.is_finished());
+
+            // the frame is completed and passed on before the reader returns
+            std::io::Write::write_all(&mut peer, &frame[3..]).unwrap();
+            let pkt = timeout(Duration::from_secs(1), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(pkt.to_frame(), frame);
+            timeout(Duration::from_secs(1), reader)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn reader_waiting_for_a_frame_returns_when_stopped() {
+        tokio_uring::start(async {
+            let (dev, _peer) = coalescer_device();
+            let (tx, _rx) = mpsc::channel(4);
+            let stop = CancellationToken::new();
+
+            // nothing sent: the reader is parked in a read
+            let reader = tokio_uring::spawn(endpoint_reader(dev, tx, true, 64, None, stop.clone()));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(!reader.is_finished());
+
+            stop.cancel();
+            timeout(Duration::from_millis(200), reader)
+                .await
+                .expect("reader still parked in the read")
+                .unwrap()
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn proxy_writes_collected_frames_when_stopped() {
+        tokio_uring::start(async {
+            let (dev, mut peer) = coalescer_device();
+            let config = AppConfig {
+                mitm: false,
+                write_coalesce_ms: 60_000,
+                write_coalesce_kb: 64,
+                ..Default::default()
+            };
+            let (tx, _rx) = mpsc::channel(4);
+            let (pkt_tx, rx) = mpsc::channel(4);
+            let (_rxr_tx, rxr) = mpsc::channel(4);
+            let (ev_tx, _ev_rx) = mpsc::channel(1);
+            let (ws_event_tx, _) = tokio::sync::broadcast::channel(1);
+            let stop = CancellationToken::new();
+            let task = tokio_uring::spawn(proxy(
+                ProxyType::HeadUnit,
+                dev,
+                Arc::new(AtomicUsize::new(0)),
+                tx,
+                rx,
+                rxr,
+                Arc::new(RwLock::new(config)),
+                Arc::new(tokio::sync::Mutex::new(None)),
+                Arc::new(tokio::sync::Mutex::new(None)),
+                Arc::new(RwLock::new(None)),
+                Arc::new(RwLock::new(None)),
+                Arc::new(RwLock::new(None)),
+                ev_tx,
+                None,
+                None,
+                HashMap::new(),
+                ws_event_tx,
+                None,
+                stop.clone(),
+            ));
+
+            pkt_tx.send(whole_frame(1)).await.unwrap();
+            pkt_tx.send(whole_frame(2)).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            // collected, not written yet
+            assert!(written(&mut peer).is_empty());
+
+            stop.cancel();
+            timeout(Duration::from_secs(1), task)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let frames = [whole_frame(1).to_frame(), whole_frame(2).to_frame()].concat();
+            assert_eq!(written(&mut peer), frames);
+        });
+    }
 }
@@ -30,7 +30,7 @@ pub enum PacketDebugServiceKind {
 }
 
 impl PacketDebugServiceKind {
-    fn as_str(self) -> &'static str {
+    pub(crate) fn as_str(self) -> &'static str {
         match self {
             Self::Unknown => "unknown",
             Self::Control => "control",
@@ -212,7 +212,7 @@ fn service_kind_matches_filter(filter: &str, kind: PacketDebugServiceKind) -> bo
     })
 }
 
-fn pkt_debug_service_kind_for_service(svc: &Service) -> PacketDebugServiceKind {
+pub(crate) fn pkt_debug_service_kind_for_service(svc: &Service) -> PacketDebugServiceKind {
     if svc.sensor_source_service.is_some() {
         PacketDebugServiceKind::SensorSource
     } else if svc.media_sink_service.is_some() {
@@ -1050,8 +1050,13 @@ pub async fn pkt_debug(
 
     // parsing data
     let data = &pkt.payload[2..]; // start of message data
-    if let Some(pretty) = pretty_packet_message(service_kind, control, message_id, data, pkt) {
-        emit_pkt_debug(wrap_pretty_block("proto", &pretty));
+    match pretty_packet_message(service_kind, control, message_id, data, pkt) {
+        Some(pretty) => emit_pkt_debug(wrap_pretty_block("proto", &pretty)),
+        // unknown message: show the payload unless it was already dumped above
+        None if hex_requested < hexdump => {
+            emit_pkt_debug(wrap_pretty_block("undecoded", &bytes_preview(data, 64)))
+        }
+        None => {}
     }
 
     Ok(())
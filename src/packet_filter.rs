@@ -0,0 +1,129 @@
+//! Per-packet filter hooks of the MITM proxy.
+//!
+//! Filters are registered at runtime (e.g. by an embedding binary or while
+//! experimenting with new behaviors) and see every decrypted packet once, as it
+//! is received from its endpoint and before the built-in transforms run. Each
+//! filter may modify the packet in place and decides what happens with it; the
+//! first filter not returning [`PacketAction::Forward`] ends the chain.
+//!
+//! With no filter registered the hot path only reads one atomic flag.
+use crate::mitm::{Packet, PacketAction, ProxyType, FRAME_TYPE_FIRST};
+use simplelog::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> packet-filter: </>";
+
+/// A packet received by the MITM proxy from the `from` side.
+pub struct FilterPacket<'a> {
+    /// side which sent the packet
+    pub from: ProxyType,
+    /// message id, known for the first frame of a message
+    pub message_id: Option<u16>,
+    /// decrypted packet, may be modified by the filter
+    pub pkt: &'a mut Packet,
+}
+
+pub trait PacketFilter: Send + Sync {
+    fn filter(&self, packet: FilterPacket) -> PacketAction;
+}
+
+impl<F> PacketFilter for F
+where
+    F: Fn(FilterPacket) -> PacketAction + Send + Sync,
+{
+    fn filter(&self, packet: FilterPacket) -> PacketAction {
+        self(packet)
+    }
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static FILTERS: RwLock<Vec<(String, Box<dyn PacketFilter>)>> = RwLock::new(Vec::new());
+
+/// adds a filter at the end of the chain, replacing a filter of the same name
+pub fn register(name: &str, filter: impl PacketFilter + 'static) {
+    let mut filters = FILTERS.write().unwrap();
+    filters.retain(|(n, _)| n != name);
+    filters.push((name.to_string(), Box::new(filter)));
+    ACTIVE.store(true, Ordering::Release);
+    info!("{} registered filter <b>{}</>", NAME, name);
+}
+
+/// removes a filter, returns false when no such filter was registered
+pub fn unregister(name: &str) -> bool {
+    let mut filters = FILTERS.write().unwrap();
+    let count = filters.len();
+    filters.retain(|(n, _)| n != name);
+    ACTIVE.store(!filters.is_empty(), Ordering::Release);
+    filters.len() != count
+}
+
+/// runs the filter chain on a packet received from the `from` side
+pub fn run(from: ProxyType, pkt: &mut Packet) -> PacketAction {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return PacketAction::Forward;
+    }
+    let filters = FILTERS.read().unwrap();
+    for (name, filter) in filters.iter() {
+        let message_id = match pkt.payload.get(0..2) {
+            Some(id) if pkt.flags & FRAME_TYPE_FIRST != 0 => {
+                Some(u16::from_be_bytes([id[0], id[1]]))
+            }
+            _ => None,
+        };
+        let action = filter.filter(FilterPacket {
+            from,
+            message_id,
+            pkt: &mut *pkt,
+        });
+        if action != PacketAction::Forward {
+            debug!(
+                "{} filter <b>{}</> decided {:?} on channel <b>{:#04x}</>",
+                NAME, name, action, pkt.channel
+            );
+            return action;
+        }
+    }
+    PacketAction::Forward
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mitm::{ENCRYPTED, FRAME_TYPE_MASK};
+
+    #[test]
+    fn filters_modify_and_drop_packets() {
+        register("mark", |p: FilterPacket| {
+            if p.from == ProxyType::HeadUnit {
+                p.pkt.payload.push(0xff);
+            }
+            PacketAction::Forward
+        });
+        register("drop", |p: FilterPacket| match p.message_id {
+            Some(0x8001) => PacketAction::Drop,
+            _ => PacketAction::Forward,
+        });
+
+        let mut pkt = Packet {
+            channel: 3,
+            flags: ENCRYPTED | FRAME_TYPE_MASK,
+            final_length: None,
+            payload: vec![0x00, 0x01],
+        };
+        assert_eq!(run(ProxyType::HeadUnit, &mut pkt), PacketAction::Forward);
+        assert_eq!(pkt.payload, vec![0x00, 0x01, 0xff]);
+
+        pkt.payload = vec![0x80, 0x01];
+        assert_eq!(run(ProxyType::MobileDevice, &mut pkt), PacketAction::Drop);
+
+        assert!(unregister("drop"));
+        assert!(unregister("mark"));
+        assert!(!unregister("mark"));
+        assert_eq!(
+            run(ProxyType::MobileDevice, &mut pkt),
+            PacketAction::Forward
+        );
+    }
+}
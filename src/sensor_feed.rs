@@ -0,0 +1,334 @@
+//! Injection of synthetic GPS position and vehicle speed on the sensor channel,
+//! for head units without their own GPS.
+//!
+//! Sensor sources push typed [`SensorUpdate`]s into the feed channel (see
+//! [`feed`]); the built-in sources are an NMEA 0183 stream and `POST /sensor`.
+//! Only sensor types which the HU doesn't advertise are added to the service
+//! discovery response and injected, so real sensor traffic is never mixed with
+//! synthetic values of the same type (which would confuse the phone's fusion).
+use crate::mitm::protos::SensorMessageId::SENSOR_MESSAGE_BATCH;
+use crate::mitm::protos::{LocationData, SensorBatch, SpeedData};
+use crate::mitm::{Packet, ENCRYPTED, FRAME_TYPE_FIRST, FRAME_TYPE_LAST};
+use protobuf::Message;
+use serde::Deserialize;
+use simplelog::*;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::Mutex;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> sensor-feed: </>";
+
+// Just a generic Result type to ease error handling for us. Errors in multithreaded
+// async contexts needs some extra restrictions
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// updates waiting for injection; a source faster than the session is slowed down
+const FEED_QUEUE_CAPACITY: usize = 32;
+// delay before reopening a failed NMEA source
+const NMEA_REOPEN_DELAY: Duration = Duration::from_secs(5);
+// delay before checking a regular NMEA file for appended sentences
+const NMEA_FOLLOW_DELAY: Duration = Duration::from_millis(500);
+const KNOTS_TO_MPS: f64 = 1852.0 / 3600.0;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GpsFix {
+    /// degrees, WGS84
+    pub latitude: f64,
+    pub longitude: f64,
+    /// meters
+    pub accuracy: Option<f64>,
+    /// meters above sea level
+    pub altitude: Option<f64>,
+    /// m/s
+    pub speed: Option<f64>,
+    /// degrees, clockwise from true north
+    pub bearing: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SensorUpdate {
+    Location(GpsFix),
+    /// vehicle speed [m/s]
+    Speed {
+        speed: f64,
+    },
+}
+
+// sensor types injected in the current session
+static INJECT_LOCATION: AtomicBool = AtomicBool::new(false);
+static INJECT_SPEED: AtomicBool = AtomicBool::new(false);
+
+static FEED: OnceLock<Sender<SensorUpdate>> = OnceLock::new();
+
+/// sets the sensor types to inject, as added to the service discovery response;
+/// cleared at session start until the new session's response is seen
+pub fn set_injected_sensors(location: bool, speed: bool) {
+    INJECT_LOCATION.store(location, Ordering::Relaxed);
+    INJECT_SPEED.store(speed, Ordering::Relaxed);
+}
+
+/// sender for pushing updates from a sensor source, `None` when injection is disabled
+pub fn feed() -> Option<Sender<SensorUpdate>> {
+    FEED.get().cloned()
+}
+
+/// Creates the feed channel and injects its updates into the running session.
+/// `tx` and `sensor_channel` are the session slots also used by the REST API.
+pub async fn run(
+    tx: Arc<Mutex<Option<Sender<Packet>>>>,
+    sensor_channel: Arc<Mutex<Option<u8>>>,
+    nmea_source: Option<PathBuf>,
+) {
+    let (feed_tx, rx) = mpsc::channel(FEED_QUEUE_CAPACITY);
+    if let Some(path) = nmea_source {
+        let feed_tx = feed_tx.clone();
+        std::thread::spawn(move || nmea_reader(path, feed_tx));
+    }
+    if FEED.set(feed_tx).is_err() {
+        error!("{} sensor feed is already running", NAME);
+        return;
+    }
+    inject(rx, tx, sensor_channel).await
+}
+
+async fn inject(
+    mut rx: Receiver<SensorUpdate>,
+    tx: Arc<Mutex<Option<Sender<Packet>>>>,
+    sensor_channel: Arc<Mutex<Option<u8>>>,
+) {
+    let mut injected: u64 = 0;
+    while let Some(update) = rx.recv().await {
+        // nothing to do between sessions
+        let Some(ch) = *sensor_channel.lock().await else {
+            continue;
+        };
+        let Some(tx) = tx.lock().await.clone() else {
+            continue;
+        };
+        let pkt = match to_packet(ch, &update) {
+            Ok(Some(pkt)) => pkt,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("{} unable to encode {:?}: {}", NAME, update, e);
+                continue;
+            }
+        };
+        // the session may just be ending, the next one takes further updates
+        if tx.send(pkt).await.is_ok() {
+            injected += 1;
+            if injected == 1 {
+                info!("{} 🛰️ injecting sensor data: {:?}", NAME, update);
+            }
+        }
+    }
+}
+
+/// sensor batch for the update, `None` when its sensor type is not injected
+fn to_packet(sensor_ch: u8, update: &SensorUpdate) -> Result<Option<Packet>> {
+    let mut msg = SensorBatch::new();
+    match update {
+        SensorUpdate::Location(fix) => {
+            if !INJECT_LOCATION.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            msg.location_data.push(location_data(fix));
+        }
+        SensorUpdate::Speed { speed } => {
+            if !INJECT_SPEED.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            let mut data = SpeedData::new();
+            data.set_speed_e3((speed * 1e3).round() as i32);
+            msg.speed_data.push(data);
+        }
+    }
+
+    let mut payload: Vec<u8> = msg.write_to_bytes()?;
+    payload.insert(0, ((SENSOR_MESSAGE_BATCH as u16) >> 8) as u8);
+    payload.insert(1, ((SENSOR_MESSAGE_BATCH as u16) & 0xff) as u8);
+
+    Ok(Some(Packet {
+        channel: sensor_ch,
+        flags: ENCRYPTED | FRAME_TYPE_FIRST | FRAME_TYPE_LAST,
+        final_length: None,
+        payload,
+    }))
+}
+
+fn location_data(fix: &GpsFix) -> LocationData {
+    let mut data = LocationData::new();
+    data.set_latitude_e7((fix.latitude * 1e7).round() as i32);
+    data.set_longitude_e7((fix.longitude * 1e7).round() as i32);
+    if let Some(accuracy) = fix.accuracy {
+        data.set_accuracy_e3((accuracy * 1e3).round() as u32);
+    }
+    if let Some(altitude) = fix.altitude {
+        data.set_altitude_e2((altitude * 1e2).round() as i32);
+    }
+    if let Some(speed) = fix.speed {
+        data.set_speed_e3((speed * 1e3).round() as i32);
+    }
+    if let Some(bearing) = fix.bearing {
+        data.set_bearing_e6((bearing * 1e6).round() as i32);
+    }
+    data
+}
+
+/// Reads NMEA sentences from a serial device, FIFO or file, reopening it on errors.
+/// A regular file is followed like `tail -f`: appended sentences are read and
+/// a reopened file continues at the last read offset (unless it was truncated).
+fn nmea_reader(path: PathBuf, tx: Sender<SensorUpdate>) {
+    let mut offset: u64 = 0;
+    loop {
+        match std::fs::File::open(&path) {
+            Ok(mut file) => {
+                let follow = file.metadata().is_ok_and(|m| m.is_file());
+                if follow {
+                    let len = file.metadata().map_or(0, |m| m.len());
+                    if len < offset {
+                        offset = 0;
+                    }
+                    if let Err(e) = file.seek(SeekFrom::Start(offset)) {
+                        warn!("{} cannot seek in {}: {}", NAME, path.display(), e);
+                    }
+                }
+                info!("{} 🛰️ reading NMEA from <b>{}</>", NAME, path.display());
+                let mut reader = BufReader::new(file);
+                let mut line = String::new();
+                loop {
+                    match reader.read_line(&mut line) {
+                        // the writer of a FIFO or device is gone
+                        Ok(0) if !follow => break,
+                        // end of a regular file, the last line may still be written
+                        Ok(_) if follow && !line.ends_with('\n') => {
+                            std::thread::sleep(NMEA_FOLLOW_DELAY);
+                            continue;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!("{} NMEA read error: {}", NAME, e);
+                            break;
+                        }
+                    }
+                    if follow {
+                        offset += line.len() as u64;
+                    }
+                    if let Some(update) = parse_nmea(&line) {
+                        if tx.blocking_send(update).is_err() {
+                            return;
+                        }
+                    }
+                    line.clear();
+                }
+            }
+            Err(e) => warn!("{} cannot open {}: {}", NAME, path.display(), e),
+        }
+        std::thread::sleep(NMEA_REOPEN_DELAY);
+    }
+}
+
+/// position, speed and course from an RMC sentence with a valid fix
+pub fn parse_nmea(line: &str) -> Option<SensorUpdate> {
+    let sentence = line.trim().strip_prefix('$')?;
+    let (body, checksum) = match sentence.split_once('*') {
+        Some((body, checksum)) => (body, Some(checksum)),
+        None => (sentence, None),
+    };
+    if let Some(checksum) = checksum {
+        let expected = u8::from_str_radix(checksum, 16).ok()?;
+        if body.bytes().fold(0, |acc, b| acc ^ b) != expected {
+            return None;
+        }
+    }
+
+    let fields: Vec<&str> = body.split(',').collect();
+    // talker id (GP, GN, ...) followed by the sentence type
+    if fields[0].get(2..) != Some("RMC") || fields.len() < 9 || fields[2] != "A" {
+        return None;
+    }
+    let latitude = coordinate(fields[3], fields[4], 2, 'S')?;
+    let longitude = coordinate(fields[5], fields[6], 3, 'W')?;
+    Some(SensorUpdate::Location(GpsFix {
+        latitude,
+        longitude,
+        accuracy: None,
+        altitude: None,
+        speed: fields[7]
+            .parse::<f64>()
+            .ok()
+            .map(|knots| knots * KNOTS_TO_MPS),
+        bearing: fields[8].parse().ok(),
+    }))
+}
+
+/// `ddmm.mmmm` (or `dddmm.mmmm`) and hemisphere to signed degrees
+fn coordinate(value: &str, hemisphere: &str, degree_digits: usize, negative: char) -> Option<f64> {
+    let degrees: f64 = value.get(..degree_digits)?.parse().ok()?;
+    let minutes: f64 = value.get(degree_digits..)?.parse().ok()?;
+    let coordinate = degrees + minutes / 60.0;
+    Some(if hemisphere.starts_with(negative) {
+        -coordinate
+    } else {
+        coordinate
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rmc_sentence_is_parsed() {
+        let update =
+            parse_nmea("$GPRMC,123519,A,4807.038,N,01131.000,W,022.4,084.4,230394,003.1,W*78")
+                .unwrap();
+        let SensorUpdate::Location(fix) = update else {
+            panic!("not a location: {:?}", update);
+        };
+        assert!((fix.latitude - 48.1173).abs() < 1e-6);
+        assert!((fix.longitude + 11.516_666).abs() < 1e-6);
+        assert!((fix.speed.unwrap() - 11.523_555).abs() < 1e-5);
+        assert_eq!(fix.bearing, Some(84.4));
+
+        // bad checksum, no fix, other sentence
+        assert!(
+            parse_nmea("$GPRMC,123519,A,4807.038,N,01131.000,W,022.4,084.4,230394,003.1,W*79")
+                .is_none()
+        );
+        assert!(parse_nmea("$GNRMC,123519,V,,,,,,,230394,,").is_none());
+        assert!(parse_nmea("$GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1").is_none());
+    }
+
+    #[test]
+    fn nmea_file_is_followed_from_its_offset() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("aa-proxy-nmea-{}", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(file, "$GPRMC,123519,A,4807.038,N,01131.000,E,,,230394,,").unwrap();
+        let (tx, mut rx) = mpsc::channel(4);
+        let reader_path = path.clone();
+        std::thread::spawn(move || nmea_reader(reader_path, tx));
+
+        let latitude = |update: SensorUpdate| match update {
+            SensorUpdate::Location(fix) => fix.latitude.round(),
+            other => panic!("not a location: {:?}", other),
+        };
+        assert_eq!(rx.blocking_recv().map(latitude), Some(48.0));
+        // a sentence written in two parts is read once it is complete
+        write!(file, "$GPRMC,123520,A,5007.038,N,").unwrap();
+        file.flush().unwrap();
+        std::thread::sleep(NMEA_FOLLOW_DELAY);
+        writeln!(file, "01131.000,E,,,230394,,").unwrap();
+        assert_eq!(rx.blocking_recv().map(latitude), Some(50.0));
+
+        drop(rx);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+//! Machine-readable `SessionEnded` record emitted at the end of each session.
+//!
+//! The record is logged as JSON and sent to the event bus (`session_ended`
+//! topic), and optionally appended to a JSON lines file. Consumers should
+//! check `schema_version`: fields are only added within a version,
+//! renames/removals bump it.
+use crate::video_stats::VideoSummary;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// version of the `SessionEnded` schema
+pub const SCHEMA_VERSION: u32 = 1;
+/// event bus topic of the record
+pub const TOPIC: &str = "session_ended";
+/// throughput is measured over windows at least this long
+const PEAK_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Throughput {
+    window_start: Instant,
+    to_hu_start: usize,
+    to_md_start: usize,
+    peak_to_hu: u64,
+    peak_to_md: u64,
+}
+
+// None until the first sample of a session
+static THROUGHPUT: Mutex<Option<Throughput>> = Mutex::new(None);
+
+/// forgets the previous session
+pub fn reset() {
+    *THROUGHPUT.lock().unwrap() = None;
+}
+
+/// feeds the total bytes written so far to the HU and to the MD
+pub fn sample_throughput(to_hu: usize, to_md: usize) {
+    sample_throughput_at(Instant::now(), to_hu, to_md);
+}
+
+fn sample_throughput_at(now: Instant, to_hu: usize, to_md: usize) {
+    let mut lock = THROUGHPUT.lock().unwrap();
+    let Some(t) = lock.as_mut() else {
+        *lock = Some(Throughput {
+            window_start: now,
+            to_hu_start: to_hu,
+            to_md_start: to_md,
+            peak_to_hu: 0,
+            peak_to_md: 0,
+        });
+        return;
+    };
+    let elapsed = now.duration_since(t.window_start);
+    if elapsed < PEAK_WINDOW {
+        return;
+    }
+    let rate = |bytes: usize| (bytes as f64 / elapsed.as_secs_f64()).round() as u64;
+    t.peak_to_hu = t.peak_to_hu.max(rate(to_hu.saturating_sub(t.to_hu_start)));
+    t.peak_to_md = t.peak_to_md.max(rate(to_md.saturating_sub(t.to_md_start)));
+    t.window_start = now;
+    t.to_hu_start = to_hu;
+    t.to_md_start = to_md;
+}
+
+/// peak throughput [B/s] of the session to the HU and to the MD
+fn peak_throughput() -> (u64, u64) {
+    THROUGHPUT
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or((0, 0), |t| (t.peak_to_hu, t.peak_to_md))
+}
+
+/// parameters negotiated during the session
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Negotiated {
+    /// AA protocol version, e.g. "1.7"
+    pub protocol_version: Option<String>,
+    pub mitm: bool,
+    /// "wireless", "usb" or "dhu"
+    pub transport: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionEnded {
+    pub schema_version: u32,
+    /// unix time [s]
+    pub ended_at: u64,
+    pub duration_secs: f64,
+    /// phone -> car
+    pub bytes_to_hu: u64,
+    /// car -> phone
+    pub bytes_to_md: u64,
+    /// [B/s]
+    pub avg_throughput_to_hu: u64,
+    pub avg_throughput_to_md: u64,
+    pub peak_throughput_to_hu: u64,
+    pub peak_throughput_to_md: u64,
+    pub end_reason: String,
+    /// WiFi MAC address of the phone, if known
+    pub device_mac: Option<String>,
+    pub negotiated: Negotiated,
+    pub video: Option<VideoSummary>,
+}
+
+impl SessionEnded {
+    /// builds the record from the session totals and the tracked peaks
+    pub fn new(
+        duration: Duration,
+        bytes_to_hu: usize,
+        bytes_to_md: usize,
+        end_reason: String,
+        device_mac: Option<String>,
+        negotiated: Negotiated,
+        video: Option<VideoSummary>,
+    ) -> Self {
+        let secs = duration.as_secs_f64();
+        let avg = |bytes: usize| {
+            if secs > 0.0 {
+                (bytes as f64 / secs).round() as u64
+            } else {
+                0
+            }
+        };
+        let (peak_to_hu, peak_to_md) = peak_throughput();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            ended_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            duration_secs: secs,
+            bytes_to_hu: bytes_to_hu as u64,
+            bytes_to_md: bytes_to_md as u64,
+            avg_throughput_to_hu: avg(bytes_to_hu),
+            avg_throughput_to_md: avg(bytes_to_md),
+            peak_throughput_to_hu: peak_to_hu,
+            peak_throughput_to_md: peak_to_md,
+            end_reason,
+            device_mac,
+            negotiated,
+            video,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// appends the record as a JSON line to `path`, `-` is stdout
+    pub fn append_to(&self, path: &Path) -> std::io::Result<()> {
+        let line = format!("{}\n", self.to_json());
+        if path == Path::new("-") {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(line.as_bytes())?;
+            return stdout.flush();
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_ended_record_with_peaks() {
+        reset();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        sample_throughput_at(at(0), 0, 0);
+        // shorter than a window: ignored
+        sample_throughput_at(at(500), 4000, 100);
+        sample_throughput_at(at(1000), 5000, 200);
+        sample_throughput_at(at(3000), 7000, 1200);
+        assert_eq!(peak_throughput(), (5000, 500));
+
+        let record = SessionEnded::new(
+            Duration::from_secs(4),
+            8000,
+            1200,
+            "test".to_string(),
+            Some("aa:bb:cc:dd:ee:ff".to_string()),
+            Negotiated {
+                protocol_version: Some("1.7".to_string()),
+                mitm: true,
+                transport: "wireless",
+            },
+            None,
+        );
+        assert_eq!(record.avg_throughput_to_hu, 2000);
+        assert_eq!(record.avg_throughput_to_md, 300);
+
+        let json: serde_json::Value = serde_json::from_str(&record.to_json()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["peak_throughput_to_hu"], 5000);
+        assert_eq!(json["negotiated"]["protocol_version"], "1.7");
+        assert_eq!(json["video"], serde_json::Value::Null);
+
+        let path = std::env::temp_dir().join(format!(
+            "aa-proxy-session-summary-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        record.append_to(&path).unwrap();
+        record.append_to(&path).unwrap();
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        assert!(lines
+            .lines()
+            .all(|l| serde_json::from_str::<serde_json::Value>(l).is_ok()));
+    }
+}
@@ -0,0 +1,146 @@
+use chrono::Local;
+use simplelog::*;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> stats: </>";
+
+/// period covered by one line of the log
+pub const STATS_LOG_PERIOD: Duration = Duration::from_secs(60);
+
+/// Append-only log with one line per minute: bytes transferred in each
+/// direction and the peak speed seen while polling. When the file grows over
+/// `max_size` it is rotated to `<path>.1` (replacing the previous one).
+/// The partial last period is written when the log is dropped with its session.
+pub struct StatsLog {
+    path: PathBuf,
+    max_size: u64,
+    period_start: Instant,
+    usb_bytes_start: usize,
+    tcp_bytes_start: usize,
+    last_sample: Instant,
+    usb_bytes_last: usize,
+    tcp_bytes_last: usize,
+    usb_peak: u64,
+    tcp_peak: u64,
+}
+
+impl StatsLog {
+    pub fn new(path: PathBuf, max_size: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            path,
+            max_size,
+            period_start: now,
+            usb_bytes_start: 0,
+            tcp_bytes_start: 0,
+            last_sample: now,
+            usb_bytes_last: 0,
+            tcp_bytes_last: 0,
+            usb_peak: 0,
+            tcp_peak: 0,
+        }
+    }
+
+    /// feeds current byte counters (phone -> car, car -> phone); writes
+    /// a line once a full period has elapsed
+    pub fn sample(&mut self, usb_bytes: usize, tcp_bytes: usize) {
+        let elapsed = self.last_sample.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            let speed = |now: usize, last: usize| ((now - last) as f64 / elapsed).round() as u64;
+            self.usb_peak = self.usb_peak.max(speed(usb_bytes, self.usb_bytes_last));
+            self.tcp_peak = self.tcp_peak.max(speed(tcp_bytes, self.tcp_bytes_last));
+        }
+        self.last_sample = Instant::now();
+        self.usb_bytes_last = usb_bytes;
+        self.tcp_bytes_last = tcp_bytes;
+
+        if self.period_start.elapsed() >= STATS_LOG_PERIOD {
+            self.write_period();
+        }
+    }
+
+    /// writes the partial period sampled so far, e.g. at the end of a session
+    pub fn flush(&mut self) {
+        if self.last_sample > self.period_start {
+            self.write_period();
+        }
+    }
+
+    // writes the line of the period up to the last sample and starts the next one
+    fn write_period(&mut self) {
+        let line = format!(
+            "{} phone_to_car_bytes={} phone_to_car_peak_bps={} car_to_phone_bytes={} car_to_phone_peak_bps={}\n",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            self.usb_bytes_last - self.usb_bytes_start,
+            self.usb_peak,
+            self.tcp_bytes_last - self.tcp_bytes_start,
+            self.tcp_peak,
+        );
+        if let Err(e) = self.append(&line) {
+            warn!(
+                "{} cannot write stats log {}: {}",
+                NAME,
+                self.path.display(),
+                e
+            );
+        }
+
+        self.period_start = Instant::now();
+        self.usb_bytes_start = self.usb_bytes_last;
+        self.tcp_bytes_start = self.tcp_bytes_last;
+        self.usb_peak = 0;
+        self.tcp_peak = 0;
+    }
+
+    fn append(&self, line: &str) -> std::io::Result<()> {
+        if std::fs::metadata(&self.path).is_ok_and(|m| m.len() >= self.max_size) {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, rotated)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+impl Drop for StatsLog {
+    /// the log ends with the session, its last minute is not lost
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_period_is_written_at_the_end() {
+        let path = std::env::temp_dir().join(format!("aa-proxy-stats-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // nothing sampled, nothing written
+        drop(StatsLog::new(path.clone(), 1024));
+        assert!(!path.exists());
+
+        let mut log = StatsLog::new(path.clone(), 1024);
+        std::thread::sleep(Duration::from_millis(5));
+        log.sample(100, 50);
+        std::thread::sleep(Duration::from_millis(5));
+        log.sample(300, 80);
+        drop(log);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.contains(" phone_to_car_bytes=300 "));
+        assert!(content.contains(" car_to_phone_bytes=80 "));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! Frame rate cap for the projected video.
+//!
+//! Video frames from the MD are passed to the HU through a token bucket per
+//! video channel, frames above the configured rate are dropped. Video channels
+//! are recognized by the codec of their MEDIA_MESSAGE_SETUP, so audio and input
+//! channels are never touched. Keyframes and codec configuration always pass,
+//! so the HU decoder can resync, and every dropped frame is acknowledged to the
+//! MD in place of the HU, so the phone's encoder doesn't stall waiting for
+//! acknowledgments. Only H.264 and H.265 are capped, keyframes of other codecs
+//! can't be recognized here.
+use crate::mitm::protos::MediaCodecType::*;
+use crate::mitm::protos::MediaMessageId::*;
+use crate::mitm::protos::*;
+use crate::mitm::{Packet, ENCRYPTED, FRAME_TYPE_FIRST, FRAME_TYPE_LAST};
+use protobuf::{Enum, Message};
+use simplelog::*;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// frames which may pass in a burst after a pause
+const BURST: f64 = 1.0;
+
+/// what to do with a packet from the MD
+pub enum FrameVerdict {
+    Forward,
+    /// a fragment of a dropped frame
+    Drop,
+    /// the first fragment of a dropped frame: send the ack back to the MD
+    Ack(Packet),
+}
+
+#[derive(Debug)]
+struct VideoChannel {
+    codec: MediaCodecType,
+    session_id: i32,
+    tokens: f64,
+    last_refill: Option<Instant>,
+    /// the remaining fragments of the current frame are dropped
+    dropping: bool,
+}
+
+impl VideoChannel {
+    fn new(codec: MediaCodecType) -> Self {
+        Self {
+            codec,
+            session_id: 0,
+            tokens: BURST,
+            last_refill: None,
+            dropping: false,
+        }
+    }
+
+    fn refill(&mut self, fps: f64, now: Instant) {
+        if let Some(last) = self.last_refill {
+            let elapsed = now.saturating_duration_since(last).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * fps).min(BURST);
+        }
+        self.last_refill = Some(now);
+    }
+}
+
+#[derive(Debug)]
+pub struct VideoFrameLimiter {
+    fps: f64,
+    channels: HashMap<u8, VideoChannel>,
+    dropped: u64,
+}
+
+impl VideoFrameLimiter {
+    pub fn new(fps: u8) -> Self {
+        Self {
+            fps: fps.into(),
+            channels: HashMap::new(),
+            dropped: 0,
+        }
+    }
+
+    /// number of frames dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// decides on a decrypted packet going from the MD to the HU
+    pub fn filter(&mut self, pkt: &Packet, now: Instant) -> FrameVerdict {
+        // only the first fragment carries the message id
+        if pkt.flags & FRAME_TYPE_FIRST == 0 {
+            return match self.channels.get_mut(&pkt.channel) {
+                Some(video) if video.dropping => {
+                    video.dropping = pkt.flags & FRAME_TYPE_LAST == 0;
+                    FrameVerdict::Drop
+                }
+                _ => FrameVerdict::Forward,
+            };
+        }
+        if pkt.payload.len() < 2 {
+            return FrameVerdict::Forward;
+        }
+        let message_id: i32 = u16::from_be_bytes([pkt.payload[0], pkt.payload[1]]).into();
+        let data = &pkt.payload[2..];
+        match MediaMessageId::from_i32(message_id) {
+            Some(MEDIA_MESSAGE_SETUP) => {
+                if let Ok(setup) = Setup::parse_from_bytes(data) {
+                    if matches!(
+                        setup.type_(),
+                        MEDIA_CODEC_VIDEO_H264_BP | MEDIA_CODEC_VIDEO_H265
+                    ) {
+                        info!(
+                            "🎞️ capping video on channel <b>{:#04x}</> to <b>{}</> fps ({:?})",
+                            pkt.channel,
+                            self.fps,
+                            setup.type_()
+                        );
+                        self.channels
+                            .insert(pkt.channel, VideoChannel::new(setup.type_()));
+                    }
+                }
+                FrameVerdict::Forward
+            }
+            _ if !self.channels.contains_key(&pkt.channel) => FrameVerdict::Forward,
+            Some(MEDIA_MESSAGE_START) => {
+                let video = self.channels.get_mut(&pkt.channel).unwrap();
+                if let Ok(start) = Start::parse_from_bytes(data) {
+                    video.session_id = start.session_id();
+                }
+                video.tokens = BURST;
+                video.last_refill = None;
+                FrameVerdict::Forward
+            }
+            Some(MEDIA_MESSAGE_DATA) => {
+                let fps = self.fps;
+                let video = self.channels.get_mut(&pkt.channel).unwrap();
+                video.refill(fps, now);
+                // media data starts with a 64-bit timestamp
+                let keyframe = data
+                    .get(8..)
+                    .is_some_and(|nals| is_keyframe(video.codec, nals));
+                if video.tokens >= 1.0 || keyframe {
+                    video.tokens = (video.tokens - 1.0).max(0.0);
+                    video.dropping = false;
+                    return FrameVerdict::Forward;
+                }
+                video.dropping = pkt.flags & FRAME_TYPE_LAST == 0;
+                self.dropped += 1;
+                FrameVerdict::Ack(ack_packet(pkt.channel, video.session_id))
+            }
+            _ => {
+                if let Some(video) = self.channels.get_mut(&pkt.channel) {
+                    video.dropping = false;
+                }
+                FrameVerdict::Forward
+            }
+        }
+    }
+}
+
+/// the acknowledgment the HU sends for a received frame
+fn ack_packet(channel: u8, session_id: i32) -> Packet {
+    let mut ack = Ack::new();
+    ack.set_session_id(session_id);
+    ack.set_ack(1);
+    let mut payload = ack.write_to_bytes().unwrap();
+    payload.insert(0, ((MEDIA_MESSAGE_ACK as u16) >> 8) as u8);
+    payload.insert(1, ((MEDIA_MESSAGE_ACK as u16) & 0xff) as u8);
+    Packet {
+        channel,
+        flags: ENCRYPTED | FRAME_TYPE_FIRST | FRAME_TYPE_LAST,
+        final_length: None,
+        payload,
+    }
+}
+
+/// looks for an IDR slice or parameter sets in Annex B data
+fn is_keyframe(codec: MediaCodecType, data: &[u8]) -> bool {
+    data.windows(4)
+        .filter(|w| w[0..3] == [0, 0, 1])
+        .any(|w| match codec {
+            // IDR slice, SPS, PPS
+            MEDIA_CODEC_VIDEO_H264_BP => matches!(w[3] & 0x1f, 5 | 7 | 8),
+            // IRAP slices, VPS, SPS, PPS
+            MEDIA_CODEC_VIDEO_H265 => matches!((w[3] >> 1) & 0x3f, 16..=21 | 32..=34),
+            _ => true,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mitm::FRAME_TYPE_MASK;
+    use std::time::Duration;
+
+    const VIDEO: u8 = 3;
+    const AUDIO: u8 = 4;
+    const INPUT: u8 = 8;
+
+    fn message(channel: u8, flags: u8, id: MediaMessageId, body: &[u8]) -> Packet {
+        let mut payload = (id as u16).to_be_bytes().to_vec();
+        payload.extend_from_slice(body);
+        Packet {
+            channel,
+            flags: ENCRYPTED | flags,
+            final_length: None,
+            payload,
+        }
+    }
+
+    fn setup(channel: u8, codec: MediaCodecType) -> Packet {
+        let mut setup = Setup::new();
+        setup.set_type(codec);
+        let body = setup.write_to_bytes().unwrap();
+        message(channel, FRAME_TYPE_MASK, MEDIA_MESSAGE_SETUP, &body)
+    }
+
+    fn frame(channel: u8, nal: u8) -> Packet {
+        let mut body = vec![0; 8];
+        body.extend_from_slice(&[0, 0, 0, 1, nal, 0xaa, 0xbb]);
+        message(channel, FRAME_TYPE_MASK, MEDIA_MESSAGE_DATA, &body)
+    }
+
+    /// a limiter at 30 fps with the video and audio channels set up
+    fn limiter() -> VideoFrameLimiter {
+        let mut limiter = VideoFrameLimiter::new(30);
+        let now = Instant::now();
+        for pkt in [
+            setup(VIDEO, MEDIA_CODEC_VIDEO_H264_BP),
+            setup(AUDIO, MEDIA_CODEC_AUDIO_PCM),
+        ] {
+            assert!(matches!(limiter.filter(&pkt, now), FrameVerdict::Forward));
+        }
+        limiter
+    }
+
+    /// feeds frames at 60 fps, returns how many were forwarded
+    fn at_60_fps(limiter: &mut VideoFrameLimiter, pkts: Vec<Packet>) -> usize {
+        let start = Instant::now();
+        pkts.iter()
+            .enumerate()
+            .filter(|(i, pkt)| {
+                let now = start + Duration::from_micros(*i as u64 * 16_667);
+                matches!(limiter.filter(pkt, now), FrameVerdict::Forward)
+            })
+            .count()
+    }
+
+    #[test]
+    fn video_frames_above_the_cap_are_dropped_and_acked() {
+        let mut limiter = limiter();
+        // non-IDR slices
+        let frames = vec![frame(VIDEO, 0x41); 60];
+        assert_eq!(at_60_fps(&mut limiter, frames), 30);
+        assert_eq!(limiter.dropped(), 30);
+
+        let now = Instant::now();
+        limiter.filter(&frame(VIDEO, 0x41), now);
+        let FrameVerdict::Ack(ack) = limiter.filter(&frame(VIDEO, 0x41), now) else {
+            panic!("frame not dropped");
+        };
+        assert_eq!(ack.channel, VIDEO);
+        assert_eq!(
+            u16::from_be_bytes([ack.payload[0], ack.payload[1]]),
+            MEDIA_MESSAGE_ACK as u16
+        );
+        assert_eq!(Ack::parse_from_bytes(&ack.payload[2..]).unwrap().ack(), 1);
+    }
+
+    #[test]
+    fn keyframes_always_pass() {
+        let mut limiter = limiter();
+        // IDR slices, SPS and PPS
+        let frames: Vec<_> = [0x65, 0x67, 0x68]
+            .iter()
+            .cycle()
+            .take(60)
+            .map(|nal| frame(VIDEO, *nal))
+            .collect();
+        assert_eq!(at_60_fps(&mut limiter, frames), 60);
+
+        let now = Instant::now();
+        limiter.filter(&frame(VIDEO, 0x41), now);
+        let config = message(
+            VIDEO,
+            FRAME_TYPE_MASK,
+            MEDIA_MESSAGE_CODEC_CONFIG,
+            &[0, 0, 1, 0x67],
+        );
+        assert!(matches!(
+            limiter.filter(&config, now),
+            FrameVerdict::Forward
+        ));
+        assert!(matches!(
+            limiter.filter(&frame(VIDEO, 0x65), now),
+            FrameVerdict::Forward
+        ));
+    }
+
+    #[test]
+    fn audio_and_input_are_untouched() {
+        let mut limiter = limiter();
+        let audio = vec![message(AUDIO, FRAME_TYPE_MASK, MEDIA_MESSAGE_DATA, &[0; 64]); 60];
+        assert_eq!(at_60_fps(&mut limiter, audio), 60);
+        // input events: 0x8001 on a channel never set up
+        let mut input = message(INPUT, FRAME_TYPE_MASK, MEDIA_MESSAGE_DATA, &[0; 16]);
+        input.payload[0..2].copy_from_slice(&0x8001u16.to_be_bytes());
+        assert_eq!(at_60_fps(&mut limiter, vec![input; 60]), 60);
+        assert_eq!(limiter.dropped(), 0);
+    }
+
+    #[test]
+    fn all_fragments_of_a_dropped_frame_are_dropped() {
+        let mut limiter = limiter();
+        let now = Instant::now();
+        limiter.filter(&frame(VIDEO, 0x41), now);
+
+        let mut first = frame(VIDEO, 0x41);
+        first.flags = ENCRYPTED | FRAME_TYPE_FIRST;
+        let middle = message(VIDEO, 0, MEDIA_MESSAGE_DATA, &[0; 16]);
+        let last = message(VIDEO, FRAME_TYPE_LAST, MEDIA_MESSAGE_DATA, &[0; 16]);
+        assert!(matches!(limiter.filter(&first, now), FrameVerdict::Ack(_)));
+        assert!(matches!(limiter.filter(&middle, now), FrameVerdict::Drop));
+        assert!(matches!(limiter.filter(&last, now), FrameVerdict::Drop));
+
+        // the next frame is forwarded whole
+        let later = now + Duration::from_millis(40);
+        first.payload = frame(VIDEO, 0x41).payload;
+        assert!(matches!(
+            limiter.filter(&first, later),
+            FrameVerdict::Forward
+        ));
+        assert!(matches!(
+            limiter.filter(&middle, later),
+            FrameVerdict::Forward
+        ));
+        assert!(matches!(
+            limiter.filter(&last, later),
+            FrameVerdict::Forward
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! Projected video frame rate and dropped/late frame estimation.
+//!
+//! Frames are counted on the video channels advertised by the HU in the
+//! ServiceDiscoveryResponse. Drops are estimated from gaps in the frame
+//! timestamps (PTS) and late frames from the HU acknowledgments. This needs
+//! the decrypted stream, so nothing is collected in passthrough mode.
+use crate::mitm::protos::MediaMessageId::*;
+use crate::mitm::protos::VideoFrameRateType::*;
+use crate::mitm::protos::*;
+use crate::mitm::{Packet, ProxyType, FRAME_TYPE_FIRST};
+use protobuf::{Enum, Message};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// frame rate assumed when the HU doesn't advertise one
+const DEFAULT_FPS: u32 = 30;
+/// a PTS gap longer than this many nominal frame intervals means dropped frames
+const GAP_TOLERANCE: f64 = 1.5;
+
+#[derive(Debug)]
+struct VideoStats {
+    channels: HashSet<u8>,
+    nominal_fps: u32,
+    frames: u64,
+    acked: u64,
+    drops: u64,
+    max_unacked: u64,
+    last_pts: HashMap<u8, u64>,
+    period_start: Instant,
+    period_frames: u64,
+    fps: f64,
+}
+
+impl VideoStats {
+    fn new() -> Self {
+        Self {
+            channels: HashSet::new(),
+            nominal_fps: DEFAULT_FPS,
+            frames: 0,
+            acked: 0,
+            drops: 0,
+            max_unacked: 0,
+            last_pts: HashMap::new(),
+            period_start: Instant::now(),
+            period_frames: 0,
+            fps: 0.0,
+        }
+    }
+
+    fn frame(&mut self, channel: u8, pts: Option<u64>) {
+        self.frames += 1;
+        self.period_frames += 1;
+        if let Some(pts) = pts {
+            if let Some(last) = self.last_pts.insert(channel, pts) {
+                let interval = 1_000_000.0 / self.nominal_fps as f64;
+                let gap = pts.saturating_sub(last) as f64;
+                if gap > interval * GAP_TOLERANCE {
+                    self.drops += (gap / interval).round() as u64 - 1;
+                }
+            }
+        }
+        self.max_unacked = self.max_unacked.max(self.unacked());
+    }
+
+    fn unacked(&self) -> u64 {
+        self.frames.saturating_sub(self.acked)
+    }
+
+    fn summary(&self) -> VideoSummary {
+        VideoSummary {
+            fps: self.fps,
+            nominal_fps: self.nominal_fps,
+            frames: self.frames,
+            acked_frames: self.acked,
+            estimated_drops: self.drops,
+            unacked: self.unacked(),
+            max_unacked: self.max_unacked,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoSummary {
+    /// frame rate over the last reporting period
+    pub fps: f64,
+    /// frame rate advertised by the HU
+    pub nominal_fps: u32,
+    pub frames: u64,
+    pub acked_frames: u64,
+    /// frames missing according to the PTS gaps
+    pub estimated_drops: u64,
+    /// frames sent to the HU but not acknowledged yet
+    pub unacked: u64,
+    pub max_unacked: u64,
+}
+
+impl fmt::Display for VideoSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "🎞️ video: {:.1} fps (nominal {}), {} frames, estimated drops: {}, unacked: {} (max {})",
+            self.fps,
+            self.nominal_fps,
+            self.frames,
+            self.estimated_drops,
+            self.unacked,
+            self.max_unacked
+        )
+    }
+}
+
+// None until the video channels of the session are known
+static VIDEO_STATS: Mutex<Option<VideoStats>> = Mutex::new(None);
+
+/// forgets the previous session
+pub fn reset() {
+    *VIDEO_STATS.lock().unwrap() = None;
+}
+
+/// starts tracking the video channels advertised by the HU
+pub fn set_video_channels(msg: &ServiceDiscoveryResponse) {
+    let mut stats = VideoStats::new();
+    for svc in msg.services.iter() {
+        let Some(video) = svc.media_sink_service.video_configs.first() else {
+            continue;
+        };
+        let Ok(channel) = u8::try_from(svc.id()) else {
+            continue;
+        };
+        stats.channels.insert(channel);
+        if video.frame_rate() == VIDEO_FPS_60 {
+            stats.nominal_fps = 60;
+        }
+    }
+    *VIDEO_STATS.lock().unwrap() = (!stats.channels.is_empty()).then_some(stats);
+}
+
+/// feeds a decrypted packet: video frames from the MD, acks from the HU
+pub fn observe(proxy_type: ProxyType, pkt: &Packet) {
+    // only the first fragment carries the message id
+    if pkt.flags & FRAME_TYPE_FIRST == 0 || pkt.payload.len() < 2 {
+        return;
+    }
+    let mut lock = VIDEO_STATS.lock().unwrap();
+    let Some(stats) = lock.as_mut() else {
+        return;
+    };
+    if !stats.channels.contains(&pkt.channel) {
+        return;
+    }
+    let message_id: i32 = u16::from_be_bytes([pkt.payload[0], pkt.payload[1]]).into();
+    let data = &pkt.payload[2..];
+    match (proxy_type, MediaMessageId::from_i32(message_id)) {
+        (ProxyType::MobileDevice, Some(MEDIA_MESSAGE_DATA)) => {
+            // media data starts with a 64-bit timestamp [µs]
+            let pts = data
+                .get(0..8)
+                .map(|ts| u64::from_be_bytes(ts.try_into().unwrap()));
+            stats.frame(pkt.channel, pts);
+        }
+        (ProxyType::HeadUnit, Some(MEDIA_MESSAGE_ACK)) => {
+            if let Ok(ack) = Ack::parse_from_bytes(data) {
+                stats.acked += u64::from(ack.ack.unwrap_or(1));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// summary for the periodic statistics; starts a new fps period
+pub fn period_summary() -> Option<VideoSummary> {
+    let mut lock = VIDEO_STATS.lock().unwrap();
+    let stats = lock.as_mut()?;
+    let elapsed = stats.period_start.elapsed().as_secs_f64();
+    if elapsed > 0.0 {
+        stats.fps = stats.period_frames as f64 / elapsed;
+    }
+    stats.period_start = Instant::now();
+    stats.period_frames = 0;
+    Some(stats.summary())
+}
+
+/// current summary, without affecting the fps period
+pub fn summary() -> Option<VideoSummary> {
+    VIDEO_STATS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(VideoStats::summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_estimated_from_pts_gaps() {
+        let mut stats = VideoStats::new();
+        let interval = 1_000_000 / u64::from(DEFAULT_FPS);
+        // 3 frames in sequence, then 2 missing
+        for i in [0, 1, 2, 5] {
+            stats.frame(1, Some(i * interval));
+        }
+        assert_eq!(stats.frames, 4);
+        assert_eq!(stats.drops, 2);
+        // a little jitter isn't a drop
+        stats.frame(1, Some(6 * interval + interval / 3));
+        assert_eq!(stats.drops, 2);
+        stats.acked = 3;
+        assert_eq!(stats.summary().unacked, 2);
+        assert_eq!(stats.max_unacked, 5);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::bluetooth::{load_known_devices, KNOWN_DEVICES_FILE};
+use crate::bluetooth::{load_known_devices, KNOWN_DEVICES_FILE, LAST_DEVICE_FILE};
 use crate::bt_helper;
 #[cfg(feature = "wasm-scripting")]
 use crate::config::wasm_script_limits_config_section;
@@ -13,20 +13,27 @@ use crate::device_info;
 use crate::ev::send_ev_data;
 use crate::ev::BatteryData;
 use crate::ev::EV_MODEL_FILE;
+use crate::log_buffer;
 use crate::mitm::protos::KeyCode;
 use crate::mitm::send_byebye;
 use crate::mitm::send_input_key;
 use crate::mitm::send_key_event;
 use crate::mitm::send_rotary_event;
 use crate::mitm::send_toll_card;
+use crate::mitm::transmission_state;
 use crate::mitm::Packet;
 use crate::mitm::Result;
 use crate::mitm::SharedServiceDiscoveryResponse;
+use crate::mitm::{
+    dpi_override, pause_transfer, resume_transfer, set_dpi_override, transfer_pause_limit,
+};
 use crate::mitm::{send_odometer_data, OdometerData};
 use crate::mitm::{send_tire_pressure_data, TirePressureData};
 #[cfg(feature = "wasm-scripting")]
 use crate::script_wasm::{LoadedScript, ScriptRegistry};
 use crate::sdr_ui;
+use crate::sensor_feed::{self, SensorUpdate};
+use crate::video_stats;
 #[cfg(not(feature = "wasm-scripting"))]
 type ScriptRegistry = ();
 use axum::{
@@ -93,6 +100,12 @@ pub struct InjectRotaryData {
     pub delta: i32,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DpiOverrideData {
+    /// DPI for the next negotiation, `null` or 0 returns to the configured value
+    pub dpi: Option<u16>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UpdateConfigEntry {
     /// Configuration key name (e.g., "dpi", "ssid", "mitm")
@@ -142,7 +155,7 @@ pub struct AppState {
 }
 
 pub fn app(state: Arc<AppState>) -> Router {
-    Router::new()
+    let router = Router::new()
         .route("/", get(index))
         .route("/config", get(get_config).post(set_config))
         .route("/config-entry", post(update_config_entry))
@@ -158,6 +171,10 @@ pub fn app(state: Arc<AppState>) -> Router {
         )
         .route("/restart", post(restart_handler))
         .route("/reboot", post(reboot_handler))
+        .route("/phone-present", post(phone_present_handler))
+        .route("/transfer/pause", post(transfer_pause_handler))
+        .route("/transfer/resume", post(transfer_resume_handler))
+        .route("/dpi", get(dpi_get_handler).post(dpi_set_handler))
         .route("/upload-hex-model", post(upload_hex_model_handler))
         .route("/upload-certs", post(upload_cert_bundle_handler))
         .route("/certs-info", get(certs_info_handler))
@@ -167,6 +184,7 @@ pub fn app(state: Arc<AppState>) -> Router {
         .route("/odometer-status", get(odometer_status_handler))
         .route("/tire-pressure", post(tire_pressure_handler))
         .route("/tire-pressure-status", get(tire_pressure_status_handler))
+        .route("/sensor", post(sensor_handler))
         .route("/inject_event", post(inject_event_handler))
         .route("/inject_rotary", post(inject_rotary_handler))
         .route("/toll-card/add", post(toll_card_add_handler))
@@ -190,6 +208,7 @@ pub fn app(state: Arc<AppState>) -> Router {
             get(service_discovery_response_handler),
         )
         .route("/version", get(version_handler))
+        .route("/health", get(health_handler))
         .route("/ws", get(ws_handler))
         .route("/raw-topic-data", post(raw_topic_data_handler))
         .route("/bt/devices", get(bt_helper::bt_devices_handler))
@@ -205,8 +224,10 @@ pub fn app(state: Arc<AppState>) -> Router {
             "/bt/known-devices",
             get(bt_known_devices_handler).delete(bt_forget_known_devices_handler),
         )
-        .route("/disconnect", post(disconnect_handler))
-        .with_state(state)
+        .route("/disconnect", post(disconnect_handler));
+    #[cfg(feature = "metrics")]
+    let router = router.route("/metrics", get(metrics_handler));
+    router.with_state(state)
 }
 
 fn linkify_git_info(git_date: &str, git_hash: &str) -> String {
@@ -467,6 +488,17 @@ async fn odometer_status_handler(State(state): State<Arc<AppState>>) -> impl Int
     }
 }
 
+pub async fn sensor_handler(Json(update): Json<SensorUpdate>) -> impl IntoResponse {
+    let Some(feed) = sensor_feed::feed() else {
+        return (StatusCode::BAD_REQUEST, "sensor_injection is disabled").into_response();
+    };
+    debug!("{} Received sensor update: {:?}", NAME, update);
+    match feed.try_send(update) {
+        Ok(()) => (StatusCode::OK, "OK").into_response(),
+        Err(_) => (StatusCode::SERVICE_UNAVAILABLE, "sensor feed is busy").into_response(),
+    }
+}
+
 pub async fn tire_pressure_handler(
     State(state): State<Arc<AppState>>,
     Json(data): Json<TirePressureData>,
@@ -715,6 +747,79 @@ async fn restart_handler(State(state): State<Arc<AppState>>) -> impl IntoRespons
         .unwrap()
 }
 
+async fn phone_present_handler() -> impl IntoResponse {
+    info!("{} 📡 phone-present trigger requested", NAME);
+    crate::bluetooth::phone_present_trigger().notify_one();
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from("Phone-present trigger has been sent"))
+        .unwrap()
+}
+
+async fn dpi_get_handler() -> impl IntoResponse {
+    Json(json!({ "dpi_override": dpi_override() }))
+}
+
+async fn dpi_set_handler(Json(data): Json<DpiOverrideData>) -> impl IntoResponse {
+    let dpi = data.dpi.filter(|dpi| *dpi > 0);
+    set_dpi_override(dpi);
+    let body = match dpi {
+        Some(dpi) => {
+            info!("{} DPI override set to {}", NAME, dpi);
+            format!(
+                "DPI override set to {}, it applies on the next negotiation (the phone may need to reconnect)",
+                dpi
+            )
+        }
+        None => {
+            info!("{} DPI override cleared", NAME);
+            "DPI override cleared, the configured DPI applies on the next negotiation".to_string()
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn transfer_pause_handler() -> impl IntoResponse {
+    let body = if pause_transfer() {
+        info!("{} ⏸️ transfer paused", NAME);
+        format!(
+            "Transfer has been paused, it is resumed on its own after {} ms before the phone ends the session",
+            transfer_pause_limit().as_millis()
+        )
+    } else {
+        "Transfer is already paused".to_string()
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn transfer_resume_handler() -> impl IntoResponse {
+    let body = match resume_transfer() {
+        Some(paused) => {
+            info!(
+                "{} ▶️ transfer resumed after {} ms",
+                NAME,
+                paused.as_millis()
+            );
+            format!("Transfer has been resumed after {} ms", paused.as_millis())
+        }
+        None => "Transfer was not paused".to_string(),
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(body))
+        .unwrap()
+}
+
 async fn reboot_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     state.config.write().await.action_requested = Some(Action::Reboot);
 
@@ -1269,6 +1374,7 @@ async fn bt_known_devices_handler() -> impl IntoResponse {
 }
 
 async fn bt_forget_known_devices_handler() -> impl IntoResponse {
+    let _ = fs::remove_file(LAST_DEVICE_FILE).await;
     let path = std::path::Path::new(KNOWN_DEVICES_FILE);
     if !path.exists() {
         info!(
@@ -1441,6 +1547,26 @@ pub async fn version_handler() -> impl IntoResponse {
     }))
 }
 
+pub async fn health_handler() -> impl IntoResponse {
+    Json(json!({
+        "status": "ok",
+        "version": env!("CARGO_PKG_VERSION"),
+        "video": video_stats::summary(),
+        "vehicle": transmission_state(),
+        "log": log_buffer::recent_lines(),
+    }))
+}
+
+#[cfg(feature = "metrics")]
+pub async fn metrics_handler() -> impl IntoResponse {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(crate::metrics::render()))
+        .unwrap()
+        .into_response()
+}
+
 async fn get_config(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     let cfg = state.config.read().await.clone();
     let mut cfg_json: Value = serde_json::to_value(cfg).unwrap_or_else(|_| json!({}));
@@ -0,0 +1,109 @@
+use mac_address::MacAddress;
+use simplelog::*;
+use tokio::process::Command;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> wifi: </>";
+
+const HOSTAPD_CLI: &str = "/usr/bin/hostapd_cli";
+const HOSTAPD_CTRL_DIR: &str = "/var/run/hostapd";
+
+/// band (as in the `band` config option) of a Wi-Fi frequency in MHz
+pub fn band_from_freq(freq: u32) -> Option<&'static str> {
+    match freq {
+        2400..=2500 => Some("2.4"),
+        5150..=5925 => Some("5"),
+        5926..=7125 => Some("6"),
+        _ => None,
+    }
+}
+
+/// `freq=` value from the `hostapd_cli status` output
+fn parse_status_freq(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("freq="))
+        .and_then(|freq| freq.trim().parse().ok())
+}
+
+async fn hostapd_cli(iface: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(HOSTAPD_CLI)
+        .arg("-i")
+        .arg(iface)
+        .args(args)
+        .output()
+        .await
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Finds the AP interface the client is associated with (there may be one
+/// per radio on dual-band setups) and returns it with the frequency in MHz.
+pub async fn client_frequency(mac: MacAddress) -> Option<(String, u32)> {
+    let mac = mac.to_string().to_lowercase();
+    let mut ifaces = tokio::fs::read_dir(HOSTAPD_CTRL_DIR).await.ok()?;
+    while let Ok(Some(entry)) = ifaces.next_entry().await {
+        let iface = entry.file_name().to_string_lossy().into_owned();
+        // `sta` prints the station details starting with its MAC when associated
+        let Some(sta) = hostapd_cli(&iface, &["sta", &mac]).await else {
+            continue;
+        };
+        if !sta.to_lowercase().starts_with(&mac) {
+            continue;
+        }
+        let status = hostapd_cli(&iface, &["status"]).await?;
+        return parse_status_freq(&status).map(|freq| (iface, freq));
+    }
+    None
+}
+
+/// Disassociates a client from the AP on `iface`, returns false when hostapd failed.
+pub async fn disassociate(iface: &str, mac: MacAddress) -> bool {
+    hostapd_cli(iface, &["disassociate", &mac.to_string()])
+        .await
+        .is_some()
+}
+
+/// Checks that the client joined the band we advertised.
+/// Returns the AP interface of the client on a detected mismatch.
+pub async fn check_client_band(mac: MacAddress, expected: &str) -> Option<String> {
+    let Some((iface, freq)) = client_frequency(mac).await else {
+        warn!(
+            "{} 📶 band check: cannot find client {} on any hostapd interface",
+            NAME, mac
+        );
+        return None;
+    };
+    let detected = band_from_freq(freq).unwrap_or("unknown");
+    if detected == expected {
+        info!(
+            "{} 📶 band check: client {} on <b>{}</> at {} MHz ({} GHz) as expected",
+            NAME, mac, iface, freq, detected
+        );
+        None
+    } else {
+        warn!(
+            "{} 📶 band mismatch: client {} on <b>{}</> at {} MHz: detected <b><red>{} GHz</>, expected <b>{} GHz</>",
+            NAME, mac, iface, freq, detected, expected
+        );
+        Some(iface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn band_detected_from_hostapd_status() {
+        let status = "state=ENABLED\nphy=phy0\nfreq=5180\nnum_sta_non_erp=0\n";
+        let freq = parse_status_freq(status).unwrap();
+        assert_eq!(freq, 5180);
+        assert_eq!(band_from_freq(freq), Some("5"));
+        assert_eq!(band_from_freq(2437), Some("2.4"));
+        assert_eq!(parse_status_freq("state=DISABLED\n"), None);
+    }
+}
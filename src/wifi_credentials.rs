@@ -0,0 +1,303 @@
+use crate::config::{WifiConfig, WifiSecurity, WpaKeyPolicy};
+use bluer::Address;
+use serde::Deserialize;
+use simplelog::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+// module name for logging engine
+const NAME: &str = "<i><bright-black> wifi: </>";
+
+/// longest WPA2-PSK passphrase (64 characters would be a raw hex PSK)
+pub const WPA_KEY_MAX_LEN: usize = 63;
+
+/// Per-device WiFi credentials file, e.g.:
+/// ```toml
+/// [devices."AA:BB:CC:DD:EE:FF"]
+/// ssid = "car-alice"
+/// wpa_key = "secret-passphrase"
+///
+/// [[fallback]]
+/// ssid = "hotspot"
+/// wpa_key = "hotspot-passphrase"
+/// ```
+/// `fallback` profiles are offered in order when the phone can't join the previous one.
+#[derive(Debug, Default, Deserialize)]
+struct WifiCredentialsFile {
+    #[serde(default)]
+    devices: HashMap<String, WifiCredentials>,
+    #[serde(default)]
+    fallback: Vec<WifiCredentials>,
+}
+
+/// valid entries of the credentials file
+#[derive(Debug, Default)]
+struct Credentials {
+    devices: HashMap<Address, WifiCredentials>,
+    fallback: Vec<WifiCredentials>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WifiCredentials {
+    ssid: String,
+    wpa_key: String,
+}
+
+impl WifiCredentials {
+    fn validate(&self) -> Result<(), String> {
+        if self.ssid.is_empty() || self.ssid.len() > 32 {
+            return Err(format!("invalid SSID length: {} bytes", self.ssid.len()));
+        }
+        if self.wpa_key.len() < 8 || self.wpa_key.len() > WPA_KEY_MAX_LEN {
+            return Err(format!(
+                "invalid WPA passphrase length: {} characters",
+                self.wpa_key.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn to_wifi_config(&self, default: &WifiConfig) -> WifiConfig {
+        WifiConfig {
+            ssid: self.ssid.clone(),
+            wpa_key: self.wpa_key.clone(),
+            ..default.clone()
+        }
+    }
+}
+
+/// parses the file content, skipping (and logging) invalid entries
+fn parse_credentials(raw: &str) -> Result<Credentials, String> {
+    let file: WifiCredentialsFile = toml_edit::de::from_str(raw).map_err(|e| e.to_string())?;
+    let mut map = HashMap::new();
+    for (mac, creds) in file.devices {
+        let addr = match mac.parse::<Address>() {
+            Ok(addr) => addr,
+            Err(e) => {
+                warn!("{} skipping entry <b>{}</>: invalid MAC: {}", NAME, mac, e);
+                continue;
+            }
+        };
+        if let Err(e) = creds.validate() {
+            warn!("{} skipping entry <b>{}</>: {}", NAME, mac, e);
+            continue;
+        }
+        map.insert(addr, creds);
+    }
+    let mut fallback = Vec::new();
+    for (i, creds) in file.fallback.into_iter().enumerate() {
+        if let Err(e) = creds.validate() {
+            warn!("{} skipping fallback profile #{}: {}", NAME, i + 1, e);
+            continue;
+        }
+        fallback.push(creds);
+    }
+    Ok(Credentials {
+        devices: map,
+        fallback,
+    })
+}
+
+/// Selects the WiFi profiles for the connecting `device` from the credentials
+/// file: its own entry (or `default` when there is no valid one) first,
+/// followed by the fallback profiles with a different SSID.
+pub fn select_wifi_configs(path: &Path, device: Address, default: &WifiConfig) -> Vec<WifiConfig> {
+    let credentials = match std::fs::read_to_string(path) {
+        Ok(raw) => match parse_credentials(&raw) {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                warn!("{} error parsing {}: {}", NAME, path.display(), e);
+                Credentials::default()
+            }
+        },
+        Err(e) => {
+            warn!("{} cannot read {}: {}", NAME, path.display(), e);
+            Credentials::default()
+        }
+    };
+
+    let first = match credentials.devices.get(&device) {
+        Some(creds) => {
+            info!(
+                "{} 🔑 using per-device WiFi credentials for <b>{}</>: SSID <b>{}</>",
+                NAME, device, creds.ssid
+            );
+            creds.to_wifi_config(default)
+        }
+        None => {
+            info!(
+                "{} 🔑 using default WiFi credentials for <b>{}</>: SSID <b>{}</>",
+                NAME, device, default.ssid
+            );
+            default.clone()
+        }
+    };
+    fallback_profiles(first, &credentials.fallback, default)
+}
+
+/// `first` followed by the `fallback` profiles, skipping repeated SSIDs
+fn fallback_profiles(
+    first: WifiConfig,
+    fallback: &[WifiCredentials],
+    default: &WifiConfig,
+) -> Vec<WifiConfig> {
+    let mut profiles = vec![first];
+    for creds in fallback {
+        if profiles.iter().any(|p| p.ssid == creds.ssid) {
+            continue;
+        }
+        profiles.push(creds.to_wifi_config(default));
+    }
+    profiles
+}
+
+/// An empty WiFi password is only accepted for an explicitly `open` AP,
+/// so a missing password can't silently turn into an unsecured network.
+pub fn check_wifi_key(wifi_config: &WifiConfig) -> Result<(), String> {
+    match (wifi_config.security, wifi_config.wpa_key.is_empty()) {
+        (WifiSecurity::Open, false) => {
+            warn!(
+                "{} 🔓 SSID <b>{}</> is an open AP, the configured password is not sent",
+                NAME, wifi_config.ssid
+            );
+            Ok(())
+        }
+        (WifiSecurity::Open, true) => Ok(()),
+        (security, true) => Err(format!(
+            "empty WiFi password for SSID {} is only allowed with security mode \"open\" (configured: {})",
+            wifi_config.ssid, security
+        )),
+        (_, false) => Ok(()),
+    }
+}
+
+/// Applies `policy` to a WPA passphrase longer than [`WPA_KEY_MAX_LEN`],
+/// which the phone would otherwise accept but then fail to join with.
+pub fn apply_wpa_key_policy(
+    mut wifi_config: WifiConfig,
+    policy: WpaKeyPolicy,
+) -> Result<WifiConfig, String> {
+    let len = wifi_config.wpa_key.chars().count();
+    // the key of an open AP is never sent
+    if len <= WPA_KEY_MAX_LEN || wifi_config.security == WifiSecurity::Open {
+        return Ok(wifi_config);
+    }
+    match policy {
+        WpaKeyPolicy::Reject => {
+            error!(
+                "{} 🔑 WPA passphrase for SSID <b>{}</> is {} characters long (max {}), refusing to send it",
+                NAME, wifi_config.ssid, len, WPA_KEY_MAX_LEN
+            );
+            Err(format!(
+                "WPA passphrase too long: {} characters (max {})",
+                len, WPA_KEY_MAX_LEN
+            ))
+        }
+        WpaKeyPolicy::Truncate => {
+            wifi_config.wpa_key = wifi_config.wpa_key.chars().take(WPA_KEY_MAX_LEN).collect();
+            warn!(
+                "{} 🔑 WPA passphrase for SSID <b>{}</> is {} characters long, truncated to {} - the AP has to use the truncated passphrase too!",
+                NAME, wifi_config.ssid, len, WPA_KEY_MAX_LEN
+            );
+            Ok(wifi_config)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_entries_are_skipped() {
+        let map = parse_credentials(
+            r#"
+            [devices."AA:BB:CC:DD:EE:01"]
+            ssid = "car-alice"
+            wpa_key = "alice-passphrase"
+
+            [devices."AA:BB:CC:DD:EE:02"]
+            ssid = "car-bob"
+            wpa_key = "short"
+
+            [devices."not-a-mac"]
+            ssid = "car-eve"
+            wpa_key = "eve-passphrase"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(map.devices.len(), 1);
+        let alice: Address = "AA:BB:CC:DD:EE:01".parse().unwrap();
+        assert_eq!(map.devices[&alice].ssid, "car-alice");
+    }
+
+    #[test]
+    fn fallback_profiles_follow_the_device_entry() {
+        let credentials = parse_credentials(
+            r#"
+            [[fallback]]
+            ssid = "hotspot"
+            wpa_key = "hotspot-passphrase"
+
+            [[fallback]]
+            ssid = "garage"
+            wpa_key = "short"
+
+            [[fallback]]
+            ssid = "car"
+            wpa_key = "car-passphrase"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(credentials.fallback.len(), 2);
+
+        let default = wifi_config("secret-passphrase");
+        let profiles = fallback_profiles(default.clone(), &credentials.fallback, &default);
+        let ssids: Vec<_> = profiles.iter().map(|p| p.ssid.as_str()).collect();
+        assert_eq!(ssids, ["car", "hotspot"]);
+        assert_eq!(profiles[1].wpa_key, "hotspot-passphrase");
+        assert_eq!(profiles[1].bssid, default.bssid);
+    }
+
+    fn wifi_config(wpa_key: &str) -> WifiConfig {
+        WifiConfig {
+            ip_addr: "10.0.0.1".into(),
+            port: 5288,
+            ssid: "car".into(),
+            bssid: "00:11:22:33:44:55".into(),
+            wpa_key: wpa_key.into(),
+            security: Default::default(),
+            channel: None,
+            band: None,
+            start_extra_fields: Default::default(),
+            info_extra_fields: Default::default(),
+        }
+    }
+
+    #[test]
+    fn empty_wifi_key_needs_open_security() {
+        assert!(check_wifi_key(&wifi_config("")).is_err());
+        assert!(check_wifi_key(&wifi_config("secret-passphrase")).is_ok());
+
+        let open = WifiConfig {
+            security: WifiSecurity::Open,
+            ..wifi_config("")
+        };
+        assert!(check_wifi_key(&open).is_ok());
+    }
+
+    #[test]
+    fn oversized_wpa_key_is_rejected_or_truncated() {
+        let valid = "k".repeat(63);
+        for policy in [WpaKeyPolicy::Reject, WpaKeyPolicy::Truncate] {
+            let cfg = apply_wpa_key_policy(wifi_config(&valid), policy).unwrap();
+            assert_eq!(cfg.wpa_key, valid);
+        }
+
+        let oversized = "k".repeat(64);
+        assert!(apply_wpa_key_policy(wifi_config(&oversized), WpaKeyPolicy::Reject).is_err());
+        let cfg = apply_wpa_key_policy(wifi_config(&oversized), WpaKeyPolicy::Truncate).unwrap();
+        assert_eq!(cfg.wpa_key, valid);
+    }
+}